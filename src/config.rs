@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use log::info;
+use serde::Deserialize;
+
+use crate::data::course;
+use crate::data::drop::DropTable;
+use crate::data::exchange::ExchangeRule;
+use crate::data::{CountedItem, ItemCategory, SellItem};
+use crate::packets::{DateTime, Mode, RoomStat};
+
+/// Operator-tunable server settings, read from `config.json` if it exists
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub game: GameConfig,
+    pub rooms: RoomConfig,
+    pub login: LoginConfig,
+    pub http: HttpConfig,
+    pub shop: ShopConfig,
+    /// Items that can drop when a player finishes a hole
+    pub drops: DropTable,
+    /// Trades players can make between currencies and items through the code centre
+    pub exchange: Vec<ExchangeRule>,
+    /// Packet IDs to log in full (contents and raw bytes), for reverse-engineering
+    pub trace_packets: Vec<i16>,
+}
+
+/// The current time as a Unix timestamp
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+impl Config {
+    /// Check whether a packet ID should be traced
+    pub fn traces(&self, id: i16) -> bool {
+        self.trace_packets.contains(&id)
+    }
+
+    /// The date and time to tell clients about, honouring `fixed_date`
+    pub fn now_datetime(&self) -> DateTime {
+        let mut now = DateTime::from_unix(unix_now());
+
+        // Only the date is pinned, so the clock still ticks along
+        if let Some(date) = self.game.fixed_date {
+            now.year = date.year;
+            now.month = date.month;
+            now.day = date.day;
+        }
+        now
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    /// How many players can be logged into the game server at once
+    pub max_players: usize,
+    /// Bonus handed out the first time a player enters the game server
+    pub welcome: WelcomeConfig,
+    /// Report this date to clients instead of the real one, for period-accurate servers
+    pub fixed_date: Option<FixedDate>,
+    /// This server's number in the login server's list, which search results are tagged with
+    pub sv_no: i8,
+    /// Drop packets whose server_cid names somebody else, instead of just warning about them
+    pub reject_spoofed_cids: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct FixedDate {
+    pub year: i16,
+    pub month: i8,
+    pub day: i8,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            max_players: 400,
+            welcome: WelcomeConfig::default(),
+            fixed_date: None,
+            sv_no: 1,
+            reject_spoofed_cids: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WelcomeConfig {
+    pub gp: i32,
+    pub sc: i32,
+    /// Items (including tickets) as raw inventory values, i.e. `(item << 10) | count`
+    pub items: Vec<CountedItem>,
+    /// Telop shown to the player when they receive the bonus
+    pub message: String,
+}
+
+impl Default for WelcomeConfig {
+    fn default() -> Self {
+        WelcomeConfig {
+            gp: 0,
+            sc: 0,
+            items: Vec::new(),
+            message: "Welcome! A gift has been added to your account.".to_string(),
+        }
+    }
+}
+
+impl WelcomeConfig {
+    /// Check whether there's actually anything to hand out
+    pub fn is_empty(&self) -> bool {
+        self.gp == 0 && self.sc == 0 && self.items.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RoomConfig {
+    /// Maximum number of players allowed in a VS room
+    pub max_vs_members: i8,
+    /// Maximum number of players allowed in a competition room
+    pub max_compe_members: i8,
+    /// Number of courses that can be selected, up to the number that exist
+    pub num_courses: i8,
+    /// Number of seasons (time-of-day settings) that can be selected, up to the number that exist
+    pub num_seasons: i8,
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        RoomConfig {
+            max_vs_members: 4,
+            max_compe_members: 20,
+            num_courses: 6,
+            num_seasons: 4,
+        }
+    }
+}
+
+impl RoomConfig {
+    /// Get the largest room size allowed for a mode
+    pub fn max_members(&self, mode: Mode) -> i8 {
+        match mode {
+            Mode::VS => self.max_vs_members,
+            Mode::Competition => self.max_compe_members,
+            _ => 1,
+        }
+    }
+
+    /// Check the settings a player has picked for a room
+    pub fn validate(&self, stat: &RoomStat) -> bool {
+        matches!(stat.num_holes, 3 | 6 | 9 | 18)
+            && (0..self.num_courses).contains(&stat.course)
+            && (0..self.num_seasons).contains(&stat.season)
+            && course::exists(stat.course, stat.season)
+            && stat.member_max >= 1
+            && stat.time_limit >= 0
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ShopConfig {
+    /// Replaces the generated salon catalog, if set
+    pub salon_items: Option<Vec<SellItem>>,
+    /// How many of an item a player can hold, by category name (e.g. `"Ball"`),
+    /// replacing the built-in limit for that category
+    pub stack_maximums: BTreeMap<String, u32>,
+    /// Give back half of an item's GP shop price when it's trashed, instead of just destroying it
+    pub refund_trash: bool,
+}
+
+impl ShopConfig {
+    /// Get the most of one item that a player can hold.
+    /// Overrides can't go beyond what fits in an inventory count.
+    pub fn maximum(&self, category: ItemCategory) -> u32 {
+        match self.stack_maximums.get(category.name()) {
+            Some(&maximum) => maximum.min(0x3FF),
+            None => category.maximum(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LoginConfig {
+    /// Client versions that are allowed to log in
+    pub versions: Vec<u16>,
+    /// Every game server that the login server knows about
+    pub servers: Vec<ServerEntry>,
+    /// Restricts specific client versions to a subset of the servers, by number.
+    /// Versions that aren't listed here get every server.
+    pub version_servers: BTreeMap<u16, Vec<i16>>,
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        LoginConfig {
+            versions: vec![956],
+            servers: vec![ServerEntry::default()],
+            version_servers: BTreeMap::new(),
+        }
+    }
+}
+
+impl LoginConfig {
+    /// Get the servers that a particular client version should see
+    pub fn servers_for(&self, version: u16) -> Vec<&ServerEntry> {
+        match self.version_servers.get(&version) {
+            Some(numbers) => self
+                .servers
+                .iter()
+                .filter(|s| numbers.contains(&s.number))
+                .collect(),
+            None => self.servers.iter().collect(),
+        }
+    }
+}
+
+/// A game server, as listed on the Server Select screen
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerEntry {
+    pub number: i16,
+    /// IP or hostname that clients should connect to
+    pub address: String,
+    pub port: u16,
+    pub name: String,
+    pub comment: String,
+    pub max: i16,
+}
+
+impl Default for ServerEntry {
+    fn default() -> Self {
+        ServerEntry {
+            number: 1,
+            address: "splash.wuffs.org".to_string(),
+            port: 2051,
+            name: "CoolServer2".to_string(),
+            comment: "hewwo???".to_string(),
+            max: 20,
+        }
+    }
+}
+
+/// Settings for the optional web-facing HTTP server
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Whether to run the HTTP server at all
+    pub enabled: bool,
+    /// Address to listen on
+    pub addr: String,
+    /// How many players to list on the leaderboard
+    pub leaderboard_size: usize,
+    /// Bearer token for the /admin endpoints; they're disabled if this isn't set
+    pub admin_token: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            enabled: false,
+            addr: "0.0.0.0:8080".to_string(),
+            leaderboard_size: 50,
+            admin_token: None,
+        }
+    }
+}
+
+/// Load the config file, falling back to the defaults if there isn't one
+pub fn load(path: &str) -> Result<Config> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(serde_json::from_str(&data)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            info!("no {path} found, using default config");
+            Ok(Config::default())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
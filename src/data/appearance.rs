@@ -1,7 +1,8 @@
-use super::CharID;
+use super::{CharID, Item, ItemCategory};
 use deku::bitvec::{BitSlice, BitVec, Msb0};
 use deku::prelude::*;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 // Assumed to always represent a Player (character type: 0).
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -33,6 +34,81 @@ pub struct Appearance {
     pub default_skin_color: u16,
 }
 
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AppearanceError {
+    #[error("wearing both bottoms and a skirt")]
+    BottomsAndSkirt,
+    #[error("{0:?} doesn't wear skirts")]
+    NoSkirts(CharID),
+}
+
+impl Appearance {
+    /// Check that the client has sent a combination that could actually be worn.
+    /// Bottoms and skirts go in separate slots but cover the same part of the body,
+    /// so only one of them can be filled at once, and only some characters have a skirt.
+    pub fn validate(&self) -> Result<(), AppearanceError> {
+        if self.bottoms.is_some() && self.skirt.is_some() {
+            return Err(AppearanceError::BottomsAndSkirt);
+        }
+        if self.skirt.is_some() && !self.character_id.wears_skirts() {
+            return Err(AppearanceError::NoSkirts(self.character_id));
+        }
+        Ok(())
+    }
+}
+
+impl Appearance {
+    /// The items being worn, for each slot that has an item category of its own.
+    /// TODO: unverified; this takes slot values to be item numbers within the
+    /// character's categories, which still needs checking against the client
+    pub fn worn_items(&self) -> Vec<Item> {
+        use ItemCategory::*;
+
+        let c = self.character_id;
+        let slots = [
+            (self.head, Head(c)),
+            (self.glasses, Glasses(c)),
+            (self.tops, Tops(c)),
+            (self.bottoms, Bottoms(c)),
+            (self.shoes, Shoes(c)),
+            (self.gloves, Gloves(c)),
+            (self.wing, Wing(c)),
+            (Some(self.hair_style), HairStyle(c)),
+            (Some(self.hair_color), HairColor(c)),
+            (Some(self.eye_color), EyeColor(c)),
+            (Some(self.skin_color), SkinColor(c)),
+            (Some(self.face_paint), FacePaint(c)),
+        ];
+        to_items(&slots)
+    }
+
+    /// The items the character started out with, which they never need to buy
+    pub fn default_items(&self) -> Vec<Item> {
+        use ItemCategory::*;
+
+        let c = self.character_id;
+        let slots = [
+            (self.default_tops, Tops(c)),
+            (self.default_bottoms, Bottoms(c)),
+            (self.default_shoes, Shoes(c)),
+            (Some(self.default_hair_color), HairColor(c)),
+            (Some(self.default_eye_color), EyeColor(c)),
+            (Some(self.default_skin_color), SkinColor(c)),
+        ];
+        to_items(&slots)
+    }
+}
+
+fn to_items(slots: &[(Option<u16>, ItemCategory)]) -> Vec<Item> {
+    slots
+        .iter()
+        .filter_map(|&(num, category)| match num {
+            Some(num) if num <= 0x7FF => Some(Item::new(category, num as u32)),
+            _ => None,
+        })
+        .collect()
+}
+
 fn unpack_optional(input: u32) -> Option<u16> {
     if (input >= 1) && (input <= 0x3FF) {
         Some((input - 1) as u16)
@@ -29,6 +29,13 @@ impl CharID {
         }
     }
 
+    /// Whether this character has a skirt to fill in place of bottoms.
+    /// TODO: unverified; this goes by which of the cast are girls, and should be
+    /// checked against what the client actually draws
+    pub fn wears_skirts(self) -> bool {
+        matches!(self, Self::Miel | Self::Rose | Self::Chocola | Self::Shelly)
+    }
+
     pub fn from_index(index: u32) -> Option<Self> {
         match index {
             1 => Some(Self::Rusk),
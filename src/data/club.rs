@@ -0,0 +1,45 @@
+use crate::packets::ClubData;
+
+/// Stats for each club, indexed by the club number used in shots:
+/// power, control, impact, spin, luck and carry distance in yards.
+/// TODO: these are modelled on a typical set of real clubs, not on the real server
+const CLUB_STATS: [(f32, f32, f32, f32, f32, f32); 15] = [
+    // 1W, 3W, 5W
+    (100.0, 60.0, 80.0, 40.0, 50.0, 250.0),
+    (92.0, 65.0, 78.0, 45.0, 50.0, 230.0),
+    (85.0, 70.0, 76.0, 50.0, 50.0, 210.0),
+    // 2I to 9I
+    (80.0, 72.0, 74.0, 55.0, 50.0, 200.0),
+    (76.0, 74.0, 72.0, 58.0, 50.0, 190.0),
+    (72.0, 76.0, 70.0, 61.0, 50.0, 180.0),
+    (68.0, 78.0, 68.0, 64.0, 50.0, 170.0),
+    (64.0, 80.0, 66.0, 67.0, 50.0, 160.0),
+    (60.0, 82.0, 64.0, 70.0, 50.0, 150.0),
+    (56.0, 84.0, 62.0, 73.0, 50.0, 140.0),
+    (52.0, 86.0, 60.0, 76.0, 50.0, 130.0),
+    // PW, AW, SW
+    (46.0, 88.0, 58.0, 80.0, 50.0, 115.0),
+    (40.0, 90.0, 56.0, 84.0, 50.0, 100.0),
+    (34.0, 92.0, 54.0, 88.0, 50.0, 85.0),
+    // PT
+    (10.0, 100.0, 50.0, 0.0, 50.0, 30.0),
+];
+
+pub fn build_club_data() -> Vec<ClubData> {
+    CLUB_STATS
+        .iter()
+        .enumerate()
+        .map(
+            |(id, &(power, control, impact, spin, luck, distance))| ClubData {
+                id: id as i16,
+                power,
+                control,
+                impact,
+                spin,
+                luck,
+                x16: 0,
+                distance,
+            },
+        )
+        .collect()
+}
@@ -0,0 +1,23 @@
+/// Number of courses the client has data for
+pub const NUM_COURSES: i8 = 6;
+/// Number of seasons (time-of-day settings) each course can be played in
+pub const NUM_SEASONS: i8 = 4;
+
+/// Check that a course and season are ones the client can actually load
+pub fn exists(course: i8, season: i8) -> bool {
+    (0..NUM_COURSES).contains(&course) && (0..NUM_SEASONS).contains(&season)
+}
+
+/// Furthest a character can stand from the origin along any axis.
+/// TODO: find the real bounds for each course; this comfortably covers all of them
+pub const MAX_EXTENT: f32 = 2000.0;
+
+/// Pull a position sent by a client back onto the course.
+/// Returns None if it isn't a usable position at all (NaN or infinite).
+pub fn clamp_position(x: f32, y: f32, z: f32) -> Option<(f32, f32, f32)> {
+    if !(x.is_finite() && y.is_finite() && z.is_finite()) {
+        return None;
+    }
+    let clamp = |v: f32| v.clamp(-MAX_EXTENT, MAX_EXTENT);
+    Some((clamp(x), clamp(y), clamp(z)))
+}
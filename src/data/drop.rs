@@ -0,0 +1,64 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+
+use super::CountedItem;
+
+/// How well a hole went, as far as item drops are concerned
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DropTier {
+    HoleInOne,
+    /// Holed out in two
+    Great,
+    Normal,
+}
+
+impl DropTier {
+    /// Work out the tier from the number of strokes taken on a hole.
+    /// Holeouts don't tell us the par, so this only goes by the stroke count.
+    pub fn from_strokes(strokes: i8) -> Self {
+        match strokes {
+            1 => DropTier::HoleInOne,
+            2 => DropTier::Great,
+            _ => DropTier::Normal,
+        }
+    }
+}
+
+/// The chance of an item dropping, and what it could be
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DropPool {
+    /// Probability of a drop, from 0 to 1
+    pub chance: f64,
+    /// Items to pick one from, as raw inventory values
+    pub rewards: Vec<CountedItem>,
+}
+
+/// Items that can be won by finishing a hole, with better holes giving better odds
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DropTable {
+    pub hole_in_one: DropPool,
+    pub great: DropPool,
+    pub normal: DropPool,
+}
+
+impl DropTable {
+    pub fn pool(&self, tier: DropTier) -> &DropPool {
+        match tier {
+            DropTier::HoleInOne => &self.hole_in_one,
+            DropTier::Great => &self.great,
+            DropTier::Normal => &self.normal,
+        }
+    }
+
+    /// See whether a hole in this tier earns an item, and if so, which one
+    pub fn roll<R: Rng>(&self, tier: DropTier, rng: &mut R) -> Option<CountedItem> {
+        let pool = self.pool(tier);
+        if pool.rewards.is_empty() || !rng.gen_bool(pool.chance.clamp(0.0, 1.0)) {
+            return None;
+        }
+        pool.rewards.choose(rng).copied()
+    }
+}
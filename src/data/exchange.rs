@@ -0,0 +1,103 @@
+use serde::Deserialize;
+
+use crate::config::ShopConfig;
+
+use super::{CountedItem, User};
+
+/// Every kind of balance a player can exchange between
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
+pub enum Wallet {
+    GP,
+    SC,
+    NP,
+    /// Game Center medals
+    Medals,
+    /// Whole Game Center coins
+    Coins,
+}
+
+impl Wallet {
+    fn balance(self, user: &mut User) -> &mut i32 {
+        match self {
+            Wallet::GP => &mut user.gp,
+            Wallet::SC => &mut user.sc,
+            Wallet::NP => &mut user.np,
+            Wallet::Medals => &mut user.game_center_medals,
+            Wallet::Coins => &mut user.coin_tenths,
+        }
+    }
+
+    /// How many of this wallet's units are stored per unit, as coins are kept in tenths
+    fn scale(self) -> i32 {
+        match self {
+            Wallet::Coins => 10,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Amount {
+    pub wallet: Wallet,
+    pub amount: i32,
+}
+
+/// A fixed-rate trade that players can make as often as they can afford
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExchangeRule {
+    /// What players enter in the code centre to make this exchange
+    pub name: String,
+    pub cost: Amount,
+    /// Balance to be paid into
+    #[serde(default)]
+    pub gives: Option<Amount>,
+    /// Items to be handed over
+    #[serde(default)]
+    pub items: Vec<CountedItem>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExchangeError {
+    CantAfford,
+    /// The player has no room for what they'd be given
+    CantHold,
+    /// The rule itself doesn't make sense, such as a negative cost
+    BadRule,
+}
+
+impl ExchangeRule {
+    /// Make the exchange on the player's behalf.
+    /// Nothing changes unless the whole trade goes through.
+    pub fn apply(&self, user: &mut User, shop: &ShopConfig) -> Result<(), ExchangeError> {
+        let cost = self.cost.amount.checked_mul(self.cost.wallet.scale());
+        let gain = match self.gives {
+            Some(gives) => gives.amount.checked_mul(gives.wallet.scale()),
+            None => Some(0),
+        };
+        let (cost, gain) = match (cost, gain) {
+            (Some(cost), Some(gain)) if cost >= 0 && gain >= 0 => (cost, gain),
+            _ => return Err(ExchangeError::BadRule),
+        };
+
+        let mut updated = user.clone();
+        let paid_from = self.cost.wallet.balance(&mut updated);
+        if *paid_from < cost {
+            return Err(ExchangeError::CantAfford);
+        }
+        *paid_from -= cost;
+
+        if let Some(gives) = self.gives {
+            let paid_into = gives.wallet.balance(&mut updated);
+            *paid_into = paid_into.checked_add(gain).ok_or(ExchangeError::CantHold)?;
+        }
+        if !updated.has_room_for(&self.items, shop) {
+            return Err(ExchangeError::CantHold);
+        }
+        for &item in &self.items {
+            updated.add_item(item);
+        }
+
+        *user = updated;
+        Ok(())
+    }
+}
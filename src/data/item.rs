@@ -79,6 +79,60 @@ impl ItemCategory {
         }
     }
 
+    /// Get the name of this category, without the character it belongs to
+    pub fn name(self) -> &'static str {
+        use ItemCategory::*;
+
+        match self {
+            ClubSet => "ClubSet",
+            Ball => "Ball",
+            CarryItemParameter => "CarryItemParameter",
+            CarryItemEnvironment => "CarryItemEnvironment",
+            CarryItemGroundRes => "CarryItemGroundRes",
+            CarryItemPowerGauge => "CarryItemPowerGauge",
+            CarryItemCaddy => "CarryItemCaddy",
+            HoldItemPoint => "HoldItemPoint",
+            HoldItemEvent => "HoldItemEvent",
+            HoldItemTicket => "HoldItemTicket",
+            HoldItemHumor => "HoldItemHumor",
+            HoldItemSupport => "HoldItemSupport",
+            Caddy => "Caddy",
+            Head(_) => "Head",
+            Glasses(_) => "Glasses",
+            Tops(_) => "Tops",
+            Bottoms(_) => "Bottoms",
+            Shoes(_) => "Shoes",
+            Gloves(_) => "Gloves",
+            Wing(_) => "Wing",
+            HairStyle(_) => "HairStyle",
+            HairColor(_) => "HairColor",
+            SkinColor(_) => "SkinColor",
+            FacePaint(_) => "FacePaint",
+            EyeColor(_) => "EyeColor",
+            HairStyleTicket(_) => "HairStyleTicket",
+            HairColorTicket(_) => "HairColorTicket",
+            SkinColorTicket(_) => "SkinColorTicket",
+            FacePaintTicket(_) => "FacePaintTicket",
+            EyeColorTicket(_) => "EyeColorTicket",
+            Chara(_) => "Chara",
+            Invalid => "Invalid",
+        }
+    }
+
+    /// Get the kind of ticket that can be traded in for this salon item
+    pub fn salon_ticket(self) -> Option<ItemCategory> {
+        use ItemCategory::*;
+
+        match self {
+            HairStyle(c) => Some(HairStyleTicket(c)),
+            HairColor(c) => Some(HairColorTicket(c)),
+            SkinColor(c) => Some(SkinColorTicket(c)),
+            FacePaint(c) => Some(FacePaintTicket(c)),
+            EyeColor(c) => Some(EyeColorTicket(c)),
+            _ => None,
+        }
+    }
+
     pub fn maximum(self) -> u32 {
         use ItemCategory::*;
 
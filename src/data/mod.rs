@@ -1,19 +1,28 @@
 pub mod appearance;
 pub mod character;
+pub mod club;
+pub mod course;
+pub mod drop;
+pub mod exchange;
 pub mod item;
+pub mod rating;
 pub mod record;
+pub mod recycle;
 pub mod report;
 pub mod shop;
+pub mod svitem;
 
 pub use appearance::Appearance;
 pub use character::{CharID, Character, ParamTuple};
 pub use item::{CountedItem, Item, ItemCategory};
+pub use rating::QuickRating;
 pub use shop::{SellCaddy, SellItem};
 
 use crate::data::shop::Currency;
 use deku::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::config::ShopConfig;
 use crate::packets::{ChrUID, Element, UID};
 
 #[derive(
@@ -86,6 +95,35 @@ impl Rank {
     }
 }
 
+/// A message from one player to another
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub mail_uid: i32,
+    pub from_uid: UID,
+    pub to_uid: UID,
+    /// When it was sent, as a Unix timestamp
+    pub sent_at: i64,
+    pub body: String,
+}
+
+/// An item sent from one player to another, waiting in the recipient's delivery box
+#[derive(Debug, Clone)]
+pub struct PendingDelivery {
+    pub delivery_uid: i32,
+    pub from_uid: UID,
+    pub to_uid: UID,
+    pub item: CountedItem,
+    pub message: String,
+}
+
+/// A caddie that the player has employed
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CaddieRental {
+    pub caddie: Item,
+    /// When the rental runs out, as a Unix timestamp; None if it never does
+    pub expires_at: Option<i64>,
+}
+
 #[derive(Debug)]
 pub struct Account {
     pub uid: UID,
@@ -101,9 +139,49 @@ pub struct User {
     pub class: Rank,
     pub gp: i32,
     pub sc: i32,
+    #[serde(default)]
+    pub np: i32,
     pub golfbag: [Item; 8],
     pub holdbox: [Item; 8],
     pub inventory: Vec<CountedItem>,
+    /// How many of each tournament award the player has received
+    #[serde(default)]
+    pub awards: [i32; 20],
+    /// Whether the one-time welcome bonus has been handed out
+    #[serde(default)]
+    pub has_received_welcome: bool,
+    /// Quick match standing in matches played with items
+    #[serde(default)]
+    pub rating_item_on: QuickRating,
+    /// Quick match standing in matches played without items
+    #[serde(default)]
+    pub rating_item_off: QuickRating,
+    /// Bitmask of every title the player has obtained
+    #[serde(default)]
+    pub titles: u128,
+    /// The title the player is showing off
+    #[serde(default)]
+    pub title: u8,
+    #[serde(default)]
+    pub caddie_rentals: Vec<CaddieRental>,
+    /// Whether the player has turned away deliveries from other players
+    #[serde(default)]
+    pub refuses_deliveries: bool,
+    /// Canned chat messages, indexed by slot; empty slots are empty strings
+    #[serde(default)]
+    pub macros: Vec<String>,
+    /// Game Center coins, counted in tenths of a coin
+    #[serde(default)]
+    pub coin_tenths: i32,
+    /// Game Center medals, spent on the slot machine
+    #[serde(default)]
+    pub game_center_medals: i32,
+    /// Server date (year, month, day) that `slots_plays_used` counts towards
+    #[serde(default)]
+    pub slots_plays_date: Option<(i16, i8, i8)>,
+    /// Free slots plays used on `slots_plays_date`
+    #[serde(default)]
+    pub slots_plays_used: u8,
 }
 
 impl Default for User {
@@ -114,14 +192,36 @@ impl Default for User {
             class: Rank::G4,
             gp: 5000,
             sc: 100,
+            np: 0,
             golfbag: Default::default(),
             holdbox: Default::default(),
             inventory: Vec::new(),
+            awards: [0; 20],
+            has_received_welcome: false,
+            rating_item_on: QuickRating::default(),
+            rating_item_off: QuickRating::default(),
+            titles: 0,
+            title: 0,
+            caddie_rentals: Vec::new(),
+            refuses_deliveries: false,
+            macros: Vec::new(),
+            coin_tenths: 0,
+            game_center_medals: 0,
+            slots_plays_date: None,
+            slots_plays_used: 0,
         }
     }
 }
 
 impl User {
+    /// Get the quick match rating for one item setting
+    pub fn rating_mut(&mut self, item_on: bool) -> &mut QuickRating {
+        match item_on {
+            true => &mut self.rating_item_on,
+            false => &mut self.rating_item_off,
+        }
+    }
+
     /// Get the quantity of a particular item within the user's inventory
     pub fn item_amount(&self, item: Item) -> u32 {
         match self.inventory.iter().find(|ci| ci.item() == item) {
@@ -130,6 +230,20 @@ impl User {
         }
     }
 
+    /// Check that the user could take all of these items without going over the most
+    /// of each that they can hold
+    pub fn has_room_for(&self, items: &[CountedItem], shop: &ShopConfig) -> bool {
+        items.iter().all(|counted_item| {
+            let item = counted_item.item();
+            let incoming: u32 = items
+                .iter()
+                .filter(|ci| ci.item() == item)
+                .map(|ci| ci.count())
+                .sum();
+            self.item_amount(item) + incoming <= shop.maximum(item.category())
+        })
+    }
+
     /// Add a particular item to the user's inventory
     pub fn add_item(&mut self, counted_item: CountedItem) {
         let item = counted_item.item();
@@ -141,6 +255,60 @@ impl User {
         }
     }
 
+    /// Take some of a particular item out of the user's inventory.
+    /// Returns false (and changes nothing) if they don't have enough of it.
+    pub fn remove_item(&mut self, counted_item: CountedItem) -> bool {
+        let item = counted_item.item();
+        let count = counted_item.count();
+
+        match self.inventory.iter().position(|ci| ci.item() == item) {
+            Some(index) if self.inventory[index].count() > count => {
+                let ci = self.inventory[index];
+                self.inventory[index] = ci.with_count(ci.count() - count);
+                true
+            }
+            Some(index) if self.inventory[index].count() == count => {
+                self.inventory.remove(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Find the rental for a caddie, if the player has ever employed them
+    pub fn caddie_rental(&self, caddie: Item) -> Option<&CaddieRental> {
+        self.caddie_rentals.iter().find(|r| r.caddie == caddie)
+    }
+
+    /// Employ a caddie for `seconds` (or forever, if None).
+    /// Time left on an existing rental carries over.
+    pub fn employ_caddie(&mut self, caddie: Item, seconds: Option<i64>, now: i64) {
+        let expires_at = match (self.caddie_rental(caddie), seconds) {
+            (_, None) => None,
+            (
+                Some(&CaddieRental {
+                    expires_at: None, ..
+                }),
+                _,
+            ) => None,
+            (
+                Some(&CaddieRental {
+                    expires_at: Some(t),
+                    ..
+                }),
+                Some(s),
+            ) => Some(t.max(now) + s),
+            (None, Some(s)) => Some(now + s),
+        };
+
+        self.caddie_rentals.retain(|r| r.caddie != caddie);
+        self.caddie_rentals
+            .push(CaddieRental { caddie, expires_at });
+        if self.item_amount(caddie) == 0 {
+            self.add_item(CountedItem::new(caddie, 1));
+        }
+    }
+
     /// Check if the user has enough money to buy something
     pub fn check_balance(&self, currency: Currency, cost: i32) -> bool {
         match currency {
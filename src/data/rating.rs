@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+/// Rating points gained for winning a quick match
+const WIN_POINTS: i16 = 30;
+/// Rating points lost for losing or retiring from a quick match
+const LOSS_POINTS: i16 = 20;
+/// Rating points needed to go up one rank
+const POINTS_PER_RANK: i16 = 100;
+/// Highest rank that fits in the client's 5-bit field
+const MAX_RANK: u8 = 31;
+/// Rating can't climb past the point where it stops meaning anything
+const MAX_SCORE: i16 = POINTS_PER_RANK * (MAX_RANK as i16 + 1) - 1;
+
+/// How a quick match went, as far as the rating is concerned
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchResult {
+    Win,
+    Loss,
+    /// Draws and anything else that shouldn't move the rating
+    Neutral,
+}
+
+/// A player's quick match standing for one item setting
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuickRating {
+    pub score: i16,
+    /// Wins in a row, reset by a loss
+    pub win_combo: u8,
+    /// Highest rank ever reached; never goes down
+    pub best_rank: u8,
+}
+
+impl QuickRating {
+    pub fn rank(&self) -> u8 {
+        ((self.score.max(0) / POINTS_PER_RANK) as u8).min(MAX_RANK)
+    }
+
+    /// Apply the result of a match, returning the rank before it
+    pub fn add_result(&mut self, result: MatchResult) -> u8 {
+        let old_rank = self.rank();
+        match result {
+            MatchResult::Win => {
+                self.score = (self.score + WIN_POINTS).min(MAX_SCORE);
+                self.win_combo = self.win_combo.saturating_add(1);
+            }
+            MatchResult::Loss => {
+                self.score = (self.score - LOSS_POINTS).max(0);
+                self.win_combo = 0;
+            }
+            MatchResult::Neutral => {}
+        }
+        self.best_rank = self.best_rank.max(self.rank());
+        old_rank
+    }
+}
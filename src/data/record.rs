@@ -1,18 +1,38 @@
+use crate::data::report::{GameReport, HoleReport};
 use crate::packets::UID;
 use deku::bitvec::{BitSlice, BitVec, Msb0};
 use deku::prelude::*;
 use serde::{Deserialize, Serialize};
 
+/// One player's row on the public leaderboard
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaderboardEntry {
+    pub uid: UID,
+    pub name: String,
+    /// Current GP balance
+    pub gp: i32,
+    /// GP earned across every round played
+    pub total_gp: u64,
+    /// Number of rounds played
+    pub num_rounds: u32,
+    /// Best score on any course, if they've played one
+    pub best_score: Option<i8>,
+}
+
 /// A specific player's records for a specific course.
 /// Keyed on UID, course, season, hole_idx(0-3).
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CRecord {
     /// Best score
     pub mx_score: i8,
-    /// Lowest score (default is -99)
-    pub lowest_score: i8,
-    /// Total score (default is 99)
-    pub total_score: i8,
+    /// Worst round score (default is -99).
+    /// Older research had this down as the lowest score, which the default doesn't fit.
+    #[serde(alias = "lowest_score")]
+    pub worst_score: i8,
+    /// Best round score (default is 99).
+    /// Older research had this down as the total score, which the default doesn't fit.
+    #[serde(alias = "total_score")]
+    pub best_score: i8,
     /// Unknown
     pub array: [i8; 18],
     /// Number of rounds
@@ -39,8 +59,8 @@ impl Default for CRecord {
     fn default() -> Self {
         CRecord {
             mx_score: 0,
-            lowest_score: -99,
-            total_score: 99,
+            worst_score: -99,
+            best_score: 99,
             array: Default::default(),
             num_rounds: 0,
             max_gp: 0,
@@ -55,14 +75,46 @@ impl Default for CRecord {
     }
 }
 
+impl CRecord {
+    /// Count a round played on this course
+    pub fn add_round(&mut self, report: &GameReport) {
+        let round_gp = report.obtained_gp_round;
+
+        self.num_rounds = self.num_rounds.saturating_add(1);
+        self.total_gp = self.total_gp.saturating_add(round_gp);
+        self.max_gp = self.max_gp.max(round_gp.min(u16::MAX as u32) as u16);
+        self.best_score = self.best_score.min(report.score);
+        self.worst_score = self.worst_score.max(report.score);
+    }
+
+    /// Fold the holes from a round into the per-hole bests in `array`.
+    /// Holes are given by their number on the course. Scores are stroke counts, so a
+    /// zero means that hole hasn't been played yet.
+    pub fn add_holes(&mut self, holes: &[(i8, HoleReport)]) {
+        for (hole, report) in holes {
+            let best = match self.array.get_mut(*hole as usize) {
+                Some(best) => best,
+                None => continue,
+            };
+            if report.score > 0 && (*best == 0 || report.score < *best) {
+                *best = report.score;
+            }
+
+            self.max_drive = self.max_drive.max(report.maximum_flight_distance);
+            self.max_chipin = self.max_chipin.max(report.longest_chip_in);
+            self.max_putt = self.max_putt.max(report.longest_putt);
+        }
+    }
+}
+
 impl DekuRead<'_> for CRecord {
     fn read(input: &BitSlice<u8, Msb0>, ctx: ()) -> Result<(&BitSlice<u8, Msb0>, Self), DekuError>
     where
         Self: Sized,
     {
         let (input, mx_score) = i8::read(input, ctx)?;
-        let (input, lowest_score) = i8::read(input, ctx)?;
-        let (input, total_score) = i8::read(input, ctx)?;
+        let (input, worst_score) = i8::read(input, ctx)?;
+        let (input, best_score) = i8::read(input, ctx)?;
         let (input, array) = <[i8; 18]>::read(input, ctx)?;
         let (input, num_rounds) = u16::read(input, ctx)?;
         let (input, max_gp) = u16::read(input, ctx)?;
@@ -75,8 +127,8 @@ impl DekuRead<'_> for CRecord {
 
         let record = CRecord {
             mx_score,
-            lowest_score,
-            total_score,
+            worst_score,
+            best_score,
             array,
             num_rounds,
             max_gp,
@@ -96,8 +148,8 @@ impl DekuRead<'_> for CRecord {
 impl DekuWrite for CRecord {
     fn write(&self, output: &mut BitVec<u8, Msb0>, ctx: ()) -> Result<(), DekuError> {
         self.mx_score.write(output, ctx)?;
-        self.lowest_score.write(output, ctx)?;
-        self.total_score.write(output, ctx)?;
+        self.worst_score.write(output, ctx)?;
+        self.best_score.write(output, ctx)?;
         self.array.write(output, ctx)?;
         self.num_rounds.write(output, ctx)?;
         self.max_gp.write(output, ctx)?;
@@ -118,7 +170,7 @@ impl DekuWrite for CRecord {
 
 /// A specific player's records.
 /// Keyed on UID.
-#[derive(Debug, Clone, Deserialize, Serialize, DekuRead, DekuWrite)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, DekuRead, DekuWrite)]
 pub struct URecord {
     /// Number of rounds played
     pub num_rounds: i16,
@@ -196,7 +248,9 @@ pub struct URecord {
     pub total_quick_participation: i32,
     /// Number of consumable items used
     pub num_consumable_item_usage: i32,
-    /// ??? Triggers the "chat memorial" photo
+    /// Triggers the "chat memorial" photo when non-zero.
+    /// We set this the first time a player wins a round; the client doesn't care about
+    /// the exact value, and the real server's condition is still unconfirmed.
     pub x_74: i32,
     /// ???
     pub x_78: i32,
@@ -222,8 +276,90 @@ pub struct URecord {
     pub x_92: [u8; 14],
 }
 
+/// A running total in a record, which stops at its maximum instead of overflowing
+trait Total {
+    fn add_saturating(&mut self, n: u32);
+}
+
+impl Total for i16 {
+    fn add_saturating(&mut self, n: u32) {
+        *self = self.saturating_add(n.try_into().unwrap_or(i16::MAX));
+    }
+}
+
+impl Total for i32 {
+    fn add_saturating(&mut self, n: u32) {
+        *self = self.saturating_add(n.try_into().unwrap_or(i32::MAX));
+    }
+}
+
+impl Total for u32 {
+    fn add_saturating(&mut self, n: u32) {
+        *self = self.saturating_add(n);
+    }
+}
+
+impl URecord {
+    /// Fold a finished round into the totals.
+    /// Reports measure distances in 1/100y, where these records use whole units.
+    pub fn add_report(&mut self, report: &GameReport, num_holes: usize) {
+        fn add<T: Total>(total: &mut T, n: u32) {
+            total.add_saturating(n);
+        }
+
+        let first_round = self.total_holes == 0;
+        add(&mut self.total_strokes, report.num_strokes);
+        add(&mut self.total_cup_ins, report.num_cup_ins);
+        add(&mut self.total_putts, report.num_putts);
+        add(&mut self.num_nice_shots, report.num_nice_shots);
+        add(&mut self.num_chip_in, report.num_tip_ins);
+        add(&mut self.num_fairway_keep, report.num_fairway_keep);
+        add(&mut self.num_ob, report.num_ob);
+        add(&mut self.num_water_hazard, report.num_water_hazard);
+        add(&mut self.num_4_or_more, report.num_4_or_more);
+        add(&mut self.num_triple_bogies, report.num_t_bogeys);
+        add(&mut self.num_double_bogies, report.num_d_bogeys);
+        add(&mut self.num_bogies, report.num_bogeys);
+        add(&mut self.num_pars, report.num_pars);
+        add(&mut self.num_birdies, report.num_birdies);
+        add(&mut self.num_eagles, report.num_eagles);
+        add(&mut self.num_albatross, report.num_albatross);
+        add(&mut self.num_hoi, report.num_hole_in_ones);
+        add(&mut self.total_distance, report.num_total_distance / 100);
+        add(&mut self.total_playtime, report.play_time);
+        add(&mut self.total_holes, num_holes as u32);
+        add(&mut self.num_direct_chip_ins, report.num_direct_tip_ins);
+        add(&mut self.num_rough, report.num_rough);
+        add(&mut self.num_bunker, report.num_bunkers);
+        add(&mut self.num_obstacle_hits, report.num_obstacle_hits);
+        add(&mut self.num_pinshots, report.num_pinshots);
+        add(&mut self.num_flagshots, report.num_flag_wraps);
+        add(
+            &mut self.num_consumable_item_usage,
+            report.num_consumable_item_usage,
+        );
+        self.total_score = self.total_score.saturating_add(report.score as i16);
+
+        self.max_drive = self.max_drive.max((report.maximum_distance / 100) as i32);
+        self.max_chip_in = self
+            .max_chip_in
+            .max((report.maximum_tip_in_distance / 100) as i32);
+        self.max_putt = self
+            .max_putt
+            .max((report.longest_putt_distance / 100) as i16);
+
+        if first_round {
+            self.highest_score = report.score;
+            self.lowest_score = report.score;
+        } else {
+            self.highest_score = self.highest_score.max(report.score);
+            self.lowest_score = self.lowest_score.min(report.score);
+        }
+    }
+}
+
 /// Global records (across all players) for a specific course.
-#[derive(Debug, Clone, DekuRead, DekuWrite)]
+#[derive(Debug, Clone, Default, DekuRead, DekuWrite)]
 pub struct GCRecord {
     pub course: i8,
     pub season: i8,
@@ -237,7 +373,7 @@ pub struct GCRecord {
 }
 
 /// Global records (across all players) for a specific hole on a course.
-#[derive(Debug, Clone, DekuRead, DekuWrite)]
+#[derive(Debug, Clone, Default, DekuRead, DekuWrite)]
 pub struct GHRecord {
     pub score: i8,
     pub score_uid: UID,
@@ -257,3 +393,161 @@ pub struct GHRecord {
     pub putt_uid: UID,
     pub putt_title: i16,
 }
+
+/// The best anybody has done on a course over a full 18-hole round.
+/// Keyed on course, season.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CourseRecord {
+    /// Best round score, if anybody has finished a round here
+    pub best_score: Option<i8>,
+    pub best_score_uid: UID,
+    pub best_score_title: u8,
+    /// Most GP earned in one round
+    pub max_gp: u32,
+    pub max_gp_uid: UID,
+    pub max_gp_title: u8,
+}
+
+impl CourseRecord {
+    /// Count one player's round, taking over any records they beat.
+    /// Returns true if anything changed hands.
+    pub fn add(&mut self, uid: UID, title: u8, report: &GameReport) -> bool {
+        let mut beaten = false;
+        if self.best_score.is_none_or(|best| report.score < best) {
+            self.best_score = Some(report.score);
+            self.best_score_uid = uid;
+            self.best_score_title = title;
+            beaten = true;
+        }
+        if report.obtained_gp_round > self.max_gp {
+            self.max_gp = report.obtained_gp_round;
+            self.max_gp_uid = uid;
+            self.max_gp_title = title;
+            beaten = true;
+        }
+        beaten
+    }
+
+    pub fn to_gc_record(&self, course: i8, season: i8, unk: i8) -> GCRecord {
+        GCRecord {
+            course,
+            season,
+            unk,
+            max_score: self.best_score.unwrap_or(0) as i32,
+            max_score_uid: self.best_score_uid,
+            max_score_title: self.best_score_title as i16,
+            max_gp: self.max_gp.min(i32::MAX as u32) as i32,
+            max_gp_uid: self.max_gp_uid,
+            max_gp_title: self.max_gp_title as i16,
+        }
+    }
+}
+
+/// The best anybody has done on a specific hole of a course.
+/// Keyed on course, season, hole number.
+/// Titles are whichever one the record holder was showing off at the time.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct HoleRecord {
+    /// Number of times this hole has been played
+    pub num_plays: u32,
+    /// Number of hole-in-ones scored here
+    pub num_hole_in_ones: u32,
+    /// Fewest strokes taken, or 0 if nobody has played it
+    pub best_score: i8,
+    pub best_score_uid: UID,
+    #[serde(default)]
+    pub best_score_title: u8,
+    /// Most GP earned on this hole
+    #[serde(default)]
+    pub max_gp: u32,
+    #[serde(default)]
+    pub max_gp_uid: UID,
+    #[serde(default)]
+    pub max_gp_title: u8,
+    /// Whoever got the first hole-in-one here
+    #[serde(default)]
+    pub hio_uid: UID,
+    #[serde(default)]
+    pub hio_title: u8,
+    /// Longest distance (1/100y)
+    pub max_drive: u32,
+    pub max_drive_uid: UID,
+    #[serde(default)]
+    pub max_drive_title: u8,
+    /// Longest chip-in distance (1/100y)
+    pub max_chipin: u32,
+    pub max_chipin_uid: UID,
+    #[serde(default)]
+    pub max_chipin_title: u8,
+    /// Longest putt distance (1/100y)
+    pub max_putt: u32,
+    pub max_putt_uid: UID,
+    #[serde(default)]
+    pub max_putt_title: u8,
+}
+
+impl HoleRecord {
+    /// Count one player's go at this hole, taking over any records they beat
+    pub fn add(&mut self, uid: UID, title: u8, report: &HoleReport) {
+        self.num_plays += 1;
+        if report.is_hole_in_one {
+            if self.num_hole_in_ones == 0 {
+                self.hio_uid = uid;
+                self.hio_title = title;
+            }
+            self.num_hole_in_ones += 1;
+        }
+        if report.score > 0 && (self.best_score == 0 || report.score < self.best_score) {
+            self.best_score = report.score;
+            self.best_score_uid = uid;
+            self.best_score_title = title;
+        }
+        if report.gp > self.max_gp {
+            self.max_gp = report.gp;
+            self.max_gp_uid = uid;
+            self.max_gp_title = title;
+        }
+        if report.maximum_flight_distance > self.max_drive {
+            self.max_drive = report.maximum_flight_distance;
+            self.max_drive_uid = uid;
+            self.max_drive_title = title;
+        }
+        if report.longest_chip_in > self.max_chipin {
+            self.max_chipin = report.longest_chip_in;
+            self.max_chipin_uid = uid;
+            self.max_chipin_title = title;
+        }
+        if report.longest_putt > self.max_putt {
+            self.max_putt = report.longest_putt;
+            self.max_putt_uid = uid;
+            self.max_putt_title = title;
+        }
+    }
+
+    /// The client shows distances in whole yards
+    pub fn to_gh_record(&self) -> GHRecord {
+        fn yards(distance: u32) -> i16 {
+            (distance / 100).min(i16::MAX as u32) as i16
+        }
+
+        GHRecord {
+            score: self.best_score,
+            score_uid: self.best_score_uid,
+            score_title: self.best_score_title as i16,
+            gp: self.max_gp.min(i32::MAX as u32) as i32,
+            gp_uid: self.max_gp_uid,
+            gp_title: self.max_gp_title as i16,
+            hio_uid: self.hio_uid,
+            hio_title: self.hio_title as i16,
+            drive: yards(self.max_drive),
+            drive_uid: self.max_drive_uid,
+            drive_title: self.max_drive_title as i16,
+            chipin: yards(self.max_chipin),
+            chipin_uid: self.max_chipin_uid,
+            chipin_title: self.max_chipin_title as i16,
+            putt: yards(self.max_putt),
+            putt_uid: self.max_putt_uid,
+            putt_title: self.max_putt_title as i16,
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use rand::prelude::*;
+
+use super::{CountedItem, Item, ItemCategory};
+
+/// Chance of an eco ticket giving a recipe's full result; gold tickets always do
+pub const ECO_TICKET_CHANCE: f64 = 0.5;
+
+/// Something the recycling shop can make out of five unwanted items
+#[derive(Clone, Debug)]
+pub struct RecycleRecipe {
+    /// One of each of these is used up
+    pub materials: [Item; 5],
+    /// What the recipe is advertised as making
+    pub result: CountedItem,
+    /// What comes out instead when an eco ticket's roll doesn't go the player's way
+    pub consolation: CountedItem,
+}
+
+impl RecycleRecipe {
+    /// Work out what comes out of the machine
+    pub fn roll(&self, gold_ticket: bool) -> CountedItem {
+        if gold_ticket || thread_rng().gen_bool(ECO_TICKET_CHANCE) {
+            self.result
+        } else {
+            self.consolation
+        }
+    }
+}
+
+/// TODO: which tickets the client treats as eco and gold ones is a guess
+pub fn eco_ticket() -> Item {
+    Item::new(ItemCategory::HoldItemTicket, 1)
+}
+
+pub fn gold_ticket() -> Item {
+    Item::new(ItemCategory::HoldItemTicket, 2)
+}
+
+pub fn build_recycle_recipes() -> Vec<RecycleRecipe> {
+    let item = Item::new;
+    let counted = |category, num, count| CountedItem::new(Item::new(category, num), count);
+
+    vec![
+        RecycleRecipe {
+            materials: [1, 2, 3, 4, 5].map(|num| item(ItemCategory::Ball, num)),
+            result: counted(ItemCategory::Ball, 10, 10),
+            consolation: counted(ItemCategory::Ball, 6, 10),
+        },
+        RecycleRecipe {
+            materials: [1, 2, 3, 4, 5].map(|num| item(ItemCategory::CarryItemGroundRes, num)),
+            result: counted(ItemCategory::CarryItemPowerGauge, 6, 3),
+            consolation: counted(ItemCategory::CarryItemPowerGauge, 1, 3),
+        },
+        RecycleRecipe {
+            materials: [1, 2, 3, 4, 5].map(|num| item(ItemCategory::ClubSet, num)),
+            result: counted(ItemCategory::ClubSet, 20, 1),
+            consolation: counted(ItemCategory::HoldItemSupport, 1, 1),
+        },
+    ]
+}
@@ -52,6 +52,62 @@ pub struct GameReport {
     pub holes: [HoleReport; 18],
 }
 
+/// Most strokes anybody could plausibly take on one hole
+const MAX_STROKES_PER_HOLE: u32 = 20;
+/// Most GP a single hole can award, going by the width of HoleReport's GP field
+const MAX_HOLE_GP: u32 = 0xFFF;
+/// Longest anybody could plausibly spend on one hole, in seconds
+const MAX_SECONDS_PER_HOLE: u32 = 60 * 60;
+/// Furthest a single shot could plausibly go, in 1/100y
+const MAX_SHOT_DISTANCE: u32 = 500 * 100;
+
+impl GameReport {
+    /// Basic sanity checks against a round of `num_holes` holes, to catch a client
+    /// sending absurd numbers
+    pub fn is_plausible(&self, num_holes: usize) -> bool {
+        let num_holes = num_holes as u32;
+        // Summed with overflow checks, since a hostile client picks these numbers
+        let hole_results = [
+            self.num_4_or_more,
+            self.num_t_bogeys,
+            self.num_d_bogeys,
+            self.num_bogeys,
+            self.num_pars,
+            self.num_birdies,
+            self.num_eagles,
+            self.num_albatross,
+            self.num_hole_in_ones,
+        ]
+        .into_iter()
+        .try_fold(0u32, |sum, n| sum.checked_add(n));
+
+        self.num_strokes <= num_holes * MAX_STROKES_PER_HOLE
+            && self.num_cup_ins <= num_holes
+            && self.num_putts <= self.num_strokes
+            && self.num_nice_shots <= self.num_strokes
+            && hole_results.is_some_and(|n| n <= num_holes)
+            && self.obtained_gp_round <= num_holes * MAX_HOLE_GP
+            && self.obtained_gp_all <= num_holes * MAX_HOLE_GP
+            && self.play_time <= num_holes * MAX_SECONDS_PER_HOLE
+            && self.num_total_distance <= self.num_strokes * MAX_SHOT_DISTANCE
+    }
+
+    /// Check that the per-hole breakdown agrees with the totals for the round.
+    /// Only the first `num_holes` holes should have been played; the rest must be empty.
+    pub fn holes_are_consistent(&self, num_holes: usize) -> bool {
+        let (played, unplayed) = self.holes.split_at(num_holes.min(self.holes.len()));
+
+        let score: i32 = played.iter().map(|hole| hole.score as i32).sum();
+        let hole_in_ones = played.iter().filter(|hole| hole.is_hole_in_one).count();
+
+        score == self.score as i32
+            && hole_in_ones as u32 == self.num_hole_in_ones
+            && unplayed
+                .iter()
+                .all(|hole| hole.score == 0 && !hole.is_hole_in_one)
+    }
+}
+
 impl DekuRead<'_> for GameReport {
     fn read(input: &BitSlice<u8, Msb0>, ctx: ()) -> Result<(&BitSlice<u8, Msb0>, Self), DekuError>
     where
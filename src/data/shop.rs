@@ -1,9 +1,11 @@
 use super::{CharID, Item, ItemCategory};
 use deku::bitvec::{BitSlice, BitVec, Msb0};
 use deku::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
 pub enum Currency {
+    #[default]
     GP,
     SC,
     TicketsOnly,
@@ -29,8 +31,9 @@ impl Currency {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
 pub enum Marketing {
+    #[default]
     None,
     New,
     Hot,
@@ -61,12 +64,15 @@ impl Marketing {
 }
 
 // Configuration for items that are available in the shops
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SellItem {
     pub item: Item,
+    #[serde(default)]
     pub currency: Currency,
+    #[serde(default)]
     pub marketing: Marketing,
     pub price: u32,
+    #[serde(default)]
     pub sp_price: u32,
 }
 
@@ -132,6 +138,50 @@ pub struct SellCaddy {
     pub infinite_rental: i32,
 }
 
+/// How long a caddie is employed for, as picked by the client
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RentalPeriod {
+    ThreeHours,
+    ThreeDays,
+    ThirtyDays,
+    Infinite,
+}
+
+impl RentalPeriod {
+    /// Decode the period from the quantity of an employment request
+    pub fn from_count(count: u32) -> Option<Self> {
+        match count {
+            0 => Some(Self::ThreeHours),
+            1 => Some(Self::ThreeDays),
+            2 => Some(Self::ThirtyDays),
+            3 => Some(Self::Infinite),
+            _ => None,
+        }
+    }
+
+    /// Length of the rental in seconds, or None if it never runs out
+    pub fn seconds(self) -> Option<i64> {
+        match self {
+            Self::ThreeHours => Some(3 * 60 * 60),
+            Self::ThreeDays => Some(3 * 24 * 60 * 60),
+            Self::ThirtyDays => Some(30 * 24 * 60 * 60),
+            Self::Infinite => None,
+        }
+    }
+}
+
+impl SellCaddy {
+    /// What it costs to employ this caddie for a period, if it can be had for that long
+    pub fn price(&self, period: RentalPeriod) -> Option<u32> {
+        match period {
+            RentalPeriod::ThreeHours => Some(self.price_3_hours),
+            RentalPeriod::ThreeDays => Some(self.price_3_days),
+            RentalPeriod::ThirtyDays => Some(self.price_30_days),
+            RentalPeriod::Infinite => self.infinite_rental.try_into().ok(),
+        }
+    }
+}
+
 impl DekuRead<'_> for SellCaddy {
     fn read(input: &BitSlice<u8, Msb0>, ctx: ()) -> Result<(&BitSlice<u8, Msb0>, Self), DekuError>
     where
@@ -521,6 +571,24 @@ pub fn build_sell_list() -> Vec<SellItem> {
     list
 }
 
+pub fn build_caddie_list() -> Vec<SellCaddy> {
+    let mut list = Vec::new();
+
+    for num in 1..=12 {
+        list.push(SellCaddy {
+            item: Item::new(ItemCategory::Caddy, num),
+            currency: Currency::GP,
+            marketing: Marketing::None,
+            price_3_hours: 10 * num,
+            price_3_days: 50 * num,
+            price_30_days: 300 * num,
+            infinite_rental: 1000 * num as i32,
+        });
+    }
+
+    list
+}
+
 pub fn build_salon_list() -> Vec<SellItem> {
     let mut list = Vec::new();
     let chars = [
@@ -0,0 +1,51 @@
+use crate::packets::SVItemData;
+
+use super::{Item, ItemCategory};
+
+/// How many entries SEND_SVITEMDATA always carries
+pub const SVITEM_SLOTS: usize = 32;
+
+/// Stats for one brand's club set: power, control, impact, spin, luck, mood.
+/// TODO: the real server's numbers aren't known, these are just plausible ones
+const BRAND_STATS: [(u32, [u8; 6]); 8] = [
+    (1, [10, 10, 10, 10, 10, 10]),
+    (2, [14, 8, 10, 9, 10, 9]),
+    (3, [8, 14, 9, 10, 9, 10]),
+    (4, [10, 9, 14, 8, 10, 9]),
+    (5, [9, 10, 8, 14, 9, 10]),
+    (6, [9, 9, 10, 10, 14, 8]),
+    (7, [10, 10, 9, 9, 8, 14]),
+    (8, [12, 12, 12, 12, 6, 6]),
+];
+
+/// Unused slots are left empty, with a brand index of -1
+pub fn build_svitem_data() -> [SVItemData; SVITEM_SLOTS] {
+    std::array::from_fn(|index| match BRAND_STATS.get(index) {
+        Some(&(club_set, [power, control, impact, spin, luck, mood])) => SVItemData {
+            item_code: Item::new(ItemCategory::ClubSet, club_set).0,
+            unk_0: [0; 11],
+            brand_index: index as i8,
+            power,
+            control,
+            impact,
+            spin,
+            luck,
+            mood,
+            other_vars: [0; 14],
+            flags: 0,
+        },
+        None => SVItemData {
+            item_code: 0,
+            unk_0: [0; 11],
+            brand_index: -1,
+            power: 0,
+            control: 0,
+            impact: 0,
+            spin: 0,
+            luck: 0,
+            mood: 0,
+            other_vars: [0; 14],
+            flags: 0,
+        },
+    })
+}
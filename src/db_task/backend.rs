@@ -1,14 +1,23 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use anyhow::{bail, Result};
 use log::error;
 use rusqlite::{params, Connection, OptionalExtension};
 use rusqlite_migration::{Migrations, M};
 
 use crate::{
-    data::{record::CRecord, Account, Appearance, Character, User},
-    packets::{ChrUID, UID},
+    data::{
+        record::{CRecord, CourseRecord, HoleRecord, LeaderboardEntry, URecord},
+        Account, Appearance, Character, CountedItem, Mail, PendingDelivery, User,
+    },
+    packets::{ChrUID, MailSendResult, SendDeliverResult, UID},
 };
 
-use super::Command;
+use super::{CodeRedemption, Command, FriendRequestResult};
+
+/// Statuses for the friends table
+const FRIEND_PENDING: i32 = 0;
+const FRIEND_ACCEPTED: i32 = 1;
 
 pub(super) struct DB {
     conn: Connection,
@@ -39,6 +48,11 @@ impl DB {
             bail!("bad password at game server")
         }
 
+        let mut stmt = self.conn.prepare(
+            "UPDATE accounts SET num_logins = num_logins + 1, last_login = ?1 WHERE uid = ?2",
+        )?;
+        stmt.execute(params![unix_time(), uid])?;
+
         let user = match data {
             Some(data) => serde_json::from_str(&data)?,
             // New accounts will have no data here
@@ -105,23 +119,22 @@ impl DB {
         uid: UID,
         appearance: Appearance,
     ) -> Result<(ChrUID, Character)> {
-        // first, ensure that this user does not already have a character
-        let mut stmt = self
-            .conn
-            .prepare("SELECT COUNT(*) FROM characters WHERE uid = ?1")?;
-        let count: usize = stmt.query_row([uid], |row| row.get(0))?;
-        if count > 0 {
-            bail!("character already exists");
-        }
-
-        // now add them
         let character = Character::new(appearance);
 
+        // Accounts only get one character, which the schema enforces for us
         let mut stmt = self
             .conn
             .prepare("INSERT INTO characters (uid, data) VALUES (?1, ?2)")?;
         let data = serde_json::to_string(&character)?;
-        let chr_uid = stmt.insert(params![uid, data])?;
+        let chr_uid = match stmt.insert(params![uid, data]) {
+            Ok(chr_uid) => chr_uid,
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE =>
+            {
+                bail!("character already exists")
+            }
+            Err(e) => return Err(e.into()),
+        };
         let chr_uid: ChrUID = chr_uid.try_into()?;
 
         Ok((chr_uid, character))
@@ -136,11 +149,21 @@ impl DB {
         Ok(())
     }
 
+    /// Accounts are normally made by hand in the SQLite console; tests need a quicker way
+    #[cfg(test)]
+    pub(super) fn add_account(&mut self, login_id: &str, password: &str) -> Result<UID> {
+        self.conn.execute(
+            "INSERT INTO accounts (login_id, password) VALUES (?1, ?2)",
+            params![login_id, password],
+        )?;
+        Ok(self.conn.last_insert_rowid().try_into()?)
+    }
+
     fn get_c_record(&mut self, uid: UID, course: i8, season: i8, holes: i8) -> Result<CRecord> {
         let mut stmt = self
             .conn
             .prepare("SELECT data FROM c_records WHERE uid = ?1 AND key = ?2")?;
-        let key = ((course as i32) * 32) + ((season as i32) * 4) + (holes as i32);
+        let key = c_record_key(course, season, holes);
 
         let data: Option<String> = stmt
             .query_row(params![uid, key], |row| row.get(0))
@@ -154,6 +177,560 @@ impl DB {
         }
     }
 
+    fn write_c_record(
+        &mut self,
+        uid: UID,
+        course: i8,
+        season: i8,
+        holes: i8,
+        data: CRecord,
+    ) -> Result<()> {
+        let key = c_record_key(course, season, holes);
+        let data = serde_json::to_string(&data)?;
+
+        // c_records has no unique key to replace on, so update first and insert if that missed
+        let updated = self.conn.execute(
+            "UPDATE c_records SET data = ?3 WHERE uid = ?1 AND key = ?2",
+            params![uid, key, data],
+        )?;
+        if updated == 0 {
+            self.conn.execute(
+                "INSERT INTO c_records (uid, key, data) VALUES (?1, ?2, ?3)",
+                params![uid, key, data],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn get_hole_record(&mut self, course: i8, season: i8, hole: i8) -> Result<HoleRecord> {
+        let mut stmt = self.conn.prepare(
+            "SELECT data FROM hole_records WHERE course = ?1 AND season = ?2 AND hole = ?3",
+        )?;
+        let data: Option<String> = stmt
+            .query_row(params![course, season, hole], |row| row.get(0))
+            .optional()?;
+        match data {
+            Some(data) => Ok(serde_json::from_str(&data)?),
+            None => Ok(HoleRecord::default()),
+        }
+    }
+
+    fn write_hole_record(
+        &mut self,
+        course: i8,
+        season: i8,
+        hole: i8,
+        data: HoleRecord,
+    ) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "INSERT OR REPLACE INTO hole_records (course, season, hole, data) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        let data = serde_json::to_string(&data)?;
+        stmt.execute(params![course, season, hole, data])?;
+        Ok(())
+    }
+
+    fn get_course_record(&mut self, course: i8, season: i8) -> Result<CourseRecord> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT data FROM g_course_records WHERE course = ?1 AND season = ?2")?;
+        let data: Option<String> = stmt
+            .query_row(params![course, season], |row| row.get(0))
+            .optional()?;
+        match data {
+            Some(data) => Ok(serde_json::from_str(&data)?),
+            None => Ok(CourseRecord::default()),
+        }
+    }
+
+    fn write_course_record(&mut self, course: i8, season: i8, data: CourseRecord) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "INSERT OR REPLACE INTO g_course_records (course, season, data) VALUES (?1, ?2, ?3)",
+        )?;
+        let data = serde_json::to_string(&data)?;
+        stmt.execute(params![course, season, data])?;
+        Ok(())
+    }
+
+    fn get_u_record(&mut self, uid: UID) -> Result<URecord> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT data FROM u_records WHERE uid = ?1")?;
+        let data: Option<String> = stmt.query_row([uid], |row| row.get(0)).optional()?;
+        let mut record = match data {
+            Some(data) => serde_json::from_str(&data)?,
+            None => URecord::default(),
+        };
+
+        // the login count lives on the account, since it changes before
+        // we know anything else about the player
+        let mut stmt = self
+            .conn
+            .prepare("SELECT num_logins FROM accounts WHERE uid = ?1")?;
+        let num_logins: i64 = match stmt.query_row([uid], |row| row.get(0)).optional()? {
+            Some(num_logins) => num_logins,
+            None => bail!("no account with UID {uid}"),
+        };
+        record.num_logins = num_logins.try_into().unwrap_or(i16::MAX);
+
+        Ok(record)
+    }
+
+    fn write_u_record(&mut self, uid: UID, data: URecord) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare("INSERT OR REPLACE INTO u_records (uid, data) VALUES (?1, ?2)")?;
+        let data = serde_json::to_string(&data)?;
+        stmt.execute(params![uid, data])?;
+        Ok(())
+    }
+
+    fn get_leaderboard(&mut self, limit: usize) -> Result<Vec<LeaderboardEntry>> {
+        // Course records are folded into each owner's totals here, so that only the
+        // rows we want ever come back. Lower round scores are better.
+        let mut stmt = self.conn.prepare(
+            "SELECT accounts.uid, accounts.name,
+				COALESCE(json_extract(accounts.data, '$.gp'), 0) AS gp,
+				COALESCE(totals.total_gp, 0) AS total_gp,
+				COALESCE(totals.num_rounds, 0),
+				totals.best_score
+			FROM accounts
+			LEFT JOIN (
+				SELECT uid,
+					SUM(json_extract(data, '$.total_gp')) AS total_gp,
+					SUM(json_extract(data, '$.num_rounds')) AS num_rounds,
+					MIN(COALESCE(
+						json_extract(data, '$.best_score'),
+						json_extract(data, '$.total_score')
+					)) AS best_score
+				FROM c_records
+				WHERE json_extract(data, '$.num_rounds') > 0
+				GROUP BY uid
+			) AS totals ON totals.uid = accounts.uid
+			WHERE accounts.name IS NOT NULL
+			ORDER BY total_gp DESC, gp DESC
+			LIMIT ?1",
+        )?;
+        let entries = stmt
+            .query_map([limit], |row| {
+                Ok(LeaderboardEntry {
+                    uid: row.get(0)?,
+                    name: row.get(1)?,
+                    gp: row.get(2)?,
+                    total_gp: row.get(3)?,
+                    num_rounds: row.get(4)?,
+                    best_score: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(entries)
+    }
+
+    fn reset_account(&mut self, uid: UID, keep_profile: bool) -> Result<bool> {
+        let count: usize = self.conn.query_row(
+            "SELECT COUNT(*) FROM accounts WHERE uid = ?1",
+            [uid],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            return Ok(false);
+        }
+
+        let mut user = User::default();
+        if keep_profile {
+            // The old data may be what's broken, so find their character from scratch
+            let chr_uid = self
+                .conn
+                .query_row(
+                    "SELECT chr_uid FROM characters WHERE uid = ?1 ORDER BY chr_uid",
+                    [uid],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(chr_uid) = chr_uid {
+                user.default_chr_uid = chr_uid;
+            }
+        } else {
+            self.conn
+                .execute("DELETE FROM characters WHERE uid = ?1", [uid])?;
+            self.conn
+                .execute("UPDATE accounts SET name = NULL WHERE uid = ?1", [uid])?;
+        }
+
+        self.write_user(uid, user)?;
+        Ok(true)
+    }
+
+    fn get_friends(&mut self, uid: UID) -> Result<Vec<UID>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT uid_b FROM friends WHERE uid_a = ?1 AND status = ?2
+			UNION SELECT uid_a FROM friends WHERE uid_b = ?1 AND status = ?2
+			ORDER BY 1",
+        )?;
+        let friends = stmt
+            .query_map(params![uid, FRIEND_ACCEPTED], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(friends)
+    }
+
+    fn get_friend_requests(&mut self, uid: UID, inbound: bool) -> Result<Vec<UID>> {
+        let query = if inbound {
+            "SELECT uid_a FROM friends WHERE uid_b = ?1 AND status = ?2 ORDER BY uid_a"
+        } else {
+            "SELECT uid_b FROM friends WHERE uid_a = ?1 AND status = ?2 ORDER BY uid_b"
+        };
+        let mut stmt = self.conn.prepare(query)?;
+        let users = stmt
+            .query_map(params![uid, FRIEND_PENDING], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(users)
+    }
+
+    fn send_friend_request(&mut self, from: UID, to: UID) -> Result<FriendRequestResult> {
+        let count: usize = self.conn.query_row(
+            "SELECT COUNT(*) FROM accounts WHERE uid = ?1",
+            [to],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            return Ok(FriendRequestResult::NoSuchUser);
+        }
+
+        // Either of them could have asked first
+        let existing: Option<(UID, i32)> = self
+            .conn
+            .query_row(
+                "SELECT uid_a, status FROM friends
+				WHERE (uid_a = ?1 AND uid_b = ?2) OR (uid_a = ?2 AND uid_b = ?1)",
+                [from, to],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        match existing {
+            Some((_, FRIEND_ACCEPTED)) => Ok(FriendRequestResult::AlreadyFriends),
+            Some((asker, _)) if asker == from => Ok(FriendRequestResult::AlreadyPending),
+            Some(_) => {
+                self.conn.execute(
+                    "UPDATE friends SET status = ?3 WHERE uid_a = ?1 AND uid_b = ?2",
+                    params![to, from, FRIEND_ACCEPTED],
+                )?;
+                Ok(FriendRequestResult::Accepted)
+            }
+            None => {
+                self.conn.execute(
+                    "INSERT INTO friends (uid_a, uid_b, status) VALUES (?1, ?2, ?3)",
+                    params![from, to, FRIEND_PENDING],
+                )?;
+                Ok(FriendRequestResult::Sent)
+            }
+        }
+    }
+
+    fn answer_friend_request(&mut self, from: UID, to: UID, accept: bool) -> Result<bool> {
+        let changed = if accept {
+            self.conn.execute(
+                "UPDATE friends SET status = ?3 WHERE uid_a = ?1 AND uid_b = ?2 AND status = ?4",
+                params![from, to, FRIEND_ACCEPTED, FRIEND_PENDING],
+            )?
+        } else {
+            self.conn.execute(
+                "DELETE FROM friends WHERE uid_a = ?1 AND uid_b = ?2 AND status = ?3",
+                params![from, to, FRIEND_PENDING],
+            )?
+        };
+        Ok(changed > 0)
+    }
+
+    fn remove_friend(&mut self, uid: UID, friend: UID) -> Result<bool> {
+        let changed = self.conn.execute(
+            "DELETE FROM friends
+			WHERE ((uid_a = ?1 AND uid_b = ?2) OR (uid_a = ?2 AND uid_b = ?1)) AND status = ?3",
+            params![uid, friend, FRIEND_ACCEPTED],
+        )?;
+        Ok(changed > 0)
+    }
+
+    fn get_blocks(&mut self, uid: UID) -> Result<Vec<UID>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT blocked_uid FROM blocks WHERE uid = ?1 ORDER BY blocked_uid")?;
+        let blocks = stmt
+            .query_map([uid], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(blocks)
+    }
+
+    fn block(&mut self, uid: UID, blocked: UID) -> Result<bool> {
+        let count: usize = self.conn.query_row(
+            "SELECT COUNT(*) FROM accounts WHERE uid = ?1",
+            [blocked],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            return Ok(false);
+        }
+
+        // Blocking somebody twice changes nothing
+        self.conn.execute(
+            "INSERT OR IGNORE INTO blocks (uid, blocked_uid) VALUES (?1, ?2)",
+            [uid, blocked],
+        )?;
+        Ok(true)
+    }
+
+    fn unblock(&mut self, uid: UID, blocked: UID) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM blocks WHERE uid = ?1 AND blocked_uid = ?2",
+            [uid, blocked],
+        )?;
+        Ok(())
+    }
+
+    fn count_unreceived_mail(&mut self, uid: UID) -> Result<usize> {
+        let count = self.conn.query_row(
+            "SELECT COUNT(*) FROM mail WHERE to_uid = ?1 AND received = 0",
+            [uid],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    fn list_unreceived_mail(&mut self, uid: UID) -> Result<Vec<i32>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT mail_uid FROM mail WHERE to_uid = ?1 AND received = 0 ORDER BY mail_uid",
+        )?;
+        let mail = stmt
+            .query_map([uid], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(mail)
+    }
+
+    fn receive_mail(&mut self, uid: UID, mail_uid: i32) -> Result<Option<Mail>> {
+        let mail = self
+            .conn
+            .query_row(
+                "SELECT from_uid, sent_at, body FROM mail WHERE mail_uid = ?1 AND to_uid = ?2",
+                [mail_uid, uid],
+                |row| {
+                    Ok(Mail {
+                        mail_uid,
+                        from_uid: row.get(0)?,
+                        to_uid: uid,
+                        sent_at: row.get(1)?,
+                        body: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        if mail.is_some() {
+            self.conn.execute(
+                "UPDATE mail SET received = 1 WHERE mail_uid = ?1",
+                [mail_uid],
+            )?;
+        }
+        Ok(mail)
+    }
+
+    fn send_mail(
+        &mut self,
+        from: UID,
+        to: UID,
+        body: String,
+        inbox_limit: usize,
+    ) -> Result<MailSendResult> {
+        let count: usize = self.conn.query_row(
+            "SELECT COUNT(*) FROM accounts WHERE uid = ?1",
+            [to],
+            |row| row.get(0),
+        )?;
+        if count == 0 {
+            return Ok(MailSendResult::Err);
+        }
+        if self.count_unreceived_mail(to)? >= inbox_limit {
+            return Ok(MailSendResult::LimitReached);
+        }
+
+        self.conn.execute(
+            "INSERT INTO mail (from_uid, to_uid, sent_at, body) VALUES (?1, ?2, ?3, ?4)",
+            params![from, to, unix_time(), body],
+        )?;
+        Ok(MailSendResult::OK)
+    }
+
+    fn send_delivery(
+        &mut self,
+        from: UID,
+        to: UID,
+        item: CountedItem,
+        message: String,
+        box_limit: usize,
+        recipient_online: bool,
+    ) -> Result<SendDeliverResult> {
+        let data: Option<Option<String>> = self
+            .conn
+            .query_row("SELECT data FROM accounts WHERE uid = ?1", [to], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let user: User = match data {
+            Some(Some(data)) => serde_json::from_str(&data)?,
+            Some(None) => Default::default(),
+            None => return Ok(SendDeliverResult::GenericError1),
+        };
+        // An online recipient's saved data can be out of date, so the game server
+        // checks their live setting instead
+        if !recipient_online && user.refuses_deliveries {
+            return Ok(SendDeliverResult::DeliveryDisabled);
+        }
+
+        let blocked: usize = self.conn.query_row(
+            "SELECT COUNT(*) FROM blocks WHERE uid = ?1 AND blocked_uid = ?2",
+            [to, from],
+            |row| row.get(0),
+        )?;
+        if blocked > 0 {
+            return Ok(SendDeliverResult::UserIsBlocked);
+        }
+
+        match self.deposit_delivery(from, to, item, message, box_limit)? {
+            true => Ok(SendDeliverResult::OK),
+            false => Ok(SendDeliverResult::LimitReached),
+        }
+    }
+
+    fn deposit_delivery(
+        &mut self,
+        from: UID,
+        to: UID,
+        item: CountedItem,
+        message: String,
+        box_limit: usize,
+    ) -> Result<bool> {
+        if self.count_deliveries(to)? >= box_limit {
+            return Ok(false);
+        }
+
+        self.conn.execute(
+            "INSERT INTO deliveries (from_uid, to_uid, item, sent_at, message) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![from, to, item.0, unix_time(), message],
+        )?;
+        Ok(true)
+    }
+
+    fn count_deliveries(&mut self, uid: UID) -> Result<usize> {
+        let count = self.conn.query_row(
+            "SELECT COUNT(*) FROM deliveries WHERE to_uid = ?1",
+            [uid],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    fn list_deliveries(&mut self, uid: UID) -> Result<Vec<PendingDelivery>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT delivery_uid, from_uid, item, message FROM deliveries
+			WHERE to_uid = ?1 ORDER BY delivery_uid",
+        )?;
+        let deliveries = stmt
+            .query_map([uid], |row| {
+                Ok(PendingDelivery {
+                    delivery_uid: row.get(0)?,
+                    from_uid: row.get(1)?,
+                    to_uid: uid,
+                    item: CountedItem(row.get(2)?),
+                    message: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(deliveries)
+    }
+
+    fn take_delivery(&mut self, uid: UID, delivery_uid: i32) -> Result<Option<PendingDelivery>> {
+        let delivery = self
+            .list_deliveries(uid)?
+            .into_iter()
+            .find(|d| d.delivery_uid == delivery_uid);
+
+        if delivery.is_some() {
+            self.conn.execute(
+                "DELETE FROM deliveries WHERE delivery_uid = ?1",
+                [delivery_uid],
+            )?;
+        }
+        Ok(delivery)
+    }
+
+    /// See whether a player could use a serial code, without using it up
+    fn check_code(&mut self, uid: UID, code: &str) -> Result<CodeRedemption> {
+        let row: Option<(String, String, Option<i32>, bool, String)> = self
+            .conn
+            .query_row(
+                "SELECT code, items, uses_remaining, once_per_account, message FROM codes
+				WHERE code = ?1",
+                [code],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let (code, items, uses_remaining, once_per_account, message) = match row {
+            Some(row) => row,
+            None => return Ok(CodeRedemption::NoSuchCode),
+        };
+
+        if once_per_account {
+            let redeemed: usize = self.conn.query_row(
+                "SELECT COUNT(*) FROM code_redemptions WHERE code = ?1 AND uid = ?2",
+                params![code, uid],
+                |row| row.get(0),
+            )?;
+            if redeemed > 0 {
+                return Ok(CodeRedemption::AlreadyRedeemed);
+            }
+        }
+        if uses_remaining.is_some_and(|uses| uses <= 0) {
+            return Ok(CodeRedemption::UsedUp);
+        }
+
+        let items: Vec<CountedItem> = serde_json::from_str(&items)?;
+        Ok(CodeRedemption::Redeemed { items, message })
+    }
+
+    fn redeem_code(&mut self, uid: UID, code: String) -> Result<CodeRedemption> {
+        let redemption = self.check_code(uid, &code)?;
+        if let CodeRedemption::Redeemed { .. } = redemption {
+            self.conn.execute(
+                "UPDATE codes SET uses_remaining = uses_remaining - 1 WHERE code = ?1",
+                [&code],
+            )?;
+            // Store the code as it was set up, not however the player typed it
+            self.conn.execute(
+                "INSERT INTO code_redemptions (code, uid, redeemed_at)
+				SELECT code, ?2, ?3 FROM codes WHERE code = ?1",
+                params![code, uid, unix_time()],
+            )?;
+        }
+        Ok(redemption)
+    }
+
+    /// Serial codes are normally made by hand in the SQLite console; tests need a quicker way
+    #[cfg(test)]
+    pub(super) fn add_code(&mut self, code: &str, items: &[CountedItem]) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO codes (code, items) VALUES (?1, ?2)",
+            params![code, serde_json::to_string(items)?],
+        )?;
+        Ok(())
+    }
+
     pub(super) fn handle_command(&mut self, command: Command) -> bool {
         match command {
             Command::AuthenticateUser { login_id, resp } => {
@@ -199,11 +776,178 @@ impl DB {
             } => resp
                 .send(self.get_c_record(uid, course, season, holes))
                 .is_ok(),
+            Command::WriteCRecord {
+                uid,
+                course,
+                season,
+                holes,
+                data,
+            } => match self.write_c_record(uid, course, season, holes, data) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("failed to save CRecord for {uid}: {e:?}");
+                    false
+                }
+            },
+            Command::GetHoleRecord {
+                course,
+                season,
+                hole,
+                resp,
+            } => resp
+                .send(self.get_hole_record(course, season, hole))
+                .is_ok(),
+            Command::WriteHoleRecord {
+                course,
+                season,
+                hole,
+                data,
+            } => match self.write_hole_record(course, season, hole, data) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("failed to save hole record for {course}/{season}/{hole}: {e:?}");
+                    false
+                }
+            },
+            Command::GetCourseRecord {
+                course,
+                season,
+                resp,
+            } => resp.send(self.get_course_record(course, season)).is_ok(),
+            Command::WriteCourseRecord {
+                course,
+                season,
+                data,
+            } => match self.write_course_record(course, season, data) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("failed to save course record for {course}/{season}: {e:?}");
+                    false
+                }
+            },
+            Command::WriteURecord { uid, data } => match self.write_u_record(uid, data) {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("failed to save URecord for {uid}: {e:?}");
+                    false
+                }
+            },
+            // commands are handled in order, so reaching this means we're caught up
+            Command::Flush { resp } => resp.send(()).is_ok(),
+            Command::GetURecord { uid, resp } => resp.send(self.get_u_record(uid)).is_ok(),
+            Command::GetLeaderboard { limit, resp } => {
+                resp.send(self.get_leaderboard(limit)).is_ok()
+            }
+            Command::ResetAccount {
+                uid,
+                keep_profile,
+                resp,
+            } => resp.send(self.reset_account(uid, keep_profile)).is_ok(),
+            Command::GetFriends { uid, resp } => resp.send(self.get_friends(uid)).is_ok(),
+            Command::GetFriendRequests { uid, inbound, resp } => {
+                resp.send(self.get_friend_requests(uid, inbound)).is_ok()
+            }
+            Command::SendFriendRequest { from, to, resp } => {
+                resp.send(self.send_friend_request(from, to)).is_ok()
+            }
+            Command::AnswerFriendRequest {
+                from,
+                to,
+                accept,
+                resp,
+            } => resp
+                .send(self.answer_friend_request(from, to, accept))
+                .is_ok(),
+            Command::RemoveFriend { uid, friend, resp } => {
+                resp.send(self.remove_friend(uid, friend)).is_ok()
+            }
+            Command::GetBlocks { uid, resp } => resp.send(self.get_blocks(uid)).is_ok(),
+            Command::Block { uid, blocked, resp } => resp.send(self.block(uid, blocked)).is_ok(),
+            Command::Unblock { uid, blocked, resp } => {
+                resp.send(self.unblock(uid, blocked)).is_ok()
+            }
+            Command::CountUnreceivedMail { uid, resp } => {
+                resp.send(self.count_unreceived_mail(uid)).is_ok()
+            }
+            Command::ListUnreceivedMail { uid, resp } => {
+                resp.send(self.list_unreceived_mail(uid)).is_ok()
+            }
+            Command::ReceiveMail {
+                uid,
+                mail_uid,
+                resp,
+            } => resp.send(self.receive_mail(uid, mail_uid)).is_ok(),
+            Command::SendMail {
+                from,
+                to,
+                body,
+                inbox_limit,
+                resp,
+            } => resp
+                .send(self.send_mail(from, to, body, inbox_limit))
+                .is_ok(),
+            Command::SendDelivery {
+                from,
+                to,
+                item,
+                message,
+                box_limit,
+                recipient_online,
+                resp,
+            } => resp
+                .send(self.send_delivery(from, to, item, message, box_limit, recipient_online))
+                .is_ok(),
+            Command::DepositDelivery {
+                from,
+                to,
+                item,
+                message,
+                box_limit,
+                resp,
+            } => resp
+                .send(self.deposit_delivery(from, to, item, message, box_limit))
+                .is_ok(),
+            Command::CountDeliveries { uid, resp } => resp.send(self.count_deliveries(uid)).is_ok(),
+            Command::ListDeliveries { uid, resp } => resp.send(self.list_deliveries(uid)).is_ok(),
+            Command::CheckCode { uid, code, resp } => {
+                resp.send(self.check_code(uid, &code)).is_ok()
+            }
+            Command::RedeemCode { uid, code, resp } => {
+                resp.send(self.redeem_code(uid, code)).is_ok()
+            }
+            Command::TakeDelivery {
+                uid,
+                delivery_uid,
+                resp,
+            } => resp.send(self.take_delivery(uid, delivery_uid)).is_ok(),
         }
     }
 }
 
+/// Where a CRecord lives in the c_records table, alongside its owner's UID
+fn c_record_key(course: i8, season: i8, holes: i8) -> i32 {
+    ((course as i32) * 32) + ((season as i32) * 4) + (holes as i32)
+}
+
+/// Seconds since the Unix epoch, for timestamp columns
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
 pub(super) fn create() -> Result<DB> {
+    open(Connection::open("splashsrv.db")?)
+}
+
+/// A database that only lasts as long as the process, for tests
+#[cfg(test)]
+pub(super) fn create_in_memory() -> Result<DB> {
+    open(Connection::open_in_memory()?)
+}
+
+fn open(mut conn: Connection) -> Result<DB> {
     let migrations = Migrations::new(vec![
         M::up(
             "CREATE TABLE accounts(
@@ -229,12 +973,103 @@ pub(super) fn create() -> Result<DB> {
 				data TEXT
 			);",
         ),
+        M::up(
+            "ALTER TABLE accounts ADD COLUMN num_logins INTEGER NOT NULL DEFAULT 0;
+			ALTER TABLE accounts ADD COLUMN last_login INTEGER;
+			CREATE TABLE u_records(
+				uid INTEGER PRIMARY KEY NOT NULL,
+				data TEXT,
+				FOREIGN KEY (uid) REFERENCES accounts(uid)
+			);",
+        ),
+        M::up(
+            "CREATE TABLE hole_records(
+				course INTEGER NOT NULL,
+				season INTEGER NOT NULL,
+				hole INTEGER NOT NULL,
+				data TEXT,
+				PRIMARY KEY (course, season, hole)
+			);",
+        ),
+        M::up("CREATE UNIQUE INDEX characters_uid ON characters(uid);"),
+        M::up(
+            "CREATE TABLE friends(
+				uid_a INTEGER NOT NULL,
+				uid_b INTEGER NOT NULL,
+				status INTEGER NOT NULL,
+				PRIMARY KEY (uid_a, uid_b),
+				FOREIGN KEY (uid_a) REFERENCES accounts(uid),
+				FOREIGN KEY (uid_b) REFERENCES accounts(uid)
+			);",
+        ),
+        M::up(
+            "CREATE TABLE blocks(
+				uid INTEGER NOT NULL,
+				blocked_uid INTEGER NOT NULL,
+				PRIMARY KEY (uid, blocked_uid),
+				FOREIGN KEY (uid) REFERENCES accounts(uid),
+				FOREIGN KEY (blocked_uid) REFERENCES accounts(uid)
+			);",
+        ),
+        M::up(
+            "CREATE TABLE mail(
+				mail_uid INTEGER PRIMARY KEY NOT NULL,
+				from_uid INTEGER NOT NULL,
+				to_uid INTEGER NOT NULL,
+				sent_at INTEGER NOT NULL,
+				body TEXT NOT NULL,
+				received INTEGER NOT NULL DEFAULT 0,
+				FOREIGN KEY (from_uid) REFERENCES accounts(uid),
+				FOREIGN KEY (to_uid) REFERENCES accounts(uid)
+			);
+			CREATE INDEX mail_to_uid ON mail(to_uid);",
+        ),
+        M::up(
+            "CREATE TABLE deliveries(
+				delivery_uid INTEGER PRIMARY KEY NOT NULL,
+				from_uid INTEGER NOT NULL,
+				to_uid INTEGER NOT NULL,
+				item INTEGER NOT NULL,
+				sent_at INTEGER NOT NULL,
+				message TEXT NOT NULL,
+				FOREIGN KEY (from_uid) REFERENCES accounts(uid),
+				FOREIGN KEY (to_uid) REFERENCES accounts(uid)
+			);
+			CREATE INDEX deliveries_to_uid ON deliveries(to_uid);",
+        ),
+        M::up(
+            "CREATE TABLE codes(
+				code TEXT PRIMARY KEY NOT NULL COLLATE NOCASE,
+				items TEXT NOT NULL DEFAULT '[]',
+				uses_remaining INTEGER,
+				once_per_account INTEGER NOT NULL DEFAULT 1,
+				message TEXT NOT NULL DEFAULT ''
+			);
+			CREATE TABLE code_redemptions(
+				code TEXT NOT NULL COLLATE NOCASE,
+				uid INTEGER NOT NULL,
+				redeemed_at INTEGER NOT NULL,
+				FOREIGN KEY (code) REFERENCES codes(code),
+				FOREIGN KEY (uid) REFERENCES accounts(uid)
+			);
+			CREATE INDEX code_redemptions_code_uid ON code_redemptions(code, uid);",
+        ),
+        M::up(
+            "CREATE TABLE g_course_records(
+				course INTEGER NOT NULL,
+				season INTEGER NOT NULL,
+				data TEXT,
+				PRIMARY KEY (course, season)
+			);",
+        ),
+        M::up("CREATE INDEX c_records_uid_key ON c_records(uid, key);"),
     ]);
 
-    let mut conn = Connection::open("splashsrv.db")?;
-
     migrations.to_latest(&mut conn)?;
 
+    // SQLite leaves the schema's foreign keys unchecked unless asked
+    conn.pragma_update(None, "foreign_keys", true)?;
+
     let db = DB { conn };
     Ok(db)
 }
@@ -2,11 +2,14 @@ use anyhow::Result;
 use tokio::sync::{mpsc, oneshot};
 
 use crate::{
-    data::{record::CRecord, Account, Appearance, Character, User},
-    packets::{ChrUID, UID},
+    data::{
+        record::{CRecord, CourseRecord, HoleRecord, LeaderboardEntry, URecord},
+        Account, Appearance, Character, CountedItem, Mail, PendingDelivery, User,
+    },
+    packets::{ChrUID, MailSendResult, SendDeliverResult, UID},
 };
 
-use super::Command;
+use super::{CodeRedemption, Command, FriendRequestResult};
 
 #[derive(Clone)]
 pub struct DBTask {
@@ -100,4 +103,372 @@ impl DBTask {
             .unwrap();
         rx.await?
     }
+
+    pub async fn write_c_record(&self, uid: UID, course: i8, season: i8, holes: i8, data: CRecord) {
+        self.tx
+            .send(Command::WriteCRecord {
+                uid,
+                course,
+                season,
+                holes,
+                data,
+            })
+            .await
+            .unwrap();
+    }
+
+    pub async fn get_hole_record(&self, course: i8, season: i8, hole: i8) -> Result<HoleRecord> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::GetHoleRecord {
+                course,
+                season,
+                hole,
+                resp,
+            })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    pub async fn write_hole_record(&self, course: i8, season: i8, hole: i8, data: HoleRecord) {
+        self.tx
+            .send(Command::WriteHoleRecord {
+                course,
+                season,
+                hole,
+                data,
+            })
+            .await
+            .unwrap();
+    }
+
+    pub async fn get_course_record(&self, course: i8, season: i8) -> Result<CourseRecord> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::GetCourseRecord {
+                course,
+                season,
+                resp,
+            })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    pub async fn write_course_record(&self, course: i8, season: i8, data: CourseRecord) {
+        self.tx
+            .send(Command::WriteCourseRecord {
+                course,
+                season,
+                data,
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Wait for every command sent so far (including writes) to be handled
+    pub async fn flush(&self) -> Result<()> {
+        let (resp, rx) = oneshot::channel();
+        self.tx.send(Command::Flush { resp }).await.unwrap();
+        Ok(rx.await?)
+    }
+
+    pub async fn get_u_record(&self, uid: UID) -> Result<URecord> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::GetURecord { uid, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    pub async fn write_u_record(&self, uid: UID, data: URecord) {
+        self.tx
+            .send(Command::WriteURecord { uid, data })
+            .await
+            .unwrap();
+    }
+
+    /// Put an account's user data back to the defaults, returning false if there's no such account.
+    /// With `keep_profile`, the player's name and characters survive the reset.
+    pub async fn reset_account(&self, uid: UID, keep_profile: bool) -> Result<bool> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::ResetAccount {
+                uid,
+                keep_profile,
+                resp,
+            })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    pub async fn get_leaderboard(&self, limit: usize) -> Result<Vec<LeaderboardEntry>> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::GetLeaderboard { limit, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Get everyone who has accepted a friendship with this player
+    pub async fn get_friends(&self, uid: UID) -> Result<Vec<UID>> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::GetFriends { uid, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Get the players who have asked to be friends with this one and are still waiting
+    pub async fn get_inbound_friend_requests(&self, uid: UID) -> Result<Vec<UID>> {
+        self.get_friend_requests(uid, true).await
+    }
+
+    /// Get the players this one has asked to be friends with, who haven't answered yet
+    pub async fn get_outbound_friend_requests(&self, uid: UID) -> Result<Vec<UID>> {
+        self.get_friend_requests(uid, false).await
+    }
+
+    async fn get_friend_requests(&self, uid: UID, inbound: bool) -> Result<Vec<UID>> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::GetFriendRequests { uid, inbound, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Ask to be friends, or accept straight away if the other player already asked
+    pub async fn send_friend_request(&self, from: UID, to: UID) -> Result<FriendRequestResult> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::SendFriendRequest { from, to, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Accept or deny a pending request, returning false if there wasn't one
+    pub async fn answer_friend_request(&self, from: UID, to: UID, accept: bool) -> Result<bool> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::AnswerFriendRequest {
+                from,
+                to,
+                accept,
+                resp,
+            })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// End a friendship, returning false if they weren't friends
+    pub async fn remove_friend(&self, uid: UID, friend: UID) -> Result<bool> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::RemoveFriend { uid, friend, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Get everyone this player has blocked
+    pub async fn get_blocks(&self, uid: UID) -> Result<Vec<UID>> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::GetBlocks { uid, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Block a player, returning false if there's no such account
+    pub async fn block(&self, uid: UID, blocked: UID) -> Result<bool> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::Block { uid, blocked, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    pub async fn unblock(&self, uid: UID, blocked: UID) -> Result<()> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::Unblock { uid, blocked, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Count the mail waiting for a player that they haven't read yet
+    pub async fn count_unreceived_mail(&self, uid: UID) -> Result<usize> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::CountUnreceivedMail { uid, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    pub async fn list_unreceived_mail(&self, uid: UID) -> Result<Vec<i32>> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::ListUnreceivedMail { uid, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Fetch a mail sent to this player and mark it as read, returning None if there's no such mail
+    pub async fn receive_mail(&self, uid: UID, mail_uid: i32) -> Result<Option<Mail>> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::ReceiveMail {
+                uid,
+                mail_uid,
+                resp,
+            })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Deliver a mail, unless the recipient already has `inbox_limit` unread ones
+    pub async fn send_mail(
+        &self,
+        from: UID,
+        to: UID,
+        body: String,
+        inbox_limit: usize,
+    ) -> Result<MailSendResult> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::SendMail {
+                from,
+                to,
+                body,
+                inbox_limit,
+                resp,
+            })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Put an item in another player's delivery box, if they'll accept it and there's room.
+    /// Whether they accept deliveries is only read from their saved data when
+    /// `recipient_online` is false.
+    pub async fn send_delivery(
+        &self,
+        from: UID,
+        to: UID,
+        item: CountedItem,
+        message: String,
+        box_limit: usize,
+        recipient_online: bool,
+    ) -> Result<SendDeliverResult> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::SendDelivery {
+                from,
+                to,
+                item,
+                message,
+                box_limit,
+                recipient_online,
+                resp,
+            })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Put an item in a delivery box regardless of who it's for, returning false if there's no room
+    pub async fn deposit_delivery(
+        &self,
+        from: UID,
+        to: UID,
+        item: CountedItem,
+        message: String,
+        box_limit: usize,
+    ) -> Result<bool> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::DepositDelivery {
+                from,
+                to,
+                item,
+                message,
+                box_limit,
+                resp,
+            })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// How many deliveries are waiting in a player's box
+    pub async fn count_deliveries(&self, uid: UID) -> Result<usize> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::CountDeliveries { uid, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Everything waiting in a player's delivery box, oldest first
+    pub async fn list_deliveries(&self, uid: UID) -> Result<Vec<PendingDelivery>> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::ListDeliveries { uid, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Take a delivery out of this player's box, returning None if there's no such delivery
+    pub async fn take_delivery(
+        &self,
+        uid: UID,
+        delivery_uid: i32,
+    ) -> Result<Option<PendingDelivery>> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::TakeDelivery {
+                uid,
+                delivery_uid,
+                resp,
+            })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// See whether this player could use a serial code, without using it up
+    pub async fn check_code(&self, uid: UID, code: String) -> Result<CodeRedemption> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::CheckCode { uid, code, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
+
+    /// Use up a serial code for this player, if it exists and they're allowed to
+    pub async fn redeem_code(&self, uid: UID, code: String) -> Result<CodeRedemption> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Command::RedeemCode { uid, code, resp })
+            .await
+            .unwrap();
+        rx.await?
+    }
 }
@@ -8,8 +8,11 @@ mod frontend;
 pub use frontend::DBTask;
 
 use crate::{
-    data::{record::CRecord, Account, Appearance, Character, User},
-    packets::{ChrUID, UID},
+    data::{
+        record::{CRecord, CourseRecord, HoleRecord, LeaderboardEntry, URecord},
+        Account, Appearance, Character, CountedItem, Mail, PendingDelivery, User,
+    },
+    packets::{ChrUID, MailSendResult, SendDeliverResult, UID},
 };
 
 enum Command {
@@ -53,12 +56,256 @@ enum Command {
         holes: i8,
         resp: Responder<Result<CRecord>>,
     },
+
+    WriteCRecord {
+        uid: UID,
+        course: i8,
+        season: i8,
+        holes: i8,
+        data: CRecord,
+    },
+
+    GetHoleRecord {
+        course: i8,
+        season: i8,
+        hole: i8,
+        resp: Responder<Result<HoleRecord>>,
+    },
+
+    WriteHoleRecord {
+        course: i8,
+        season: i8,
+        hole: i8,
+        data: HoleRecord,
+    },
+
+    GetCourseRecord {
+        course: i8,
+        season: i8,
+        resp: Responder<Result<CourseRecord>>,
+    },
+
+    WriteCourseRecord {
+        course: i8,
+        season: i8,
+        data: CourseRecord,
+    },
+
+    GetURecord {
+        uid: UID,
+        resp: Responder<Result<URecord>>,
+    },
+
+    WriteURecord {
+        uid: UID,
+        data: URecord,
+    },
+
+    /// Replies once every command sent before it has been handled
+    Flush {
+        resp: Responder<()>,
+    },
+
+    GetLeaderboard {
+        limit: usize,
+        resp: Responder<Result<Vec<LeaderboardEntry>>>,
+    },
+
+    ResetAccount {
+        uid: UID,
+        keep_profile: bool,
+        resp: Responder<Result<bool>>,
+    },
+
+    GetFriends {
+        uid: UID,
+        resp: Responder<Result<Vec<UID>>>,
+    },
+
+    /// Pending requests sent to this player if `inbound` is set, otherwise ones sent by them
+    GetFriendRequests {
+        uid: UID,
+        inbound: bool,
+        resp: Responder<Result<Vec<UID>>>,
+    },
+
+    SendFriendRequest {
+        from: UID,
+        to: UID,
+        resp: Responder<Result<FriendRequestResult>>,
+    },
+
+    AnswerFriendRequest {
+        from: UID,
+        to: UID,
+        accept: bool,
+        resp: Responder<Result<bool>>,
+    },
+
+    RemoveFriend {
+        uid: UID,
+        friend: UID,
+        resp: Responder<Result<bool>>,
+    },
+
+    GetBlocks {
+        uid: UID,
+        resp: Responder<Result<Vec<UID>>>,
+    },
+
+    /// Replies with false if there's nobody to block
+    Block {
+        uid: UID,
+        blocked: UID,
+        resp: Responder<Result<bool>>,
+    },
+
+    Unblock {
+        uid: UID,
+        blocked: UID,
+        resp: Responder<Result<()>>,
+    },
+
+    CountUnreceivedMail {
+        uid: UID,
+        resp: Responder<Result<usize>>,
+    },
+
+    ListUnreceivedMail {
+        uid: UID,
+        resp: Responder<Result<Vec<i32>>>,
+    },
+
+    /// Replies with the mail if it was sent to this player, marking it as received
+    ReceiveMail {
+        uid: UID,
+        mail_uid: i32,
+        resp: Responder<Result<Option<Mail>>>,
+    },
+
+    SendMail {
+        from: UID,
+        to: UID,
+        body: String,
+        inbox_limit: usize,
+        resp: Responder<Result<MailSendResult>>,
+    },
+
+    /// Checks that the recipient will take the delivery before putting it in their box
+    SendDelivery {
+        from: UID,
+        to: UID,
+        item: CountedItem,
+        message: String,
+        box_limit: usize,
+        /// Online players' refusal setting is checked by the game server instead
+        recipient_online: bool,
+        resp: Responder<Result<SendDeliverResult>>,
+    },
+
+    /// Puts something in a delivery box with no questions asked, replying with false if it's full
+    DepositDelivery {
+        from: UID,
+        to: UID,
+        item: CountedItem,
+        message: String,
+        box_limit: usize,
+        resp: Responder<Result<bool>>,
+    },
+
+    CountDeliveries {
+        uid: UID,
+        resp: Responder<Result<usize>>,
+    },
+
+    ListDeliveries {
+        uid: UID,
+        resp: Responder<Result<Vec<PendingDelivery>>>,
+    },
+
+    /// Replies with the delivery if it was sent to this player, removing it from their box
+    TakeDelivery {
+        uid: UID,
+        delivery_uid: i32,
+        resp: Responder<Result<Option<PendingDelivery>>>,
+    },
+
+    /// Marks a serial code as used by this player and replies with what it gives them
+    CheckCode {
+        uid: UID,
+        code: String,
+        resp: Responder<Result<CodeRedemption>>,
+    },
+
+    RedeemCode {
+        uid: UID,
+        code: String,
+        resp: Responder<Result<CodeRedemption>>,
+    },
+}
+
+/// What became of a friend request
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FriendRequestResult {
+    /// The request is waiting for the other player to answer it
+    Sent,
+    /// The other player had already asked, so now they're friends
+    Accepted,
+    AlreadyFriends,
+    AlreadyPending,
+    NoSuchUser,
+}
+
+/// What happened when a player typed in a serial code
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CodeRedemption {
+    /// The code was good; the player should now be given these.
+    /// When only checking a code, this is what they would get.
+    Redeemed {
+        items: Vec<CountedItem>,
+        message: String,
+    },
+    NoSuchCode,
+    /// This player has already used this code
+    AlreadyRedeemed,
+    /// Nobody can use this code any more
+    UsedUp,
 }
 
 type Responder<T> = oneshot::Sender<T>;
 
 pub fn run() -> Result<DBTask> {
-    let mut db = backend::create()?;
+    Ok(spawn(backend::create()?))
+}
+
+/// Start a throwaway database with these (login ID, password) accounts already in it
+#[cfg(test)]
+pub fn run_in_memory(accounts: &[(&str, &str)]) -> Result<DBTask> {
+    let mut db = backend::create_in_memory()?;
+    for (login_id, password) in accounts {
+        db.add_account(login_id, password)?;
+    }
+    Ok(spawn(db))
+}
+
+/// Start a throwaway database with these accounts, plus serial codes that each account
+/// can use once
+#[cfg(test)]
+pub fn run_in_memory_with_codes(
+    accounts: &[(&str, &str)],
+    codes: &[(&str, &[CountedItem])],
+) -> Result<DBTask> {
+    let mut db = backend::create_in_memory()?;
+    for (login_id, password) in accounts {
+        db.add_account(login_id, password)?;
+    }
+    for (code, items) in codes {
+        db.add_code(code, items)?;
+    }
+    Ok(spawn(db))
+}
+
+fn spawn(mut db: backend::DB) -> DBTask {
     let (tx, mut rx) = mpsc::channel(100);
 
     // TODO: should this be spawn_blocking?
@@ -70,5 +317,5 @@ pub fn run() -> Result<DBTask> {
         }
     });
 
-    Ok(DBTask { tx })
+    DBTask { tx }
 }
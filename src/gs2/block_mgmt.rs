@@ -0,0 +1,61 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::packets::{Packet, Status, UID};
+
+use super::GameServer;
+
+impl GameServer {
+    /// Check whether either of two connected players has blocked the other
+    pub(super) fn either_blocks(&self, a: usize, b: usize) -> bool {
+        let (a, b) = (&self.conns[a], &self.conns[b]);
+        a.blocks.contains(&b.uid) || b.blocks.contains(&a.uid)
+    }
+
+    pub(super) async fn handle_get_blocklist(&self, pid: i16, who: usize) -> Result<()> {
+        let users: Vec<UID> = self.conns[who].blocks.iter().copied().collect();
+        // TODO: find out what the two unknowns are for
+        let packet = Packet::PKT_118 {
+            unk1: 0,
+            unk2: 0,
+            count: users.len() as i32,
+            users,
+        };
+        self.conns[who].write_with_pid(packet, pid).await
+    }
+
+    /// Stop seeing chat from another player
+    pub(super) async fn handle_block(&mut self, pid: i16, who: usize, blocked: UID) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let status = if blocked == uid {
+            warn!("{uid} tried to block themselves");
+            Status::Err
+        } else if self.db.block(uid, blocked).await? {
+            info!("🚫 {uid} blocked {blocked}");
+            self.conns[who].blocks.insert(blocked);
+            Status::OK
+        } else {
+            warn!("{uid} tried to block {blocked}, who doesn't exist");
+            Status::Err
+        };
+
+        self.conns[who]
+            .write_with_pid(Packet::PKT_120(blocked, status), pid)
+            .await
+    }
+
+    pub(super) async fn handle_unblock(
+        &mut self,
+        pid: i16,
+        who: usize,
+        blocked: UID,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        self.db.unblock(uid, blocked).await?;
+        self.conns[who].blocks.remove(&blocked);
+
+        self.conns[who]
+            .write_with_pid(Packet::PKT_122(blocked, Status::OK), pid)
+            .await
+    }
+}
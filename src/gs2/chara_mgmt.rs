@@ -1,12 +1,12 @@
-use anyhow::{bail, Result};
-use log::{error, warn};
+use anyhow::Result;
+use log::{error, info, warn};
 
 use crate::{
-    data::Appearance,
+    data::{Appearance, Item, User},
     packets::{ChrUID, Packet, Status, CID},
 };
 
-use super::GameServer;
+use super::{error::GameError, GameServer};
 
 impl GameServer {
     /// Allow a player to create their first character
@@ -16,7 +16,26 @@ impl GameServer {
         appear: Appearance,
     ) -> Result<()> {
         if self.conns[who].user.default_chr_uid != -1 {
-            // Fail right away if they already have a character assigned.
+            // They already have a character, most likely because the client
+            // retried. Don't make another one, but let the client carry on.
+            warn!(
+                "uid {} tried to create a first character but already has {}",
+                self.conns[who].uid, self.conns[who].user.default_chr_uid
+            );
+            self.conns[who]
+                .write(Packet::ACK_FIRST_CHARACTER_APPEARANCE(Status::OK))
+                .await?;
+            return Ok(());
+        }
+
+        if let Err(e) = appear.validate() {
+            warn!(
+                "uid {} sent an invalid first character: {e}",
+                self.conns[who].uid
+            );
+            self.conns[who]
+                .write(Packet::ACK_FIRST_CHARACTER_APPEARANCE(Status::Err))
+                .await?;
             return Ok(());
         }
 
@@ -43,6 +62,26 @@ impl GameServer {
         Ok(())
     }
 
+    /// List the characters belonging to a player
+    pub(super) async fn handle_get_chruids(&self, pid: i16, who: usize, cid: CID) -> Result<()> {
+        let victim = match self.conn_lookup.get(&cid) {
+            Some(&victim) => victim,
+            None => {
+                warn!("PKT_98 for unknown cid {cid}");
+                return Ok(());
+            }
+        };
+
+        let chr_uids = self.conns[victim]
+            .characters
+            .iter()
+            .map(|(chr_uid, _)| *chr_uid)
+            .collect();
+        self.conns[who]
+            .write_with_pid(make_chruid_packet(cid, chr_uids), pid)
+            .await
+    }
+
     /// Fetch one character
     pub(super) async fn handle_req_chrdata(
         &self,
@@ -111,6 +150,52 @@ impl GameServer {
         Ok(())
     }
 
+    /// Switch to playing as another of the player's characters.
+    /// A character they don't own gets the current one sent back, so the client re-syncs.
+    pub(super) async fn handle_change_curr_chr_uid(
+        &mut self,
+        pid: i16,
+        who: usize,
+        chr_uid: ChrUID,
+    ) -> Result<()> {
+        let conn = &self.conns[who];
+        let (uid, cid) = (conn.uid, conn.cid);
+        if !conn.characters.iter().any(|(c, _)| *c == chr_uid) {
+            warn!("{uid} tried to switch to character {chr_uid}, which isn't theirs");
+            let packet = Packet::SEND_CRCHRUID {
+                cid,
+                now_chr_uid: conn.user.default_chr_uid,
+            };
+            return conn.write_with_pid(packet, pid).await;
+        }
+
+        info!("{uid} switched to character {chr_uid}");
+        self.conns[who].user.default_chr_uid = chr_uid;
+        self.save_user(who).await;
+        let packet = Packet::SEND_CRCHRUID {
+            cid,
+            now_chr_uid: chr_uid,
+        };
+        self.conns[who].write_with_pid(packet.clone(), pid).await?;
+
+        // Everyone else in the lobby, rooms included, needs to draw the new character
+        let conn = &self.conns[who];
+        let members = match self.lobbies.lobby_members(conn.mode, conn.cur_lobby) {
+            Some(members) => members.to_vec(),
+            None => return Ok(()),
+        };
+        for member in members {
+            if member == cid {
+                continue;
+            }
+            if let Some(index) = self.find_conn(member) {
+                self.conns[index].write(packet.clone()).await?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Get the active character ID for a particular player
     pub(super) async fn handle_get_curr_chr_uid(
         &self,
@@ -145,12 +230,22 @@ impl GameServer {
         if cid != self.conns[who].cid {
             error!("REQ_CHG_APPEAR for other cid {cid}, chr_uid {chr_uid}");
             self.conns[who].write(Packet::PKT_104(Status::Err)).await?;
+        } else if let Err(e) = appear.validate() {
+            warn!("REQ_CHG_APPEAR for chr_uid {chr_uid} is invalid: {e}");
+            self.conns[who].write(Packet::PKT_104(Status::Err)).await?;
         } else {
             let mut found = false;
 
-            for (check_chr_uid, chara) in &mut self.conns[who].characters {
-                if *check_chr_uid == chr_uid {
-                    // This is the one
+            let conn = &mut self.conns[who];
+            for (check_chr_uid, chara) in &mut conn.characters {
+                // A character can't be turned into somebody else
+                if *check_chr_uid == chr_uid && chara.appearance.character_id == appear.character_id
+                {
+                    // This is the one, but they can only put on what they've got
+                    if let Some(item) = unowned_item(&conn.user, &chara.appearance, &appear) {
+                        warn!("REQ_CHG_APPEAR for chr_uid {chr_uid} wears {item:?}, which they don't own");
+                        break;
+                    }
                     chara.appearance = appear;
                     self.db.write_character(chr_uid, chara.clone()).await;
                     found = true;
@@ -204,9 +299,29 @@ impl GameServer {
                 .write(Packet::ACK_CHG_CHR_PARAM(status))
                 .await?;
         } else {
-            bail!("bad packet")
+            return Err(GameError::BadPacket.into());
         }
 
         Ok(())
     }
 }
+
+/// Build a SEND_CHRUID, whose count also covers the leading cid
+pub(super) fn make_chruid_packet(cid: CID, chr_uids: Vec<ChrUID>) -> Packet {
+    Packet::SEND_CHRUID {
+        count: chr_uids.len() as i32 + 1,
+        cid,
+        chr_uids,
+    }
+}
+
+/// Find anything a new appearance puts on that the player doesn't own.
+/// Whatever the character already wears, or started out with, is always fine.
+fn unowned_item(user: &User, current: &Appearance, new: &Appearance) -> Option<Item> {
+    let mut allowed = current.worn_items();
+    allowed.extend(current.default_items());
+
+    new.worn_items()
+        .into_iter()
+        .find(|item| !allowed.contains(item) && user.item_amount(*item) == 0)
+}
@@ -0,0 +1,150 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::packets::{Packet, Status, CID};
+
+use super::GameServer;
+
+/// Chat to everybody nearby: the sender's room if they're in one, otherwise their lobby
+const MSG_ALL: i8 = 0;
+/// Chat to one player, picked out by the name field
+const MSG_WHISPER: i8 = 1;
+/// Chat to the sender's circle, which we don't have yet
+const MSG_CIRCLE_ALL: i8 = 3;
+
+/// Longest message we'll pass along, in UTF-16 units
+const MAX_MESSAGE_LEN: usize = 256;
+
+/// How many chat macros a player can keep
+const NUM_MACROS: usize = 10;
+
+impl GameServer {
+    /// Pass a chat message on to whoever it's meant for
+    pub(super) async fn handle_send_message(
+        &self,
+        who: usize,
+        claimed_cid: CID,
+        msg_type: i8,
+        name: &str,
+        mut message: Vec<u16>,
+    ) -> Result<()> {
+        let conn = &self.conns[who];
+        if claimed_cid != -1 && claimed_cid != conn.cid {
+            warn!("{} sent chat claiming to be {claimed_cid}", conn.cid);
+        }
+
+        // Never send on more than we've actually got, or more than anyone needs
+        message.truncate(MAX_MESSAGE_LEN);
+        if message.is_empty() {
+            return Ok(());
+        }
+        info!(
+            "💬 [{}] ({msg_type}) {}",
+            conn.cid,
+            String::from_utf16_lossy(&message)
+        );
+
+        let recipients: Vec<CID> = match msg_type {
+            MSG_ALL => {
+                if let Some(room) = self.lobbies.room(conn.mode, conn.cur_lobby, conn.cur_room) {
+                    room.members.clone()
+                } else if let Some(members) = self.lobbies.lobby_members(conn.mode, conn.cur_lobby)
+                {
+                    members.to_vec()
+                } else {
+                    warn!("{} chatted from nowhere", conn.cid);
+                    return Ok(());
+                }
+            }
+            MSG_WHISPER => match self.conns.iter().find(|c| c.name == name) {
+                // the sender gets a copy so their client can show what they said
+                Some(target) => vec![target.cid, conn.cid],
+                None => {
+                    info!("{} whispered to {name:?}, who isn't here", conn.cid);
+                    return Ok(());
+                }
+            },
+            MSG_CIRCLE_ALL => {
+                info!("{} sent circle chat, but there are no circles", conn.cid);
+                return Ok(());
+            }
+            _ => {
+                warn!("{} sent chat with unknown type {msg_type}", conn.cid);
+                return Ok(());
+            }
+        };
+
+        let packet = Packet::SEND_MESSAGE {
+            cid: conn.cid,
+            msg_type,
+            server_id: self.config.game.sv_no,
+            name: name.parse()?,
+            len: message.len() as i16,
+            message,
+        };
+        let sender = conn.uid;
+        for cid in recipients {
+            if let Some(index) = self.find_conn(cid) {
+                if self.conns[index].blocks.contains(&sender) {
+                    continue;
+                }
+                self.conns[index].write(packet.clone()).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send the player every chat macro they've saved
+    pub(super) async fn handle_get_macros(&self, who: usize) -> Result<()> {
+        for (which, text) in self.conns[who].user.macros.iter().enumerate() {
+            if text.is_empty() {
+                continue;
+            }
+            let packet = Packet::PKT_163 {
+                which: which as i8,
+                text: text.parse()?,
+            };
+            self.conns[who].write(packet).await?;
+        }
+        Ok(())
+    }
+
+    /// Save a chat macro into one of the player's slots.
+    /// `text` is None if the client sent something that isn't UTF-8.
+    pub(super) async fn handle_save_macro(
+        &mut self,
+        who: usize,
+        which: i8,
+        text: Option<String>,
+    ) -> Result<()> {
+        let slot = match usize::try_from(which) {
+            Ok(slot) if slot < NUM_MACROS => Some(slot),
+            _ => None,
+        };
+        // there has to be room left over for the terminator
+        let text = text
+            .map(|text| text.trim().to_string())
+            .filter(|text| text.len() < 65);
+
+        let status = match (slot, text) {
+            (Some(slot), Some(text)) => {
+                let macros = &mut self.conns[who].user.macros;
+                if macros.len() <= slot {
+                    macros.resize(slot + 1, String::new());
+                }
+                macros[slot] = text;
+                self.save_user(who).await;
+                Status::OK
+            }
+            _ => {
+                warn!("{} sent a bad macro for slot {which}", self.conns[who].cid);
+                Status::Err
+            }
+        };
+
+        self.conns[who]
+            .write(Packet::PKT_165 { which, status })
+            .await
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::data::CountedItem;
+use crate::db_task::CodeRedemption;
+use crate::packets::{Packet, Status};
+
+use super::GameServer;
+
+/// How many rewards PKT_271 can show
+const MAX_SHOWN_ITEMS: usize = 5;
+
+impl GameServer {
+    /// Try to use a serial code, handing over its items if it works.
+    /// Returns the items and message to show the player, or None if the code was no good.
+    async fn redeem_code(
+        &mut self,
+        who: usize,
+        code: &str,
+    ) -> Result<Option<(Vec<CountedItem>, String)>> {
+        let uid = self.conns[who].uid;
+        let code = code.trim();
+
+        // Don't use the code up if the player has nowhere to put what it gives
+        if let CodeRedemption::Redeemed { items, .. } =
+            self.db.check_code(uid, code.to_string()).await?
+        {
+            if !self.conns[who].user.has_room_for(&items, &self.config.shop) {
+                warn!("{uid} can't hold everything from code {code:?}");
+                return Ok(None);
+            }
+        }
+
+        let (items, message) = match self.db.redeem_code(uid, code.to_string()).await? {
+            CodeRedemption::Redeemed { items, message } => (items, message),
+            refusal => {
+                warn!("{uid} couldn't redeem code {code:?}: {refusal:?}");
+                return Ok(None);
+            }
+        };
+
+        info!("🎟️ {uid} redeemed code {code:?} for {items:?}");
+        for &item in &items {
+            self.conns[who].user.add_item(item);
+        }
+        self.save_user(who).await;
+        Ok(Some((items, message)))
+    }
+
+    /// Redeem a code from the code centre, and show the player what they got
+    pub(super) async fn handle_redeem_code(&mut self, who: usize, code: &str) -> Result<()> {
+        let mut shown = [CountedItem::default(); MAX_SHOWN_ITEMS];
+        let packet = match self.redeem_code(who, code).await? {
+            Some((items, message)) => {
+                let count = items.len().min(MAX_SHOWN_ITEMS);
+                shown[..count].copy_from_slice(&items[..count]);
+                Packet::PKT_271 {
+                    items: shown,
+                    status: Status::OK,
+                    count: count as i8,
+                    text: message.parse().unwrap_or_default(),
+                }
+            }
+            None => Packet::PKT_271 {
+                items: shown,
+                status: Status::Err,
+                count: 0,
+                text: Default::default(),
+            },
+        };
+        self.conns[who].write(packet).await
+    }
+
+    /// Make one of the configured exchanges, if that's what the code names.
+    /// Returns None if it isn't an exchange at all.
+    async fn try_exchange(&mut self, who: usize, code: &str) -> Result<Option<Status>> {
+        let config = self.config.clone();
+        let rule = match config
+            .exchange
+            .iter()
+            .find(|rule| rule.name.eq_ignore_ascii_case(code.trim()))
+        {
+            Some(rule) => rule,
+            None => return Ok(None),
+        };
+
+        let uid = self.conns[who].uid;
+        let mut user = self.conns[who].user.clone();
+        if let Err(e) = rule.apply(&mut user, &config.shop) {
+            warn!("{uid} couldn't make exchange {}: {e:?}", rule.name);
+            return Ok(Some(Status::Err));
+        }
+
+        info!("💱 {uid} made exchange {}", rule.name);
+        let np_added = user.np > self.conns[who].user.np;
+        self.conns[who].user = user;
+        self.save_user(who).await;
+        self.refresh_currencies(who).await?;
+        if np_added {
+            self.conns[who].write(Packet::ACK_ADD_NP).await?;
+        }
+        Ok(Some(Status::OK))
+    }
+
+    /// Redeem a code through the second code centre flow, which only wants to hear
+    /// whether it worked.
+    /// Configured exchanges go through here too.
+    pub(super) async fn handle_redeem_code_quietly(
+        &mut self,
+        pid: i16,
+        who: usize,
+        code: &str,
+    ) -> Result<()> {
+        let status = match self.try_exchange(who, code).await? {
+            Some(status) => status,
+            None => match self.redeem_code(who, code).await? {
+                Some(_) => Status::OK,
+                None => Status::Err,
+            },
+        };
+        self.conns[who]
+            .write_with_pid(Packet::PKT_273(status), pid)
+            .await
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use log::{info, warn};
+use rand::prelude::*;
+
+use crate::data::{CountedItem, Item, ItemCategory};
+use crate::packets::{Element, LobbyNum, Mode, Packet, RoomNum, CID};
+
+use super::GameServer;
+
+const ELEMENTS: [Element; 5] = [
+    Element::Blue,
+    Element::Red,
+    Element::Green,
+    Element::Yellow,
+    Element::Pink,
+];
+
+/// GP awarded to each member of a colour, indexed by how that colour placed
+const COLOR_GP_REWARDS: [i32; 5] = [500, 300, 200, 100, 50];
+
+/// Competition players are split up into colours, and each colour competes as a team.
+#[derive(Default)]
+pub(super) struct ColorCycle {
+    assigned: BTreeMap<CID, Element>,
+    scores: BTreeMap<CID, i8>,
+}
+
+/// The outcome of a colour cycle for one player
+#[derive(Debug, Clone)]
+pub(super) struct ColorResult {
+    pub(super) cid: CID,
+    pub(super) last_element: Element,
+    /// Where this player's colour placed, starting at 0 for the winner
+    pub(super) color_result: i8,
+    /// Where this player placed within their colour, starting at 1
+    pub(super) rank_in_color: i32,
+    pub(super) gp: i32,
+    pub(super) item: CountedItem,
+    /// Where this player placed overall, starting at 1
+    pub(super) placement: usize,
+}
+
+impl ColorCycle {
+    pub(super) fn is_active(&self) -> bool {
+        !self.assigned.is_empty()
+    }
+
+    pub(super) fn element(&self, cid: CID) -> Element {
+        self.assigned.get(&cid).copied().unwrap_or(Element::None)
+    }
+
+    /// Abandon the cycle without ranking anybody
+    pub(super) fn reset(&mut self) {
+        self.assigned.clear();
+        self.scores.clear();
+    }
+
+    /// Split the players evenly between the colours
+    pub(super) fn assign<R: Rng>(&mut self, members: &[CID], rng: &mut R) {
+        let mut members = members.to_vec();
+        members.shuffle(rng);
+
+        // vary which colours get used when there are fewer than five players
+        let mut elements = ELEMENTS;
+        elements.shuffle(rng);
+
+        self.assigned.clear();
+        self.scores.clear();
+        for (index, cid) in members.into_iter().enumerate() {
+            self.assigned.insert(cid, elements[index % elements.len()]);
+        }
+    }
+
+    /// Note a player's final score, returning true once everybody has finished
+    pub(super) fn record_score(&mut self, cid: CID, score: i8) -> bool {
+        if self.assigned.contains_key(&cid) {
+            self.scores.insert(cid, score);
+        }
+        self.scores.len() >= self.assigned.len()
+    }
+
+    /// Take out a player who has gone, returning true if everybody still in the cycle
+    /// has now finished
+    pub(super) fn remove_player(&mut self, cid: CID) -> bool {
+        let was_assigned = self.assigned.remove(&cid).is_some();
+        self.scores.remove(&cid);
+        was_assigned && self.is_active() && self.scores.len() >= self.assigned.len()
+    }
+
+    /// Rank the colours and their members, then clear out the cycle
+    pub(super) fn finish(&mut self) -> Vec<ColorResult> {
+        // Group everybody who finished by colour, best (lowest) score first
+        let mut groups: BTreeMap<Element, Vec<(CID, i8)>> = BTreeMap::new();
+        for (&cid, &element) in &self.assigned {
+            if let Some(&score) = self.scores.get(&cid) {
+                groups.entry(element).or_default().push((cid, score));
+            }
+        }
+        for group in groups.values_mut() {
+            group.sort_by_key(|&(_, score)| score);
+        }
+
+        // Colours are compared on their average score so uneven teams are fair
+        let mut ranking: Vec<(Element, i32)> = groups
+            .iter()
+            .map(|(&element, group)| {
+                let total: i32 = group.iter().map(|&(_, score)| score as i32).sum();
+                (element, total * 100 / group.len() as i32)
+            })
+            .collect();
+        ranking.sort_by_key(|&(_, average)| average);
+
+        // Individual placings go by score alone, with ties sharing a place
+        let mut all_scores: Vec<i8> = self.scores.values().copied().collect();
+        all_scores.sort();
+        let placement = |score: i8| all_scores.iter().take_while(|&&s| s < score).count() + 1;
+
+        let mut results = Vec::new();
+        for (color_index, (element, _)) in ranking.into_iter().enumerate() {
+            let item = if color_index == 0 {
+                Item::new(ItemCategory::HoldItemPoint, 1).one()
+            } else {
+                CountedItem::default()
+            };
+
+            for (rank, &(cid, score)) in groups[&element].iter().enumerate() {
+                results.push(ColorResult {
+                    cid,
+                    last_element: element,
+                    color_result: color_index as i8,
+                    rank_in_color: rank as i32 + 1,
+                    gp: COLOR_GP_REWARDS[color_index],
+                    item,
+                    placement: placement(score),
+                });
+            }
+        }
+
+        self.reset();
+        results
+    }
+}
+
+impl GameServer {
+    /// Put everybody in a competition room into a colour, if that hasn't happened yet
+    pub(super) async fn start_color_cycle(&mut self, who: usize) -> Result<()> {
+        let conn = &self.conns[who];
+        let room = match self
+            .lobbies
+            .room_mut(Mode::Competition, conn.cur_lobby, conn.cur_room)
+        {
+            Some(room) => room,
+            None => return Ok(()),
+        };
+
+        if room.color_cycle.is_active() {
+            return Ok(());
+        }
+
+        room.color_cycle.assign(&room.members, &mut self.rng);
+        let assigned: Vec<(CID, Element)> = room
+            .members
+            .iter()
+            .map(|&cid| (cid, room.color_cycle.element(cid)))
+            .collect();
+
+        for (cid, element) in assigned {
+            let index = match self.find_conn(cid) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            self.conns[index].element = element;
+            self.conns[index]
+                .write(Packet::ORD_COLOR_RESULT {
+                    element,
+                    last_element: Element::None,
+                    color_result: 0,
+                    rank_in_color: 0,
+                    gp: 0,
+                    item: Default::default(),
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Give a player their colour rewards and tell them about it.
+    /// Whatever the packet advertises is exactly what ends up in their inventory.
+    async fn send_color_result(&mut self, index: usize, mut result: ColorResult) -> Result<()> {
+        let shop = &self.config.shop;
+        self.conns[index].element = Element::None;
+        let user = &mut self.conns[index].user;
+        user.gp += result.gp;
+
+        if result.item != CountedItem::default() {
+            let item = result.item.item();
+            let new_amount = user.item_amount(item) + result.item.count();
+            if new_amount <= shop.maximum(item.category()) {
+                user.add_item(result.item);
+            } else {
+                warn!("{} can't hold any more of {item:?}", result.cid);
+                result.item = CountedItem::default();
+            }
+        }
+        self.save_user(index).await;
+
+        self.conns[index]
+            .write(Packet::ORD_COLOR_RESULT {
+                element: Element::None,
+                last_element: result.last_element,
+                color_result: result.color_result,
+                rank_in_color: result.rank_in_color,
+                gp: result.gp,
+                item: result.item,
+            })
+            .await?;
+        self.refresh_currencies(index).await
+    }
+
+    /// Record a competitor's score, and hand out the rewards once the whole room is done
+    pub(super) async fn record_color_score(&mut self, who: usize, score: i8) -> Result<()> {
+        let conn = &self.conns[who];
+        let room = match self
+            .lobbies
+            .room_mut(Mode::Competition, conn.cur_lobby, conn.cur_room)
+        {
+            Some(room) => room,
+            None => return Ok(()),
+        };
+
+        if !room.color_cycle.is_active() || !room.color_cycle.record_score(conn.cid, score) {
+            return Ok(());
+        }
+
+        let (lobby_num, room_num) = (conn.cur_lobby, conn.cur_room);
+        self.finish_color_cycle(lobby_num, room_num).await
+    }
+
+    /// Rank a room's colour cycle and hand out the rewards, if it's still going
+    pub(super) async fn finish_color_cycle(
+        &mut self,
+        lobby_num: LobbyNum,
+        room_num: RoomNum,
+    ) -> Result<()> {
+        let room = match self
+            .lobbies
+            .room_mut(Mode::Competition, lobby_num, room_num)
+        {
+            Some(room) if room.color_cycle.is_active() => room,
+            _ => return Ok(()),
+        };
+
+        let results = room.color_cycle.finish();
+        info!("colour cycle finished: {results:?}");
+
+        for result in results {
+            let index = match self.find_conn(result.cid) {
+                Some(index) => index,
+                None => continue,
+            };
+            let placement = result.placement;
+            self.send_color_result(index, result).await?;
+            self.record_placement(index, placement).await?;
+        }
+
+        Ok(())
+    }
+}
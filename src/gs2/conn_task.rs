@@ -1,32 +1,78 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::Result;
+use deku::{DekuContainerWrite, DekuEnumExt};
 use log::{error, info, warn};
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::TcpStream,
     sync::{mpsc, oneshot},
     task::JoinHandle,
+    time::{timeout_at, Instant},
 };
 use tokio_rustls::TlsAcceptor;
 
 use crate::{
-    packets::{Packet, UData, CID},
-    stream::Connection,
+    config::Config,
+    packets::{EntirePacket, Packet, PacketHeader, UData, CID},
+    stream::{Connection, Frame},
 };
 
 use super::{LoginResult, Message};
 
-pub type ConnMessage = (Option<i16>, Packet);
+/// How long a closing connection keeps sending queued packets
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Almost everything sent is a plain packet, so boxing those to shrink this would cost more than it saves
+#[allow(clippy::large_enum_variant)]
+pub enum ConnMessage {
+    /// A packet to serialise, with the pid of the request it answers, if any
+    Packet(Option<i16>, Packet),
+    /// A packet that has already been serialised for sending to several clients
+    Frame(Frame),
+}
+
 pub type ConnSender = mpsc::Sender<ConnMessage>;
 pub type ConnReceiver = mpsc::Receiver<ConnMessage>;
 
-async fn do_handshake(
+/// Log a packet in full if the config asks for it
+fn trace_packet(config: &Config, cid: CID, direction: &str, packet: &Packet, pid: Option<i16>) {
+    let id = match packet.deku_id() {
+        Ok(id) => id,
+        Err(_) => return,
+    };
+    if !config.traces(id) {
+        return;
+    }
+
+    let entire = EntirePacket {
+        header: PacketHeader {
+            id,
+            pid: pid.unwrap_or(0),
+        },
+        packet: packet.clone(),
+    };
+    let bytes = match entire.to_bytes() {
+        Ok(bytes) => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+        Err(e) => format!("<unencodable: {e}>"),
+    };
+    info!("🔎 [{cid}] {direction} {id}: {packet:#?}\n[{bytes}]");
+}
+
+pub(super) async fn do_handshake<S: AsyncRead + AsyncWrite + Unpin>(
     gs2: mpsc::Sender<Message>,
-    conn: &mut Connection,
+    addr: SocketAddr,
+    conn: &mut Connection<S>,
 ) -> Result<Option<(CID, ConnReceiver)>> {
     while let Some(packet) = conn.read_packet().await? {
         if let Packet::SEND_IDPASS_G(p) = packet.packet {
             // Try to get ourselves in
+            // If we bail out after this point, the server notices that
+            // resp_rx was dropped and cleans up after us.
             let (resp_tx, resp_rx) = oneshot::channel();
-            gs2.send(Message::Login(p, resp_tx)).await?;
+            gs2.send(Message::Login(p, addr, resp_tx)).await?;
 
             match resp_rx.await? {
                 LoginResult::Fail(code) => {
@@ -49,21 +95,50 @@ async fn do_handshake(
     Ok(None)
 }
 
+/// Why a connection ended badly, and how far it got before that happened
+enum ConnectionError {
+    /// Something went wrong before the player had logged in
+    Handshake(anyhow::Error),
+    /// Something went wrong while the player was logged in as this cid
+    Session(CID, anyhow::Error),
+}
+
 async fn handle_connection(
     gs2: mpsc::Sender<Message>,
     stream: TcpStream,
+    addr: SocketAddr,
     acceptor: TlsAcceptor,
-) -> Result<()> {
+    config: Arc<Config>,
+) -> Result<(), ConnectionError> {
+    info!("Game connection from {addr}");
+
     // Establish a TLS session
-    let stream = acceptor.accept(stream).await?;
+    let stream = acceptor
+        .accept(stream)
+        .await
+        .map_err(|e| ConnectionError::Handshake(e.into()))?;
     let mut conn = Connection::new(stream);
 
     // Allow the client to log in
-    let (cid, mut packet_rx) = match do_handshake(gs2.clone(), &mut conn).await? {
-        Some(t) => t,
-        None => return Ok(()),
+    let (cid, packet_rx) = match do_handshake(gs2.clone(), addr, &mut conn).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return Ok(()),
+        Err(e) => return Err(ConnectionError::Handshake(e)),
     };
 
+    run_session(gs2, conn, addr, config, cid, packet_rx)
+        .await
+        .map_err(|e| ConnectionError::Session(cid, e))
+}
+
+async fn run_session(
+    gs2: mpsc::Sender<Message>,
+    mut conn: Connection,
+    addr: SocketAddr,
+    config: Arc<Config>,
+    cid: CID,
+    mut packet_rx: ConnReceiver,
+) -> Result<()> {
     // We are now authenticated with the server.
     // From this point on, we should not terminate without telling it beforehand.
     loop {
@@ -74,18 +149,13 @@ async fn handle_connection(
                         // The server has kicked us off.
                         break;
                     }
-                    Some((pid, packet)) => {
+                    Some(message) => {
                         // This packet needs to go to the client
-                        let result = match pid {
-                            Some(pid) => conn.write_packet_with_pid(packet, pid).await,
-                            None => conn.write_packet(packet).await
-                        };
-
-                        if let Err(e) = result {
+                        if let Err(e) = send_outbound(&config, cid, &mut conn, message).await {
                             // It's all over
-                            warn!("Error writing to client: {e:?}");
+                            warn!("[{cid} {addr}] error writing to client: {e:?}");
                             gs2.send(Message::Logout(cid)).await?;
-                            break;
+                            return conn.shutdown().await;
                         }
                     }
                 }
@@ -97,17 +167,18 @@ async fn handle_connection(
                         // This packet needs to go to the server
                         let pid = packet.header.pid;
                         let packet = packet.packet;
+                        trace_packet(&config, cid, "->", &packet, Some(pid));
                         gs2.send(Message::PlayerData { cid, pid, packet }).await?;
                     }
                     Ok(None) => {
                         // Client disconnected
-                        info!("Client disconnected!");
+                        info!("[{cid} {addr}] client disconnected");
                         gs2.send(Message::Logout(cid)).await?;
                         break;
                     }
                     Err(e) => {
                         // Connection error
-                        warn!("Error reading from client: {e:?}");
+                        warn!("[{cid} {addr}] error reading from client: {e:?}");
                         gs2.send(Message::Logout(cid)).await?;
                         break;
                     }
@@ -116,21 +187,66 @@ async fn handle_connection(
         }
     }
 
+    // Whatever the server sent before letting go of us still deserves to arrive
+    drain_outbound(&config, cid, &mut conn, &mut packet_rx).await;
     conn.shutdown().await?;
 
     Ok(())
 }
 
+async fn send_outbound<S: AsyncRead + AsyncWrite + Unpin>(
+    config: &Config,
+    cid: CID,
+    conn: &mut Connection<S>,
+    message: ConnMessage,
+) -> Result<()> {
+    match message {
+        ConnMessage::Packet(pid, packet) => {
+            trace_packet(config, cid, "<-", &packet, pid);
+            match pid {
+                Some(pid) => conn.write_packet_with_pid(packet, pid).await,
+                None => conn.write_packet(packet).await,
+            }
+        }
+        ConnMessage::Frame(frame) => {
+            trace_packet(config, cid, "<-", &frame.packet, None);
+            conn.write_frame(&frame).await
+        }
+    }
+}
+
+/// Send any packets still queued for a departing client, until the server drops
+/// its end of the channel or we run out of patience
+pub(super) async fn drain_outbound<S: AsyncRead + AsyncWrite + Unpin>(
+    config: &Config,
+    cid: CID,
+    conn: &mut Connection<S>,
+    packet_rx: &mut ConnReceiver,
+) {
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    while let Ok(Some(message)) = timeout_at(deadline, packet_rx.recv()).await {
+        if let Err(e) = send_outbound(config, cid, conn, message).await {
+            info!("[{cid}] gave up flushing packets: {e:?}");
+            return;
+        }
+    }
+}
+
 pub(super) fn run_connection(
     gs2: mpsc::Sender<Message>,
     stream: TcpStream,
+    addr: SocketAddr,
     acceptor: TlsAcceptor,
+    config: Arc<Config>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
-        match handle_connection(gs2, stream, acceptor).await {
+        match handle_connection(gs2, stream, addr, acceptor, config).await {
             Ok(_) => {}
-            Err(err) => {
-                error!("connection failed: {err:?}");
+            Err(ConnectionError::Handshake(err)) => {
+                error!("connection from {addr} failed during handshake: {err:?}");
+            }
+            Err(ConnectionError::Session(cid, err)) => {
+                error!("[{cid} {addr}] connection failed during gameplay: {err:?}");
             }
         }
     })
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use log::{error, info, warn};
+
+use crate::data::CountedItem;
+use crate::packets::{Delivery, Packet, SendDeliverResult};
+
+use super::GameServer;
+
+/// Most deliveries that can be waiting in a player's box
+pub(super) const DELIVERY_BOX_LIMIT: usize = 20;
+
+impl GameServer {
+    /// Send everything waiting in the player's delivery box, one packet each.
+    /// TODO: the meaning of the unknowns is a best guess; we use unk1 to identify the
+    /// delivery when it's answered, and unk2 for the sender
+    pub(super) async fn handle_get_deliveries(&self, who: usize) -> Result<()> {
+        let deliveries = self.db.list_deliveries(self.conns[who].uid).await?;
+        for (index, delivery) in deliveries.into_iter().enumerate() {
+            let msg = match delivery.message.parse() {
+                Ok(msg) => msg,
+                Err(_) => "".parse()?,
+            };
+            let packet = Packet::SEND_DELIVER(Delivery {
+                unk1: delivery.delivery_uid,
+                dest_uid: delivery.to_uid,
+                item: delivery.item.item(),
+                unk2: delivery.from_uid,
+                delivery_index: index as i8,
+                unk3: [0; 3],
+                msg,
+            });
+            self.conns[who].write(packet).await?;
+        }
+        Ok(())
+    }
+
+    /// Tell the client how full the delivery box is
+    pub(super) async fn handle_count_deliveries(&self, pid: i16, who: usize) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let packet = match self.db.count_deliveries(uid).await {
+            Ok(count) => Packet::PKT_193 {
+                unk: 0,
+                num_items: count as i32,
+            },
+            Err(e) => {
+                error!("couldn't count the deliveries for {uid}: {e:?}");
+                Packet::PKT_193 {
+                    unk: -3,
+                    num_items: 0,
+                }
+            }
+        };
+        self.conns[who].write_with_pid(packet, pid).await
+    }
+
+    fn check_delivery(&self, who: usize, delivery: &Delivery) -> Result<(), SendDeliverResult> {
+        let conn = &self.conns[who];
+        if delivery.dest_uid == conn.uid {
+            return Err(SendDeliverResult::GenericError1);
+        }
+        if conn.user.item_amount(delivery.item) == 0 {
+            return Err(SendDeliverResult::IncorrectItemInfo);
+        }
+        if delivery.msg.try_to_string().is_none() {
+            return Err(SendDeliverResult::IncorrectItemInfo);
+        }
+        // Online players may have changed their mind since they were last saved
+        let recipient = self.conns.iter().find(|c| c.uid == delivery.dest_uid);
+        if recipient.is_some_and(|c| c.user.refuses_deliveries) {
+            return Err(SendDeliverResult::DeliveryDisabled);
+        }
+        Ok(())
+    }
+
+    /// Send one of an item to another player's delivery box.
+    /// It only leaves the sender's inventory once it's safely in the box.
+    pub(super) async fn handle_send_delivery(
+        &mut self,
+        who: usize,
+        delivery: Delivery,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let to = delivery.dest_uid;
+        let item = CountedItem::new(delivery.item, 1);
+
+        let result = match self.check_delivery(who, &delivery) {
+            Ok(()) => {
+                let message = delivery.msg.try_to_string().unwrap_or_default();
+                let online = self.conns.iter().any(|c| c.uid == to);
+                self.db
+                    .send_delivery(uid, to, item, message, DELIVERY_BOX_LIMIT, online)
+                    .await?
+            }
+            Err(result) => result,
+        };
+        info!("📦 delivery of {item:?} from {uid} to {to}: {result:?}");
+
+        if result == SendDeliverResult::OK {
+            self.conns[who].user.remove_item(item);
+            self.save_user(who).await;
+        }
+        self.conns[who]
+            .write(Packet::ACK_SEND_DELIVER(delivery.delivery_index, result))
+            .await
+    }
+
+    /// Take a delivery out of the box and into the inventory.
+    /// TODO: unk4 probably says whether the delivery is being accepted or turned down;
+    /// for now, everything is accepted
+    pub(super) async fn handle_answer_delivery(
+        &mut self,
+        who: usize,
+        delivery: Delivery,
+        unk4: i8,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let result = self.accept_delivery(who, delivery.unk1).await?;
+        info!(
+            "📦 {uid} answered delivery {} with {unk4}: {result:?}",
+            delivery.unk1
+        );
+
+        self.conns[who]
+            .write(Packet::ACK_ANS_DELIVER {
+                index: delivery.delivery_index,
+                unk4,
+                result,
+            })
+            .await
+    }
+
+    async fn accept_delivery(
+        &mut self,
+        who: usize,
+        delivery_uid: i32,
+    ) -> Result<SendDeliverResult> {
+        let uid = self.conns[who].uid;
+        let waiting = self.db.list_deliveries(uid).await?;
+        let item = match waiting.iter().find(|d| d.delivery_uid == delivery_uid) {
+            Some(delivery) => delivery.item,
+            None => {
+                warn!("{uid} answered delivery {delivery_uid}, which isn't in their box");
+                return Ok(SendDeliverResult::SenderCancelledDelivery);
+            }
+        };
+
+        // Leave it in the box if there's no room for it
+        let maximum = self.config.shop.maximum(item.item().category());
+        if self.conns[who].user.item_amount(item.item()) + item.count() > maximum {
+            return Ok(SendDeliverResult::LimitReached);
+        }
+
+        match self.db.take_delivery(uid, delivery_uid).await? {
+            Some(delivery) => {
+                self.conns[who].user.add_item(delivery.item);
+                self.save_user(who).await;
+                Ok(SendDeliverResult::OK)
+            }
+            None => Ok(SendDeliverResult::SenderCancelledDelivery),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use thiserror::Error;
+
+use crate::packets::Mode;
+
+/// Things that commonly go wrong when a player asks for something.
+/// These are usually down to the client being out of sync with us, rather than a server bug.
+#[derive(Error, Debug)]
+pub(super) enum GameError {
+    #[error("lobby does not exist")]
+    InvalidLobby,
+    #[error("player isn't in that mode")]
+    WrongMode,
+    #[error("player isn't in that lobby")]
+    NotInLobby,
+    #[error("player is already in a lobby")]
+    AlreadyInLobby,
+    #[error("lobby is full")]
+    LobbyFull,
+    #[error("player isn't in a room")]
+    NotInRoom,
+    #[error("player is already in a room")]
+    AlreadyInRoom,
+    #[error("player isn't playing a round")]
+    NotInRound,
+    #[error("room settings are not allowed")]
+    InvalidRoomSettings,
+    #[error("no free room numbers")]
+    NoFreeRooms,
+    #[error("can't go from mode {0:?} to {1:?}")]
+    IllegalModeChange(Mode, Mode),
+    #[error("game mode {0:?} is not supported here")]
+    UnsupportedMode(Mode),
+    #[error("packet refers to somebody else")]
+    BadPacket,
+    #[error("packet contains text that can't be decoded")]
+    BadText,
+}
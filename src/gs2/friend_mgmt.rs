@@ -0,0 +1,141 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::db_task::FriendRequestResult;
+use crate::packets::{ChgUDataType, FriendRequestStatus, Packet, Status, UID};
+
+use super::GameServer;
+
+impl GameServer {
+    /// Let a player know that something happened to their friends list, if they're online
+    async fn notify_friend_change(&self, uid: UID, chg: ChgUDataType, p0: UID) -> Result<()> {
+        if let Some(conn) = self.conns.iter().find(|c| c.uid == p0) {
+            let packet = Packet::SEND_CHG_UDATA {
+                uid,
+                chg,
+                p0,
+                p1: 0,
+            };
+            conn.write(packet).await?;
+        }
+        Ok(())
+    }
+
+    pub(super) async fn handle_get_friends(&self, pid: i16, who: usize) -> Result<()> {
+        let users = self.db.get_friends(self.conns[who].uid).await?;
+        let packet = Packet::PKT_72 {
+            count: users.len() as i32,
+            users,
+        };
+        self.conns[who].write_with_pid(packet, pid).await
+    }
+
+    /// List the friend requests that other players have sent us
+    pub(super) async fn handle_get_inbound_requests(&self, pid: i16, who: usize) -> Result<()> {
+        let users = self
+            .db
+            .get_inbound_friend_requests(self.conns[who].uid)
+            .await?;
+        let packet = Packet::PKT_74 {
+            count: users.len() as i32,
+            users,
+        };
+        self.conns[who].write_with_pid(packet, pid).await
+    }
+
+    /// List the friend requests we've sent that haven't been answered
+    pub(super) async fn handle_get_outbound_requests(&self, pid: i16, who: usize) -> Result<()> {
+        let users = self
+            .db
+            .get_outbound_friend_requests(self.conns[who].uid)
+            .await?;
+        let packet = Packet::PKT_76 {
+            count: users.len() as i32,
+            users,
+        };
+        self.conns[who].write_with_pid(packet, pid).await
+    }
+
+    /// Ask another player to be friends. If they've already asked us, that counts as
+    /// accepting their request.
+    pub(super) async fn handle_friend_request(&self, pid: i16, who: usize, to: UID) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let result = if to == uid {
+            warn!("{uid} tried to befriend themselves");
+            FriendRequestResult::NoSuchUser
+        } else {
+            self.db.send_friend_request(uid, to).await?
+        };
+        info!("🤝 friend request from {uid} to {to}: {result:?}");
+
+        let status = match result {
+            FriendRequestResult::Sent | FriendRequestResult::Accepted => Status::OK,
+            _ => Status::Err,
+        };
+        self.conns[who]
+            .write_with_pid(Packet::PKT_70(to, status), pid)
+            .await?;
+
+        match result {
+            FriendRequestResult::Sent => {
+                self.notify_friend_change(uid, ChgUDataType::FriendRequestReceived, to)
+                    .await
+            }
+            FriendRequestResult::Accepted => {
+                self.notify_friend_change(uid, ChgUDataType::FriendRequestAccepted, to)
+                    .await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Accept or deny a request that somebody sent us
+    pub(super) async fn handle_answer_friend_request(
+        &self,
+        pid: i16,
+        who: usize,
+        from: UID,
+        answer: FriendRequestStatus,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let accept = answer == FriendRequestStatus::Accept;
+        let found = self.db.answer_friend_request(from, uid, accept).await?;
+
+        let status = if found { Status::OK } else { Status::Err };
+        self.conns[who]
+            .write_with_pid(Packet::PKT_78(from, status, answer), pid)
+            .await?;
+
+        if !found {
+            warn!("{uid} answered a friend request from {from} that doesn't exist");
+            return Ok(());
+        }
+        let chg = if accept {
+            ChgUDataType::FriendRequestAccepted
+        } else {
+            ChgUDataType::FriendRequestRejected
+        };
+        self.notify_friend_change(uid, chg, from).await
+    }
+
+    pub(super) async fn handle_remove_friend(
+        &self,
+        pid: i16,
+        who: usize,
+        friend: UID,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let found = self.db.remove_friend(uid, friend).await?;
+
+        let status = if found { Status::OK } else { Status::Err };
+        self.conns[who]
+            .write_with_pid(Packet::PKT_80(friend, status), pid)
+            .await?;
+
+        if found {
+            self.notify_friend_change(uid, ChgUDataType::RemoveFriend, friend)
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::Result;
+use log::{info, warn};
+use rand::prelude::*;
+
+use crate::data::{CountedItem, Item, ItemCategory};
+use crate::packets::Packet;
+
+use super::{delivery_mgmt::DELIVERY_BOX_LIMIT, GameServer};
+
+/// PKT_199 and PKT_214 result codes
+const GAME_OK: i8 = 0;
+/// Not enough coins (UFO catcher) or medals (slots)
+const GAME_CANT_PAY: i8 = -1;
+const GAME_BOX_FULL: i8 = -2;
+
+/// Outcome for a UFO catcher play that didn't catch anything
+const UFO_MISS: i8 = 0;
+/// Outcome for a slots play that didn't line anything up
+const SLOTS_MISS: i8 = 4;
+
+/// PKT_213 argument for paying with a medal
+const SLOTS_PAY_MEDAL: i8 = 3;
+/// PKT_213 argument for using one of the day's free plays
+const SLOTS_PAY_DAILY: i8 = 12;
+/// Free slots plays each player gets per day
+const DAILY_SLOTS_PLAYS: u8 = 3;
+
+/// Something that can be won in the Game Center
+struct Prize {
+    /// Outcome code shown to the client
+    outcome: i8,
+    /// Relative chance of this prize, against every other entry
+    weight: u32,
+    item: Option<CountedItem>,
+}
+
+fn prize_item(category: ItemCategory, num: u32, count: u32) -> Option<CountedItem> {
+    Some(CountedItem::new(Item::new(category, num), count))
+}
+
+/// Everything the UFO catcher can give out
+fn ufo_prizes() -> Vec<Prize> {
+    vec![
+        Prize {
+            outcome: UFO_MISS,
+            weight: 60,
+            item: None,
+        },
+        Prize {
+            outcome: 1,
+            weight: 25,
+            item: prize_item(ItemCategory::Ball, 1, 5),
+        },
+        Prize {
+            outcome: 2,
+            weight: 10,
+            item: prize_item(ItemCategory::CarryItemPowerGauge, 1, 3),
+        },
+        Prize {
+            outcome: 3,
+            weight: 4,
+            item: prize_item(ItemCategory::HoldItemSupport, 1, 1),
+        },
+        Prize {
+            outcome: 4,
+            weight: 1,
+            item: prize_item(ItemCategory::ClubSet, 10, 1),
+        },
+    ]
+}
+
+/// Everything the slot machine can give out
+fn slot_prizes() -> Vec<Prize> {
+    vec![
+        Prize {
+            outcome: 0,
+            weight: 2,
+            item: prize_item(ItemCategory::ClubSet, 12, 1),
+        },
+        Prize {
+            outcome: 1,
+            weight: 8,
+            item: prize_item(ItemCategory::HoldItemSupport, 2, 1),
+        },
+        Prize {
+            outcome: 2,
+            weight: 20,
+            item: prize_item(ItemCategory::Ball, 2, 5),
+        },
+        Prize {
+            outcome: SLOTS_MISS,
+            weight: 70,
+            item: None,
+        },
+    ]
+}
+
+/// Cost of one UFO catcher play, in tenths of a coin, from the mode the client asks for
+fn ufo_cost(mode: i8) -> Option<i32> {
+    match mode {
+        1 => Some(10),
+        2 => Some(1),
+        _ => None,
+    }
+}
+
+impl GameServer {
+    /// Pick a prize and put it in the player's delivery box.
+    /// Returns None if there was a prize but no room for it.
+    async fn award_prize<'a>(&self, who: usize, prizes: &'a [Prize]) -> Result<Option<&'a Prize>> {
+        let uid = self.conns[who].uid;
+        let prize = prizes.choose_weighted(&mut thread_rng(), |p| p.weight)?;
+        if let Some(item) = prize.item {
+            let message = "Game Center prize".to_string();
+            if !self
+                .db
+                .deposit_delivery(uid, uid, item, message, DELIVERY_BOX_LIMIT)
+                .await?
+            {
+                return Ok(None);
+            }
+        }
+        Ok(Some(prize))
+    }
+
+    /// Have a go on the UFO catcher.
+    /// Prizes go to the delivery box rather than straight into the inventory, and the
+    /// coins are only taken once we know the prize has somewhere to go.
+    pub(super) async fn handle_play_ufo(&mut self, who: usize, mode: i8) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let cost = match ufo_cost(mode) {
+            Some(cost) => cost,
+            None => {
+                warn!("{uid} played the UFO catcher in unknown mode {mode}");
+                return Ok(());
+            }
+        };
+
+        let reply = |result, outcome, item: Option<CountedItem>| Packet::PKT_199 {
+            item: item.unwrap_or_default(),
+            result,
+            outcome,
+        };
+        if self.conns[who].user.coin_tenths < cost {
+            let packet = reply(GAME_CANT_PAY, UFO_MISS, None);
+            return self.conns[who].write(packet).await;
+        }
+
+        let prizes = ufo_prizes();
+        let (outcome, item) = match self.award_prize(who, &prizes).await? {
+            Some(prize) => (prize.outcome, prize.item),
+            None => {
+                let packet = reply(GAME_BOX_FULL, UFO_MISS, None);
+                return self.conns[who].write(packet).await;
+            }
+        };
+
+        self.conns[who].user.coin_tenths -= cost;
+        self.save_user(who).await;
+        info!("🛸 {uid} played the UFO catcher and got {item:?}");
+
+        let packet = reply(GAME_OK, outcome, item);
+        self.conns[who].write(packet).await
+    }
+
+    /// Free slots plays the player has left today, starting a new day's allowance if
+    /// the server's date has moved on since they last played
+    fn daily_slots_plays(&mut self, who: usize) -> u8 {
+        let now = self.config.now_datetime();
+        let today = (now.year, now.month, now.day);
+        let user = &mut self.conns[who].user;
+        if user.slots_plays_date != Some(today) {
+            user.slots_plays_date = Some(today);
+            user.slots_plays_used = 0;
+        }
+        DAILY_SLOTS_PLAYS.saturating_sub(user.slots_plays_used)
+    }
+
+    pub(super) async fn handle_get_slots_plays(&mut self, who: usize) -> Result<()> {
+        let remaining = self.daily_slots_plays(who);
+        self.conns[who]
+            .write(Packet::PKT_228(remaining as i8))
+            .await
+    }
+
+    /// Have a go on the slot machine, paying with a medal or a free daily play.
+    /// As with the UFO catcher, prizes go to the delivery box and nothing is charged
+    /// unless the prize fits.
+    pub(super) async fn handle_play_slots(&mut self, who: usize, mode: i8) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let reply = |result, outcome, item: Option<CountedItem>| Packet::PKT_214 {
+            item: item.unwrap_or_default(),
+            result,
+            outcome,
+        };
+
+        let can_pay = match mode {
+            SLOTS_PAY_MEDAL => self.conns[who].user.game_center_medals > 0,
+            SLOTS_PAY_DAILY => self.daily_slots_plays(who) > 0,
+            _ => {
+                warn!("{uid} played the slots in unknown mode {mode}");
+                return Ok(());
+            }
+        };
+        if !can_pay {
+            let packet = reply(GAME_CANT_PAY, SLOTS_MISS, None);
+            return self.conns[who].write(packet).await;
+        }
+
+        let prizes = slot_prizes();
+        let (outcome, item) = match self.award_prize(who, &prizes).await? {
+            Some(prize) => (prize.outcome, prize.item),
+            None => {
+                let packet = reply(GAME_BOX_FULL, SLOTS_MISS, None);
+                return self.conns[who].write(packet).await;
+            }
+        };
+
+        let user = &mut self.conns[who].user;
+        match mode {
+            SLOTS_PAY_MEDAL => user.game_center_medals -= 1,
+            _ => user.slots_plays_used += 1,
+        }
+        self.save_user(who).await;
+        info!("🎰 {uid} played the slots and got {item:?}");
+
+        let packet = reply(GAME_OK, outcome, item);
+        self.conns[who].write(packet).await
+    }
+}
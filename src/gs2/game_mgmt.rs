@@ -1,14 +1,25 @@
-use anyhow::{bail, Result};
+use anyhow::Result;
 use log::{error, info, warn};
 use rand::prelude::*;
 
+use crate::data::course;
+use crate::data::drop::DropTier;
+use crate::data::report::GameReport;
 use crate::data::{Item, ItemCategory};
+use crate::stream::Frame;
 use crate::{
     data::CountedItem,
-    packets::{Mode, Packet, Status, CID},
+    packets::{LobbyNum, Mode, Outcome, Packet, RoomNum, ShotClub, Stat, Status, CID},
 };
 
-use super::{lobby_mgmt::Room, GameServer};
+use super::{error::GameError, lobby_mgmt::Room, GameServer};
+
+/// Single-player rounds are played on Southern Country...
+pub(super) const ROUND_COURSE: i8 = 0;
+/// ...in the daytime
+pub(super) const ROUND_SEASON: i8 = 1;
+/// Number of holes in a single-player round
+const SINGLE_HOLES: usize = 3;
 
 fn generate_single_mode_game(cid: CID) -> Packet {
     let mut rng = thread_rng();
@@ -29,9 +40,9 @@ fn generate_single_mode_game(cid: CID) -> Packet {
         time: 0, // unlimited
         member: 1,
         member_max: 1,
-        course: 0, // southern country
-        season: 1, // daytime
-        holes: 3,
+        course: ROUND_COURSE,
+        season: ROUND_SEASON,
+        holes: SINGLE_HOLES as i8,
         hole_no,
         wind_dir,
         wind_pow,
@@ -45,33 +56,43 @@ fn generate_single_mode_game(cid: CID) -> Packet {
     }
 }
 
-fn generate_vs_game(room: &Room) -> Packet {
-    // TODO: actually use all the interesting parameters in the room config
+/// How many players fit into the ORD_GAMESTART arrays
+pub(super) const MAX_GAME_MEMBERS: usize = 50;
+
+pub(super) fn generate_vs_game(mode: Mode, room: &Room) -> Packet {
     // TODO: prefill caddies, ball_array, hold_box with appropriate info from the participants
-    let mut rng = thread_rng();
-    let mut hole_no = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17];
+    let holes = room.holes_in_round() as i8;
     let wind_dir = [0; 18];
     let wind_pow = [0; 18];
     let weather = [0; 18];
     let cup_pos = [0; 18];
-    hole_no.shuffle(&mut rng);
-    hole_no[3..].fill(-1);
 
-    let mut cid_array = [-1; 50];
-    for (index, cid) in room.members.iter().enumerate() {
+    if room.num_players() > MAX_GAME_MEMBERS {
+        warn!(
+            "room has {} players, only the first {MAX_GAME_MEMBERS} can play",
+            room.num_players()
+        );
+    }
+
+    // Watchers aren't part of the game, so only the round's players go in here
+    let mut cid_array = [-1; MAX_GAME_MEMBERS];
+    for (index, cid) in room.players.iter().take(MAX_GAME_MEMBERS).enumerate() {
         cid_array[index] = *cid;
     }
+    let member = room.players.len().min(MAX_GAME_MEMBERS);
+    let member_max = room.max_members.min(MAX_GAME_MEMBERS);
 
     Packet::ORD_GAMESTART {
-        mode: Mode::VS,
-        rule: 0, // strokes
-        time: 0, // unlimited
-        member: room.members.len().try_into().unwrap(),
-        member_max: room.max_members.try_into().unwrap(),
-        course: 0, // southern country
-        season: 1, // daytime
-        holes: 3,
-        hole_no,
+        mode,
+        rule: room.rules,
+        // rooms can't be set up with a negative limit, but no limit is the safe fallback
+        time: u8::try_from(room.time_limit).unwrap_or(0),
+        member: member as i8,
+        member_max: member_max as i8,
+        course: room.course,
+        season: room.season,
+        holes,
+        hole_no: room.hole_order,
         wind_dir,
         wind_pow,
         weather,
@@ -84,6 +105,34 @@ fn generate_vs_game(room: &Room) -> Packet {
     }
 }
 
+/// CLIENT_LOADSTAT value sent once the client has loaded the round
+const LOADSTAT_DONE: i8 = 1;
+/// CLIENT_LOADSTAT2 value sent once the client has loaded the round
+const LOADSTAT2_DONE: i8 = 100;
+
+/// How far a player has got with loading the round
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct LoadState {
+    stat: i8,
+    stat2: i8,
+}
+
+impl LoadState {
+    pub(super) fn is_done(&self) -> bool {
+        self.stat >= LOADSTAT_DONE && self.stat2 >= LOADSTAT2_DONE
+    }
+}
+
+/// Experience given to each of a character's parameters at the end of a round
+fn round_exp(outcome: Outcome) -> i16 {
+    match outcome {
+        Outcome::Win | Outcome::UnearnedWin => 3,
+        Outcome::Draw => 2,
+        Outcome::Lose | Outcome::Conv => 1,
+        Outcome::Aborted | Outcome::Invalid => 0,
+    }
+}
+
 impl GameServer {
     /// Return the list of Carry Items available in single mode
     pub(super) async fn handle_init_single_mode(&self, who: usize) -> Result<()> {
@@ -116,22 +165,38 @@ impl GameServer {
             Mode::Single => {
                 // this is the most basic case
                 let packet = generate_single_mode_game(self.conns[who].cid);
+                self.conns[who].stat.insert(Stat::ROUND);
+                self.send_caddie_list(who).await?;
                 self.conns[who].write(packet).await?;
                 self.conns[who]
                     .write(Packet::ACK_GAMESTART(Status::OK))
                     .await?;
             }
-            Mode::VS => {
+            Mode::VS | Mode::Competition => {
+                if mode == Mode::Competition {
+                    self.start_color_cycle(who).await?;
+                }
+
                 if let Some(room) = self.lobbies.room_mut(mode, lobby_num, room_num) {
-                    let packet = generate_vs_game(room);
+                    room.start_round();
+                    let packet = generate_vs_game(mode, room);
+                    room.round_start = Some(packet.clone());
+                    let members = room.members.clone();
+                    let players = room.players.clone();
 
                     // Tell every player in the room
-                    for cid in &room.members {
-                        let victim = self.conn_lookup[cid];
+                    for cid in members {
+                        let victim = match self.find_conn(cid) {
+                            Some(index) => index,
+                            None => continue,
+                        };
                         self.conns[victim].write(packet.clone()).await?;
+                        if players.contains(&cid) {
+                            self.conns[victim].stat.insert(Stat::ROUND);
+                            self.send_caddie_list(victim).await?;
+                        }
                     }
 
-                    // TODO: send EnableCaddieList here based off logs
                     self.conns[who]
                         .write(Packet::ACK_GAMESTART(Status::OK))
                         .await?;
@@ -139,19 +204,304 @@ impl GameServer {
                     self.conns[who]
                         .write(Packet::ACK_GAMESTART(Status::Err))
                         .await?;
-                    bail!("not in a room");
+                    return Err(GameError::NotInRoom.into());
                 }
             }
             _ => {
-                bail!("unknown game mode: {mode:?}")
+                self.conns[who]
+                    .write(Packet::ACK_GAMESTART(Status::Err))
+                    .await?;
+                return Err(GameError::UnsupportedMode(mode).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tell a player which caddies they can bring into the round: everybody they've
+    /// employed, plus whoever their character has taken on
+    async fn send_caddie_list(&self, who: usize) -> Result<()> {
+        let conn = &self.conns[who];
+        let mut list: Vec<i32> = conn
+            .user
+            .inventory
+            .iter()
+            .map(|ci| ci.item())
+            .filter(|item| item.category() == ItemCategory::Caddy)
+            .map(|item| item.0 as i32)
+            .collect();
+        if let Some(chara) = conn.active_character() {
+            let caddie = chara.caddie.0 as i32;
+            if chara.caddie != Item::default() && !list.contains(&caddie) {
+                list.push(caddie);
+            }
+        }
+
+        let count = list.len() as i32;
+        conn.write(Packet::PKT_231 { count, list }).await
+    }
+
+    /// Handle a player's report at the end of a round.
+    /// Reports that fail the sanity check, or that don't match what we saw of the round,
+    /// still count as a finish, but none of their numbers are kept.
+    pub(super) async fn handle_send_score(&mut self, who: usize, report: GameReport) -> Result<()> {
+        let cid = self.conns[who].cid;
+        let mode = self.conns[who].mode;
+        let lobby_num = self.conns[who].cur_lobby;
+        let room_num = self.conns[who].cur_room;
+
+        let room = match self.lobbies.room_mut(mode, lobby_num, room_num) {
+            Some(room) if room.players.contains(&cid) => room,
+            _ if mode == Mode::Single && self.conns[who].stat.contains(Stat::ROUND) => {
+                // Single mode has no room, so there's nobody else to wait for
+                let outcome = report.outcome;
+                let report = if report.is_plausible(SINGLE_HOLES) {
+                    Some(&report)
+                } else {
+                    warn!("{cid} sent an implausible score report, ignoring it");
+                    None
+                };
+                return self
+                    .settle_player(who, mode, outcome, report, SINGLE_HOLES)
+                    .await;
+            }
+            _ => return Err(GameError::NotInRound.into()),
+        };
+
+        // Compare the client's numbers against the shots and holes we've seen
+        let counted = room.strokes.get(&cid).copied().unwrap_or(0);
+        let awarded = room.round_gp(cid);
+        let trusted = if !report.is_plausible(room.holes_in_round()) {
+            warn!("{cid} sent an implausible score report, ignoring it");
+            false
+        } else if counted != report.num_strokes {
+            warn!(
+                "{cid} reported {} strokes, but we counted {counted}, ignoring it",
+                report.num_strokes
+            );
+            false
+        } else if awarded != report.obtained_gp_round {
+            warn!(
+                "{cid} reported {} GP for the round, but their holes came to {awarded}, ignoring it",
+                report.obtained_gp_round
+            );
+            false
+        } else {
+            true
+        };
+
+        room.outcomes.insert(cid, report.outcome);
+        if trusted {
+            room.scorecard.insert(cid, report);
+        }
+        if room.is_round_over() {
+            self.finish_round(mode, lobby_num, room_num).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Run the end-of-round sequence for a room, once everyone has finished or retired
+    pub(super) async fn finish_round(
+        &mut self,
+        mode: Mode,
+        lobby_num: LobbyNum,
+        room_num: RoomNum,
+    ) -> Result<()> {
+        let room = match self.lobbies.room_mut(mode, lobby_num, room_num) {
+            Some(room) if room.in_round => room,
+            _ => return Ok(()),
+        };
+
+        room.apply_hole_scores();
+        room.settle_outcomes();
+        let hole_order = room.hole_order;
+        let (course, season) = (room.course, room.season);
+        let num_holes = room.holes_in_round();
+        let (outcomes, scorecard) = room.end_round();
+        let members = room.members.clone();
+        let items_allowed = room.items_allowed;
+        info!("🏁 round finished in room {room_num}: {outcomes:?}");
+
+        for (&cid, &outcome) in &outcomes {
+            if let Some(index) = self.find_conn(cid) {
+                if mode == Mode::Quick {
+                    self.settle_quick_rating(index, items_allowed, outcome)
+                        .await?;
+                }
+                self.settle_player(index, mode, outcome, scorecard.get(&cid), num_holes)
+                    .await?;
+            }
+        }
+
+        for (&cid, report) in &scorecard {
+            if let Some(index) = self.find_conn(cid) {
+                self.record_course(index, course, season, &hole_order[..num_holes], report)
+                    .await?;
+            }
+        }
+
+        // Colour rewards depend on how everybody did, so these go out last
+        if mode == Mode::Competition {
+            for (&cid, report) in &scorecard {
+                if let Some(index) = self.find_conn(cid) {
+                    self.record_color_score(index, report.score).await?;
+                }
+            }
+        }
+
+        // Everyone's back in the room, so let them all see that
+        let room_stat = match self.lobbies.room(mode, lobby_num, room_num) {
+            Some(room) => room.make_room_stat(),
+            None => return Ok(()),
+        };
+        let indices: Vec<usize> = members
+            .iter()
+            .filter_map(|&cid| self.find_conn(cid))
+            .collect();
+        for &index in &indices {
+            let ulist = self.conns[index].make_ulist();
+            for &other in &indices {
+                self.conns[other]
+                    .write(Packet::SEND_ULIST(ulist.clone()))
+                    .await?;
+            }
+        }
+        // The lounge loads back in just as it did on entry, and waits for the same ending
+        for &index in &indices {
+            self.conns[index]
+                .write(Packet::ACK_ULIST_R(Status::OK))
+                .await?;
+        }
+        for &index in &indices {
+            self.conns[index]
+                .write(Packet::PKT_30(room_stat.clone()))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Hand out one player's share of a finished round and save it
+    async fn settle_player(
+        &mut self,
+        who: usize,
+        mode: Mode,
+        outcome: Outcome,
+        report: Option<&GameReport>,
+        num_holes: usize,
+    ) -> Result<()> {
+        let conn = &mut self.conns[who];
+        conn.stat.remove(Stat::ROUND);
+        if let Some(report) = report {
+            // In a room, this has already been checked against the GP from each hole
+            conn.user.gp += report.obtained_gp_round as i32;
+        }
+
+        let gain = round_exp(outcome);
+        let grown = match conn.active_character_mut() {
+            Some((chr_uid, chara)) if gain > 0 => {
+                chara.exp.power += gain;
+                chara.exp.control += gain;
+                chara.exp.impact += gain;
+                chara.exp.spin += gain;
+                Some((chr_uid, chara.clone()))
             }
+            _ => None,
+        };
+
+        self.save_user(who).await;
+        if let Some((chr_uid, chara)) = grown {
+            self.db.write_character(chr_uid, chara.clone()).await;
+
+            // TODO: the meaning of these fields is a best guess
+            self.conns[who]
+                .write(Packet::SEND_GROW_PARAM {
+                    a: 0,
+                    master_point: 0,
+                    p0_a: gain,
+                    p1_a: gain,
+                    p2_a: gain,
+                    p3_a: gain,
+                    p0_b: chara.exp.power,
+                    p1_b: chara.exp.control,
+                    p2_b: chara.exp.impact,
+                    p3_b: chara.exp.spin,
+                    caddie_point: 0,
+                    extra_bonus_value: 0,
+                })
+                .await?;
+        }
+
+        self.record_round(who, mode, outcome, report, num_holes)
+            .await?;
+        self.refresh_currencies(who).await
+    }
+
+    /// Handle a player bowing out of a round.
+    /// Players forfeit and drop out of the turn order but stay in the room;
+    /// spectators just leave the gallery.
+    pub(super) async fn handle_retire(&mut self, who: usize) -> Result<()> {
+        let cid = self.conns[who].cid;
+        let mode = self.conns[who].mode;
+        let lobby_num = self.conns[who].cur_lobby;
+        let room_num = self.conns[who].cur_room;
+
+        let room = match self.lobbies.room_mut(mode, lobby_num, room_num) {
+            Some(room) => room,
+            None => return Err(GameError::NotInRoom.into()),
+        };
+
+        let mut notify = room.members.clone();
+        if let Some(index) = room.players.iter().position(|&p| p == cid) {
+            info!("{cid} retired from the round in room {room_num}");
+            room.players.remove(index);
+            room.outcomes.insert(cid, Outcome::Aborted);
+            if room.current_player == cid {
+                room.current_player = -1;
+            }
+            self.conns[who].stat.remove(Stat::ROUND);
+        } else {
+            info!("{cid} left the gallery in room {room_num}");
+            room.members.retain(|&m| m != cid);
+            room.watchers.retain(|&m| m != cid);
+            self.conns[who].stat.remove(Stat::GALLERY);
+            self.conns[who].cur_room = -1;
+        }
+
+        let room_stat = room.make_room_stat();
+        let ulist = self.conns[who].make_ulist();
+
+        // Everyone who was in the room, including the retiree, needs to see the change
+        notify.retain(|&m| m != cid);
+        notify.push(cid);
+        for member in notify {
+            let index = match self.find_conn(member) {
+                Some(index) => index,
+                None => continue,
+            };
+            self.conns[index]
+                .write(Packet::SEND_ULIST(ulist.clone()))
+                .await?;
+            self.conns[index]
+                .write(Packet::PKT_30(room_stat.clone()))
+                .await?;
+        }
+
+        // The retiree might have been the last one out on the course
+        let round_over = self
+            .lobbies
+            .room(mode, lobby_num, room_num)
+            .is_some_and(|room| room.is_round_over());
+        if round_over {
+            self.finish_round(mode, lobby_num, room_num).await?;
         }
 
         Ok(())
     }
 
     /// Sync the "loaded yes/no" flag to the other players in a room
-    pub(super) async fn handle_send_loadstat(&self, who: usize, progress: i8) -> Result<()> {
+    pub(super) async fn handle_send_loadstat(&mut self, who: usize, progress: i8) -> Result<()> {
         let my_cid = self.conns[who].cid;
         let packet = Packet::SEND_LOADSTAT(self.conns[who].cid, progress);
 
@@ -163,7 +513,10 @@ impl GameServer {
         ) {
             for &cid in &room.members {
                 if cid != my_cid {
-                    let victim = *self.conn_lookup.get(&cid).unwrap();
+                    let victim = match self.find_conn(cid) {
+                        Some(index) => index,
+                        None => continue,
+                    };
                     self.conns[victim].write(packet.clone()).await?;
                 }
             }
@@ -171,11 +524,12 @@ impl GameServer {
             warn!("received LoadStat for someone who isn't in a room");
         }
 
-        Ok(())
+        self.update_load_state(who, |state| state.stat = progress)
+            .await
     }
 
     /// Sync detailed info about loading progress to the other players in a room
-    pub(super) async fn handle_send_loadstat2(&self, who: usize, progress: i8) -> Result<()> {
+    pub(super) async fn handle_send_loadstat2(&mut self, who: usize, progress: i8) -> Result<()> {
         let my_cid = self.conns[who].cid;
         let packet = Packet::SEND_LOADSTAT2(self.conns[who].cid, progress);
 
@@ -187,7 +541,10 @@ impl GameServer {
         ) {
             for &cid in &room.members {
                 if cid != my_cid {
-                    let victim = *self.conn_lookup.get(&cid).unwrap();
+                    let victim = match self.find_conn(cid) {
+                        Some(index) => index,
+                        None => continue,
+                    };
                     self.conns[victim].write(packet.clone()).await?;
                 }
             }
@@ -195,6 +552,68 @@ impl GameServer {
             warn!("received LoadStat2 for someone who isn't in a room");
         }
 
+        self.update_load_state(who, |state| state.stat2 = progress)
+            .await
+    }
+
+    /// Note how far a player has got with loading, and get the round going once everyone's ready
+    async fn update_load_state<F: FnOnce(&mut LoadState)>(
+        &mut self,
+        who: usize,
+        f: F,
+    ) -> Result<()> {
+        let cid = self.conns[who].cid;
+        let conn = &self.conns[who];
+        let room = match self
+            .lobbies
+            .room_mut(conn.mode, conn.cur_lobby, conn.cur_room)
+        {
+            Some(room) => room,
+            None => return Ok(()),
+        };
+
+        if let Some(state) = room.loading.get_mut(&cid) {
+            f(state);
+        }
+        if !room.finished_loading() {
+            return Ok(());
+        }
+
+        let members = room.members.clone();
+        self.send_start_clock(&members).await
+    }
+
+    /// Stop waiting for a player who has gone away while the round was loading
+    pub(super) async fn abandon_loading(&mut self, who: usize) -> Result<()> {
+        let cid = self.conns[who].cid;
+        let conn = &self.conns[who];
+        let room = match self
+            .lobbies
+            .room_mut(conn.mode, conn.cur_lobby, conn.cur_room)
+        {
+            Some(room) => room,
+            None => return Ok(()),
+        };
+
+        if room.loading.remove(&cid).is_none() || !room.finished_loading() {
+            return Ok(());
+        }
+
+        let members: Vec<CID> = room.members.iter().copied().filter(|&m| m != cid).collect();
+        self.send_start_clock(&members).await
+    }
+
+    /// Everybody has loaded in, so start the round's clock
+    async fn send_start_clock(&self, members: &[CID]) -> Result<()> {
+        info!("⏱️ everyone has loaded, starting the clock for {members:?}");
+        for &cid in members {
+            if let Some(index) = self.find_conn(cid) {
+                self.conns[index]
+                    .write(Packet::REP_CLOCK { timer: 0, unk: 0 })
+                    .await?;
+            }
+        }
+
         Ok(())
     }
 
@@ -228,13 +647,26 @@ impl GameServer {
         hit_y: i8,
         club: i8,
     ) -> Result<()> {
+        let cid = self.conns[who].cid;
+        let shot = ShotClub::decode(club);
+        if shot.is_none() {
+            warn!("{cid} sent a shot with an invalid club value: {club:#x}");
+        }
+
         // Keep track of who made this shot
         if let Some(room) = self.lobbies.room_mut(
             self.conns[who].mode,
             self.conns[who].cur_lobby,
             self.conns[who].cur_room,
         ) {
-            room.current_player = self.conns[who].cid;
+            room.current_player = cid;
+
+            if let Some(shot) = shot {
+                // No ball is going to land, so nothing else will move the turn on
+                if room.count_shot(cid, shot) {
+                    room.current_player = room.next_player(cid);
+                }
+            }
         }
 
         let packet = Packet::SEND_SHOT {
@@ -250,9 +682,91 @@ impl GameServer {
         self.send_packet_to_roommates(who, packet).await
     }
 
+    /// Check the score a player reports when they finish a hole, and let the rest of
+    /// the room know about it.
+    /// This handles both cup-in and holeout, which report the same hole.
+    pub(super) async fn handle_holeout(
+        &mut self,
+        who: usize,
+        hole: i8,
+        score: i8,
+        gp: i16,
+    ) -> Result<()> {
+        let cid = self.conns[who].cid;
+        let conn = &self.conns[who];
+        let (earns_drop, score, gp) =
+            match self
+                .lobbies
+                .room_mut(conn.mode, conn.cur_lobby, conn.cur_room)
+            {
+                Some(room) if room.players.contains(&cid) => {
+                    if !(0..room.holes_in_round() as i8).contains(&hole) {
+                        warn!("{cid} finished hole {hole}, which isn't part of this round");
+                        return Ok(());
+                    }
+                    // Only roll once per hole, whichever of cup-in and holeout arrives
+                    // first, and only for a hole we've actually seen them play
+                    let earns_drop =
+                        !room.has_finished_hole(cid, hole) && room.has_shots_on_hole(cid);
+                    (
+                        earns_drop,
+                        room.record_hole_score(cid, hole, score),
+                        room.record_hole_gp(cid, hole, gp),
+                    )
+                }
+                _ => {
+                    warn!("{cid} finished hole {hole} without being in a round");
+                    return Ok(());
+                }
+            };
+
+        let packet = Packet::SEND_HOLEOUT {
+            cid,
+            hole,
+            score,
+            gp,
+        };
+        self.send_packet_to_roommates(who, packet).await?;
+
+        if earns_drop {
+            self.roll_hole_drop(who, score).await?;
+        }
+        Ok(())
+    }
+
+    /// Give a player the chance of winning an item for finishing a hole
+    async fn roll_hole_drop(&mut self, who: usize, strokes: i8) -> Result<()> {
+        let tier = DropTier::from_strokes(strokes);
+        let counted_item = match self.config.drops.roll(tier, &mut self.rng) {
+            Some(counted_item) => counted_item,
+            None => return Ok(()),
+        };
+
+        let item = counted_item.item();
+        let maximum = self.config.shop.maximum(item.category());
+        let conn = &mut self.conns[who];
+        let cid = conn.cid;
+        if conn.user.item_amount(item) + counted_item.count() > maximum {
+            info!("{cid} won {counted_item:?}, but can't hold any more");
+            return Ok(());
+        }
+        info!("🎲 {cid} won {counted_item:?} for a {tier:?} hole");
+        conn.user.add_item(counted_item);
+        self.save_user(who).await;
+
+        let mut items = [CountedItem::default(); 10];
+        items[0] = counted_item;
+        self.conns[who].write(Packet::SEND_DROPITEM(items)).await?;
+
+        // TODO: find out what val means; everyone in the room gets to see the drop
+        let packet = Packet::REP_ITEMDROP { cid, val: 1 };
+        self.conns[who].write(packet.clone()).await?;
+        self.send_packet_to_roommates(who, packet).await
+    }
+
     /// Sync the ball position to the other players in a room
     pub(super) async fn handle_ballpos(
-        &self,
+        &mut self,
         who: usize,
         hole: i8,
         stat: i8,
@@ -268,12 +782,72 @@ impl GameServer {
             y,
             z,
         };
+        self.remember_ball_position(who, &packet);
+        self.send_packet_to_roommates(who, packet).await
+    }
+
+    /// Keep hold of where a player's ball last was, for watchers who arrive later
+    fn remember_ball_position(&mut self, who: usize, packet: &Packet) {
+        let conn = &self.conns[who];
+        if let Some(room) = self
+            .lobbies
+            .room_mut(conn.mode, conn.cur_lobby, conn.cur_room)
+        {
+            if room.in_round {
+                room.ball_positions.insert(conn.cid, packet.clone());
+            }
+        }
+    }
+
+    /// Sync a player's character position to the others in a room.
+    /// `facing` is the float the client sends alongside the position, which looks to be
+    /// the direction the character is turned; it's relayed untouched.
+    pub(super) async fn handle_chrpos(
+        &mut self,
+        who: usize,
+        facing: f32,
+        x: f32,
+        y: f32,
+        z: f32,
+    ) -> Result<()> {
+        let conn = &self.conns[who];
+        let (x, y, z) = match course::clamp_position(x, y, z) {
+            Some(pos) if facing.is_finite() => pos,
+            _ => {
+                warn!(
+                    "{} sent a broken character position: {facing} at ({x}, {y}, {z})",
+                    conn.uid
+                );
+                return Ok(());
+            }
+        };
+
+        let room = match self
+            .lobbies
+            .room_mut(conn.mode, conn.cur_lobby, conn.cur_room)
+        {
+            Some(room) => room,
+            None => return Err(GameError::NotInRoom.into()),
+        };
+        // Nobody is on the course outside a round, so there's nothing to show
+        if !room.in_round {
+            return Ok(());
+        }
+
+        let packet = Packet::SEND_CHRPOS {
+            cid: conn.cid,
+            unk: facing,
+            x,
+            y,
+            z,
+        };
+        room.chr_positions.insert(conn.cid, packet.clone());
         self.send_packet_to_roommates(who, packet).await
     }
 
     /// Sync the ball stop position to the players in a room
     pub(super) async fn handle_stop_ballpos(
-        &self,
+        &mut self,
         who: usize,
         hole: i8,
         stat: i8,
@@ -302,6 +876,7 @@ impl GameServer {
             y,
             z,
         };
+        self.remember_ball_position(who, &packet);
         // The client expects to *receive* it too, it seems
         self.conns[who].write(packet.clone()).await?;
         self.send_packet_to_roommates(who, packet).await
@@ -331,24 +906,33 @@ impl GameServer {
         self.send_packet_to_roommates(who, packet).await
     }
 
-    async fn send_packet_to_roommates(&self, who: usize, packet: Packet) -> Result<()> {
+    pub(super) async fn send_packet_to_roommates(&self, who: usize, packet: Packet) -> Result<()> {
         let my_cid = self.conns[who].cid;
-
-        if let Some(room) = self.lobbies.room(
+        let room = match self.lobbies.room(
             self.conns[who].mode,
             self.conns[who].cur_lobby,
             self.conns[who].cur_room,
         ) {
-            for &cid in &room.members {
-                if cid != my_cid {
-                    let victim = *self.conn_lookup.get(&cid).unwrap();
-                    self.conns[victim].write(packet.clone()).await?;
-                }
-            }
+            Some(room) => room,
+            None => return Err(GameError::NotInRoom.into()),
+        };
 
-            Ok(())
-        } else {
-            bail!("user is not in a room!")
+        let victims: Vec<usize> = room
+            .members
+            .iter()
+            .filter(|&&cid| cid != my_cid)
+            .filter_map(|&cid| self.find_conn(cid))
+            .collect();
+        if victims.is_empty() {
+            return Ok(());
+        }
+
+        // These go out all the time during a round, so only serialise them once
+        let frame = Frame::new(packet)?;
+        for victim in victims {
+            self.conns[victim].write_frame(&frame).await?;
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::data::{CountedItem, Item};
+use crate::packets::Packet;
+
+use super::GameServer;
+
+/// New count reported when an item couldn't be used, so the client re-syncs
+const USE_FAILED: i32 = -1;
+
+impl GameServer {
+    /// Take one of an item out of the player's inventory, returning how many are left
+    async fn use_up_item(&mut self, who: usize, item: Item) -> Option<i32> {
+        let user = &mut self.conns[who].user;
+        if !user.remove_item(CountedItem::new(item, 1)) {
+            return None;
+        }
+        let left = user.item_amount(item) as i32;
+        self.save_user(who).await;
+        Some(left)
+    }
+
+    /// Use a consumable partway through a round, letting the rest of the room see it.
+    /// TODO: find out what the flag means
+    pub(super) async fn handle_use_item(&mut self, who: usize, item: Item, flag: i8) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let conn = &self.conns[who];
+        let playing = self
+            .lobbies
+            .room(conn.mode, conn.cur_lobby, conn.cur_room)
+            .is_some_and(|room| room.in_round && room.players.contains(&conn.cid));
+
+        // Items are only any use to somebody playing out on the course
+        let left = if playing {
+            self.use_up_item(who, item).await
+        } else {
+            None
+        };
+        let left = match left {
+            Some(left) => left,
+            None => {
+                warn!("{uid} couldn't use {item:?} (flag {flag})");
+                let packet = Packet::ACK_USE_ITEM(item, USE_FAILED);
+                return self.conns[who].write(packet).await;
+            }
+        };
+
+        info!("{uid} used {item:?}, {left} left");
+        self.conns[who]
+            .write(Packet::ACK_USE_ITEM(item, left))
+            .await?;
+        let cid = self.conns[who].cid;
+        self.send_packet_to_roommates(who, Packet::REP_USE_ITEM(cid, item, left))
+            .await
+    }
+
+    /// Use a hold item, which only matters to the player using it
+    pub(super) async fn handle_use_hold_item(&mut self, who: usize, item: Item) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let new_count = match self.use_up_item(who, item).await {
+            Some(left) => {
+                info!("{uid} used hold item {item:?}, {left} left");
+                left
+            }
+            None => {
+                warn!("{uid} tried to use hold item {item:?}, which they don't have");
+                USE_FAILED
+            }
+        };
+        self.conns[who]
+            .write(Packet::ACK_USE_HOLDITEM {
+                item_id: item,
+                new_count,
+            })
+            .await
+    }
+}
@@ -1,10 +1,29 @@
-use anyhow::{bail, Result};
-use log::error;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{error, info, warn};
+use rand::prelude::*;
 use thiserror::Error;
 
-use crate::packets::{LobbyData, LobbyNum, Mode, Packet, Packet19, RoomNum, RoomStat, Status, CID};
+use crate::data::{report::GameReport, Rank};
+use crate::packets::{
+    Element, LobbyData, LobbyNum, Mode, Outcome, Packet, Packet19, RoomNum, RoomStat, ShotClub,
+    Stat, Status, CID, UID,
+};
+
+use super::{
+    color_mgmt::ColorCycle,
+    error::GameError,
+    game_mgmt::{LoadState, MAX_GAME_MEMBERS},
+    GameServer,
+};
 
-use super::GameServer;
+/// Most strokes a player can add to a hole on top of the shots they took, e.g. for OB
+const MAX_PENALTY_STROKES: i8 = 2;
+
+/// Highest team a player can pick; the ulists only have a single bit for it
+const MAX_TEAM: i8 = 1;
 
 #[derive(Error, Debug)]
 enum EnterRoomError {
@@ -14,15 +33,87 @@ enum EnterRoomError {
     RoomNotFound,
     #[error("room is full")]
     RoomIsFull,
+    #[error("room does not allow spectators")]
+    NoSpectators,
+    #[error("room is in the middle of a round")]
+    RoundInProgress,
     #[error("incorrect password specified")]
     WrongPassword,
+    #[error("player's rank is outside the room's limits")]
+    RankRestricted,
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+/// Whether somebody is entering a room to play, or just to watch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum EntryIntent {
+    Player,
+    Watcher,
+}
+
+/// How long a player has to come back before we forget where they were
+pub(super) const SESSION_INTENT_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Where a player was (or should be) when they next arrive on this server.
+/// This covers both reconnecting after a dropped connection, and arriving after a RANKJUMP.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct SessionIntent {
+    mode: Mode,
+    lobby: LobbyNum,
+    room: RoomNum,
+    entry: EntryIntent,
+    team: i8,
+    pub(super) recorded: Instant,
+}
+
+impl SessionIntent {
+    fn is_expired(&self) -> bool {
+        self.recorded.elapsed() >= SESSION_INTENT_TTL
+    }
+}
+
+/// Who a competition room lets in to play, from the `limit_b_*` fields of its RoomStat.
+/// TODO: this layout is unverified. Our best reading of those fields is two rank bounds,
+/// each an enable bit followed by a 7-bit rank, with a 4-bit team count in between.
+/// The values are kept as sent so that they go back out to clients unchanged.
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct RankLimits {
+    ceiling_enabled: bool,
+    /// Highest rank allowed to play
+    rank_ceiling: u8,
+    /// How many teams the room is split into; not enforced yet
+    team_count: u8,
+    floor_enabled: bool,
+    /// Lowest rank allowed to play
+    rank_floor: u8,
+}
+
+impl RankLimits {
+    fn from_room_stat(stat: &RoomStat) -> RankLimits {
+        RankLimits {
+            ceiling_enabled: stat.limit_b_0 != 0,
+            rank_ceiling: stat.limit_b_1,
+            team_count: stat.limit_b_2,
+            floor_enabled: stat.limit_b_3 != 0,
+            rank_floor: stat.limit_b_4,
+        }
+    }
+
+    /// Check whether somebody of this rank may play in the room.
+    /// This relies on the unverified reading of the limit fields described above.
+    pub(super) fn allows(&self, rank: Rank) -> bool {
+        let rank = rank as u8;
+        !(self.ceiling_enabled && rank > self.rank_ceiling
+            || self.floor_enabled && rank < self.rank_floor)
+    }
+}
+
 pub(super) struct Lobbies {
     vs_lobbies: Vec<Lobby>,
     compe_lobbies: Vec<Lobby>,
+    // Players don't pick this themselves; it holds the rooms for quick matches
+    quick_lobbies: Vec<Lobby>,
 }
 
 impl Lobbies {
@@ -30,6 +121,7 @@ impl Lobbies {
         match mode {
             Mode::VS => Some(&self.vs_lobbies),
             Mode::Competition => Some(&self.compe_lobbies),
+            Mode::Quick => Some(&self.quick_lobbies),
             _ => None,
         }
     }
@@ -38,6 +130,7 @@ impl Lobbies {
         match mode {
             Mode::VS => Some(&mut self.vs_lobbies),
             Mode::Competition => Some(&mut self.compe_lobbies),
+            Mode::Quick => Some(&mut self.quick_lobbies),
             _ => None,
         }
     }
@@ -60,12 +153,76 @@ impl Lobbies {
         }
     }
 
+    /// The lobbies players can see and pick from in a mode.
+    /// Quick match rooms are handed out by the matchmaker, so its lobby stays hidden.
+    fn listed_lobbies(&self, mode: Mode) -> Option<&Vec<Lobby>> {
+        match mode {
+            Mode::Quick => None,
+            _ => self.lobbies(mode),
+        }
+    }
+
+    fn listed_lobby(&self, mode: Mode, num: LobbyNum) -> Option<&Lobby> {
+        self.listed_lobbies(mode)?.get(usize::try_from(num).ok()?)
+    }
+
+    /// Put a player into a lobby on the server's behalf, if they aren't there already
+    pub(super) fn add_lobby_member(&mut self, mode: Mode, num: LobbyNum, cid: CID) -> bool {
+        match self.lobby_mut(mode, num) {
+            Some(lobby) => {
+                if !lobby.members.contains(&cid) {
+                    lobby.members.push(cid);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Everyone in a lobby, whether or not they're in one of its rooms
+    pub(super) fn lobby_members(&self, mode: Mode, num: LobbyNum) -> Option<&[CID]> {
+        self.lobby(mode, num).map(|lobby| lobby.members.as_slice())
+    }
+
+    /// Every room in a mode, along with the number of the lobby it lives in
+    pub(super) fn rooms(&self, mode: Mode) -> impl Iterator<Item = (LobbyNum, &Room)> {
+        self.lobbies(mode)
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .flat_map(|(num, lobby)| lobby.rooms.iter().map(move |room| (num as LobbyNum, room)))
+    }
+
+    /// Create a room on the server's behalf, returning its number
+    pub(super) fn create_room(
+        &mut self,
+        mode: Mode,
+        lobby_num: LobbyNum,
+        data: Packet19,
+        members: Vec<CID>,
+    ) -> Option<RoomNum> {
+        let lobby = self.lobby_mut(mode, lobby_num)?;
+        let room_num = lobby.pick_free_room_num()?;
+        lobby.add_room(room_num, data, members);
+        Some(room_num)
+    }
+
+    /// Close a room, handing back what was in it
+    pub(super) fn remove_room(
+        &mut self,
+        mode: Mode,
+        lobby_num: LobbyNum,
+        room_num: RoomNum,
+    ) -> Option<Room> {
+        let lobby = self.lobby_mut(mode, lobby_num)?;
+        let index = lobby.room_index(room_num)?;
+        Some(lobby.rooms.remove(index))
+    }
+
     pub(super) fn room(&self, mode: Mode, lobby_num: LobbyNum, room_num: RoomNum) -> Option<&Room> {
         let lobby = self.lobby(mode, lobby_num)?;
-        match lobby.rooms.binary_search_by_key(&room_num, |r| r.room_num) {
-            Ok(index) => Some(&lobby.rooms[index]),
-            Err(_) => None,
-        }
+        let index = lobby.room_index(room_num)?;
+        Some(&lobby.rooms[index])
     }
 
     pub(super) fn room_mut(
@@ -75,10 +232,8 @@ impl Lobbies {
         room_num: RoomNum,
     ) -> Option<&mut Room> {
         let lobby = self.lobby_mut(mode, lobby_num)?;
-        match lobby.rooms.binary_search_by_key(&room_num, |r| r.room_num) {
-            Ok(index) => Some(&mut lobby.rooms[index]),
-            Err(_) => None,
-        }
+        let index = lobby.room_index(room_num)?;
+        Some(&mut lobby.rooms[index])
     }
 }
 
@@ -91,8 +246,13 @@ pub(super) struct Lobby {
 
 pub(super) struct Room {
     pub(super) room_num: RoomNum,
+    /// Everyone in the room, including watchers
     pub(super) members: Vec<CID>,
+    /// Members who are only here to watch; they don't count towards max_members
+    pub(super) watchers: Vec<CID>,
     pub(super) max_members: usize,
+    /// The member in charge of the room, who can kick others and hand it over
+    pub(super) owner: CID,
     pub(super) name: String,
     pub(super) password: Option<String>,
     pub(super) allow_spectators: bool,
@@ -110,12 +270,37 @@ pub(super) struct Room {
     pub(super) limit_5: u8,
     pub(super) limit_6: u8,
     pub(super) limit_7: u8,
-    pub(super) limit_b_0: u8,
-    pub(super) limit_b_1: u8,
-    pub(super) limit_b_2: u8,
-    pub(super) limit_b_3: u8,
-    pub(super) limit_b_4: u8,
+    pub(super) rank_limits: RankLimits,
     pub(super) current_player: CID,
+    pub(super) in_round: bool,
+    /// Members who are playing the current round, in turn order.
+    /// Anyone else in the room is watching from the gallery.
+    pub(super) players: Vec<CID>,
+    /// How each player's round ended, if it has
+    pub(super) outcomes: BTreeMap<CID, Outcome>,
+    /// Strokes each player has taken this round, counted from their shots
+    pub(super) strokes: BTreeMap<CID, u32>,
+    /// Strokes each player has taken on the hole they're currently playing
+    hole_strokes: BTreeMap<CID, u32>,
+    /// Validated score for each hole a player has finished, keyed on hole index
+    pub(super) hole_scores: BTreeMap<CID, BTreeMap<i8, i8>>,
+    /// GP each player has been awarded for the holes they've finished, keyed on hole index
+    hole_gp: BTreeMap<CID, BTreeMap<i8, i16>>,
+    /// Players who are still loading the round; the clock starts once this empties
+    pub(super) loading: BTreeMap<CID, LoadState>,
+    /// Reports sent in by players who have finished this round
+    pub(super) scorecard: BTreeMap<CID, GameReport>,
+    /// Which of the course's holes are played this round, in order, padded out with -1
+    pub(super) hole_order: [i8; 18],
+    /// The ORD_GAMESTART for the current round, for anyone who turns up partway through
+    pub(super) round_start: Option<Packet>,
+    /// Latest SEND_BALLPOS or SEND_STOP_BALLPOS for each player this round
+    pub(super) ball_positions: BTreeMap<CID, Packet>,
+    /// Latest SEND_CHRPOS for each player this round
+    pub(super) chr_positions: BTreeMap<CID, Packet>,
+    pub(super) color_cycle: ColorCycle,
+    /// Whether this quick match is played with items, which decides the rating it counts towards
+    pub(super) items_allowed: bool,
 }
 
 impl Lobby {
@@ -130,6 +315,17 @@ impl Lobby {
         }
     }
 
+    /// Find where a room is in the list, which must be kept sorted by number
+    fn room_index(&self, room_num: RoomNum) -> Option<usize> {
+        debug_assert!(
+            self.rooms.windows(2).all(|w| w[0].room_num < w[1].room_num),
+            "rooms are out of order"
+        );
+        self.rooms
+            .binary_search_by_key(&room_num, |r| r.room_num)
+            .ok()
+    }
+
     fn pick_free_room_num(&self) -> Option<RoomNum> {
         // assume that our list of rooms is sorted by number!
 
@@ -151,6 +347,31 @@ impl Lobby {
         // if we got here, then 'candidate' should be free
         Some(candidate)
     }
+
+    /// Throw away the round state of every room that isn't playing, bringing everyone
+    /// in them back to the lounge.
+    /// Returns the members of rooms that are still mid-round, so they can be left alone.
+    fn return_to_lounge(&mut self) -> Vec<CID> {
+        let mut busy = Vec::new();
+        for room in &mut self.rooms {
+            if room.in_round {
+                busy.extend(&room.members);
+                continue;
+            }
+            room.end_round();
+            room.color_cycle.reset();
+        }
+        busy
+    }
+
+    fn add_room(&mut self, room_num: RoomNum, data: Packet19, members: Vec<CID>) {
+        let mut room = Room::new(room_num, data);
+        room.owner = members.first().copied().unwrap_or(-1);
+        room.members = members;
+
+        self.rooms.push(room);
+        self.rooms.sort_by_key(|l| l.room_num);
+    }
 }
 
 impl Room {
@@ -164,7 +385,9 @@ impl Room {
         Room {
             room_num,
             members: Vec::new(),
+            watchers: Vec::new(),
             max_members: data.room_stat.member_max as usize,
+            owner: -1,
             name: data.room_name.to_string(),
             password,
             allow_spectators: (data.room_stat.flag & 2) != 0,
@@ -182,16 +405,323 @@ impl Room {
             limit_5: data.room_stat.limit_5,
             limit_6: data.room_stat.limit_6,
             limit_7: data.room_stat.limit_7,
-            limit_b_0: data.room_stat.limit_b_0,
-            limit_b_1: data.room_stat.limit_b_1,
-            limit_b_2: data.room_stat.limit_b_2,
-            limit_b_3: data.room_stat.limit_b_3,
-            limit_b_4: data.room_stat.limit_b_4,
+            rank_limits: RankLimits::from_room_stat(&data.room_stat),
             current_player: -1,
+            in_round: false,
+            players: Vec::new(),
+            outcomes: BTreeMap::new(),
+            strokes: BTreeMap::new(),
+            hole_strokes: BTreeMap::new(),
+            hole_scores: BTreeMap::new(),
+            hole_gp: BTreeMap::new(),
+            loading: BTreeMap::new(),
+            scorecard: BTreeMap::new(),
+            hole_order: [-1; 18],
+            round_start: None,
+            ball_positions: BTreeMap::new(),
+            chr_positions: BTreeMap::new(),
+            color_cycle: ColorCycle::default(),
+            items_allowed: false,
         }
     }
 
-    fn make_room_stat(&self) -> RoomStat {
+    /// How many members are here to play
+    pub(super) fn num_players(&self) -> usize {
+        self.members.len() - self.watchers.len()
+    }
+
+    /// Work out whose turn comes after this player's
+    pub(super) fn next_player(&self, cid: CID) -> CID {
+        match self.players.iter().position(|&p| p == cid) {
+            Some(index) => self.players[(index + 1) % self.players.len()],
+            None => -1,
+        }
+    }
+
+    /// Take somebody out of the room.
+    /// If they were playing, they drop out of the turn order with an aborted round,
+    /// and if it was their turn it passes to whoever is next.
+    /// If they owned the room, whoever has been here longest takes over, and this
+    /// returns true.
+    pub(super) fn remove_member(&mut self, cid: CID) -> bool {
+        if self.current_player == cid {
+            let next = self.next_player(cid);
+            self.current_player = if next == cid { -1 } else { next };
+        }
+        if let Some(index) = self.players.iter().position(|&p| p == cid) {
+            self.players.remove(index);
+            self.outcomes.entry(cid).or_insert(Outcome::Aborted);
+        }
+
+        self.members.retain(|&m| m != cid);
+        self.watchers.retain(|&m| m != cid);
+        self.loading.remove(&cid);
+        self.ball_positions.remove(&cid);
+        self.chr_positions.remove(&cid);
+
+        if self.owner != cid {
+            return false;
+        }
+        self.owner = self.members.first().copied().unwrap_or(-1);
+        true
+    }
+
+    /// Lock in who is playing this round
+    pub(super) fn start_round(&mut self) {
+        self.in_round = true;
+        self.players = self
+            .members
+            .iter()
+            .filter(|cid| !self.watchers.contains(cid))
+            .take(MAX_GAME_MEMBERS)
+            .copied()
+            .collect();
+        self.loading = self
+            .players
+            .iter()
+            .map(|&cid| (cid, LoadState::default()))
+            .collect();
+        self.outcomes.clear();
+        self.strokes.clear();
+        self.hole_strokes.clear();
+        self.hole_scores.clear();
+        self.hole_gp.clear();
+        self.scorecard.clear();
+        self.ball_positions.clear();
+        self.chr_positions.clear();
+        self.current_player = -1;
+
+        let mut hole_order = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17];
+        hole_order.shuffle(&mut thread_rng());
+        hole_order[self.holes_in_round()..].fill(-1);
+        self.hole_order = hole_order;
+    }
+
+    /// How many holes the current round is played over
+    pub(super) fn holes_in_round(&self) -> usize {
+        self.num_holes.clamp(1, 18) as usize
+    }
+
+    /// Count a shot that we've seen a player take.
+    /// Returns true if the turn should pass on, as no ball is going to land.
+    pub(super) fn count_shot(&mut self, cid: CID, shot: ShotClub) -> bool {
+        *self.strokes.entry(cid).or_default() += 1;
+        *self.hole_strokes.entry(cid).or_default() += 1;
+        matches!(shot, ShotClub::Timeout | ShotClub::Whiff)
+    }
+
+    /// Check whether we've seen a player take any shots on the hole they're playing
+    pub(super) fn has_shots_on_hole(&self, cid: CID) -> bool {
+        self.hole_strokes.get(&cid).is_some_and(|&n| n > 0)
+    }
+
+    /// Check whether a player has already reported finishing a hole
+    pub(super) fn has_finished_hole(&self, cid: CID, hole: i8) -> bool {
+        self.hole_scores
+            .get(&cid)
+            .is_some_and(|holes| holes.contains_key(&hole))
+    }
+
+    /// Check a player's reported score for a hole against the shots we saw them take,
+    /// and start counting afresh for the next hole.
+    /// The score is assumed to be the number of strokes on the hole, so it can't be
+    /// lower than what we counted, and can only exceed it by a few penalty strokes.
+    pub(super) fn record_hole_score(&mut self, cid: CID, hole: i8, score: i8) -> i8 {
+        if let Some(&score) = self.hole_scores.get(&cid).and_then(|h| h.get(&hole)) {
+            // Cup-in and holeout both report the same hole
+            return score;
+        }
+
+        let counted = self.hole_strokes.remove(&cid).unwrap_or(0);
+        let lowest = counted.min(i8::MAX as u32) as i8;
+        let highest = lowest.saturating_add(MAX_PENALTY_STROKES);
+        let validated = score.clamp(lowest, highest);
+        if validated != score {
+            warn!("{cid} reported {score} on hole {hole}, but we counted {counted} strokes");
+        }
+
+        self.hole_scores
+            .entry(cid)
+            .or_default()
+            .insert(hole, validated);
+        validated
+    }
+
+    /// Note the GP a player was awarded for a hole, returning what that hole is worth.
+    /// Cup-in doesn't carry any GP, and holeout repeats the hole, so only the most
+    /// we've been told about for each hole counts.
+    pub(super) fn record_hole_gp(&mut self, cid: CID, hole: i8, gp: i16) -> i16 {
+        let hole_gp = self
+            .hole_gp
+            .entry(cid)
+            .or_default()
+            .entry(hole)
+            .or_default();
+        *hole_gp = (*hole_gp).max(gp);
+        *hole_gp
+    }
+
+    /// Total GP a player has been awarded for the holes they've finished this round
+    pub(super) fn round_gp(&self, cid: CID) -> u32 {
+        self.hole_gp
+            .get(&cid)
+            .map(|holes| holes.values().map(|&gp| gp.max(0) as u32).sum())
+            .unwrap_or(0)
+    }
+
+    /// Check whether everybody has now loaded the round.
+    /// This only returns true once, as the wait set is cleared afterwards.
+    pub(super) fn finished_loading(&mut self) -> bool {
+        if self.loading.is_empty() || !self.loading.values().all(|s| s.is_done()) {
+            return false;
+        }
+        self.loading.clear();
+        true
+    }
+
+    /// Check whether every player has either finished or retired
+    pub(super) fn is_round_over(&self) -> bool {
+        self.in_round
+            && self
+                .players
+                .iter()
+                .all(|cid| self.outcomes.contains_key(cid))
+    }
+
+    /// Swap the hole scores on each report for the ones we checked as the holes were
+    /// finished, so that the result and the records only ever go by those
+    pub(super) fn apply_hole_scores(&mut self) {
+        let num_holes = self.holes_in_round();
+        for (cid, report) in &mut self.scorecard {
+            let holes = match self.hole_scores.get(cid) {
+                Some(holes) => holes,
+                None => continue,
+            };
+            for (&hole, &score) in holes {
+                if let Some(hole_report) = report.holes.get_mut(hole as usize) {
+                    hole_report.score = score;
+                    hole_report.is_hole_in_one &= score == 1;
+                }
+            }
+
+            let played = &report.holes[..num_holes.min(report.holes.len())];
+            report.score = played.iter().map(|hole| hole.score as i32).sum::<i32>() as i8;
+            report.num_hole_in_ones =
+                played.iter().filter(|hole| hole.is_hole_in_one).count() as u32;
+        }
+    }
+
+    /// Work out who won, going by the scores on the scorecard.
+    /// A lone player keeps whatever outcome their client reported.
+    pub(super) fn settle_outcomes(&mut self) {
+        if self.scorecard.len() < 2 {
+            // Nobody to beat, but everybody else gave up
+            if self.scorecard.len() == 1 && self.outcomes.len() > 1 {
+                for cid in self.scorecard.keys() {
+                    self.outcomes.insert(*cid, Outcome::UnearnedWin);
+                }
+            }
+            return;
+        }
+
+        let best = self.scorecard.values().map(|r| r.score).min().unwrap_or(0);
+        let num_best = self.scorecard.values().filter(|r| r.score == best).count();
+        for (cid, report) in &self.scorecard {
+            let outcome = match (report.score == best, num_best) {
+                (true, 1) => Outcome::Win,
+                (true, _) => Outcome::Draw,
+                (false, _) => Outcome::Lose,
+            };
+            self.outcomes.insert(*cid, outcome);
+        }
+    }
+
+    /// Wrap up the round, handing back the outcomes and scorecard
+    pub(super) fn end_round(&mut self) -> (BTreeMap<CID, Outcome>, BTreeMap<CID, GameReport>) {
+        self.in_round = false;
+        self.players.clear();
+        self.loading.clear();
+        self.strokes.clear();
+        self.hole_strokes.clear();
+        self.hole_scores.clear();
+        self.hole_gp.clear();
+        self.round_start = None;
+        self.ball_positions.clear();
+        self.chr_positions.clear();
+        self.current_player = -1;
+        let outcomes = std::mem::take(&mut self.outcomes);
+        let scorecard = std::mem::take(&mut self.scorecard);
+        (outcomes, scorecard)
+    }
+
+    /// Everything somebody arriving mid-round needs to see the course as it stands:
+    /// the round itself, where everyone is, and the holes they've finished
+    pub(super) fn catch_up_packets(&self) -> Vec<Packet> {
+        let round_start = match &self.round_start {
+            Some(packet) if self.in_round => packet.clone(),
+            _ => return Vec::new(),
+        };
+
+        let mut packets = vec![round_start];
+        packets.extend(self.chr_positions.values().cloned());
+        packets.extend(self.ball_positions.values().cloned());
+        for (&cid, scores) in &self.hole_scores {
+            let hole_gp = self.hole_gp.get(&cid);
+            for (&hole, &score) in scores {
+                let gp = hole_gp.and_then(|h| h.get(&hole)).copied().unwrap_or(0);
+                packets.push(Packet::SEND_HOLEOUT {
+                    cid,
+                    hole,
+                    score,
+                    gp,
+                });
+            }
+        }
+        packets
+    }
+
+    pub(super) fn make_room_data(&self, mode: Mode, lobby: LobbyNum) -> Result<Packet19> {
+        Ok(Packet19 {
+            mode,
+            lobby,
+            room_name: self.name.parse()?,
+            room_password: match &self.password {
+                Some(p) => p.parse()?,
+                None => "".parse()?,
+            },
+            room_stat: self.make_room_stat(),
+        })
+    }
+
+    /// Take on the settings an owner has picked, keeping room for everyone already here
+    fn apply_settings(&mut self, data: &Packet19, max_members: usize) {
+        let stat = &data.room_stat;
+        self.name = data.room_name.to_string();
+        self.password = if (stat.flag & 4) != 0 {
+            Some(data.room_password.to_string())
+        } else {
+            None
+        };
+        self.allow_spectators = (stat.flag & 2) != 0;
+        self.max_members = max_members.max(self.num_players());
+        self.rules = stat.rules;
+        self.course = stat.course;
+        self.season = stat.season;
+        self.time_limit = stat.time_limit;
+        self.num_holes = stat.num_holes;
+        self.course_setting = stat.course_setting;
+        self.limit_0 = stat.limit_0;
+        self.limit_1 = stat.limit_1;
+        self.limit_2 = stat.limit_2;
+        self.limit_3 = stat.limit_3;
+        self.limit_4 = stat.limit_4;
+        self.limit_5 = stat.limit_5;
+        self.limit_6 = stat.limit_6;
+        self.limit_7 = stat.limit_7;
+        self.rank_limits = RankLimits::from_room_stat(stat);
+    }
+
+    pub(super) fn make_room_stat(&self) -> RoomStat {
         // TODO: add flag 1 here, "in round"?
         let flag =
             if self.allow_spectators { 2 } else { 0 } | if self.password.is_some() { 4 } else { 0 };
@@ -200,8 +730,8 @@ impl Room {
             room: self.room_num,
             flag,
             member_max: self.max_members.try_into().unwrap(),
-            member: self.members.len().try_into().unwrap(),
-            watcher: 0, // i think this is a count?
+            member: self.num_players().try_into().unwrap(),
+            watcher: self.watchers.len().try_into().unwrap(),
             rules: self.rules,
             time_limit: self.time_limit,
             course: self.course,
@@ -216,22 +746,32 @@ impl Room {
             limit_5: self.limit_5,
             limit_6: self.limit_6,
             limit_7: self.limit_7,
-            limit_b_0: self.limit_b_0,
-            limit_b_1: self.limit_b_1,
-            limit_b_2: self.limit_b_2,
-            limit_b_3: self.limit_b_3,
-            limit_b_4: self.limit_b_4,
+            limit_b_0: self.rank_limits.ceiling_enabled as u8,
+            limit_b_1: self.rank_limits.rank_ceiling,
+            limit_b_2: self.rank_limits.team_count,
+            limit_b_3: self.rank_limits.floor_enabled as u8,
+            limit_b_4: self.rank_limits.rank_floor,
         }
     }
 }
 
 impl GameServer {
     pub(super) async fn handle_get_lobby_num(&self, who: usize) -> Result<()> {
-        if let Some(count) = self.lobbies.lobbies(self.conns[who].mode).map(Vec::len) {
-            let packet = Packet::SEND_LOBBY_NUM(count.try_into()?);
-            self.conns[who].write(packet).await?;
+        match self
+            .lobbies
+            .listed_lobbies(self.conns[who].mode)
+            .map(Vec::len)
+        {
+            Some(count) => {
+                let packet = Packet::SEND_LOBBY_NUM(count.try_into()?);
+                self.conns[who].write(packet).await
+            }
+            None => {
+                // Better an empty lobby list than a client waiting forever
+                self.conns[who].write(Packet::SEND_LOBBY_NUM(0)).await?;
+                Err(GameError::WrongMode.into())
+            }
         }
-        Ok(())
     }
 
     pub(super) async fn handle_get_lobby_data(
@@ -241,7 +781,7 @@ impl GameServer {
         num: LobbyNum,
         mode: Mode,
     ) -> Result<()> {
-        if let Some(lobby) = self.lobbies.lobby(mode, num) {
+        if let Some(lobby) = self.lobbies.listed_lobby(mode, num) {
             let data = lobby.make_lobby_data(mode, num);
             self.conns[who]
                 .write_with_pid(Packet::SEND_LOBBY_DATA(data), pid)
@@ -251,21 +791,40 @@ impl GameServer {
         Ok(())
     }
 
-    pub(super) async fn handle_enter_lobby(&mut self, who: usize, num: LobbyNum) -> Result<()> {
+    /// Make sure a player is able to enter a lobby
+    fn check_enter_lobby(&self, who: usize, num: LobbyNum) -> Result<(), GameError> {
         if self.conns[who].cur_lobby >= 0 {
-            bail!("trying to join lobby {num} while already in a lobby!");
+            return Err(GameError::AlreadyInLobby);
         }
 
-        let lobby = match self.lobbies.lobby_mut(self.conns[who].mode, num) {
-            Some(lobby) => lobby,
-            None => bail!("invalid lobby"),
-        };
+        let lobby = self
+            .lobbies
+            .listed_lobby(self.conns[who].mode, num)
+            .ok_or(GameError::InvalidLobby)?;
 
         // is there space?
         if lobby.members.len() >= lobby.max_members {
-            self.conns[who].write(Packet::ACK_ENTER_LOBBY(-1)).await?;
+            return Err(GameError::LobbyFull);
+        }
+
+        Ok(())
+    }
+
+    pub(super) async fn handle_enter_lobby(&mut self, who: usize, num: LobbyNum) -> Result<()> {
+        if num >= 0 && self.conns[who].cur_lobby == num {
+            // We may have already put them here after reconnecting
+            self.conns[who].write(Packet::ACK_ENTER_LOBBY(num)).await?;
             return Ok(());
         }
+        if let Err(e) = self.check_enter_lobby(who, num) {
+            self.conns[who].write(Packet::ACK_ENTER_LOBBY(-1)).await?;
+            return Err(e.into());
+        }
+
+        let lobby = match self.lobbies.lobby_mut(self.conns[who].mode, num) {
+            Some(lobby) => lobby,
+            None => return Err(GameError::InvalidLobby.into()),
+        };
 
         // add this dude
         lobby.members.push(self.conns[who].cid);
@@ -273,11 +832,15 @@ impl GameServer {
         self.conns[who].write(Packet::ACK_ENTER_LOBBY(num)).await?;
 
         // Notify all other users in the lobby
+        let members = lobby.members.clone();
         let ulist_l = self.conns[who].make_ulist_l();
         let my_cid = self.conns[who].cid;
-        for &cid in &lobby.members {
+        for &cid in &members {
             if cid != my_cid {
-                let member_index = *self.conn_lookup.get(&cid).unwrap();
+                let member_index = match self.find_conn(cid) {
+                    Some(index) => index,
+                    None => continue,
+                };
                 self.conns[member_index]
                     .write(Packet::SEND_ULIST_L(ulist_l.clone()))
                     .await?;
@@ -287,26 +850,146 @@ impl GameServer {
         Ok(())
     }
 
-    /// Kick a player out of the lobby that they're in
-    pub(super) async fn eject_from_lobby(&mut self, who: usize) -> Result<()> {
-        let lobby = match self
-            .lobbies
-            .lobby_mut(self.conns[who].mode, self.conns[who].cur_lobby)
-        {
-            Some(lobby) => lobby,
-            None => bail!("invalid lobby"),
+    /// Remember which lobby and room a player is in, so they can be returned there
+    /// if they come back soon enough
+    pub(super) fn record_session_intent(&mut self, who: usize) {
+        // Anybody who never came back has had their chance
+        self.session_intents
+            .retain(|_, intent| !intent.is_expired());
+
+        // Quick match rooms are only ever handed out by the matchmaker
+        let conn = &self.conns[who];
+        if conn.cur_lobby >= 0 && conn.mode != Mode::Quick {
+            let entry = match self.lobbies.room(conn.mode, conn.cur_lobby, conn.cur_room) {
+                Some(room) if room.watchers.contains(&conn.cid) => EntryIntent::Watcher,
+                _ => EntryIntent::Player,
+            };
+            let intent = SessionIntent {
+                mode: conn.mode,
+                lobby: conn.cur_lobby,
+                room: conn.cur_room,
+                entry,
+                team: conn.team,
+                recorded: Instant::now(),
+            };
+            self.session_intents.insert(conn.uid, intent);
+        }
+    }
+
+    /// Check whether a player has somewhere to go back to in a particular mode
+    pub(super) fn has_session_intent(&self, uid: UID, mode: Mode) -> bool {
+        self.session_intents
+            .get(&uid)
+            .is_some_and(|intent| intent.mode == mode && !intent.is_expired())
+    }
+
+    /// Put a returning player back into the lobby and room they were last in, as long
+    /// as they've entered the same mode again. Each intent only gets used once.
+    pub(super) async fn restore_session_intent(&mut self, who: usize, mode: Mode) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let intent = match self.session_intents.get(&uid) {
+            Some(intent) if intent.is_expired() => {
+                self.session_intents.remove(&uid);
+                return Ok(());
+            }
+            Some(intent) if intent.mode == mode => *intent,
+            _ => return Ok(()),
+        };
+        self.session_intents.remove(&uid);
+        info!("restoring {uid} to {intent:?}");
+        self.conns[who].team = intent.team;
+
+        if self.lobbies.lobby(intent.mode, intent.lobby).is_none() {
+            return Ok(());
+        }
+        self.handle_enter_lobby(who, intent.lobby).await?;
+
+        // The room may well have gone away in the meantime, which is fine
+        let password = match self.lobbies.room(intent.mode, intent.lobby, intent.room) {
+            Some(room) => room.password.clone().unwrap_or_default(),
+            None => return Ok(()),
         };
+        if let Err(e) = self
+            ._enter_room_internal(-1, who, intent.room, &password, intent.entry)
+            .await
+        {
+            warn!("couldn't return {uid} to room {}: {e:?}", intent.room);
+        }
+
+        Ok(())
+    }
 
+    /// Pick a team in the VS/Compe lounge
+    pub(super) async fn handle_set_team(&mut self, who: usize, team: i8) -> Result<()> {
         let cid = self.conns[who].cid;
-        let pos = lobby.members.iter().position(|c| *c == cid).unwrap();
-        lobby.members.remove(pos);
+        let mode = self.conns[who].mode;
+        let lobby_num = self.conns[who].cur_lobby;
+        if !(0..=MAX_TEAM).contains(&team) {
+            warn!("{cid} tried to join team {team}");
+            // Put their lounge back to the team they're really on
+            let current = self.conns[who].team;
+            return self.conns[who]
+                .write(Packet::SEND_SET_TEAM(cid, current))
+                .await;
+        }
+        self.conns[who].team = team;
+
+        // Roommates need to know, but if we're not in a room then the lobby will do
+        let members = match self.lobbies.room(mode, lobby_num, self.conns[who].cur_room) {
+            Some(room) => room.members.clone(),
+            None => match self.lobbies.lobby(mode, lobby_num) {
+                Some(lobby) => lobby.members.clone(),
+                None => vec![cid],
+            },
+        };
+
+        for member in members {
+            let index = match self.find_conn(member) {
+                Some(index) => index,
+                None => continue,
+            };
+            self.conns[index]
+                .write(Packet::SEND_SET_TEAM(cid, team))
+                .await?;
+        }
+
+        Ok(())
+    }
 
+    /// Kick a player out of the lobby that they're in.
+    /// This is part of bigger cleanups like logging out, so a lobby that has already
+    /// gone (or has already lost track of them) just leaves them lobby-less.
+    pub(super) async fn eject_from_lobby(&mut self, who: usize) -> Result<()> {
+        let cid = self.conns[who].cid;
+        let lobby_num = self.conns[who].cur_lobby;
         self.conns[who].cur_lobby = -1;
 
-        // Notify all other users in the lobby
+        let lobby = match self.lobbies.lobby_mut(self.conns[who].mode, lobby_num) {
+            Some(lobby) => lobby,
+            None => {
+                warn!("{cid} was in lobby {lobby_num}, which doesn't exist");
+                return Ok(());
+            }
+        };
+
+        match lobby.members.iter().position(|c| *c == cid) {
+            Some(pos) => {
+                lobby.members.remove(pos);
+            }
+            None => warn!("{cid} was missing from the members of lobby {lobby_num}"),
+        }
+
+        // Notify all other users in the lobby, unless it's the hidden quick match one
+        let members = match self.conns[who].mode {
+            Mode::Quick => Vec::new(),
+            _ => lobby.members.clone(),
+        };
         let ulist_l = self.conns[who].make_ulist_l();
-        for &cid in &lobby.members {
-            let member_index = *self.conn_lookup.get(&cid).unwrap();
+        for &cid in &members {
+            let member_index = match self.find_conn(cid) {
+                Some(index) => index,
+                None => continue,
+            };
             self.conns[member_index]
                 .write(Packet::SEND_ULIST_L(ulist_l.clone()))
                 .await?;
@@ -315,7 +998,218 @@ impl GameServer {
         Ok(())
     }
 
-    /// Get the list of players in the lobby
+    /// Take a player out of their room, whether they've gone away or been kicked.
+    /// A round in progress carries on without them, or wraps up if they were the last
+    /// one still playing; an empty room is closed.
+    pub(super) async fn leave_room(&mut self, who: usize) -> Result<()> {
+        let cid = self.conns[who].cid;
+        let mode = self.conns[who].mode;
+        let lobby_num = self.conns[who].cur_lobby;
+        let room_num = self.conns[who].cur_room;
+        self.conns[who].cur_room = -1;
+
+        let lobby = match self.lobbies.lobby_mut(mode, lobby_num) {
+            Some(lobby) => lobby,
+            None => return Ok(()),
+        };
+        let room = match lobby.rooms.iter_mut().find(|r| r.room_num == room_num) {
+            Some(room) => room,
+            None => {
+                warn!("{cid} was in room {room_num}, which doesn't exist");
+                return Ok(());
+            }
+        };
+
+        let owner_changed = room.remove_member(cid);
+        let cycle_done = room.color_cycle.remove_player(cid);
+        if room.members.is_empty() {
+            info!("closing room {room_num}, as its last member left");
+            self.lobbies.remove_room(mode, lobby_num, room_num);
+            return Ok(());
+        }
+
+        let members = room.members.clone();
+        let owner = room.owner;
+        let room_stat = room.make_room_stat();
+        let round_over = room.is_round_over();
+
+        let conn = &mut self.conns[who];
+        conn.stat.insert(Stat::EXIT);
+        let ulist = conn.make_ulist();
+        conn.stat
+            .remove(Stat::EXIT | Stat::READY | Stat::GALLERY | Stat::ROUND);
+        for &member in &members {
+            let index = match self.find_conn(member) {
+                Some(index) => index,
+                None => continue,
+            };
+            self.conns[index]
+                .write(Packet::SEND_ULIST(ulist.clone()))
+                .await?;
+            self.conns[index]
+                .write(Packet::PKT_30(room_stat.clone()))
+                .await?;
+            if owner_changed {
+                self.conns[index]
+                    .write(Packet::SEND_CHG_OWNER(owner))
+                    .await?;
+            }
+        }
+        if owner_changed {
+            info!("{owner} now owns room {room_num}");
+        }
+
+        if round_over {
+            self.finish_round(mode, lobby_num, room_num).await?;
+        }
+        // Nobody else should be kept waiting on their colour results by somebody who left
+        if cycle_done {
+            self.finish_color_cycle(lobby_num, room_num).await?;
+        }
+        Ok(())
+    }
+
+    /// Leave the current room and go back to the lobby
+    pub(super) async fn handle_exit_room(&mut self, pid: i16, who: usize) -> Result<()> {
+        if self.conns[who].cur_room < 0 {
+            warn!(
+                "{} tried to exit a room while not in one",
+                self.conns[who].uid
+            );
+            return self.conns[who]
+                .write_with_pid(Packet::ACK_EXIT_ROOM(Status::Err), pid)
+                .await;
+        }
+
+        self.conns[who]
+            .write_with_pid(Packet::ACK_EXIT_ROOM(Status::OK), pid)
+            .await?;
+        self.abandon_loading(who).await?;
+        self.leave_room(who).await
+    }
+
+    /// Find the room a player is in, as long as they own it
+    fn owned_room_mut(&mut self, who: usize) -> Option<&mut Room> {
+        let conn = &self.conns[who];
+        self.lobbies
+            .room_mut(conn.mode, conn.cur_lobby, conn.cur_room)
+            .filter(|room| room.owner == conn.cid)
+    }
+
+    /// Change the settings of a room, which only its owner can do between rounds
+    pub(super) async fn handle_update_room(
+        &mut self,
+        pid: i16,
+        who: usize,
+        data: Packet19,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let (mode, lobby_num) = (self.conns[who].mode, self.conns[who].cur_lobby);
+        let max_members = self
+            .config
+            .rooms
+            .max_members(mode)
+            .min(MAX_GAME_MEMBERS as i8)
+            .min(data.room_stat.member_max)
+            .max(0) as usize;
+        let valid = self.config.rooms.validate(&data.room_stat);
+
+        let room = match self.owned_room_mut(who) {
+            Some(room) if !room.in_round && valid => room,
+            Some(_) => {
+                warn!("{uid} sent room settings that can't be used right now: {data:?}");
+                return self.conns[who]
+                    .write_with_pid(Packet::PKT_29(Status::Err), pid)
+                    .await;
+            }
+            None => {
+                warn!("{uid} tried to change the settings of a room they don't own");
+                return self.conns[who]
+                    .write_with_pid(Packet::PKT_29(Status::Err), pid)
+                    .await;
+            }
+        };
+        room.apply_settings(&data, max_members);
+        let room_num = room.room_num;
+        let room_stat = room.make_room_stat();
+        info!("{uid} changed the settings of room {room_num}");
+
+        self.conns[who]
+            .write_with_pid(Packet::PKT_29(Status::OK), pid)
+            .await?;
+
+        // Room members are still in the lobby, so this reaches them too
+        let members = self
+            .lobbies
+            .lobby_members(mode, lobby_num)
+            .unwrap_or_default()
+            .to_vec();
+        for cid in members {
+            if let Some(index) = self.find_conn(cid) {
+                self.conns[index]
+                    .write(Packet::PKT_30(room_stat.clone()))
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hand the room over to another member
+    pub(super) async fn handle_change_owner(&mut self, who: usize, target: CID) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let room = match self.owned_room_mut(who) {
+            Some(room) if room.members.contains(&target) => room,
+            _ => {
+                warn!("{uid} tried to hand a room they don't own to {target}");
+                return self.conns[who].write(Packet::PKT_220(Status::Err)).await;
+            }
+        };
+
+        room.owner = target;
+        info!("{uid} handed room {} over to {target}", room.room_num);
+        let members = room.members.clone();
+        for cid in members {
+            if let Some(index) = self.find_conn(cid) {
+                self.conns[index]
+                    .write(Packet::SEND_CHG_OWNER(target))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Throw somebody out of the room
+    pub(super) async fn handle_kick_member(&mut self, who: usize, target: CID) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let my_cid = self.conns[who].cid;
+        let room = match self.owned_room_mut(who) {
+            Some(room) if target != my_cid && room.members.contains(&target) => room,
+            _ => {
+                warn!("{uid} tried to kick {target} from a room they don't own");
+                return self.conns[who].write(Packet::PKT_220(Status::Err)).await;
+            }
+        };
+
+        info!("👢 {uid} kicked {target} from room {}", room.room_num);
+        let members = room.members.clone();
+        for cid in members {
+            if let Some(index) = self.find_conn(cid) {
+                self.conns[index]
+                    .write(Packet::SEND_KICK_MEMBER(target))
+                    .await?;
+            }
+        }
+        self.conns[who].write(Packet::PKT_220(Status::OK)).await?;
+
+        match self.find_conn(target) {
+            Some(index) => self.leave_room(index).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Get the list of players in the lobby.
+    /// Unlike the room list, nothing marks the end of this one.
     pub(super) async fn handle_req_lobby_members(
         &self,
         pid: i16,
@@ -325,65 +1219,149 @@ impl GameServer {
     ) -> Result<()> {
         if let Some(lobby) = self.lobbies.lobby(mode, num) {
             for &cid in &lobby.members {
-                let member_index = *self.conn_lookup.get(&cid).unwrap();
+                let member_index = match self.find_conn(cid) {
+                    Some(index) => index,
+                    None => continue,
+                };
                 let packet = Packet::SEND_ULIST_L(self.conns[member_index].make_ulist_l());
                 self.conns[who].write_with_pid(packet, pid).await?;
             }
             Ok(())
         } else {
-            bail!("invalid lobby")
+            Err(GameError::InvalidLobby.into())
         }
     }
 
-    /// Allow players to make rooms
-    pub(super) async fn handle_make_room(
-        &mut self,
-        pid: i16,
-        who: usize,
-        data: Packet19,
-    ) -> Result<()> {
-        let lobby = match self.lobbies.lobby_mut(data.mode, data.lobby) {
-            Some(lobby) => lobby,
-            None => bail!("invalid lobby"),
-        };
-
+    /// Make sure a player is allowed to make the room they've asked for
+    fn check_make_room(&self, who: usize, data: &Packet19) -> Result<(), GameError> {
+        if self.lobbies.lobby(data.mode, data.lobby).is_none() {
+            return Err(GameError::InvalidLobby);
+        }
         if self.conns[who].mode != data.mode {
-            bail!("user isn't in the mode")
+            return Err(GameError::WrongMode);
         }
         if self.conns[who].cur_lobby != data.lobby {
-            bail!("user isn't in the lobby")
+            return Err(GameError::NotInLobby);
         }
         if self.conns[who].cur_room >= 0 {
-            bail!("user is already in a room")
+            return Err(GameError::AlreadyInRoom);
         }
 
-        // allocate a number for the room
-        let room_num = match lobby.pick_free_room_num() {
-            Some(n) => n,
-            None => {
-                error!("failed to create room, all slots full");
-                let packet = Packet::ACK_MAKE_ROOM(-1);
-                self.conns[who].write_with_pid(packet, pid).await?;
-                return Ok(());
-            }
-        };
+        // don't let the client pick settings that the game can't support
+        if !self.config.rooms.validate(&data.room_stat) {
+            error!("rejecting room with invalid settings: {:?}", data.room_stat);
+            return Err(GameError::InvalidRoomSettings);
+        }
 
-        let mut room = Room::new(room_num, data);
+        Ok(())
+    }
+
+    /// Put a player into a brand new room
+    fn make_room(&mut self, who: usize, mut data: Packet19) -> Result<RoomNum, GameError> {
+        self.check_make_room(who, &data)?;
+
+        // the game start packet can't describe more than 50 players either
+        let max_members = self
+            .config
+            .rooms
+            .max_members(data.mode)
+            .min(MAX_GAME_MEMBERS as i8);
+        data.room_stat.member_max = data.room_stat.member_max.min(max_members);
+
+        // allocate a number for the room
+        let lobby = self
+            .lobbies
+            .lobby_mut(data.mode, data.lobby)
+            .ok_or(GameError::InvalidLobby)?;
+        let room_num = lobby.pick_free_room_num().ok_or(GameError::NoFreeRooms)?;
 
         // player will be in the new room by default
-        room.members.push(self.conns[who].cid);
+        lobby.add_room(room_num, data, vec![self.conns[who].cid]);
         self.conns[who].cur_room = room_num;
 
-        lobby.rooms.push(room);
-        lobby.rooms.sort_by_key(|l| l.room_num);
+        Ok(room_num)
+    }
 
-        // inform them of success
-        let packet = Packet::ACK_MAKE_ROOM(room_num);
-        self.conns[who].write_with_pid(packet, pid).await?;
+    /// Bring an entire competition lobby back to the lounge between colour cycles
+    pub(super) async fn handle_return_lounge_all(&mut self, who: usize) -> Result<()> {
+        let conn = &self.conns[who];
+        if conn.mode != Mode::Competition {
+            return Err(GameError::WrongMode.into());
+        }
+        if conn.stat.contains(Stat::ROUND) {
+            warn!("{} asked to return the lounge mid-round", conn.cid);
+            return Ok(());
+        }
+        // Only somebody running a room gets to call everyone back
+        let cid = conn.cid;
+        let lobby_num = conn.cur_lobby;
+        if self.owned_room_mut(who).is_none() {
+            warn!("{cid} asked to return the lounge without owning a room");
+            return Ok(());
+        }
+
+        let lobby = match self.lobbies.lobby_mut(Mode::Competition, lobby_num) {
+            Some(lobby) => lobby,
+            None => return Err(GameError::NotInLobby.into()),
+        };
+        info!("{cid} is returning competition lobby {lobby_num} to the lounge");
+        let busy = lobby.return_to_lounge();
+
+        // Anyone who has already left isn't a member any more, so they're left alone,
+        // as is anyone whose round is still going
+        let members = lobby.members.clone();
+        let indices: Vec<usize> = members
+            .iter()
+            .filter(|cid| !busy.contains(cid))
+            .filter_map(|&cid| self.find_conn(cid))
+            .collect();
+        for &index in &indices {
+            let conn = &mut self.conns[index];
+            conn.stat.remove(Stat::ROUND);
+            conn.element = Element::None;
+        }
+
+        for &index in &indices {
+            let conn = &self.conns[index];
+            let ustat = Packet::SEND_USTAT {
+                cid: conn.cid,
+                uid: conn.uid,
+                stat: conn.stat,
+            };
+            for &other in &indices {
+                self.conns[other].write(ustat.clone()).await?;
+            }
+        }
+        for &index in &indices {
+            self.conns[index]
+                .write(Packet::REP_RETURN_LOUNGE_ALL)
+                .await?;
+        }
 
         Ok(())
     }
 
+    /// Allow players to make rooms
+    pub(super) async fn handle_make_room(
+        &mut self,
+        pid: i16,
+        who: usize,
+        data: Packet19,
+    ) -> Result<()> {
+        match self.make_room(who, data) {
+            Ok(room_num) => {
+                // inform them of success
+                let packet = Packet::ACK_MAKE_ROOM(room_num);
+                self.conns[who].write_with_pid(packet, pid).await
+            }
+            Err(e) => {
+                let packet = Packet::ACK_MAKE_ROOM(-1);
+                self.conns[who].write_with_pid(packet, pid).await?;
+                Err(e.into())
+            }
+        }
+    }
+
     /// Tell a lobby entrant about the rooms that exist
     pub(super) async fn handle_get_rooms(&self, pid: i16, who: usize) -> Result<()> {
         let lobby = match self
@@ -391,20 +1369,11 @@ impl GameServer {
             .lobby(self.conns[who].mode, self.conns[who].cur_lobby)
         {
             Some(lobby) => lobby,
-            None => bail!("invalid lobby"),
+            None => return Err(GameError::InvalidLobby.into()),
         };
 
         for room in &lobby.rooms {
-            let data = Packet19 {
-                mode: self.conns[who].mode,
-                lobby: self.conns[who].cur_lobby,
-                room_name: room.name.parse()?,
-                room_password: match &room.password {
-                    Some(p) => p.parse()?,
-                    None => "".parse()?,
-                },
-                room_stat: room.make_room_stat(),
-            };
+            let data = room.make_room_data(self.conns[who].mode, self.conns[who].cur_lobby)?;
             self.conns[who]
                 .write_with_pid(Packet::PKT_19(data), pid)
                 .await?;
@@ -419,6 +1388,7 @@ impl GameServer {
         who: usize,
         room_num: RoomNum,
         password: &str,
+        intent: EntryIntent,
     ) -> Result<(), EnterRoomError> {
         let mode = self.conns[who].mode;
         let lobby_num = self.conns[who].cur_lobby;
@@ -438,34 +1408,59 @@ impl GameServer {
             }
         }
 
-        if room.members.len() >= room.max_members {
-            return Err(EnterRoomError::RoomIsFull);
+        // Somebody who wanted to play can still watch a full room, if it allows that.
+        // Nobody can join a round that's already under way, so they can only watch that too.
+        let full = room.num_players() >= room.max_members;
+        let intent = match intent {
+            EntryIntent::Player if !full && !room.in_round => EntryIntent::Player,
+            _ if room.allow_spectators => EntryIntent::Watcher,
+            EntryIntent::Watcher => return Err(EnterRoomError::NoSpectators),
+            EntryIntent::Player if room.in_round => return Err(EnterRoomError::RoundInProgress),
+            EntryIntent::Player => return Err(EnterRoomError::RoomIsFull),
+        };
+
+        // Rank limits only keep people from playing; anyone can still watch.
+        // TODO: the limit layout behind this is a guess, see RankLimits
+        if mode == Mode::Competition
+            && intent == EntryIntent::Player
+            && !room.rank_limits.allows(self.conns[who].user.class)
+        {
+            return Err(EnterRoomError::RankRestricted);
         }
 
         // If all that succeeded, we can put them in
-        room.members.push(self.conns[who].cid);
+        let cid = self.conns[who].cid;
+        room.members.push(cid);
+        if intent == EntryIntent::Watcher {
+            room.watchers.push(cid);
+            self.conns[who].stat.insert(Stat::GALLERY);
+        }
         self.conns[who].cur_room = room_num;
 
-        let data = Packet19 {
-            mode,
-            lobby: lobby_num,
-            room_name: room.name.parse().unwrap(),
-            room_password: match &room.password {
-                Some(p) => p.parse()?,
-                None => "".parse()?,
-            },
-            room_stat: room.make_room_stat(),
+        let data = room.make_room_data(mode, lobby_num)?;
+        let catch_up = match intent {
+            EntryIntent::Watcher => room.catch_up_packets(),
+            EntryIntent::Player => Vec::new(),
         };
         self.conns[who]
             .write_with_pid(Packet::ACK_ENTER_ROOM(data), pid)
             .await?;
 
+        // Watchers arriving mid-round would otherwise be staring at an empty course
+        for packet in catch_up {
+            self.conns[who].write(packet).await?;
+        }
+
         // Notify all other users in the room
+        let members = room.members.clone();
         let ulist = self.conns[who].make_ulist();
         let my_cid = self.conns[who].cid;
-        for &cid in &room.members {
+        for &cid in &members {
             if cid != my_cid {
-                let member_index = *self.conn_lookup.get(&cid).unwrap();
+                let member_index = match self.find_conn(cid) {
+                    Some(index) => index,
+                    None => continue,
+                };
                 self.conns[member_index]
                     .write(Packet::SEND_ULIST(ulist.clone()))
                     .await?;
@@ -475,16 +1470,17 @@ impl GameServer {
         Ok(())
     }
 
-    /// Allow players to enter a room
+    /// Allow players to enter a room, either to play or to watch from the gallery
     pub(super) async fn handle_enter_room(
         &mut self,
         pid: i16,
         who: usize,
         room_num: RoomNum,
         password: &str,
+        intent: EntryIntent,
     ) -> Result<()> {
         if let Err(e) = self
-            ._enter_room_internal(pid, who, room_num, password)
+            ._enter_room_internal(pid, who, room_num, password, intent)
             .await
         {
             error!("failed to enter room: {e:?}");
@@ -504,7 +1500,49 @@ impl GameServer {
         Ok(())
     }
 
-    /// List the players in a particular room
+    /// Re-send the data for a single room, so the client can refresh a stale entry
+    pub(super) async fn handle_refresh_room(
+        &self,
+        pid: i16,
+        who: usize,
+        mode: u32,
+        lobby_num: u32,
+        room_num: u32,
+    ) -> Result<()> {
+        // The client stores these as 32-bit values, so narrow them carefully
+        let mode = Mode::from_u32(mode);
+        let lobby_num = LobbyNum::try_from(lobby_num).ok();
+        let room_num = RoomNum::try_from(room_num).ok();
+
+        let data = match (mode, lobby_num, room_num) {
+            (Some(mode), Some(lobby_num), Some(room_num)) => {
+                match self.lobbies.room(mode, lobby_num, room_num) {
+                    Some(room) => Some(room.make_room_data(mode, lobby_num)?),
+                    None => None,
+                }
+            }
+            _ => None,
+        };
+
+        let data = match data {
+            Some(data) => data,
+            None => {
+                warn!("room refresh for invalid room: {mode:?} {lobby_num:?} {room_num:?}");
+                Packet19::create_error(
+                    mode.unwrap_or(self.conns[who].mode),
+                    lobby_num.unwrap_or(-1),
+                    -1,
+                )
+            }
+        };
+
+        self.conns[who]
+            .write_with_pid(Packet::PKT_19(data), pid)
+            .await
+    }
+
+    /// List the players in a particular room, for a lounge that's loading in.
+    /// The list always ends with ACK_ULIST_R, which the client waits on.
     pub(super) async fn handle_get_room_members(
         &self,
         pid: i16,
@@ -515,17 +1553,22 @@ impl GameServer {
     ) -> Result<()> {
         if let Some(room) = self.lobbies.room(mode, lobby_num, room_num) {
             for cid in &room.members {
-                let member_index = *self.conn_lookup.get(cid).unwrap();
+                let member_index = match self.find_conn(*cid) {
+                    Some(index) => index,
+                    None => continue,
+                };
                 let packet = Packet::SEND_ULIST(self.conns[member_index].make_ulist());
                 self.conns[who].write_with_pid(packet, pid).await?;
             }
 
-            // it seems like we need this to get out of the loading screen
             self.conns[who]
                 .write_with_pid(Packet::ACK_ULIST_R(Status::OK), pid)
                 .await?;
         } else {
-            // room not found
+            warn!(
+                "{} asked for the members of missing room {mode:?}/{lobby_num}/{room_num}",
+                self.conns[who].cid
+            );
             self.conns[who]
                 .write_with_pid(Packet::ACK_ULIST_R(Status::Err), pid)
                 .await?;
@@ -533,6 +1576,53 @@ impl GameServer {
 
         Ok(())
     }
+
+    /// Ask another player to come and join the room we're in.
+    /// Invites from somebody the target has blocked never reach them.
+    pub(super) async fn handle_invite(&self, who: usize, target: CID) -> Result<()> {
+        let conn = &self.conns[who];
+        let room = match self.lobbies.room(conn.mode, conn.cur_lobby, conn.cur_room) {
+            Some(room) => room,
+            None => {
+                warn!(
+                    "{} tried to invite {target} without being in a room",
+                    conn.cid
+                );
+                return Ok(());
+            }
+        };
+        let index = match self.find_conn(target) {
+            Some(index) => index,
+            None => {
+                warn!("{} tried to invite {target}, who isn't here", conn.cid);
+                return Ok(());
+            }
+        };
+        if self.conns[index].blocks.contains(&conn.uid) {
+            info!(
+                "dropping invite from {} to {target}, who has blocked them",
+                conn.cid
+            );
+            return Ok(());
+        }
+
+        let mut room_member_uids = [-1; 50];
+        let member_uids = room
+            .members
+            .iter()
+            .filter_map(|&cid| self.find_conn(cid))
+            .map(|index| self.conns[index].uid);
+        for (slot, uid) in room_member_uids.iter_mut().zip(member_uids) {
+            *slot = uid;
+        }
+
+        let packet = Packet::PKT_280 {
+            source_uid: conn.uid,
+            room_member_uids,
+            room_data: room.make_room_data(conn.mode, conn.cur_lobby)?,
+        };
+        self.conns[index].write(packet).await
+    }
 }
 
 pub(super) fn create_initial_lobbies() -> Lobbies {
@@ -550,8 +1640,16 @@ pub(super) fn create_initial_lobbies() -> Lobbies {
         rooms: Vec::new(),
     }];
 
+    let quick_lobbies = vec![Lobby {
+        name: "Quick".to_string(),
+        members: Vec::new(),
+        max_members: 256,
+        rooms: Vec::new(),
+    }];
+
     Lobbies {
         vs_lobbies,
         compe_lobbies,
+        quick_lobbies,
     }
 }
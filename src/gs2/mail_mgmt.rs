@@ -0,0 +1,105 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::packets::{DateTime, MailSendResult, Packet, UID};
+
+use super::GameServer;
+
+/// Most unread mail a player can have waiting before nobody can send them any more
+const INBOX_LIMIT: usize = 20;
+/// Longest mail we'll store, in bytes
+const MAX_MAIL_LEN: usize = 1024;
+
+impl GameServer {
+    /// Tell a player how much unread mail they have
+    async fn send_unreceived_mail_count(&self, who: usize, pid: Option<i16>) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let count = self.db.count_unreceived_mail(uid).await?;
+        let packet = Packet::SEND_UNRECEIVE_SMAIL_CNT {
+            uid,
+            cnt: count.min(i8::MAX as usize) as i8,
+        };
+        match pid {
+            Some(pid) => self.conns[who].write_with_pid(packet, pid).await,
+            None => self.conns[who].write(packet).await,
+        }
+    }
+
+    pub(super) async fn handle_get_unreceived_mail_count(
+        &self,
+        pid: i16,
+        who: usize,
+    ) -> Result<()> {
+        self.send_unreceived_mail_count(who, Some(pid)).await
+    }
+
+    /// List the IDs of a player's unread mail, which the client then fetches one by one
+    pub(super) async fn handle_get_mail_list(&self, pid: i16, who: usize) -> Result<()> {
+        let values = self.db.list_unreceived_mail(self.conns[who].uid).await?;
+        let packet = Packet::PKT_112 {
+            unk1: 0,
+            unk2: 0,
+            cnt: values.len() as i32,
+            values,
+        };
+        self.conns[who].write_with_pid(packet, pid).await
+    }
+
+    /// Hand over a mail, which counts as it being read
+    pub(super) async fn handle_get_mail(&self, pid: i16, who: usize, mail_uid: i32) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let mail = match self.db.receive_mail(uid, mail_uid).await? {
+            Some(mail) => mail,
+            None => {
+                warn!("{uid} asked for mail {mail_uid}, which isn't theirs");
+                return Ok(());
+            }
+        };
+
+        let utf8_text = mail.body.into_bytes();
+        let packet = Packet::PKT_114 {
+            mail_uid: mail.mail_uid,
+            from_uid: mail.from_uid,
+            to_uid: mail.to_uid,
+            date_time: DateTime::from_unix(mail.sent_at),
+            len: utf8_text.len() as i16,
+            utf8_text,
+        };
+        self.conns[who].write_with_pid(packet, pid).await
+    }
+
+    /// Send mail to another player.
+    /// The client leaves the sender and date for us to fill in, so anything it puts
+    /// there is ignored.
+    pub(super) async fn handle_send_mail(
+        &self,
+        pid: i16,
+        who: usize,
+        to: UID,
+        utf8_text: Vec<u8>,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let result = match String::from_utf8(utf8_text) {
+            Ok(body) if !body.is_empty() && body.len() <= MAX_MAIL_LEN => {
+                self.db.send_mail(uid, to, body, INBOX_LIMIT).await?
+            }
+            _ => {
+                warn!("{uid} tried to send mail that's empty, too long, or garbled");
+                MailSendResult::Err
+            }
+        };
+        info!("✉️ mail from {uid} to {to}: {result:?}");
+
+        self.conns[who]
+            .write_with_pid(Packet::PKT_116(result), pid)
+            .await?;
+
+        // If they're around, let the recipient know there's something waiting
+        if result == MailSendResult::OK {
+            if let Some(index) = self.conns.iter().position(|c| c.uid == to) {
+                self.send_unreceived_mail_count(index, None).await?;
+            }
+        }
+        Ok(())
+    }
+}
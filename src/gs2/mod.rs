@@ -1,35 +1,101 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use anyhow::Result;
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use tokio::net::{TcpListener, ToSocketAddrs};
 use tokio::sync::{mpsc, oneshot};
 use tokio_rustls::rustls::ServerConfig;
 use tokio_rustls::TlsAcceptor;
 
-use crate::data::shop::{build_salon_list, build_sell_list};
-use crate::data::{Character, SellItem, User};
+use crate::config::Config;
+use crate::data::recycle::{build_recycle_recipes, RecycleRecipe};
+use crate::data::shop::{build_caddie_list, build_salon_list, build_sell_list};
+use crate::data::{Character, SellCaddy, SellItem, User};
 use crate::db_task::DBTask;
 use crate::packets::{
-    AckIDPassResult, ChrUID, Element, IDPass, LobbyNum, Mode, ModeCtrl, Packet, RoomNum, Stat,
-    UData, UList, UListL, CID, UID,
+    AckIDPassResult, ChrUID, DateTime, Element, IDPass, LobbyNum, Mode, ModeCtrl, Packet, RoomNum,
+    Stat, UData, UList, UListL, CID, UID,
 };
+use crate::stream::Frame;
 
-use self::conn_task::{ConnReceiver, ConnSender};
+use self::conn_task::{ConnMessage, ConnReceiver, ConnSender};
+use self::error::GameError;
 
+mod block_mgmt;
 mod chara_mgmt;
+mod chat_mgmt;
+mod code_mgmt;
+mod color_mgmt;
 mod conn_task;
+mod delivery_mgmt;
+mod error;
+mod friend_mgmt;
+mod game_center_mgmt;
 mod game_mgmt;
+mod item_mgmt;
 mod lobby_mgmt;
+mod mail_mgmt;
+mod quick_mgmt;
 mod record_mgmt;
+mod recycle_mgmt;
+mod search_mgmt;
 mod shop_mgmt;
+#[cfg(test)]
+mod tests;
+mod title_mgmt;
 mod user_mgmt;
 
+/// The range of cids handed out to players
+const MIN_CID: CID = 600;
+const MAX_CID: CID = 999;
+
 enum Message {
-    Login(IDPass, oneshot::Sender<LoginResult>),
-    PlayerData { cid: CID, pid: i16, packet: Packet },
+    Login(IDPass, SocketAddr, oneshot::Sender<LoginResult>),
+    PlayerData {
+        cid: CID,
+        pid: i16,
+        packet: Packet,
+    },
     Logout(CID),
+    SaveAll(oneshot::Sender<usize>),
+    ResetUser {
+        uid: UID,
+        keep_profile: bool,
+        resp: oneshot::Sender<Result<bool>>,
+    },
+}
+
+/// A handle for talking to the game server from outside of it
+#[derive(Clone)]
+pub struct GameTask {
+    tx: mpsc::Sender<Message>,
+}
+
+impl GameTask {
+    /// Write every online player to the DB, returning how many records were saved
+    pub async fn save_all(&self) -> Result<usize> {
+        let (resp, rx) = oneshot::channel();
+        self.tx.send(Message::SaveAll(resp)).await?;
+        Ok(rx.await?)
+    }
+
+    /// Wipe a player's progress, kicking them off first if they're online.
+    /// Returns false if there's no such account.
+    pub async fn reset_user(&self, uid: UID, keep_profile: bool) -> Result<bool> {
+        let (resp, rx) = oneshot::channel();
+        self.tx
+            .send(Message::ResetUser {
+                uid,
+                keep_profile,
+                resp,
+            })
+            .await?;
+        rx.await?
+    }
 }
 
 #[derive(Debug)]
@@ -48,12 +114,35 @@ struct Player {
     cur_lobby: LobbyNum,
     cur_room: RoomNum,
     stat: Stat,
+    /// Team picked in the VS/Compe lounge; kept for the rest of the session
+    team: i8,
+    /// Colour assigned for the current competition cycle. This only lasts as long as
+    /// the cycle does, so it's never saved over the user's own element.
+    element: Element,
     mode: Mode,
+    /// Where they're connecting from, for tying log lines to an IP
+    addr: SocketAddr,
+    /// Players whose chat this player doesn't want to see
+    blocks: BTreeSet<UID>,
     packet_tx: ConnSender,
 }
 
+/// Players start out in no mode at all, go to the main menu, and from there pick a
+/// mode to play in. Leaving a mode always goes back through the main menu.
+fn is_valid_mode_change(old_mode: Mode, new_mode: Mode) -> bool {
+    use Mode::*;
+
+    match (old_mode, new_mode) {
+        _ if old_mode == new_mode => true,
+        (None, Main) => true,
+        (Main, VS | Competition | Quick | Single) => true,
+        (VS | Competition | Quick | Single, Main) => true,
+        _ => false,
+    }
+}
+
 impl Player {
-    fn make_udata(&self) -> UData {
+    fn make_udata(&self, now: &DateTime) -> UData {
         UData {
             cid: self.cid,
             uid: self.uid,
@@ -62,22 +151,22 @@ impl Player {
             holdbox: self.user.holdbox,
             medals: [[0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
             // these are all the amounts of awards received for tournaments
-            awards: [0; 20],
-            rank_score_item_on: 0,
-            rank_score_item_off: 0,
+            awards: self.user.awards,
+            rank_score_item_on: self.user.rating_item_on.score,
+            rank_score_item_off: self.user.rating_item_off.score,
             mp: 0,
-            year: 2023,
-            month: 8,
-            day: 23,
+            year: now.year,
+            month: now.month,
+            day: now.day,
             name: self.name.parse().unwrap(),
-            element: self.user.element,
+            element: self.element,
             class: self.user.class,
             // *** check GetPlayerGrade func for these ***
-            rank_item_on: 0,  // PlayerGrade
-            rank_item_off: 0, // PlayerGrade
-            best_rank_item_on: 0,
-            best_rank_item_off: 0,
-            x_f4: 0,
+            rank_item_on: self.user.rating_item_on.rank(), // PlayerGrade
+            rank_item_off: self.user.rating_item_off.rank(), // PlayerGrade
+            best_rank_item_on: self.user.rating_item_on.best_rank,
+            best_rank_item_off: self.user.rating_item_off.best_rank,
+            x_f4: if self.user.refuses_deliveries { 4 } else { 0 },
             debug: false,
         }
     }
@@ -87,13 +176,13 @@ impl Player {
             cid: self.cid,
             uid: self.uid,
             stat: self.stat.bits() as u16,
-            team: 0, // fix me
+            team: self.team as u8,
             mode: self.mode,
             lobby: self.cur_lobby,
             room: self.cur_room,
             pclass: self.user.class.class(), // TODO is this the selected class?
-            element: self.user.element,
-            title: 0, // todo
+            element: self.element,
+            title: self.user.title,
             sv_no: 0,
             circle: 0,
             name: self.name.parse().unwrap(),
@@ -105,24 +194,52 @@ impl Player {
             cid: self.cid,
             uid: self.uid,
             stat: self.stat.bits() as u16,
-            team: 0, // fix me
+            team: self.team as u8,
             mode: self.mode,
             lobby: self.cur_lobby,
             room: self.cur_room,
             pclass: self.user.class.class(), // TODO is this the selected class?
-            element: self.user.element,
-            title: 0, // todo
+            element: self.element,
+            title: self.user.title,
             circle: 0,
             name: self.name.parse().unwrap(),
         }
     }
 
+    fn active_character(&self) -> Option<&Character> {
+        self.characters
+            .iter()
+            .find(|(chr_uid, _)| *chr_uid == self.user.default_chr_uid)
+            .map(|(_, chara)| chara)
+    }
+
+    fn active_character_mut(&mut self) -> Option<(ChrUID, &mut Character)> {
+        let chr_uid = self.user.default_chr_uid;
+        self.characters
+            .iter_mut()
+            .find(|(c, _)| *c == chr_uid)
+            .map(|(c, chara)| (*c, chara))
+    }
+
     async fn write(&self, packet: Packet) -> Result<()> {
-        Ok(self.packet_tx.send((None, packet)).await?)
+        Ok(self
+            .packet_tx
+            .send(ConnMessage::Packet(None, packet))
+            .await?)
     }
 
     async fn write_with_pid(&self, packet: Packet, pid: i16) -> Result<()> {
-        Ok(self.packet_tx.send((Some(pid), packet)).await?)
+        Ok(self
+            .packet_tx
+            .send(ConnMessage::Packet(Some(pid), packet))
+            .await?)
+    }
+
+    async fn write_frame(&self, frame: &Frame) -> Result<()> {
+        Ok(self
+            .packet_tx
+            .send(ConnMessage::Frame(frame.clone()))
+            .await?)
     }
 }
 
@@ -131,22 +248,48 @@ struct GameServer {
     conns: Vec<Player>,
     conn_lookup: BTreeMap<CID, usize>,
     lobbies: lobby_mgmt::Lobbies,
+    session_intents: BTreeMap<UID, lobby_mgmt::SessionIntent>,
+    quick_pool: Vec<quick_mgmt::QuickEntry>,
     shop_items: Vec<SellItem>,
     salon_items: Vec<SellItem>,
+    caddie_items: Vec<SellCaddy>,
+    recycle_recipes: Vec<RecycleRecipe>,
+    /// Randomness for anything that hands out rewards, so tests can pin it down
+    rng: StdRng,
+    config: Arc<Config>,
     db: DBTask,
 }
 
 impl GameServer {
-    fn generate_cid(&mut self) -> CID {
-        loop {
+    fn generate_cid(&mut self) -> Option<CID> {
+        // give up once we've tried every possible cid
+        for _ in MIN_CID..=MAX_CID {
             let cid = self.next_cid;
             self.next_cid += 1;
-            if self.next_cid > 999 {
-                self.next_cid = 600;
+            if self.next_cid > MAX_CID {
+                self.next_cid = MIN_CID;
             }
 
             if !self.conn_lookup.contains_key(&cid) {
-                return cid;
+                return Some(cid);
+            }
+        }
+
+        None
+    }
+
+    /// Find the index of a connected player.
+    /// If the lookup table has gone out of sync with the player list, log it rather than panicking.
+    fn find_conn(&self, cid: CID) -> Option<usize> {
+        match self.conn_lookup.get(&cid).copied() {
+            Some(index) if index < self.conns.len() && self.conns[index].cid == cid => Some(index),
+            Some(index) => {
+                error!("⚠️ conn_lookup has stale entry {index} for cid {cid}");
+                None
+            }
+            None => {
+                error!("⚠️ cid {cid} is missing from conn_lookup");
+                None
             }
         }
     }
@@ -157,30 +300,97 @@ impl GameServer {
         self.db.write_user(conn.uid, conn.user.clone()).await;
     }
 
+    /// Flush everybody's data to the DB, and wait until it's all been written
+    async fn save_all(&self) -> Result<usize> {
+        let mut count = 0;
+        for (who, conn) in self.conns.iter().enumerate() {
+            self.save_user(who).await;
+            count += 1;
+
+            for (chr_uid, chara) in &conn.characters {
+                self.db.write_character(*chr_uid, chara.clone()).await;
+                count += 1;
+            }
+        }
+
+        self.db.flush().await?;
+        info!(
+            "💾 flushed {count} records for {} players",
+            self.conns.len()
+        );
+        Ok(count)
+    }
+
+    /// Reset an account back to a fresh state.
+    /// Anyone using it gets disconnected, so that they don't save over the reset
+    /// and so their client reloads everything when they come back.
+    async fn reset_user(&mut self, uid: UID, keep_profile: bool) -> Result<bool> {
+        if let Some(cid) = self.conns.iter().find(|c| c.uid == uid).map(|c| c.cid) {
+            info!("disconnecting cid:{cid} so their account can be reset");
+            self.remove_player(cid).await?;
+        }
+        self.session_intents.remove(&uid);
+
+        let found = self.db.reset_account(uid, keep_profile).await?;
+        if found {
+            warn!("🧹 reset account {uid} (keep_profile: {keep_profile})");
+        }
+        Ok(found)
+    }
+
     /// Try and switch a player to a different game mode.
     async fn handle_change_mode(&mut self, who: usize, new_mode: Mode) -> Result<()> {
         let cid = self.conns[who].cid;
         let old_mode = self.conns[who].mode;
         info!("📦 {cid} changing from mode {old_mode:?} to {new_mode:?}");
 
+        // Somebody coming back after a disconnect or a RANKJUMP may skip the main menu
+        let resuming =
+            old_mode == Mode::None && self.has_session_intent(self.conns[who].uid, new_mode);
+        if !resuming && !is_valid_mode_change(old_mode, new_mode) {
+            // Tell them they're staying put
+            self.conns[who]
+                .write(Packet::ACK_CHG_MODE(old_mode))
+                .await?;
+            return Err(GameError::IllegalModeChange(old_mode, new_mode).into());
+        }
+
         if old_mode != new_mode {
             if self.conns[who].cur_lobby >= 0 {
                 self.eject_from_lobby(who).await?;
             }
+            if old_mode == Mode::Quick {
+                let cid = self.conns[who].cid;
+                self.quick_pool.retain(|e| e.cid != cid);
+            }
 
+            // Quick mode has no lobbies to pick from, so players stay outside of
+            // one and go straight to matchmaking with REQ_ADD_RANKMEMBER
             self.conns[who].mode = new_mode;
         }
 
         self.conns[who]
             .write(Packet::ACK_CHG_MODE(new_mode))
             .await?;
+
+        // If they were in this mode before being disconnected, put them back where they were
+        if self.conns[who].cur_lobby < 0 {
+            self.restore_session_intent(who, new_mode).await?;
+        }
+
         Ok(())
     }
 
     /// Try and add a player to the server.
-    async fn handle_login(&mut self, p: IDPass) -> LoginResult {
-        let login_id = p.username.to_string();
-        let password = p.password.to_string();
+    async fn handle_login(&mut self, p: IDPass, addr: SocketAddr) -> LoginResult {
+        let (login_id, password) = match p.credentials() {
+            Some(credentials) => credentials,
+            None => {
+                warn!("rejecting login from {addr} with malformed credentials: {p:?}");
+                return LoginResult::Fail(AckIDPassResult::IDError);
+            }
+        };
+        let username = login_id.clone();
 
         let account = match self.db.authenticate_user_to_game(login_id, password).await {
             Ok(account) => account,
@@ -197,30 +407,62 @@ impl GameServer {
             }
         }
 
+        // Is there room for them?
+        // The client has no "server full" message, so this is the closest we can get
+        if self.conns.len() >= self.config.game.max_players {
+            warn!("rejecting login for {}, server is full", account.uid);
+            return LoginResult::Fail(AckIDPassResult::AccountNotError);
+        }
+
         // All checks out
-        let cid = self.generate_cid();
+        let cid = match self.generate_cid() {
+            Some(cid) => cid,
+            None => {
+                error!("rejecting login for {}, out of cids", account.uid);
+                return LoginResult::Fail(AckIDPassResult::AccountNotError);
+            }
+        };
+        let blocks = match self.db.get_blocks(account.uid).await {
+            Ok(blocks) => blocks.into_iter().collect(),
+            Err(e) => {
+                error!("failed to load block list for {}: {e:?}", account.uid);
+                BTreeSet::new()
+            }
+        };
         let (packet_tx, packet_rx) = mpsc::channel(128);
-        let name = account
-            .name
-            .unwrap_or_else(|| format!("_{}", p.username.to_string()));
+        let name = account.name.unwrap_or_else(|| format!("_{username}"));
+
+        // First visit? Hand out the welcome bonus before they see their balance
+        let mut user = account.user;
+        let welcomed = self.grant_welcome(account.uid, &mut user);
+        if welcomed {
+            self.db.write_user(account.uid, user.clone()).await;
+        }
 
         let who = self.conns.len();
         let player = Player {
             cid,
             uid: account.uid,
             name,
-            user: account.user,
+            user,
             characters: account.characters,
             cur_lobby: -1,
             cur_room: -1,
             stat: Stat::empty(),
+            team: 0,
+            element: Element::None,
             mode: Mode::None,
+            addr,
+            blocks,
             packet_tx,
         };
+        info!("👋 {} logged in as cid:{cid} from {addr}", account.uid);
 
         // Send their initial packets
         player
-            .write(Packet::ACK_IDPASS_G(player.make_udata()))
+            .write(Packet::ACK_IDPASS_G(
+                player.make_udata(&self.config.now_datetime()),
+            ))
             .await
             .unwrap();
         player
@@ -238,6 +480,12 @@ impl GameServer {
         self.conns.push(player);
         self.conn_lookup.insert(cid, who);
 
+        if welcomed && !self.config.game.welcome.is_empty() {
+            if let Err(e) = self.send_welcome_telop(who).await {
+                warn!("failed to send welcome telop to {cid}: {e:?}");
+            }
+        }
+
         LoginResult::Success { cid, packet_rx }
     }
 
@@ -245,8 +493,16 @@ impl GameServer {
     async fn remove_player(&mut self, cid: CID) -> Result<()> {
         match self.conn_lookup.remove(&cid) {
             Some(who) => {
-                info!("removing player cid:{cid}");
+                info!("removing player cid:{cid} ({})", self.conns[who].addr);
+
+                // Remember where they were, in case they come back
+                self.record_session_intent(who);
+                self.quick_pool.retain(|e| e.cid != cid);
 
+                if self.conns[who].cur_room >= 0 {
+                    self.abandon_loading(who).await?;
+                    self.leave_room(who).await?;
+                }
                 if self.conns[who].cur_lobby >= 0 {
                     self.eject_from_lobby(who).await?;
                 }
@@ -257,7 +513,9 @@ impl GameServer {
                 // If this occurs, we need to fix their entry in the lookup map.
                 if let Some(pawn) = self.conns.get(who) {
                     let old_entry = self.conn_lookup.insert(pawn.cid, who);
-                    assert_eq!(old_entry, Some(self.conns.len()));
+                    if old_entry != Some(self.conns.len()) {
+                        error!("⚠️ conn_lookup for cid {} was {old_entry:?}", pawn.cid);
+                    }
                 }
 
                 // So long, gay Bowser
@@ -272,11 +530,47 @@ impl GameServer {
         Ok(())
     }
 
+    /// Check that a packet's server_cid, which clients normally leave for the server to fill
+    /// in, doesn't name somebody else.
+    /// Returns false if the packet should be dropped.
+    fn check_server_cid(&self, who: usize, packet: &Packet) -> bool {
+        use Packet::*;
+
+        let server_cid = match packet {
+            CLIENT_SHOT { server_cid, .. }
+            | CLIENT_BALLPOS { server_cid, .. }
+            | CLIENT_HOLEOUT { server_cid, .. }
+            | PKT_85 { server_cid, .. }
+            | CLIENT_PCOMMAND { server_cid, .. }
+            | PKT_194 { server_cid, .. }
+            | CLIENT_STOP_BALLPOS { server_cid, .. } => *server_cid,
+            _ => return true,
+        };
+
+        // Unset fields come through as 0 or -1
+        let conn = &self.conns[who];
+        if server_cid <= 0 || server_cid == conn.cid {
+            return true;
+        }
+
+        warn!(
+            "{} ({}) sent a packet claiming to be from cid {server_cid}",
+            conn.cid, conn.addr
+        );
+        !self.config.game.reject_spoofed_cids
+    }
+
     /// Handle a packet received from a player, dispatching to other components as necessary.
     async fn handle_player_data(&mut self, who: usize, pid: i16, packet: Packet) -> Result<()> {
         use Packet::*;
 
-        info!("[{}] {:?}", self.conns[who].cid, packet);
+        // anything in trace_packets has already been logged in full by the connection
+        let conn = &self.conns[who];
+        debug!("[{} {}] {:?}", conn.cid, conn.addr, packet);
+
+        if !self.check_server_cid(who, &packet) {
+            return Ok(());
+        }
 
         match packet {
             REQ_CHG_MODE(mode) => self.handle_change_mode(who, mode).await?,
@@ -292,17 +586,32 @@ impl GameServer {
                 unk_room_flag: _,
                 room_password,
             } => {
-                self.handle_enter_room(pid, who, room, &room_password.to_string())
+                // TODO: find out whether unk_room_flag is the client asking to watch.
+                // Until then, everyone asks to play and overflow goes to the gallery.
+                let password = room_password.to_string();
+                let intent = lobby_mgmt::EntryIntent::Player;
+                self.handle_enter_room(pid, who, room, &password, intent)
                     .await?
             }
             REQ_ULIST(mode, lobby, room) => {
                 self.handle_get_room_members(pid, who, mode, lobby, room)
                     .await?
             }
-            // 24 - exit room
+            PKT_24 => self.handle_exit_room(pid, who).await?,
             SEND_USTAT { cid, uid, stat } => self.handle_send_ustat(who, cid, uid, stat).await?,
-            // 27 - SEND_MESSAGE
-            // 28 - update room
+            SEND_MESSAGE {
+                cid,
+                msg_type,
+                name,
+                message,
+                ..
+            } => {
+                // A whisper target we can't read could only ever match the wrong player
+                let name = name.try_to_string().ok_or(GameError::BadText)?;
+                self.handle_send_message(who, cid, msg_type, &name, message)
+                    .await?
+            }
+            PKT_28(data) => self.handle_update_room(pid, who, data).await?,
             REQ_GAMESTART => self.handle_start_game(who).await?,
             CLIENT_CRCLUB(club) => self.handle_shot_club(who, club).await?,
             CLIENT_DIRECTION(dir) => self.handle_shot_dir(who, dir).await?,
@@ -319,8 +628,8 @@ impl GameServer {
                 self.handle_shot_info(who, clock, dir, power, impact, hit_x, hit_y, club)
                     .await?
             }
-            // 39 - send score
-            // 40 - request URecord
+            SEND_SCORE(report) => self.handle_send_score(who, report).await?,
+            REQ_URECORD(uid) => self.handle_get_u_record(pid, who, uid).await?,
             REQ_CRECORD {
                 uid,
                 course,
@@ -339,22 +648,37 @@ impl GameServer {
                 y,
                 z,
             } => self.handle_ballpos(who, hole, stat, x, y, z).await?,
-            // 48 - holeout
-            // 50 - ready for quick matching
-            // 52 - un-ready for quick matching
-            // 55 - rank jump complete
-            // 56 - start quick matching game
+            CLIENT_HOLEOUT {
+                hole, score, gp, ..
+            } => self.handle_holeout(who, hole, score, gp).await?,
+            REQ_ADD_RANKMEMBER(data) => self.handle_add_rank_member(who, data).await?,
+            REQ_RMV_RANKMEMBER => self.handle_remove_rank_member(who).await?,
+            PKT_55 => {
+                // This only finishes arriving in the mode the client asked for
+                let mode = self.conns[who].mode;
+                self.restore_session_intent(who, mode).await?
+            }
+            PKT_56(_) => self.handle_start_rank(who).await?,
             // 65 - look up player by UID?
             // 67 - look up player by name?
-            // 69 - send friend req
-            // 71 - get friends
-            // 73 - get inbound requests
-            // 75 - get outbound requests
-            // 77 - accept/deny request
-            // 79 - remove friend
+            PKT_69(uid) => self.handle_friend_request(pid, who, uid).await?,
+            REQ_FRIENDS => self.handle_get_friends(pid, who).await?,
+            REQ_INBOUND_REQUESTS => self.handle_get_inbound_requests(pid, who).await?,
+            REQ_OUTBOUND_REQUESTS => self.handle_get_outbound_requests(pid, who).await?,
+            PKT_77(uid, answer) => {
+                self.handle_answer_friend_request(pid, who, uid, answer)
+                    .await?
+            }
+            PKT_79(uid) => self.handle_remove_friend(pid, who, uid).await?,
             // 81 - cancel request
             REQ_APPEAR(cid) => self.get_active_appearance(pid, who, cid).await?,
-            // 85 - chrpos
+            PKT_85 {
+                server_cid: _,
+                unk: facing,
+                x,
+                y,
+                z,
+            } => self.handle_chrpos(who, facing, x, y, z).await?,
             REQ_ULIST_L(mode, index) => {
                 self.handle_req_lobby_members(pid, who, index, mode).await?
             }
@@ -365,7 +689,7 @@ impl GameServer {
                 self.handle_create_first_character(who, appear).await?
             }
             // 97 - ? uid ?
-            // 98 - ? cid ?
+            PKT_98(cid) => self.handle_get_chruids(pid, who, cid).await?,
             REQ_CHRDATA { cid, chr_uid } => self.handle_req_chrdata(pid, who, cid, chr_uid).await?,
             GET_CHRDATA(cid) => self.handle_get_chrdata(who, cid).await?,
             REQ_CHG_APPEAR {
@@ -378,21 +702,35 @@ impl GameServer {
             }
             SET_PLAYER_NAME(data) => self.handle_set_player_name(who, data).await?,
 
-            // 107 - gets global course record
-            // 109 - REQ_UNRECEIVE_SMAIL_CNT
-            // 111 - also mail related
-            // 113 - gets a mail
-            // 117 - REQ_BLOCKLIST
-            // 119 - block user
-            // 121 - unblock user
-            // 123 - search players
+            PKT_107 {
+                course,
+                season,
+                unk,
+            } => {
+                self.handle_get_g_course_record(pid, who, course, season, unk)
+                    .await?
+            }
+            REQ_UNRECEIVE_SMAIL_CNT(_) => self.handle_get_unreceived_mail_count(pid, who).await?,
+            PKT_111(_) => self.handle_get_mail_list(pid, who).await?,
+            PKT_113(_, mail_uid) => self.handle_get_mail(pid, who, mail_uid).await?,
+            PKT_115 {
+                to_uid, utf8_text, ..
+            } => self.handle_send_mail(pid, who, to_uid, utf8_text).await?,
+            REQ_BLOCKLIST(_) => self.handle_get_blocklist(pid, who).await?,
+            PKT_119(uid) => self.handle_block(pid, who, uid).await?,
+            PKT_121(uid) => self.handle_unblock(pid, who, uid).await?,
+            PKT_123 { name, flags, .. } => {
+                // There's no failure reply for a search; a query we can't read simply
+                // finds nobody, just as a search with no matches does
+                let name = name.try_to_string().ok_or(GameError::BadText)?;
+                self.handle_search_user(pid, who, &name, flags).await?
+            }
             // 125 - some stat update
-
-            // 126 - CLIENT_CUP_IN
+            CLIENT_CUP_IN { hole, score } => self.handle_holeout(who, hole, score, 0).await?,
             // 128 - REP_CLOCK
-            // 129 - room search
-            PKT_131(_) => self.handle_get_inventory(who).await?,
-            PKT_133(_) => self.handle_get_golfbag(who).await?,
+            PKT_129 { gmsv_no, .. } => self.handle_search_room(pid, who, gmsv_no).await?,
+            PKT_131(_) => self.handle_get_inventory(pid, who).await?,
+            PKT_133(_) => self.handle_get_golfbag(pid, who).await?,
 
             CLIENT_PCOMMAND {
                 server_cid: _,
@@ -402,23 +740,26 @@ impl GameServer {
             } => self.handle_send_pcommand(who, cmd_and_flag, p0, p1).await?,
             PKT_137(cid) => self.handle_get_curr_chr_uid(pid, who, cid).await?,
 
-            // 138 - REQ_CHG_CRCHRUID
+            REQ_CHG_CRCHRUID(chr_uid) => self.handle_change_curr_chr_uid(pid, who, chr_uid).await?,
             // 141 - ?
             REQ_CHG_CHR_PARAM { .. } => self.handle_req_chg_chr_param(who, packet).await?,
-            // 147 - get sell caddies
-            // 149 - delivery related
-            // 151 - employ a caddy
-            // 153 - get caddie data?
-            // 155 - use item?
-            // 158 - send delivery
-            // 160 - another delivery thing
-            // 162 - get macro data
-            // 164 - store macro
+            PKT_147 => self.handle_get_sell_caddies(who).await?,
+            PKT_149(_) => self.handle_get_deliveries(who).await?,
+            PKT_151(request) => self.handle_employ_caddie(who, request).await?,
+            // 153 - get caddie data? (reply with the request pid)
+            PKT_155(item, flag) => self.handle_use_item(who, item, flag).await?,
+            PKT_158(delivery) => self.handle_send_delivery(who, delivery).await?,
+            PKT_160 { delivery, unk4 } => self.handle_answer_delivery(who, delivery, unk4).await?,
+            PKT_162(_) => self.handle_get_macros(who).await?,
+            PKT_164 { which, text } => {
+                self.handle_save_macro(who, which, text.try_to_string())
+                    .await?
+            }
             PKT_166 => self.handle_get_salon_items(who).await?,
-            // 168 - buy salon item
-            // 170 - get title list
-            // 172 - get title
-            // 174 - REQ_CHG_TITLE
+            PKT_168(item) => self.handle_buy_salon_item(who, item).await?,
+            PKT_170 => self.handle_get_titles(pid, who).await?,
+            PKT_172(title) => self.handle_obtain_title(pid, who, title).await?,
+            REQ_CHG_TITLE(title) => self.handle_change_title(pid, who, title).await?,
             // 176 - client-side send telop
             // 179 - CompeLounge related
             REQ_UDATA(uid) => self.handle_req_udata(pid, who, uid).await?,
@@ -427,23 +768,26 @@ impl GameServer {
 
             PKT_189 { hold_item } => self.handle_chg_holdbox(who, hold_item).await?,
 
-            // 192 - game centre/code centre related
+            PKT_192(_) => self.handle_count_deliveries(pid, who).await?,
             // 194 - send command 2
             // 196 - buy item by ticket
-            // 198 - play UFO game
+            PKT_198(mode) => self.handle_play_ufo(who, mode).await?,
             // 200 - employ caddy by ticket
-            // 202 - buy salon item by ticket
-            // 204 - get NP?
-            // 208 - buy item by NP
-            // 211 - set team
-            // 213 - play slots game
+            PKT_202 { ticket, item } => {
+                self.handle_buy_salon_item_by_ticket(who, ticket, item)
+                    .await?
+            }
+            PKT_204(_) => self.handle_get_np(pid, who).await?,
+            PKT_208(item) => self.handle_buy_item_by_np(who, item).await?,
+            PKT_211(team) => self.handle_set_team(who, team).await?,
+            PKT_213(mode) => self.handle_play_slots(who, mode).await?,
             // 215 - set quick settings itemon
-            // 216 - REQ_CHG_OWNER?
+            REQ_CHG_OWNER(cid) => self.handle_change_owner(who, cid).await?,
             // 217 - accept/deny owner transfer?
-            // 219 - kick user?
+            PKT_219(cid) => self.handle_kick_member(who, cid).await?,
             // 222 - ReqChgCaddieByItem
-            // 227 - GameCenter get number of plays?
-            // 229 - one type of ping
+            PKT_227 => self.handle_get_slots_plays(who).await?,
+            PKT_229 => self.handle_ping(pid, who).await?,
             // 232 - update game options
             CLIENT_STOP_BALLPOS {
                 server_cid: _,
@@ -454,13 +798,19 @@ impl GameServer {
                 z,
             } => self.handle_stop_ballpos(who, hole, stat, x, y, z).await?,
 
-            // 238 - REQ_ADD_GP
-            // 240 - reload room data?
-            // 241 - CaddieItemRecoveryOB_Task ItemUseRequest - USE_HOLDITEM?
-            // 246 - return lounge all
-            // 250 - REQ_PING
-            // 263 - init recycle system
-            // 266 - start recycling
+            REQ_ADD_GP { unk: _, result_gp } => self.handle_add_gp(who, result_gp).await?,
+            PKT_240 { mode, lobby, room } => {
+                self.handle_refresh_room(pid, who, mode, lobby, room)
+                    .await?
+            }
+            PKT_241(item) => self.handle_use_hold_item(who, item).await?,
+            PKT_246 => self.handle_return_lounge_all(who).await?,
+            REQ_PING(value) => self.handle_discon_check_ping(pid, who, value).await?,
+            PKT_263 => self.handle_init_recycling(who).await?,
+            PKT_266 {
+                index,
+                is_gold_ticket,
+            } => self.handle_recycle(who, index, is_gold_ticket).await?,
             GET_MODECTRL => {
                 let modectrl = ModeCtrl { flags: [true; 92] };
                 self.conns[who]
@@ -468,16 +818,22 @@ impl GameServer {
                     .await?;
             }
 
-            // 270 - redeem code
-            // 272 - redeem code
+            PKT_270(code) => {
+                let code = code.try_to_string().unwrap_or_default();
+                self.handle_redeem_code(who, &code).await?
+            }
+            PKT_272(code) => {
+                let code = code.try_to_string().unwrap_or_default();
+                self.handle_redeem_code_quietly(pid, who, &code).await?
+            }
             PKT_274 => self.handle_init_single_mode(who).await?,
 
-            // 276 - trash items
-            // 279 - send invite
+            PKT_276 { count, items } => self.handle_trash_items(who, count, &items).await?,
+            PKT_279(target) => self.handle_invite(who, target).await?,
             // 283 - GG CSAuth response
-            // 286 - retire?
-            // 308 - REQ_SVITEMDATA
-            // 311 - REQ_CLUBDATA
+            PKT_286 => self.handle_retire(who).await?,
+            REQ_SVITEMDATA => self.handle_get_svitem_data(who).await?,
+            REQ_CLUBDATA => self.handle_get_club_data(who).await?,
             // 316 - debug message
             _ => {
                 error!("🔥 unhandled!");
@@ -487,27 +843,45 @@ impl GameServer {
         Ok(())
     }
 
-    fn start(db: DBTask) -> mpsc::Sender<Message> {
+    fn new(db: DBTask, config: Arc<Config>) -> GameServer {
+        GameServer {
+            next_cid: MIN_CID,
+            conns: Vec::new(),
+            conn_lookup: BTreeMap::new(),
+            lobbies: lobby_mgmt::create_initial_lobbies(),
+            session_intents: BTreeMap::new(),
+            quick_pool: Vec::new(),
+            shop_items: build_sell_list(),
+            salon_items: match &config.shop.salon_items {
+                Some(items) => items.clone(),
+                None => build_salon_list(),
+            },
+            caddie_items: build_caddie_list(),
+            recycle_recipes: build_recycle_recipes(),
+            rng: StdRng::from_entropy(),
+            config,
+            db,
+        }
+    }
+
+    fn start(db: DBTask, config: Arc<Config>) -> mpsc::Sender<Message> {
         let (msg_tx, mut msg_rx) = mpsc::channel(1024);
 
         tokio::spawn(async move {
-            let mut gs = GameServer {
-                next_cid: 600,
-                conns: Vec::new(),
-                conn_lookup: BTreeMap::new(),
-                lobbies: lobby_mgmt::create_initial_lobbies(),
-                shop_items: build_sell_list(),
-                salon_items: build_salon_list(),
-                db,
-            };
+            let mut gs = GameServer::new(db, config);
 
             while let Some(msg) = msg_rx.recv().await {
                 match msg {
-                    Message::Login(p, resp) => {
-                        let result = gs.handle_login(p).await;
-                        // something has gone really wrong if the receiver has been dropped
-                        resp.send(result)
-                            .expect("LoginResult should always be received!");
+                    Message::Login(p, addr, resp) => {
+                        let result = gs.handle_login(p, addr).await;
+                        // If the receiver has been dropped, the connection died mid-handshake
+                        // and nobody will ever send a Logout for this player
+                        if let Err(LoginResult::Success { cid, .. }) = resp.send(result) {
+                            warn!("connection for cid={cid} went away during the handshake");
+                            if let Err(e) = gs.remove_player(cid).await {
+                                error!("failed to clean up abandoned player {cid}: {e:?}");
+                            }
+                        }
                     }
 
                     Message::Logout(cid) => {
@@ -517,10 +891,30 @@ impl GameServer {
                         }
                     }
 
+                    Message::SaveAll(resp) => match gs.save_all().await {
+                        Ok(count) => {
+                            let _ = resp.send(count);
+                        }
+                        Err(e) => error!("failed to save all players: {e:?}"),
+                    },
+
+                    Message::ResetUser {
+                        uid,
+                        keep_profile,
+                        resp,
+                    } => {
+                        let _ = resp.send(gs.reset_user(uid, keep_profile).await);
+                    }
+
                     Message::PlayerData { cid, pid, packet } => match gs.conn_lookup.get(&cid) {
                         Some(&who) => {
                             if let Err(e) = gs.handle_player_data(who, pid, packet).await {
-                                error!("error while handling pid={pid} from cid={cid}: {e:?}");
+                                if let Some(e) = e.downcast_ref::<GameError>() {
+                                    // the client has already been told, if it needed to be
+                                    warn!("rejected pid={pid} from cid={cid}: {e}");
+                                } else {
+                                    error!("error while handling pid={pid} from cid={cid}: {e:?}");
+                                }
                             }
                         }
                         None => {
@@ -535,17 +929,27 @@ impl GameServer {
     }
 }
 
-pub async fn run<A: ToSocketAddrs>(db: DBTask, config: Arc<ServerConfig>, addr: A) -> Result<()> {
-    let acceptor = TlsAcceptor::from(config);
-    let listener = TcpListener::bind(addr).await?;
+/// Spin up the game server task
+pub fn start(db: DBTask, config: Arc<Config>) -> GameTask {
+    GameTask {
+        tx: GameServer::start(db, config),
+    }
+}
 
-    let gs2 = GameServer::start(db);
+pub async fn run<A: ToSocketAddrs>(
+    gs2: GameTask,
+    tls_config: Arc<ServerConfig>,
+    config: Arc<Config>,
+    addr: A,
+) -> Result<()> {
+    let acceptor = TlsAcceptor::from(tls_config);
+    let listener = TcpListener::bind(addr).await?;
 
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, addr) = listener.accept().await?;
         let acceptor = acceptor.clone();
-        let gs2 = gs2.clone();
+        let gs2 = gs2.tx.clone();
 
-        conn_task::run_connection(gs2, stream, acceptor);
+        conn_task::run_connection(gs2, stream, addr, acceptor, config.clone());
     }
 }
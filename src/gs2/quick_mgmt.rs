@@ -0,0 +1,306 @@
+use anyhow::{bail, Result};
+use log::{info, warn};
+use rand::prelude::*;
+
+use crate::data::rating::MatchResult;
+use crate::packets::{
+    Mode, Outcome, Packet, Packet19, Packet50Data, QuickMatchHoleSetting, QuickMatchRuleSetting,
+    QuickMatchTimeSetting, RoomStat, Status, CID,
+};
+
+use super::{error::GameError, game_mgmt::generate_vs_game, GameServer};
+
+/// The lobby within the quick-match mode that holds every quick match room
+const QUICK_LOBBY: i8 = 0;
+
+/// A player who is waiting to be matched
+pub(super) struct QuickEntry {
+    pub(super) cid: CID,
+    pub(super) data: Packet50Data,
+}
+
+/// A quick match preference that can be left up to chance
+trait QuickSetting: Copy + Eq + Sized + 'static {
+    const RANDOM: Self;
+    const CHOICES: &'static [Self];
+}
+
+impl QuickSetting for QuickMatchRuleSetting {
+    const RANDOM: Self = Self::Random;
+    const CHOICES: &'static [Self] = &[Self::Stroke, Self::Match];
+}
+
+impl QuickSetting for QuickMatchHoleSetting {
+    const RANDOM: Self = Self::Random;
+    const CHOICES: &'static [Self] = &[Self::Hole3, Self::Hole6, Self::Hole9, Self::Hole18];
+}
+
+impl QuickSetting for QuickMatchTimeSetting {
+    const RANDOM: Self = Self::Random;
+    const CHOICES: &'static [Self] = &[Self::Time30, Self::Time60, Self::Time90, Self::Time120];
+}
+
+/// Pick a setting that satisfies both players.
+/// Random defers to the other player's choice; if both are Random, we choose.
+/// Returns None if the players have asked for two different things.
+fn resolve<T: QuickSetting, R: Rng>(a: T, b: T, rng: &mut R) -> Option<T> {
+    match (a == T::RANDOM, b == T::RANDOM) {
+        (true, true) => T::CHOICES.choose(rng).copied(),
+        (true, false) => Some(b),
+        (false, true) => Some(a),
+        (false, false) if a == b => Some(a),
+        (false, false) => None,
+    }
+}
+
+/// The concrete settings for a quick match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct QuickSettings {
+    pub(super) rule: QuickMatchRuleSetting,
+    pub(super) holes: QuickMatchHoleSetting,
+    pub(super) time: QuickMatchTimeSetting,
+    pub(super) item_on: bool,
+}
+
+impl QuickSettings {
+    /// Work out what two players should play, if they're compatible at all
+    pub(super) fn resolve<R: Rng>(a: &Packet50Data, b: &Packet50Data, rng: &mut R) -> Option<Self> {
+        // Items are on or off for the whole match, so both players have to agree
+        if a.item_on != b.item_on {
+            return None;
+        }
+
+        Some(QuickSettings {
+            rule: resolve(a.rule_setting, b.rule_setting, rng)?,
+            holes: resolve(a.hole_setting, b.hole_setting, rng)?,
+            time: resolve(a.time_setting, b.time_setting, rng)?,
+            item_on: a.item_on && b.item_on,
+        })
+    }
+
+    fn make_room_data(self) -> Result<Packet19> {
+        let num_holes = match self.holes {
+            QuickMatchHoleSetting::Hole3 => 3,
+            QuickMatchHoleSetting::Hole6 => 6,
+            QuickMatchHoleSetting::Hole9 => 9,
+            QuickMatchHoleSetting::Hole18 => 18,
+            QuickMatchHoleSetting::Random => bail!("unresolved hole setting"),
+        };
+        let time_limit = match self.time {
+            QuickMatchTimeSetting::Time30 => 30,
+            QuickMatchTimeSetting::Time60 => 60,
+            QuickMatchTimeSetting::Time90 => 90,
+            QuickMatchTimeSetting::Time120 => 120,
+            QuickMatchTimeSetting::Random => bail!("unresolved time setting"),
+        };
+        let rules = match self.rule {
+            QuickMatchRuleSetting::Stroke => 0,
+            QuickMatchRuleSetting::Match => 1,
+            QuickMatchRuleSetting::Random => bail!("unresolved rule setting"),
+        };
+
+        Ok(Packet19 {
+            mode: Mode::Quick,
+            lobby: QUICK_LOBBY,
+            room_stat: RoomStat {
+                member_max: 2,
+                rules,
+                time_limit,
+                season: 1,
+                num_holes,
+                ..Default::default()
+            },
+            room_name: "Quick Match".parse()?,
+            room_password: "".parse()?,
+        })
+    }
+}
+
+impl GameServer {
+    /// Add a player to the pool of players waiting for a quick match
+    pub(super) async fn handle_add_rank_member(
+        &mut self,
+        who: usize,
+        data: Packet50Data,
+    ) -> Result<()> {
+        let cid = self.conns[who].cid;
+        if self.conns[who].mode != Mode::Quick {
+            warn!("{cid} tried to join the quick match pool outside of quick mode");
+            self.conns[who]
+                .write(Packet::ACK_ADD_RANKMEMBER(Status::Err))
+                .await?;
+            return Ok(());
+        }
+        if self.quick_pool.iter().any(|e| e.cid == cid) || self.conns[who].cur_room >= 0 {
+            warn!("{cid} tried to join the quick match pool twice");
+            self.conns[who]
+                .write(Packet::ACK_ADD_RANKMEMBER(Status::Err))
+                .await?;
+            return Ok(());
+        }
+
+        self.quick_pool.push(QuickEntry { cid, data });
+        self.conns[who]
+            .write(Packet::ACK_ADD_RANKMEMBER(Status::OK))
+            .await?;
+
+        self.try_quick_match().await
+    }
+
+    /// Take a player back out of the quick match pool
+    pub(super) async fn handle_remove_rank_member(&mut self, who: usize) -> Result<()> {
+        let cid = self.conns[who].cid;
+        let status = match self.quick_pool.iter().position(|e| e.cid == cid) {
+            Some(index) => {
+                self.quick_pool.remove(index);
+                Status::OK
+            }
+            None => Status::Err,
+        };
+
+        self.conns[who]
+            .write(Packet::ACK_RMV_RANKMEMBER(status))
+            .await
+    }
+
+    /// Check whether two waiting players are willing to face each other at all
+    fn can_meet(&self, a: CID, b: CID) -> bool {
+        match (self.find_conn(a), self.find_conn(b)) {
+            (Some(a), Some(b)) => !self.either_blocks(a, b),
+            _ => false,
+        }
+    }
+
+    /// Find the longest-waiting pair of compatible players
+    fn find_quick_pair<R: Rng>(&self, rng: &mut R) -> Option<(usize, usize, QuickSettings)> {
+        for (i, a) in self.quick_pool.iter().enumerate() {
+            for (j, b) in self.quick_pool.iter().enumerate().skip(i + 1) {
+                if !self.can_meet(a.cid, b.cid) {
+                    continue;
+                }
+                if let Some(settings) = QuickSettings::resolve(&a.data, &b.data, rng) {
+                    return Some((i, j, settings));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Pair up the longest-waiting compatible players, if there are any
+    async fn try_quick_match(&mut self) -> Result<()> {
+        let (i, j, settings) = match self.find_quick_pair(&mut thread_rng()) {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+
+        // j is always after i, so remove it first
+        let b = self.quick_pool.remove(j);
+        let a = self.quick_pool.remove(i);
+        info!("quick match: {} vs {} with {settings:?}", a.cid, b.cid);
+
+        let data = settings.make_room_data()?;
+        let room_num =
+            match self
+                .lobbies
+                .create_room(Mode::Quick, QUICK_LOBBY, data, vec![a.cid, b.cid])
+            {
+                Some(room_num) => room_num,
+                None => return Err(GameError::NoFreeRooms.into()),
+            };
+        if let Some(room) = self.lobbies.room_mut(Mode::Quick, QUICK_LOBBY, room_num) {
+            room.items_allowed = settings.item_on;
+        }
+
+        // Tell each player about their opponent
+        for (me, them) in [(&a, &b), (&b, &a)] {
+            let (index, other) = match (self.find_conn(me.cid), self.find_conn(them.cid)) {
+                (Some(index), Some(other)) => (index, other),
+                _ => continue,
+            };
+
+            let char_type = self.conns[other]
+                .active_character()
+                .map(|c| c.appearance.character_id.to_index() as i8)
+                .unwrap_or(0);
+
+            self.lobbies
+                .add_lobby_member(Mode::Quick, QUICK_LOBBY, me.cid);
+            self.conns[index].mode = Mode::Quick;
+            self.conns[index].cur_lobby = QUICK_LOBBY;
+            self.conns[index].cur_room = room_num;
+            self.conns[index]
+                .write(Packet::SEND_RANK_EDATA {
+                    char_type,
+                    rank: them.data.rank,
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Both players have seen their opponent, so kick off the match
+    pub(super) async fn handle_start_rank(&mut self, who: usize) -> Result<()> {
+        let conn = &self.conns[who];
+        let room = match self
+            .lobbies
+            .room_mut(Mode::Quick, conn.cur_lobby, conn.cur_room)
+        {
+            Some(room) => room,
+            None => return Err(GameError::NotInRoom.into()),
+        };
+
+        if room.in_round {
+            // the other player got there first
+            return Ok(());
+        }
+        room.start_round();
+
+        let packet = generate_vs_game(Mode::Quick, room);
+        let members = room.members.clone();
+        for cid in members {
+            if let Some(index) = self.find_conn(cid) {
+                self.conns[index].write(packet.clone()).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move a player's quick match rating after a match, and tell them where they stand.
+    /// This only changes the user in memory; saving is left to the caller.
+    pub(super) async fn settle_quick_rating(
+        &mut self,
+        who: usize,
+        item_on: bool,
+        outcome: Outcome,
+    ) -> Result<()> {
+        let result = match outcome {
+            Outcome::Win | Outcome::UnearnedWin => MatchResult::Win,
+            Outcome::Lose | Outcome::Aborted => MatchResult::Loss,
+            Outcome::Draw | Outcome::Conv | Outcome::Invalid => MatchResult::Neutral,
+        };
+
+        let conn = &mut self.conns[who];
+        let rating = conn.user.rating_mut(item_on);
+        let old_rank = rating.add_result(result);
+        let rating = *rating;
+        info!(
+            "{} quick match rating is now {} (rank {})",
+            conn.uid,
+            rating.score,
+            rating.rank()
+        );
+
+        conn.write(Packet::SEND_RANKDATA {
+            uid: conn.uid,
+            score: rating.score,
+            win_combo: rating.win_combo,
+            rank: rating.rank(),
+            rank_up: rating.rank() > old_rank,
+            rank_down: rating.rank() < old_rank,
+        })
+        .await
+    }
+}
@@ -1,14 +1,206 @@
 use anyhow::Result;
-use log::error;
+use log::{error, info, warn};
 
 use crate::{
-    data::record::CRecord,
-    packets::{Packet, Status, UID},
+    data::{
+        record::{CRecord, GHRecord, URecord},
+        report::{GameReport, HoleReport},
+    },
+    packets::{Mode, Outcome, Packet, Status, UID},
 };
 
 use super::GameServer;
 
+/// Holes in a round that plays the whole course
+const FULL_ROUND_HOLES: usize = 18;
+
+/// Which set of course records a round with this many holes counts towards
+fn hole_idx(num_holes: usize) -> Option<i8> {
+    match num_holes {
+        3 => Some(0),
+        6 => Some(1),
+        9 => Some(2),
+        18 => Some(3),
+        _ => None,
+    }
+}
+
 impl GameServer {
+    pub(super) async fn handle_get_u_record(&self, pid: i16, who: usize, uid: UID) -> Result<()> {
+        // the client sometimes asks with -1, meaning its own record
+        let uid = if uid == -1 { self.conns[who].uid } else { uid };
+
+        let packet = match self.db.get_u_record(uid).await {
+            Ok(data) => Packet::SEND_URECORD {
+                uid,
+                data,
+                status: Status::OK,
+            },
+            Err(e) => {
+                error!("error fetching URecord: {e:?}");
+                Packet::SEND_URECORD {
+                    uid,
+                    data: URecord::default(),
+                    status: Status::Err,
+                }
+            }
+        };
+        self.conns[who].write_with_pid(packet, pid).await?;
+
+        Ok(())
+    }
+
+    /// Count a competition finish towards the player's records.
+    /// The first three awards slots are assumed to be 1st, 2nd and 3rd place.
+    pub(super) async fn record_placement(&mut self, who: usize, placement: usize) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let mut record = self.db.get_u_record(uid).await?;
+
+        record.total_tournament_participation += 1;
+        match placement {
+            1 => record.num_1st += 1,
+            2 => record.num_2nd += 1,
+            3 => record.num_3rd += 1,
+            _ => {}
+        }
+        self.db.write_u_record(uid, record).await;
+
+        if (1..=3).contains(&placement) {
+            self.conns[who].user.awards[placement - 1] += 1;
+            self.save_user(who).await;
+        }
+
+        Ok(())
+    }
+
+    /// Count a finished (or abandoned) round towards the player's records
+    pub(super) async fn record_round(
+        &mut self,
+        who: usize,
+        mode: Mode,
+        outcome: Outcome,
+        report: Option<&GameReport>,
+        num_holes: usize,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let mut record = self.db.get_u_record(uid).await?;
+
+        record.num_rounds += 1;
+        match mode {
+            Mode::VS => record.total_vs_participation += 1,
+            Mode::Quick => record.total_quick_participation += 1,
+            _ => {}
+        }
+        match outcome {
+            Outcome::Aborted => record.num_retirements += 1,
+            Outcome::Win if record.x_74 == 0 => {
+                info!("📸 {uid} won their first round, unlocking the chat memorial");
+                record.x_74 = 1;
+            }
+            _ => {}
+        }
+        if let Some(report) = report {
+            record.total_round_gp += report.obtained_gp_round as i32;
+            record.add_report(report, num_holes);
+        }
+        self.db.write_u_record(uid, record).await;
+
+        Ok(())
+    }
+
+    /// Count a round towards the player's course records, including its per-hole
+    /// breakdown, and store the round and its breakdown in the records for the course
+    /// and each hole that anyone can beat.
+    /// `hole_order` gives the course hole number for each hole that was played.
+    pub(super) async fn record_course(
+        &mut self,
+        who: usize,
+        course: i8,
+        season: i8,
+        hole_order: &[i8],
+        report: &GameReport,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let title = self.conns[who].user.title;
+        let hole_idx = match hole_idx(hole_order.len()) {
+            Some(hole_idx) => hole_idx,
+            None => return Ok(()),
+        };
+        let mut record = self.db.get_c_record(uid, course, season, hole_idx).await?;
+        record.add_round(report);
+
+        if !report.holes_are_consistent(hole_order.len()) {
+            warn!("{uid} sent hole results that don't add up, not recording them");
+            self.db
+                .write_c_record(uid, course, season, hole_idx, record)
+                .await;
+            return Ok(());
+        }
+
+        // Shorter rounds would beat every full one on score, so only those can set
+        // a record for the whole course
+        if hole_order.len() == FULL_ROUND_HOLES {
+            let mut course_record = self.db.get_course_record(course, season).await?;
+            if course_record.add(uid, title, report) {
+                info!("🏆 {uid} set a new record on course {course}/{season}");
+                self.db
+                    .write_course_record(course, season, course_record)
+                    .await;
+            }
+        }
+
+        // The client lists holes in the order they were played
+        let holes: Vec<(i8, HoleReport)> = hole_order
+            .iter()
+            .zip(&report.holes)
+            .map(|(&hole, hole_report)| (hole, hole_report.clone()))
+            .collect();
+
+        record.add_holes(&holes);
+        self.db
+            .write_c_record(uid, course, season, hole_idx, record)
+            .await;
+
+        for (hole, hole_report) in &holes {
+            let mut record = self.db.get_hole_record(course, season, *hole).await?;
+            record.add(uid, title, hole_report);
+            self.db
+                .write_hole_record(course, season, *hole, record)
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Send the best anybody has done on a course, and on each of its holes
+    pub(super) async fn handle_get_g_course_record(
+        &self,
+        pid: i16,
+        who: usize,
+        course: i8,
+        season: i8,
+        unk: i8,
+    ) -> Result<()> {
+        let gcrecord = self
+            .db
+            .get_course_record(course, season)
+            .await?
+            .to_gc_record(course, season, unk);
+
+        let mut ghrecord: [GHRecord; 18] = Default::default();
+        for (hole, record) in ghrecord.iter_mut().enumerate() {
+            *record = self
+                .db
+                .get_hole_record(course, season, hole as i8)
+                .await?
+                .to_gh_record();
+        }
+
+        self.conns[who]
+            .write_with_pid(Packet::PKT_108 { gcrecord, ghrecord }, pid)
+            .await
+    }
+
     pub(super) async fn handle_get_c_record(
         &self,
         pid: i16,
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::data::recycle::{eco_ticket, gold_ticket};
+use crate::data::CountedItem;
+use crate::packets::{Packet, RecycleData};
+
+use super::GameServer;
+
+/// PKT_267 results
+const RECYCLE_OK: i8 = 0;
+const RECYCLE_FAILED: i8 = -1;
+
+impl GameServer {
+    /// List everything the recycling shop can make
+    pub(super) async fn handle_init_recycling(&self, who: usize) -> Result<()> {
+        let items: Vec<RecycleData> = self
+            .recycle_recipes
+            .iter()
+            .enumerate()
+            .map(|(index, recipe)| RecycleData {
+                index: index as i16,
+                unk: 0,
+                result: recipe.result.item(),
+                materials: recipe.materials,
+            })
+            .collect();
+        let packet = Packet::PKT_264 {
+            count: items.len() as i16,
+            items,
+        };
+        self.conns[who].write(packet).await?;
+        self.conns[who].write(Packet::PKT_265).await
+    }
+
+    /// Turn a ticket and five materials into something new.
+    /// Nothing is taken unless the player has everything the recipe needs, and room for
+    /// whatever comes out.
+    pub(super) async fn handle_recycle(
+        &mut self,
+        who: usize,
+        index: i16,
+        is_gold_ticket: bool,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let recipe = match usize::try_from(index)
+            .ok()
+            .and_then(|index| self.recycle_recipes.get(index))
+        {
+            Some(recipe) => recipe,
+            None => {
+                warn!("{uid} tried to recycle with unknown recipe {index}");
+                return self.conns[who].write(Packet::PKT_267(RECYCLE_FAILED)).await;
+            }
+        };
+
+        let ticket = match is_gold_ticket {
+            true => gold_ticket(),
+            false => eco_ticket(),
+        };
+        let result = recipe.roll(is_gold_ticket);
+        if !self.conns[who]
+            .user
+            .has_room_for(&[result], &self.config.shop)
+        {
+            warn!("{uid} can't hold {result:?} from recycling {index}");
+            return self.conns[who].write(Packet::PKT_267(RECYCLE_FAILED)).await;
+        }
+
+        let mut user = self.conns[who].user.clone();
+        let paid = user.remove_item(CountedItem::new(ticket, 1))
+            && recipe
+                .materials
+                .iter()
+                .all(|&material| user.remove_item(CountedItem::new(material, 1)));
+        if !paid {
+            warn!("{uid} tried to recycle {index} without the ticket or materials");
+            return self.conns[who].write(Packet::PKT_267(RECYCLE_FAILED)).await;
+        }
+
+        info!("♻️ {uid} recycled {:?} into {result:?}", recipe.materials);
+        user.add_item(result);
+
+        self.conns[who].user = user;
+        self.save_user(who).await;
+        self.conns[who].write(Packet::PKT_267(RECYCLE_OK)).await
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use log::debug;
+
+use crate::packets::{Packet, Packet19, UList};
+
+use super::GameServer;
+
+/// Most results we'll send back for a single search, across every server
+const MAX_SEARCH_RESULTS: usize = 50;
+
+/// A batch of search results, along with the server they were found on.
+///
+/// Every result that goes to the client is tagged with its server's `sv_no`, which is
+/// what lets the client offer to move over to another server to follow a player or
+/// join a room. Only this server contributes a batch for now, but the handlers are
+/// written to merge as many as they're given:
+///
+/// 1. Each game server would forward the query to its siblings (the other entries in
+///    `login.servers`) over a link we don't have yet, and collect their batches
+///    alongside its own, giving up on any sibling that doesn't answer promptly.
+/// 2. Batches are merged in server number order and cut off at `MAX_SEARCH_RESULTS`,
+///    so the client sees the same thing no matter which server it asked.
+/// 3. A sibling only ever searches its own players and rooms, so queries can't bounce
+///    around between servers.
+struct SearchBatch<T> {
+    sv_no: i8,
+    results: Vec<T>,
+}
+
+/// Flatten batches from one or more servers into the packets we send back
+fn merge_batches<T>(batches: Vec<SearchBatch<T>>, make: impl Fn(i8, T) -> Packet) -> Vec<Packet> {
+    let mut batches = batches;
+    batches.sort_by_key(|batch| batch.sv_no);
+    batches
+        .into_iter()
+        .flat_map(|batch| {
+            let sv_no = batch.sv_no;
+            batch.results.into_iter().map(move |result| (sv_no, result))
+        })
+        .take(MAX_SEARCH_RESULTS)
+        .map(|(sv_no, result)| make(sv_no, result))
+        .collect()
+}
+
+impl GameServer {
+    fn sv_no(&self) -> i8 {
+        self.config.game.sv_no
+    }
+
+    /// Find players on this server whose name contains the query, ignoring case
+    fn search_local_users(&self, query: &str) -> SearchBatch<UList> {
+        let query = query.to_lowercase();
+        let results = self
+            .conns
+            .iter()
+            .filter(|conn| !conn.name.is_empty() && conn.name.to_lowercase().contains(&query))
+            .map(|conn| conn.make_ulist())
+            .collect();
+
+        SearchBatch {
+            sv_no: self.sv_no(),
+            results,
+        }
+    }
+
+    /// List the rooms on this server that are open in a particular mode
+    fn search_local_rooms(&self, who: usize) -> Result<SearchBatch<Packet19>> {
+        let mode = self.conns[who].mode;
+        let mut results = Vec::new();
+        for (lobby_num, room) in self.lobbies.rooms(mode) {
+            results.push(room.make_room_data(mode, lobby_num)?);
+        }
+
+        Ok(SearchBatch {
+            sv_no: self.sv_no(),
+            results,
+        })
+    }
+
+    /// Look players up by name. Each match gets its own SEND_SEARCH_USER.
+    pub(super) async fn handle_search_user(
+        &self,
+        pid: i16,
+        who: usize,
+        query: &str,
+        flags: u32,
+    ) -> Result<()> {
+        // TODO: find out what the two flag bits narrow the search down to
+        debug!("player search for {query:?} with flags {:#x}", flags & 3);
+
+        if query.is_empty() {
+            return Ok(());
+        }
+
+        // Sibling servers' batches would join this one, see SearchBatch
+        let batches = vec![self.search_local_users(query)];
+        let packets = merge_batches(batches, |sv_no, mut ulist| {
+            ulist.sv_no = sv_no;
+            Packet::SEND_SEARCH_USER { sv_no, ulist }
+        });
+
+        for packet in packets {
+            self.conns[who].write_with_pid(packet, pid).await?;
+        }
+        Ok(())
+    }
+
+    /// List the rooms on a server. Each room gets its own SEND_SEARCH_ROOM.
+    pub(super) async fn handle_search_room(&self, pid: i16, who: usize, gmsv_no: i8) -> Result<()> {
+        // Requests for a particular sibling's rooms would be forwarded there instead;
+        // until that exists, we can only speak for ourselves
+        let batches = if gmsv_no < 0 || gmsv_no == self.sv_no() {
+            vec![self.search_local_rooms(who)?]
+        } else {
+            debug!("can't search rooms on server {gmsv_no} from here");
+            Vec::new()
+        };
+
+        let packets = merge_batches(batches, |sv_no, data| Packet::SEND_SEARCH_ROOM {
+            sv_no,
+            data,
+        });
+        for packet in packets {
+            self.conns[who].write_with_pid(packet, pid).await?;
+        }
+        Ok(())
+    }
+}
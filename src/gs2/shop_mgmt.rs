@@ -1,10 +1,29 @@
-use crate::data::shop::Currency;
-use crate::data::{CountedItem, Item, SellItem};
+use crate::config::unix_now;
+use crate::data::club::build_club_data;
+use crate::data::shop::{Currency, RentalPeriod};
+use crate::data::svitem::build_svitem_data;
+use crate::data::{CountedItem, SellItem};
 use anyhow::Result;
-use log::error;
+use log::{error, info, warn};
 
 use crate::gs2::GameServer;
-use crate::packets::{BuyItemResult, Packet};
+use crate::packets::{BuyItemResult, Packet, Status};
+
+/// Which list of items a purchase is being made from
+#[derive(Clone, Copy)]
+enum Catalog {
+    Shop,
+    Salon,
+}
+
+/// How a purchase is being paid for
+#[derive(Clone, Copy)]
+enum Payment {
+    /// In whichever currency the item is listed for
+    Listed,
+    /// With NP, at the item's NP price
+    NP,
+}
 
 impl GameServer {
     /// Return all purchasable items in regular shops to the player
@@ -17,6 +36,32 @@ impl GameServer {
         Ok(())
     }
 
+    /// Send the stats for each brand's items
+    pub(super) async fn handle_get_svitem_data(&self, who: usize) -> Result<()> {
+        let items = build_svitem_data();
+        let count = items.iter().filter(|item| item.item_code != 0).count();
+        self.conns[who]
+            .write(Packet::SEND_SVITEMDATA(items))
+            .await?;
+        self.conns[who]
+            .write(Packet::ACK_SEND_SVITEMDATA {
+                count: count as i16,
+            })
+            .await
+    }
+
+    /// Send the stats and distances for each club
+    pub(super) async fn handle_get_club_data(&self, who: usize) -> Result<()> {
+        let clubdata = build_club_data();
+        let count = clubdata.len() as i32;
+        self.conns[who]
+            .write(Packet::SEND_CLUBDATA { count, clubdata })
+            .await?;
+        self.conns[who]
+            .write(Packet::REP_END_CLUBDATA { count })
+            .await
+    }
+
     /// Return all purchasable items in the salon to the player
     pub(super) async fn handle_get_salon_items(&self, who: usize) -> Result<()> {
         let packet = Packet::SEND_SALON_ITEM_LIST {
@@ -27,44 +72,204 @@ impl GameServer {
         Ok(())
     }
 
-    fn do_buy_item(&mut self, who: usize, counted_item: CountedItem) -> Result<BuyItemResult> {
+    /// Return all caddies that can be employed to the player
+    pub(super) async fn handle_get_sell_caddies(&self, who: usize) -> Result<()> {
+        let packet = Packet::SEND_SELL_CADDIE_LIST {
+            count: self.caddie_items.len() as i16,
+            items: self.caddie_items.clone(),
+        };
+        self.conns[who].write(packet).await
+    }
+
+    fn catalog(&self, catalog: Catalog) -> &[SellItem] {
+        match catalog {
+            Catalog::Shop => &self.shop_items,
+            Catalog::Salon => &self.salon_items,
+        }
+    }
+
+    /// Find an item in a catalog and make sure the player has room for however many they want
+    fn check_purchase(
+        &self,
+        who: usize,
+        counted_item: CountedItem,
+        catalog: Catalog,
+    ) -> Result<SellItem, BuyItemResult> {
         let item = counted_item.item();
 
         // find the corresponding metadata for this item
-        let sell_item = match self.shop_items.iter().find(|s| s.item == item) {
+        let sell_item = match self.catalog(catalog).iter().find(|s| s.item == item) {
             Some(sell_item) => sell_item.clone(),
-            None => return Ok(BuyItemResult::InvalidItemType),
+            None => return Err(BuyItemResult::InvalidItemType),
         };
 
         // enforce various checks
         let current_amount = self.conns[who].user.item_amount(item);
-        if counted_item.count() <= 0 || counted_item.count() > item.category().maximum() {
-            return Ok(BuyItemResult::InvalidCount);
+        let maximum = self.config.shop.maximum(item.category());
+        if counted_item.count() <= 0 || counted_item.count() > maximum {
+            return Err(BuyItemResult::InvalidCount);
         }
 
         let new_amount = current_amount + counted_item.count();
-        if new_amount <= 0 || new_amount > item.category().maximum() {
-            return Ok(BuyItemResult::InvalidCount);
+        if new_amount <= 0 || new_amount > maximum {
+            return Err(BuyItemResult::InvalidCount);
         }
 
-        let cost = counted_item.count() * sell_item.price;
-        let cost: i32 = cost.try_into()?;
-        if !self.conns[who].user.check_balance(sell_item.currency, cost) {
-            return Ok(BuyItemResult::Balance);
+        Ok(sell_item)
+    }
+
+    fn do_buy_item(
+        &mut self,
+        who: usize,
+        counted_item: CountedItem,
+        catalog: Catalog,
+        payment: Payment,
+    ) -> Result<BuyItemResult> {
+        let sell_item = match self.check_purchase(who, counted_item, catalog) {
+            Ok(sell_item) => sell_item,
+            Err(result) => return Ok(result),
+        };
+
+        let user = &mut self.conns[who].user;
+        match payment {
+            Payment::Listed => {
+                if sell_item.currency == Currency::TicketsOnly {
+                    return Ok(BuyItemResult::NoTicket);
+                }
+
+                let cost = counted_item.count() * sell_item.price;
+                let cost: i32 = cost.try_into()?;
+                if !user.check_balance(sell_item.currency, cost) {
+                    return Ok(BuyItemResult::Balance);
+                }
+                user.adjust_balance(sell_item.currency, -cost);
+            }
+            Payment::NP => {
+                // items without an NP price can't be bought with it
+                if sell_item.sp_price == 0 {
+                    return Ok(BuyItemResult::InvalidItemType);
+                }
+
+                let cost = counted_item.count() * sell_item.sp_price;
+                let cost: i32 = cost.try_into()?;
+                if cost > user.np {
+                    return Ok(BuyItemResult::Balance);
+                }
+                user.np -= cost;
+            }
         }
 
         // we should be OK
-        self.conns[who]
-            .user
-            .adjust_balance(sell_item.currency, -cost);
-        self.conns[who].user.add_item(counted_item);
+        user.add_item(counted_item);
+
+        Ok(BuyItemResult::OK)
+    }
+
+    fn do_buy_salon_item_by_ticket(
+        &mut self,
+        who: usize,
+        ticket: CountedItem,
+        counted_item: CountedItem,
+    ) -> BuyItemResult {
+        let sell_item = match self.check_purchase(who, counted_item, Catalog::Salon) {
+            Ok(sell_item) => sell_item,
+            Err(result) => return result,
+        };
+
+        // each kind of salon item has its own ticket
+        if sell_item.item.category().salon_ticket() != Some(ticket.item().category()) {
+            return BuyItemResult::NoTicket;
+        }
+
+        // one ticket per item bought
+        let user = &mut self.conns[who].user;
+        let needed = ticket.with_count(counted_item.count());
+        if !user.remove_item(needed) {
+            return BuyItemResult::NoTicket;
+        }
+        user.add_item(counted_item);
+
+        BuyItemResult::OK
+    }
+
+    /// The quantity of the request picks how long the caddie is employed for
+    fn do_employ_caddie(&mut self, who: usize, request: CountedItem) -> Result<BuyItemResult> {
+        let caddie = request.item();
+        let sell_caddy = match self.caddie_items.iter().find(|s| s.item == caddie) {
+            Some(sell_caddy) => sell_caddy.clone(),
+            None => return Ok(BuyItemResult::InvalidItemType),
+        };
+        let (period, price) = match RentalPeriod::from_count(request.count()) {
+            Some(period) => match sell_caddy.price(period) {
+                Some(price) => (period, price),
+                None => return Ok(BuyItemResult::InvalidCount),
+            },
+            None => return Ok(BuyItemResult::InvalidCount),
+        };
+
+        if sell_caddy.currency == Currency::TicketsOnly {
+            return Ok(BuyItemResult::NoTicket);
+        }
+
+        // there's no point paying for more time on a caddie who's already here for good
+        let user = &mut self.conns[who].user;
+        if let Some(rental) = user.caddie_rental(caddie) {
+            if rental.expires_at.is_none() {
+                return Ok(BuyItemResult::InvalidCount);
+            }
+        }
+
+        let cost: i32 = price.try_into()?;
+        if !user.check_balance(sell_caddy.currency, cost) {
+            return Ok(BuyItemResult::Balance);
+        }
+
+        user.adjust_balance(sell_caddy.currency, -cost);
+        user.employ_caddie(caddie, period.seconds(), unix_now());
 
         Ok(BuyItemResult::OK)
     }
 
+    /// Try to employ a caddie using GP or SC
+    pub(super) async fn handle_employ_caddie(
+        &mut self,
+        who: usize,
+        request: CountedItem,
+    ) -> Result<()> {
+        let result = match self.do_employ_caddie(who, request) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("failed to employ caddie {request:?} for {who}: {e:?}");
+                BuyItemResult::Err
+            }
+        };
+
+        self.finish_purchase(who, result, Packet::ACK_EMPLOY_CADDIE)
+            .await
+    }
+
+    /// Tell the player how their purchase went, and save it if it worked
+    async fn finish_purchase(
+        &mut self,
+        who: usize,
+        result: BuyItemResult,
+        reply: fn(BuyItemResult) -> Packet,
+    ) -> Result<()> {
+        self.conns[who].write(reply(result)).await?;
+
+        // update the displayed balances
+        self.refresh_currencies(who).await?;
+
+        if result == BuyItemResult::OK {
+            self.save_user(who).await;
+        }
+
+        Ok(())
+    }
+
     /// Try to buy a regular item using GP or SC
     pub(super) async fn handle_buy_item(&mut self, who: usize, item: CountedItem) -> Result<()> {
-        let result = match self.do_buy_item(who, item) {
+        let result = match self.do_buy_item(who, item, Catalog::Shop, Payment::Listed) {
             Ok(r) => r,
             Err(e) => {
                 error!("failed to buy item {item:?} for {who}: {e:?}");
@@ -72,13 +277,115 @@ impl GameServer {
             }
         };
 
-        self.conns[who].write(Packet::ACK_BUY_ITEM(result)).await?;
+        self.finish_purchase(who, result, Packet::ACK_BUY_ITEM)
+            .await
+    }
 
-        // update the displayed balances
-        self.handle_get_money(-1, who).await?;
+    /// Try to buy a regular item using NP
+    pub(super) async fn handle_buy_item_by_np(
+        &mut self,
+        who: usize,
+        item: CountedItem,
+    ) -> Result<()> {
+        let result = match self.do_buy_item(who, item, Catalog::Shop, Payment::NP) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("failed to buy item {item:?} by NP for {who}: {e:?}");
+                BuyItemResult::Err
+            }
+        };
 
-        self.save_user(who).await;
+        self.finish_purchase(who, result, Packet::ACK_BUY_ITEM_BY_NP)
+            .await
+    }
 
-        Ok(())
+    /// Try to buy a salon item (an appearance unlock) using GP or SC.
+    /// Bought items go into the inventory, which is what marks them as owned.
+    pub(super) async fn handle_buy_salon_item(
+        &mut self,
+        who: usize,
+        item: CountedItem,
+    ) -> Result<()> {
+        let result = match self.do_buy_item(who, item, Catalog::Salon, Payment::Listed) {
+            Ok(r) => r,
+            Err(e) => {
+                error!("failed to buy salon item {item:?} for {who}: {e:?}");
+                BuyItemResult::Err
+            }
+        };
+
+        self.finish_purchase(who, result, Packet::ACK_BUY_SALON_ITEM)
+            .await
+    }
+
+    /// Try to buy a salon item by trading in the matching ticket
+    pub(super) async fn handle_buy_salon_item_by_ticket(
+        &mut self,
+        who: usize,
+        ticket: CountedItem,
+        item: CountedItem,
+    ) -> Result<()> {
+        let result = self.do_buy_salon_item_by_ticket(who, ticket, item);
+        self.finish_purchase(who, result, Packet::ACK_BUY_SALON_ITEM_BY_TICKET)
+            .await
+    }
+
+    /// How much GP trashing some items gives back, if refunds are turned on.
+    /// Only items sold for GP are worth anything.
+    fn trash_refund(&self, items: &[CountedItem]) -> Result<i32> {
+        if !self.config.shop.refund_trash {
+            return Ok(0);
+        }
+
+        let mut refund = 0;
+        for counted_item in items {
+            match self
+                .shop_items
+                .iter()
+                .find(|s| s.item == counted_item.item())
+            {
+                Some(sell_item) if sell_item.currency == Currency::GP => {
+                    refund += counted_item.count() * sell_item.price / 2;
+                }
+                _ => {}
+            }
+        }
+        Ok(refund.try_into()?)
+    }
+
+    /// Throw away some items from the inventory.
+    /// Either all of them go or none do.
+    pub(super) async fn handle_trash_items(
+        &mut self,
+        who: usize,
+        count: i32,
+        items: &[CountedItem],
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let items = match usize::try_from(count) {
+            Ok(count) if count <= items.len() => &items[..count],
+            _ => {
+                warn!("{uid} tried to trash {count} items");
+                return self.conns[who].write(Packet::PKT_277(Status::Err)).await;
+            }
+        };
+
+        let mut user = self.conns[who].user.clone();
+        let all_removed = items
+            .iter()
+            .all(|&ci| ci.count() > 0 && user.remove_item(ci));
+        if !all_removed {
+            warn!("{uid} tried to trash items they don't have: {items:?}");
+            return self.conns[who].write(Packet::PKT_277(Status::Err)).await;
+        }
+
+        let refund = self.trash_refund(items)?;
+        user.adjust_balance(Currency::GP, refund);
+        info!("🗑️ {uid} trashed {items:?} for {refund} GP");
+
+        self.conns[who].user = user;
+        self.save_user(who).await;
+        self.conns[who].write(Packet::PKT_277(Status::OK)).await?;
+        self.refresh_currencies(who).await
     }
 }
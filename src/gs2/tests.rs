@@ -0,0 +1,3456 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use deku::bitvec::{BitVec, BitView, Msb0};
+use deku::{DekuContainerRead, DekuContainerWrite, DekuEnumExt, DekuRead, DekuWrite};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::config::{Config, ShopConfig};
+use crate::data::appearance::AppearanceError;
+use crate::data::club::build_club_data;
+use crate::data::course;
+use crate::data::drop::{DropPool, DropTable, DropTier};
+use crate::data::exchange::{Amount, ExchangeError, ExchangeRule, Wallet};
+use crate::data::record::{CRecord, URecord};
+use crate::data::report::GameReport;
+use crate::data::shop::Currency;
+use crate::data::svitem::{build_svitem_data, SVITEM_SLOTS};
+use crate::data::{
+    Appearance, CharID, Class, CountedItem, Item, ItemCategory, ParamTuple, QuickRating, Rank,
+    SellItem, User,
+};
+use crate::db_task::{self, DBTask};
+use crate::login_server;
+use crate::packets::{
+    AckIDPassResult, BuyItemResult, Delivery, Element, EntirePacket, IDPass, Mode, Outcome, Packet,
+    Packet19, Packet50Data, PacketHeader, QuickMatchHoleSetting, QuickMatchRuleSetting,
+    QuickMatchTimeSetting, RoomNum, RoomStat, SendDeliverResult, Stat, Status, UData, UList, CID,
+};
+use crate::stream::{Connection, Frame};
+
+use super::chara_mgmt::make_chruid_packet;
+use super::color_mgmt::ColorCycle;
+use super::conn_task::{do_handshake, drain_outbound, ConnMessage, ConnReceiver};
+use super::delivery_mgmt::DELIVERY_BOX_LIMIT;
+use super::game_mgmt::{generate_vs_game, MAX_GAME_MEMBERS, ROUND_COURSE, ROUND_SEASON};
+use super::lobby_mgmt::{create_initial_lobbies, SESSION_INTENT_TTL};
+use super::quick_mgmt::QuickSettings;
+use super::{GameServer, LoginResult, Message};
+
+const LOGIN_ID: &str = "tester";
+const PASSWORD: &str = "hunter2";
+const RIVAL_LOGIN_ID: &str = "rival";
+const RIVAL_PASSWORD: &str = "correcthorse";
+const THIRD_LOGIN_ID: &str = "latecomer";
+const THIRD_PASSWORD: &str = "batterystaple";
+const FOURTH_LOGIN_ID: &str = "bystander";
+const FOURTH_PASSWORD: &str = "letmein";
+
+fn plain_appearance() -> Appearance {
+    Appearance {
+        character_id: CharID::from_index(1).unwrap(),
+        head: None,
+        face: None,
+        glasses: None,
+        tops: None,
+        bottoms: None,
+        shoes: None,
+        gloves: None,
+        wing: None,
+        club: None,
+        skirt: None,
+        hair_style: 0,
+        hair_color: 0,
+        eye_color: 0,
+        skin_color: 0,
+        face_paint: 0,
+        default_tops: None,
+        default_bottoms: None,
+        default_shoes: None,
+        default_hair_color: 0,
+        default_eye_color: 0,
+        default_skin_color: 0,
+    }
+}
+
+/// Log into an account without doing anything else
+async fn connect(gs: &mut GameServer, login_id: &str, password: &str) -> (usize, ConnReceiver) {
+    let login = IDPass {
+        username: login_id.parse().unwrap(),
+        password: password.parse().unwrap(),
+        version: 956,
+    };
+    let addr = "127.0.0.1:2051".parse().unwrap();
+    match gs.handle_login(login, addr).await {
+        LoginResult::Success { cid, packet_rx } => (gs.find_conn(cid).unwrap(), packet_rx),
+        LoginResult::Fail(code) => panic!("login failed: {code:?}"),
+    }
+}
+
+/// Log into an account and give it a character
+async fn log_in(gs: &mut GameServer, login_id: &str, password: &str) -> (usize, ConnReceiver) {
+    let (who, packet_rx) = connect(gs, login_id, password).await;
+    gs.handle_create_first_character(who, plain_appearance())
+        .await
+        .unwrap();
+
+    (who, packet_rx)
+}
+
+/// Start a server with one account, log into it and give it a character
+async fn logged_in_server() -> (GameServer, DBTask, usize, ConnReceiver) {
+    let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (who, packet_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+
+    (gs, db, who, packet_rx)
+}
+
+/// Take everything the server has sent so far
+fn received(packet_rx: &mut ConnReceiver) -> Vec<Packet> {
+    received_with_pids(packet_rx)
+        .into_iter()
+        .map(|(_, packet)| packet)
+        .collect()
+}
+
+/// Take everything the server has sent so far, along with the request pid each answers
+fn received_with_pids(packet_rx: &mut ConnReceiver) -> Vec<(Option<i16>, Packet)> {
+    std::iter::from_fn(|| packet_rx.try_recv().ok())
+        .map(|message| match message {
+            ConnMessage::Packet(pid, packet) => (pid, packet),
+            ConnMessage::Frame(frame) => (None, (*frame.packet).clone()),
+        })
+        .collect()
+}
+
+/// Throw away everything the server has sent so far, returning the last packet
+fn last_packet(packet_rx: &mut ConnReceiver) -> Option<Packet> {
+    received(packet_rx).pop()
+}
+
+/// Send a packet as though it came from a player's client
+async fn send(gs: &mut GameServer, who: usize, packet: Packet) {
+    gs.handle_player_data(who, 0, packet).await.unwrap();
+}
+
+/// Make the report a client would send after playing these holes, earning `gp` on each
+fn round_report(outcome: Outcome, hole_scores: &[i8], gp: u32) -> GameReport {
+    // Start from an all-zero report, as there are far too many fields to list
+    let zeros = [0u8; 1024];
+    let (_, mut report) = GameReport::read(zeros.view_bits::<Msb0>(), ()).unwrap();
+
+    let num_holes = hole_scores.len() as u32;
+    let strokes: i8 = hole_scores.iter().sum();
+    report.outcome = outcome;
+    report.num_strokes = strokes as u32;
+    report.num_cup_ins = num_holes;
+    report.num_putts = num_holes;
+    report.num_pars = num_holes;
+    report.score = strokes;
+    report.obtained_gp_round = gp * num_holes;
+    report.obtained_gp_all = gp * num_holes;
+    for (hole, &score) in report.holes.iter_mut().zip(hole_scores) {
+        hole.score = score;
+        hole.gp = gp;
+    }
+    report
+}
+
+#[tokio::test]
+async fn bought_club_can_be_equipped_and_persists() {
+    let (mut gs, db, who, mut packet_rx) = logged_in_server().await;
+
+    let club = Item::new(ItemCategory::ClubSet, 5);
+    let price = gs
+        .shop_items
+        .iter()
+        .find(|sell| sell.item == club)
+        .expect("club set should be in the shop")
+        .price as i32;
+    let gp_before = gs.conns[who].user.gp;
+
+    // Buy it
+    gs.handle_buy_item(who, CountedItem::new(club, 1))
+        .await
+        .unwrap();
+    let acks: Vec<Packet> = received(&mut packet_rx)
+        .into_iter()
+        .filter(|packet| matches!(packet, Packet::ACK_BUY_ITEM(_)))
+        .collect();
+    assert!(matches!(
+        acks[..],
+        [Packet::ACK_BUY_ITEM(BuyItemResult::OK)]
+    ));
+    assert_eq!(gs.conns[who].user.item_amount(club), 1);
+    assert_eq!(gs.conns[who].user.gp, gp_before - price);
+
+    // Equip it
+    let chr_uid = gs.conns[who].user.default_chr_uid;
+    let chara = gs.conns[who].active_character().unwrap().clone();
+    let request = Packet::REQ_CHG_CHR_PARAM {
+        chr_uid,
+        cr_class: Class::G,
+        power: -1,
+        impact: -1,
+        params: [ParamTuple {
+            power: 0,
+            control: 0,
+            impact: 0,
+            spin: 0,
+        }; 8],
+        club,
+        ball: chara.ball,
+        caddie: chara.caddie,
+    };
+    gs.handle_req_chg_chr_param(who, request).await.unwrap();
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_CHG_CHR_PARAM(Status::OK))
+    ));
+
+    // Everything should survive a trip through the database
+    db.flush().await.unwrap();
+    let account = db
+        .authenticate_user_to_game(LOGIN_ID.to_string(), PASSWORD.to_string())
+        .await
+        .unwrap();
+    assert_eq!(account.user.item_amount(club), 1);
+    assert_eq!(account.user.gp, gp_before - price);
+    let (_, saved) = account
+        .characters
+        .iter()
+        .find(|(c, _)| *c == chr_uid)
+        .expect("character should be saved");
+    assert_eq!(saved.club, club);
+}
+
+/// Run a test on a thread of its own.
+/// Unoptimised builds of the packet dispatcher need more stack than a test thread gets.
+fn run_with_big_stack<F: Future<Output = ()> + 'static>(test: fn() -> F) {
+    std::thread::Builder::new()
+        .stack_size(8 * 1024 * 1024)
+        .spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(test())
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+/// Bring these players into a VS room made by the first of them, and start a round there
+async fn start_vs_round(gs: &mut GameServer, players: &[usize], num_holes: i8) -> RoomNum {
+    let room_num = make_room(gs, Mode::VS, players, num_holes).await;
+    start_round(gs, players).await;
+    room_num
+}
+
+/// Bring these players into a room made by the first of them
+async fn make_room(gs: &mut GameServer, mode: Mode, players: &[usize], num_holes: i8) -> RoomNum {
+    // Everyone heads into the same lobby
+    for &who in players {
+        send(gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+        send(gs, who, Packet::REQ_CHG_MODE(mode)).await;
+        send(gs, who, Packet::REQ_ENTER_LOBBY(0)).await;
+    }
+
+    // The host makes a room, and the rest join it
+    let host = players[0];
+    let room = Packet19 {
+        mode,
+        lobby: 0,
+        room_stat: RoomStat {
+            member_max: players.len() as i8,
+            num_holes,
+            course: ROUND_COURSE,
+            season: ROUND_SEASON,
+            ..Default::default()
+        },
+        room_name: "Grudge Match".parse().unwrap(),
+        room_password: Default::default(),
+    };
+    send(gs, host, Packet::REQ_MAKE_ROOM(room)).await;
+    let room_num = gs.conns[host].cur_room;
+    assert!(room_num >= 0, "room should have been made");
+    for &who in &players[1..] {
+        let enter = Packet::REQ_ENTER_ROOM {
+            room: room_num,
+            unk_room_flag: false,
+            room_password: Default::default(),
+        };
+        send(gs, who, enter).await;
+        assert_eq!(gs.conns[who].cur_room, room_num);
+    }
+
+    room_num
+}
+
+/// Have everyone in a room ready up, start a round, and load in.
+/// The first of them should own the room.
+async fn start_round(gs: &mut GameServer, players: &[usize]) {
+    let host = players[0];
+    for &who in players {
+        let (cid, uid) = (gs.conns[who].cid, gs.conns[who].uid);
+        let stat = Stat::READY;
+        send(gs, who, Packet::SEND_USTAT { cid, uid, stat }).await;
+    }
+    send(gs, host, Packet::REQ_GAMESTART).await;
+    for &who in players {
+        send(gs, who, Packet::CLIENT_LOADSTAT(1)).await;
+        send(gs, who, Packet::CLIENT_LOADSTAT2(100)).await;
+    }
+}
+
+/// Take `strokes` shots at a hole and hole out, earning `gp`
+async fn play_hole(gs: &mut GameServer, who: usize, hole: i8, strokes: i8, gp: i16) {
+    for _ in 0..strokes {
+        let shot = Packet::CLIENT_SHOT {
+            clock: 0,
+            server_cid: -1,
+            dir: 0.0,
+            power: 100,
+            impact: 0,
+            hit_x: 0,
+            hit_y: 0,
+            club: 0,
+        };
+        send(gs, who, shot).await;
+    }
+    let holeout = Packet::CLIENT_HOLEOUT {
+        server_cid: -1,
+        hole,
+        score: strokes,
+        gp,
+    };
+    send(gs, who, holeout).await;
+}
+
+#[test]
+fn vs_round_is_recorded_for_both_players() {
+    run_with_big_stack(play_vs_round);
+}
+
+/// Two players make a room, play three holes against each other, and hand in their scores
+async fn play_vs_round() {
+    const NUM_HOLES: usize = 3;
+    const HOLE_GP: u32 = 10;
+
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, mut host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let players = [host, rival];
+    let gp_before = players.map(|who| gs.conns[who].user.gp);
+
+    let room_num = start_vs_round(&mut gs, &players, NUM_HOLES as i8).await;
+    let rival_seen = received(&mut rival_rx);
+    assert!(rival_seen
+        .iter()
+        .any(|packet| matches!(packet, Packet::ORD_GAMESTART { .. })));
+    received(&mut host_rx);
+
+    // The host takes three shots a hole, the rival four
+    let strokes = [3, 4];
+    for hole in 0..NUM_HOLES as i8 {
+        for (&who, &strokes) in players.iter().zip(&strokes) {
+            for _ in 0..strokes {
+                let shot = Packet::CLIENT_SHOT {
+                    clock: 0,
+                    server_cid: -1,
+                    dir: 0.0,
+                    power: 100,
+                    impact: 0,
+                    hit_x: 0,
+                    hit_y: 0,
+                    club: 0,
+                };
+                send(&mut gs, who, shot).await;
+                let ballpos = Packet::CLIENT_BALLPOS {
+                    server_cid: -1,
+                    hole,
+                    stat: 0,
+                    x: 1.0,
+                    y: 0.0,
+                    z: 1.0,
+                };
+                send(&mut gs, who, ballpos).await;
+            }
+            let holeout = Packet::CLIENT_HOLEOUT {
+                server_cid: -1,
+                hole,
+                score: strokes,
+                gp: HOLE_GP as i16,
+            };
+            send(&mut gs, who, holeout).await;
+        }
+    }
+
+    // Each side sees the other's shots and holeouts
+    let host_cid = gs.conns[host].cid;
+    let rival_seen = received(&mut rival_rx);
+    let host_shots = rival_seen
+        .iter()
+        .filter(|packet| matches!(packet, Packet::SEND_SHOT { cid, .. } if *cid == host_cid))
+        .count();
+    assert_eq!(host_shots, 3 * NUM_HOLES);
+    let host_holeouts = rival_seen
+        .iter()
+        .filter(|packet| matches!(packet, Packet::SEND_HOLEOUT { cid, score: 3, .. } if *cid == host_cid))
+        .count();
+    assert_eq!(host_holeouts, NUM_HOLES);
+
+    // Send in the scores, which wraps the round up
+    for (&who, &strokes) in players.iter().zip(&strokes) {
+        let report = round_report(Outcome::Win, &[strokes; NUM_HOLES], HOLE_GP);
+        send(&mut gs, who, Packet::SEND_SCORE(report)).await;
+    }
+    let round = gs.lobbies.room(Mode::VS, 0, room_num).unwrap();
+    assert!(!round.in_round, "round should be over");
+    let hole_order = round.hole_order;
+
+    // GP from the round lands in everyone's wallet
+    let round_gp = (HOLE_GP * NUM_HOLES as u32) as i32;
+    for (who, gp_before) in players.into_iter().zip(gp_before) {
+        assert_eq!(gs.conns[who].user.gp, gp_before + round_gp);
+    }
+
+    // ...and everything is written down
+    db.flush().await.unwrap();
+    let logins = [(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)];
+    for (&who, (login_id, password)) in players.iter().zip(logins) {
+        let account = db
+            .authenticate_user_to_game(login_id.to_string(), password.to_string())
+            .await
+            .unwrap();
+        assert_eq!(account.user.gp, gs.conns[who].user.gp);
+    }
+
+    for (&who, &strokes) in players.iter().zip(&strokes) {
+        let uid = gs.conns[who].uid;
+        let total = (strokes as usize * NUM_HOLES) as i8;
+
+        let record = db.get_u_record(uid).await.unwrap();
+        assert_eq!(record.num_rounds, 1);
+        assert_eq!(record.total_vs_participation, 1);
+        assert_eq!(record.total_strokes, total as i32);
+        assert_eq!(record.total_holes, NUM_HOLES as i32);
+        assert_eq!(record.total_round_gp, round_gp);
+
+        let record = db
+            .get_c_record(uid, ROUND_COURSE, ROUND_SEASON, 0)
+            .await
+            .unwrap();
+        assert_eq!(record.num_rounds, 1);
+        assert_eq!(record.best_score, total);
+    }
+
+    // Only the host won, which unlocks the chat memorial for them alone
+    let host_record = db.get_u_record(gs.conns[host].uid).await.unwrap();
+    let rival_record = db.get_u_record(gs.conns[rival].uid).await.unwrap();
+    assert_eq!((host_record.x_74, rival_record.x_74), (1, 0));
+
+    let hole = db
+        .get_hole_record(ROUND_COURSE, ROUND_SEASON, hole_order[0])
+        .await
+        .unwrap();
+    assert_eq!(hole.num_plays, 2);
+    assert_eq!(hole.best_score, 3);
+}
+
+#[test]
+fn vs_reports_are_only_paid_when_they_match_the_round() {
+    run_with_big_stack(overclaim_vs_round);
+}
+
+async fn overclaim_vs_round() {
+    const HOLE_GP: u32 = 10;
+
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let players = [host, rival];
+    let gp_before = players.map(|who| gs.conns[who].user.gp);
+    start_vs_round(&mut gs, &players, 3).await;
+    for hole in 0..3 {
+        for who in players {
+            play_hole(&mut gs, who, hole, 3, HOLE_GP as i16).await;
+        }
+    }
+
+    // The rival claims far more GP than the hole gave out
+    let report = round_report(Outcome::Win, &[3; 3], HOLE_GP);
+    send(&mut gs, host, Packet::SEND_SCORE(report)).await;
+    let report = round_report(Outcome::Win, &[3; 3], HOLE_GP * 50);
+    send(&mut gs, rival, Packet::SEND_SCORE(report)).await;
+
+    assert_eq!(gs.conns[host].user.gp, gp_before[0] + 3 * HOLE_GP as i32);
+    assert_eq!(gs.conns[rival].user.gp, gp_before[1]);
+}
+
+#[test]
+fn records_use_the_hole_scores_we_counted() {
+    run_with_big_stack(underreport_holes);
+}
+
+/// Both players take three shots a hole, but claim to have cupped in with two
+async fn underreport_holes() {
+    const NUM_HOLES: usize = 3;
+
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let players = [host, rival];
+    let room_num = start_vs_round(&mut gs, &players, NUM_HOLES as i8).await;
+
+    for hole in 0..NUM_HOLES as i8 {
+        for who in players {
+            for _ in 0..3 {
+                let shot = Packet::CLIENT_SHOT {
+                    clock: 0,
+                    server_cid: -1,
+                    dir: 0.0,
+                    power: 100,
+                    impact: 0,
+                    hit_x: 0,
+                    hit_y: 0,
+                    club: 0,
+                };
+                send(&mut gs, who, shot).await;
+            }
+            send(&mut gs, who, Packet::CLIENT_CUP_IN { hole, score: 2 }).await;
+            let holeout = Packet::CLIENT_HOLEOUT {
+                server_cid: -1,
+                hole,
+                score: 2,
+                gp: 0,
+            };
+            send(&mut gs, who, holeout).await;
+        }
+    }
+    let hole_order = gs.lobbies.room(Mode::VS, 0, room_num).unwrap().hole_order;
+
+    for who in players {
+        let mut report = round_report(Outcome::Win, &[2; NUM_HOLES], 0);
+        report.num_strokes = 3 * NUM_HOLES as u32;
+        send(&mut gs, who, Packet::SEND_SCORE(report)).await;
+    }
+
+    db.flush().await.unwrap();
+    for who in players {
+        let record = db
+            .get_c_record(gs.conns[who].uid, ROUND_COURSE, ROUND_SEASON, 0)
+            .await
+            .unwrap();
+        assert_eq!(record.best_score, 3 * NUM_HOLES as i8);
+        assert_eq!(record.array[hole_order[0] as usize], 3);
+    }
+    let hole = db
+        .get_hole_record(ROUND_COURSE, ROUND_SEASON, hole_order[0])
+        .await
+        .unwrap();
+    assert_eq!(hole.best_score, 3);
+}
+
+#[test]
+fn only_full_rounds_set_course_records() {
+    run_with_big_stack(short_and_full_rounds);
+}
+
+async fn short_and_full_rounds() {
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+
+    for num_holes in [3, 18] {
+        let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+        let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+        let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+        start_vs_round(&mut gs, &[host, rival], num_holes as i8).await;
+        for hole in 0..num_holes as i8 {
+            play_hole(&mut gs, host, hole, 3, 10).await;
+            play_hole(&mut gs, rival, hole, 4, 10).await;
+        }
+        let report = round_report(Outcome::Win, &vec![3; num_holes], 10);
+        send(&mut gs, host, Packet::SEND_SCORE(report)).await;
+        let report = round_report(Outcome::Lose, &vec![4; num_holes], 10);
+        send(&mut gs, rival, Packet::SEND_SCORE(report)).await;
+        db.flush().await.unwrap();
+
+        let record = db
+            .get_course_record(ROUND_COURSE, ROUND_SEASON)
+            .await
+            .unwrap();
+        if num_holes == 18 {
+            assert_eq!(record.best_score, Some(54));
+            assert_eq!(record.best_score_uid, gs.conns[host].uid);
+            assert_eq!(record.max_gp, 180);
+        } else {
+            assert_eq!(record.best_score, None, "a short round set a course record");
+            assert_eq!(record.max_gp, 0);
+        }
+    }
+}
+
+#[test]
+fn full_round_hole_results_are_recorded() {
+    run_with_big_stack(record_full_round_holes);
+}
+
+/// An 18-hole round where the host's report has something different on every hole
+async fn record_full_round_holes() {
+    const NUM_HOLES: usize = 18;
+
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, mut host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let room_num = start_vs_round(&mut gs, &[host, rival], NUM_HOLES as i8).await;
+    let hole_order = gs.lobbies.room(Mode::VS, 0, room_num).unwrap().hole_order;
+
+    let host_scores: Vec<i8> = (0..NUM_HOLES).map(|hole| 3 + (hole % 2) as i8).collect();
+    for (hole, &strokes) in host_scores.iter().enumerate() {
+        play_hole(&mut gs, host, hole as i8, strokes, 10).await;
+        play_hole(&mut gs, rival, hole as i8, 5, 10).await;
+
+        // A whole round's worth of shots won't fit in a connection's queue
+        received(&mut host_rx);
+        received(&mut rival_rx);
+    }
+
+    let mut report = round_report(Outcome::Win, &host_scores, 10);
+    for (hole, hole_report) in report.holes.iter_mut().enumerate() {
+        hole_report.maximum_flight_distance = 20000 + hole as u32 * 100;
+        hole_report.longest_putt = 500 + hole as u32;
+    }
+    assert!(report.holes_are_consistent(NUM_HOLES));
+    send(&mut gs, host, Packet::SEND_SCORE(report.clone())).await;
+    let rival_report = round_report(Outcome::Lose, &[5; NUM_HOLES], 10);
+    send(&mut gs, rival, Packet::SEND_SCORE(rival_report)).await;
+    db.flush().await.unwrap();
+
+    // Each hole's result is filed under the hole it was played on
+    let host_uid = gs.conns[host].uid;
+    let record = db
+        .get_c_record(host_uid, ROUND_COURSE, ROUND_SEASON, 3)
+        .await
+        .unwrap();
+    for (index, &hole) in hole_order.iter().enumerate() {
+        assert_eq!(record.array[hole as usize], host_scores[index]);
+
+        let hole_record = db
+            .get_hole_record(ROUND_COURSE, ROUND_SEASON, hole)
+            .await
+            .unwrap();
+        assert_eq!(hole_record.num_plays, 2);
+        assert_eq!(hole_record.best_score, host_scores[index]);
+        assert_eq!(hole_record.max_drive, 20000 + index as u32 * 100);
+        assert_eq!(hole_record.max_drive_uid, host_uid);
+        assert_eq!(hole_record.max_putt, 500 + index as u32);
+    }
+    assert_eq!(record.max_drive, 20000 + 17 * 100);
+
+    // Reports whose holes don't add up to the round aren't trusted
+    let mut lopsided = report.clone();
+    lopsided.holes[0].score += 1;
+    assert!(!lopsided.holes_are_consistent(NUM_HOLES));
+    let mut padded = round_report(Outcome::Win, &[3; 3], 10);
+    padded.holes[10].score = 2;
+    assert!(!padded.holes_are_consistent(3));
+}
+
+#[tokio::test]
+async fn leaderboard_ranks_by_gp_and_keeps_the_lowest_score() {
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (idle, _idle_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    let (host, rival, idle) = (gs.conns[host].uid, gs.conns[rival].uid, gs.conns[idle].uid);
+    for (uid, name) in [(host, "Host"), (rival, "Rival"), (idle, "Idle")] {
+        db.set_player_name(uid, name.to_string()).await.unwrap();
+    }
+
+    let rounds = [(host, 0, 100, 70), (host, 1, 50, 65), (rival, 0, 300, 80)];
+    for (uid, course, total_gp, best_score) in rounds {
+        let record = CRecord {
+            num_rounds: 1,
+            total_gp,
+            best_score,
+            ..Default::default()
+        };
+        db.write_c_record(uid, course, 0, 0, record).await;
+    }
+
+    let leaders = db.get_leaderboard(2).await.unwrap();
+    let ranked: Vec<_> = leaders.iter().map(|e| (e.uid, e.total_gp)).collect();
+    assert_eq!(ranked, [(rival, 300), (host, 150)]);
+    assert_eq!(leaders[1].best_score, Some(65));
+    assert_eq!(leaders[1].num_rounds, 2);
+
+    // Players who haven't finished a round yet still make up the numbers
+    let leaders = db.get_leaderboard(10).await.unwrap();
+    assert_eq!(leaders.len(), 3);
+    assert_eq!((leaders[2].uid, leaders[2].best_score), (idle, None));
+}
+
+#[test]
+fn course_records_keep_best_and_worst_apart() {
+    let mut record = CRecord::default();
+    for score in [72, 68, 75] {
+        record.add_round(&round_report(Outcome::Win, &[score], 0));
+    }
+    assert_eq!((record.best_score, record.worst_score), (68, 75));
+}
+
+#[test]
+fn absurd_report_counters_are_implausible() {
+    let report = round_report(Outcome::Win, &[3, 3, 3], 10);
+    assert!(report.is_plausible(3));
+
+    // Counters that only fit in a u32 once they've wrapped around
+    let mut wrapping = report.clone();
+    wrapping.num_pars = u32::MAX;
+    wrapping.num_birdies = 4;
+    assert!(!wrapping.is_plausible(3));
+
+    let mut slow = report.clone();
+    slow.play_time = u32::MAX;
+    assert!(!slow.is_plausible(3));
+
+    let mut far = report;
+    far.num_total_distance = u32::MAX;
+    assert!(!far.is_plausible(3));
+}
+
+#[test]
+fn player_record_totals_stop_at_their_maximum() {
+    let mut report = round_report(Outcome::Win, &[3, 3, 3], 10);
+    report.play_time = i32::MAX as u32;
+    report.num_total_distance = u32::MAX;
+
+    let mut record = URecord::default();
+    record.add_report(&report, 3);
+    record.add_report(&report, 3);
+    assert_eq!(record.total_playtime, i32::MAX);
+    assert_eq!(record.total_strokes, 18);
+}
+
+#[test]
+fn single_mode_scores_only_count_once_per_round() {
+    run_with_big_stack(score_single_round);
+}
+
+async fn score_single_round() {
+    let (mut gs, _db, who, _packet_rx) = logged_in_server().await;
+    let report = round_report(Outcome::Win, &[3, 3, 3], 10);
+    let gp_before = gs.conns[who].user.gp;
+
+    // Somebody sitting in a menu hasn't played anything
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Single)).await;
+    let sent = Packet::SEND_SCORE(report.clone());
+    assert!(gs.handle_player_data(who, 0, sent).await.is_err());
+    assert_eq!(gs.conns[who].user.gp, gp_before);
+
+    // Once they've played, they get paid for it
+    send(&mut gs, who, Packet::REQ_GAMESTART).await;
+    assert!(gs.conns[who].stat.contains(Stat::ROUND));
+    send(&mut gs, who, Packet::SEND_SCORE(report.clone())).await;
+    assert_eq!(gs.conns[who].user.gp, gp_before + 30);
+    assert!(!gs.conns[who].stat.contains(Stat::ROUND));
+
+    // ...but only the once
+    let sent = Packet::SEND_SCORE(report);
+    assert!(gs.handle_player_data(who, 0, sent).await.is_err());
+    assert_eq!(gs.conns[who].user.gp, gp_before + 30);
+}
+
+#[test]
+fn single_rounds_enable_employed_caddies() {
+    run_with_big_stack(bring_caddie_to_practice);
+}
+
+async fn bring_caddie_to_practice() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    let caddie = Item::new(ItemCategory::Caddy, 2);
+    gs.conns[who].user.add_item(caddie.one());
+
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Single)).await;
+    received(&mut packet_rx);
+    send(&mut gs, who, Packet::REQ_GAMESTART).await;
+
+    // The caddie list has to arrive before the round does
+    let packets = received(&mut packet_rx);
+    let list_at = packets
+        .iter()
+        .position(|packet| matches!(packet, Packet::PKT_231 { .. }))
+        .expect("no caddie list was sent");
+    let start_at = packets
+        .iter()
+        .position(|packet| matches!(packet, Packet::ORD_GAMESTART { .. }))
+        .expect("the round didn't start");
+    assert!(list_at < start_at);
+    match &packets[list_at] {
+        Packet::PKT_231 { count, list } => {
+            assert_eq!(*count, 1);
+            assert_eq!(list[..], [caddie.0 as i32]);
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn hole_in_one_drops_at_the_configured_rate() {
+    const ROLLS: usize = 10_000;
+
+    let ball = CountedItem::new(Item::new(ItemCategory::Ball, 1), 1);
+    let table = DropTable {
+        hole_in_one: DropPool {
+            chance: 0.25,
+            rewards: vec![ball],
+        },
+        ..Default::default()
+    };
+
+    let mut rng = StdRng::seed_from_u64(758);
+    let drops = (0..ROLLS)
+        .filter_map(|_| table.roll(DropTier::HoleInOne, &mut rng))
+        .inspect(|&item| assert_eq!(item, ball))
+        .count();
+    assert!((2300..2700).contains(&drops), "got {drops} drops");
+
+    // Nothing is configured for the other tiers
+    assert_eq!(table.roll(DropTier::Normal, &mut rng), None);
+}
+
+#[test]
+fn holes_only_drop_items_once() {
+    run_with_big_stack(replay_holeouts);
+}
+
+/// A player sinks a hole in one, then tries to claim it again and again
+async fn replay_holeouts() {
+    let ball = CountedItem::new(Item::new(ItemCategory::Ball, 1), 1);
+    let mut config = Config::default();
+    config.drops.hole_in_one = DropPool {
+        chance: 1.0,
+        rewards: vec![ball],
+    };
+
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(config));
+    gs.rng = StdRng::seed_from_u64(758);
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    start_vs_round(&mut gs, &[host, rival], 3).await;
+
+    play_hole(&mut gs, host, 0, 1, 0).await;
+    assert_eq!(gs.conns[host].user.item_amount(ball.item()), 1);
+
+    // Claiming the same hole again, a hole without a shot, or a hole that isn't
+    // in the round gets nothing
+    for hole in [0, 1, 2, 17, -1] {
+        let holeout = Packet::CLIENT_HOLEOUT {
+            server_cid: -1,
+            hole,
+            score: 1,
+            gp: 0,
+        };
+        send(&mut gs, host, holeout).await;
+    }
+    assert_eq!(gs.conns[host].user.item_amount(ball.item()), 1);
+}
+
+#[tokio::test]
+async fn only_a_second_character_counts_as_already_existing() {
+    let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+    let account = db
+        .authenticate_user_to_game(LOGIN_ID.to_string(), PASSWORD.to_string())
+        .await
+        .unwrap();
+
+    db.create_character(account.uid, plain_appearance())
+        .await
+        .unwrap();
+    let second = db.create_character(account.uid, plain_appearance()).await;
+    assert_eq!(second.unwrap_err().to_string(), "character already exists");
+
+    // An account that isn't there breaks the foreign key, which is a different problem
+    let orphan = db
+        .create_character(account.uid + 100, plain_appearance())
+        .await;
+    let error = orphan.unwrap_err().to_string();
+    assert_ne!(error, "character already exists");
+    assert!(error.contains("FOREIGN KEY"), "unexpected error: {error}");
+}
+
+#[tokio::test]
+async fn repeated_first_characters_are_acknowledged_but_not_made() {
+    let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (who, mut packet_rx) = connect(&mut gs, LOGIN_ID, PASSWORD).await;
+    received(&mut packet_rx);
+
+    gs.handle_create_first_character(who, plain_appearance())
+        .await
+        .unwrap();
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_FIRST_CHARACTER_APPEARANCE(Status::OK))
+    ));
+    let chr_uid = gs.conns[who].user.default_chr_uid;
+    assert_ne!(chr_uid, -1);
+
+    // A client retrying still hears back, and keeps the character it already has
+    gs.handle_create_first_character(who, plain_appearance())
+        .await
+        .unwrap();
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_FIRST_CHARACTER_APPEARANCE(Status::OK))
+    ));
+    assert_eq!(gs.conns[who].user.default_chr_uid, chr_uid);
+    assert_eq!(gs.conns[who].characters.len(), 1);
+}
+
+#[tokio::test]
+async fn welcome_bonus_is_only_granted_once() {
+    let ball = CountedItem::new(Item::new(ItemCategory::Ball, 1), 3);
+    let mut config = Config::default();
+    config.game.welcome.gp = 1000;
+    config.game.welcome.items = vec![ball];
+    let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+    let fresh = db
+        .authenticate_user_to_game(LOGIN_ID.to_string(), PASSWORD.to_string())
+        .await
+        .unwrap()
+        .user;
+    let mut gs = GameServer::new(db.clone(), Arc::new(config));
+    let welcomed = |packets: &[Packet]| {
+        packets
+            .iter()
+            .any(|packet| matches!(packet, Packet::PKT_304 { .. }))
+    };
+
+    // The first visit pays out and says so
+    let (who, mut packet_rx) = connect(&mut gs, LOGIN_ID, PASSWORD).await;
+    assert!(welcomed(&received(&mut packet_rx)));
+    assert_eq!(gs.conns[who].user.gp, fresh.gp + 1000);
+    assert_eq!(gs.conns[who].user.item_amount(ball.item()), 3);
+    gs.remove_player(gs.conns[who].cid).await.unwrap();
+    db.flush().await.unwrap();
+
+    // Coming back doesn't
+    let (who, mut packet_rx) = connect(&mut gs, LOGIN_ID, PASSWORD).await;
+    assert!(!welcomed(&received(&mut packet_rx)));
+    assert_eq!(gs.conns[who].user.gp, fresh.gp + 1000);
+    assert_eq!(gs.conns[who].user.item_amount(ball.item()), 3);
+    assert!(gs.conns[who].user.has_received_welcome);
+}
+
+#[tokio::test]
+async fn queued_packets_still_go_out_after_a_disconnect() {
+    let (server_end, client_end) = tokio::io::duplex(4096);
+    let mut conn = Connection::new(server_end);
+    let mut client = Connection::new(client_end);
+
+    // The server has a couple of packets lined up and then lets go of the player
+    let (packet_tx, mut packet_rx) = mpsc::channel(8);
+    packet_tx
+        .send(ConnMessage::Packet(
+            Some(93),
+            Packet::REP_MONEY { gp: 5, sc: 6 },
+        ))
+        .await
+        .unwrap();
+    packet_tx
+        .send(ConnMessage::Packet(None, Packet::PKT_93))
+        .await
+        .unwrap();
+    drop(packet_tx);
+
+    drain_outbound(&Config::default(), 600, &mut conn, &mut packet_rx).await;
+    conn.shutdown().await.unwrap();
+
+    let first = client.read_packet().await.unwrap().unwrap();
+    assert_eq!(first.header.pid, 93);
+    assert!(matches!(first.packet, Packet::REP_MONEY { gp: 5, sc: 6 }));
+    let second = client.read_packet().await.unwrap().unwrap();
+    assert!(matches!(second.packet, Packet::PKT_93));
+    assert!(client.read_packet().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn logins_are_refused_once_the_server_is_full() {
+    let mut config = Config::default();
+    config.game.max_players = 2;
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db, Arc::new(config));
+    let (host, _host_rx) = connect(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (_rival, _rival_rx) = connect(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+
+    // One past the limit is turned away
+    let login = IDPass {
+        username: THIRD_LOGIN_ID.parse().unwrap(),
+        password: THIRD_PASSWORD.parse().unwrap(),
+        version: 956,
+    };
+    let addr = "127.0.0.1:2051".parse().unwrap();
+    assert!(matches!(
+        gs.handle_login(login.clone(), addr).await,
+        LoginResult::Fail(AckIDPassResult::AccountNotError)
+    ));
+    assert_eq!(gs.conns.len(), 2);
+
+    // ...until somebody leaves
+    gs.remove_player(gs.conns[host].cid).await.unwrap();
+    assert!(matches!(
+        gs.handle_login(login, addr).await,
+        LoginResult::Success { .. }
+    ));
+}
+
+#[test]
+fn handshakes_that_drop_out_leave_nobody_behind() {
+    run_with_big_stack(drop_out_of_handshake);
+}
+
+/// One connection goes away before it hears whether it logged in, and another stays
+async fn drop_out_of_handshake() {
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let tx = GameServer::start(db, Arc::new(Config::default()));
+    let login = |login_id: &str, password: &str| IDPass {
+        username: login_id.parse().unwrap(),
+        password: password.parse().unwrap(),
+        version: 956,
+    };
+    let addr = "127.0.0.1:2051".parse().unwrap();
+    let online = || async {
+        let (resp, rx) = oneshot::channel();
+        tx.send(Message::SaveAll(resp)).await.unwrap();
+        rx.await.unwrap()
+    };
+
+    let (resp, rx) = oneshot::channel();
+    drop(rx);
+    tx.send(Message::Login(login(LOGIN_ID, PASSWORD), addr, resp))
+        .await
+        .unwrap();
+    assert_eq!(online().await, 0);
+
+    let (resp, rx) = oneshot::channel();
+    tx.send(Message::Login(
+        login(RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        addr,
+        resp,
+    ))
+    .await
+    .unwrap();
+    assert!(matches!(rx.await.unwrap(), LoginResult::Success { .. }));
+    assert_eq!(online().await, 1);
+}
+
+#[tokio::test]
+async fn skirts_and_bottoms_go_to_the_right_characters() {
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+
+    let trousers = Appearance {
+        character_id: CharID::Rusk,
+        bottoms: Some(0),
+        ..plain_appearance()
+    };
+    let skirt = Appearance {
+        character_id: CharID::Miel,
+        skirt: Some(0),
+        ..plain_appearance()
+    };
+    for (appear, (login_id, password)) in [trousers.clone(), skirt.clone()]
+        .into_iter()
+        .zip([(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)])
+    {
+        assert_eq!(appear.validate(), Ok(()));
+        let (who, mut packet_rx) = connect(&mut gs, login_id, password).await;
+        received(&mut packet_rx);
+        gs.handle_create_first_character(who, appear).await.unwrap();
+        assert!(matches!(
+            last_packet(&mut packet_rx),
+            Some(Packet::ACK_FIRST_CHARACTER_APPEARANCE(Status::OK))
+        ));
+    }
+
+    // Swapping them over doesn't work
+    let skirted_rusk = Appearance {
+        bottoms: None,
+        skirt: Some(0),
+        ..trousers
+    };
+    assert_eq!(
+        skirted_rusk.validate(),
+        Err(AppearanceError::NoSkirts(CharID::Rusk))
+    );
+    let both = Appearance {
+        bottoms: Some(0),
+        ..skirt
+    };
+    assert_eq!(both.validate(), Err(AppearanceError::BottomsAndSkirt));
+}
+
+#[test]
+fn appearance_changes_need_owned_items() {
+    run_with_big_stack(change_into_unowned_items);
+}
+
+async fn change_into_unowned_items() {
+    let (mut gs, db, who, mut packet_rx) = logged_in_server().await;
+    let cid = gs.conns[who].cid;
+    let (chr_uid, chara) = gs.conns[who].characters[0].clone();
+    let character_id = chara.appearance.character_id;
+
+    let dressed_up = Appearance {
+        tops: Some(5),
+        hair_style: 3,
+        ..chara.appearance
+    };
+    let change = |appear: &Appearance| Packet::REQ_CHG_APPEAR {
+        cid,
+        chr_uid,
+        appear: appear.clone(),
+    };
+
+    // Nothing bought yet, so it's refused
+    received(&mut packet_rx);
+    send(&mut gs, who, change(&dressed_up)).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::PKT_104(Status::Err))
+    ));
+
+    // Owning only one of them isn't enough
+    let tops = Item::new(ItemCategory::Tops(character_id), 5);
+    let hair = Item::new(ItemCategory::HairStyle(character_id), 3);
+    gs.conns[who].user.add_item(CountedItem::new(tops, 1));
+    send(&mut gs, who, change(&dressed_up)).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::PKT_104(Status::Err))
+    ));
+
+    gs.conns[who].user.add_item(CountedItem::new(hair, 1));
+    send(&mut gs, who, change(&dressed_up)).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::PKT_104(Status::OK))
+    ));
+
+    // Whatever's already being worn can stay on without owning it
+    gs.conns[who].user = User::default();
+    let dressed_down = Appearance {
+        tops: None,
+        ..dressed_up
+    };
+    send(&mut gs, who, change(&dressed_down)).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::PKT_104(Status::OK))
+    ));
+
+    let account = db
+        .authenticate_user_to_game(LOGIN_ID.to_string(), PASSWORD.to_string())
+        .await
+        .unwrap();
+    let (_, saved) = account
+        .characters
+        .iter()
+        .find(|(c, _)| *c == chr_uid)
+        .expect("character should be saved");
+    assert_eq!(saved.appearance, dressed_down);
+}
+
+#[tokio::test]
+async fn trashing_refunds_half_the_gp_price_only_when_asked() {
+    let gp_item = Item::new(ItemCategory::Ball, 1);
+    let sc_item = Item::new(ItemCategory::Ball, 2);
+    let sell = |item, currency, price| SellItem {
+        item,
+        currency,
+        marketing: Default::default(),
+        price,
+        sp_price: 0,
+    };
+
+    for refund_trash in [false, true] {
+        let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+        let mut config = Config::default();
+        config.shop.refund_trash = refund_trash;
+        let mut gs = GameServer::new(db, Arc::new(config));
+        gs.shop_items = vec![
+            sell(gp_item, Currency::GP, 301),
+            sell(sc_item, Currency::SC, 50),
+        ];
+        let (who, mut packet_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+        gs.conns[who].user.add_item(CountedItem::new(gp_item, 5));
+        gs.conns[who].user.add_item(CountedItem::new(sc_item, 1));
+        let gp_before = gs.conns[who].user.gp;
+
+        received(&mut packet_rx);
+        let trash = [CountedItem::new(gp_item, 3), CountedItem::new(sc_item, 1)];
+        gs.handle_trash_items(who, trash.len() as i32, &trash)
+            .await
+            .unwrap();
+
+        // Half of 3 x 301 GP, rounded down; SC items aren't worth anything back
+        let refund = if refund_trash { 451 } else { 0 };
+        let user = &gs.conns[who].user;
+        assert_eq!(user.item_amount(gp_item), 2);
+        assert_eq!(user.item_amount(sc_item), 0);
+        assert_eq!(user.gp, gp_before + refund);
+        let packets = received(&mut packet_rx);
+        assert!(matches!(packets[0], Packet::PKT_277(Status::OK)));
+        assert!(packets
+            .iter()
+            .any(|p| matches!(p, Packet::REP_MONEY { gp, .. } if *gp == user.gp)));
+    }
+}
+
+#[test]
+fn character_detail_replies_echo_the_request_pid() {
+    run_with_big_stack(query_character_details);
+}
+
+async fn query_character_details() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    received(&mut packet_rx);
+
+    let requests = [
+        (131, Packet::PKT_131(0)),
+        (133, Packet::PKT_133(0)),
+        (204, Packet::PKT_204(0)),
+    ];
+    for (pid, request) in requests {
+        gs.handle_player_data(who, pid, request).await.unwrap();
+        let replies = received_with_pids(&mut packet_rx);
+        assert!(!replies.is_empty(), "nothing came back for {pid}");
+        for (reply_pid, reply) in replies {
+            assert_eq!(reply_pid, Some(pid), "{reply:?} has the wrong pid");
+        }
+    }
+}
+
+#[test]
+fn money_requests_echo_the_pid_but_refreshes_dont() {
+    run_with_big_stack(ask_for_money);
+}
+
+async fn ask_for_money() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    received(&mut packet_rx);
+
+    gs.handle_player_data(who, 93, Packet::PKT_93)
+        .await
+        .unwrap();
+    assert!(matches!(
+        received_with_pids(&mut packet_rx)[..],
+        [(Some(93), Packet::REP_MONEY { .. })]
+    ));
+
+    // Buying something pushes the new balance unprompted, so it isn't tied to a request
+    let ball = CountedItem::new(Item::new(ItemCategory::Ball, 1), 1);
+    gs.handle_buy_item(who, ball).await.unwrap();
+    let refreshes: Vec<Option<i16>> = received_with_pids(&mut packet_rx)
+        .into_iter()
+        .filter(|(_, packet)| matches!(packet, Packet::REP_MONEY { .. }))
+        .map(|(pid, _)| pid)
+        .collect();
+    assert_eq!(refreshes, [None]);
+}
+
+#[tokio::test]
+async fn stack_maximum_overrides_apply_to_purchases() {
+    let mut config = Config::default();
+    config.shop.stack_maximums.insert("Ball".to_string(), 2);
+    let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db, Arc::new(config));
+    let (who, mut packet_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let ball = Item::new(ItemCategory::Ball, 1);
+    assert_eq!(gs.config.shop.maximum(ItemCategory::Ball), 2);
+    let bought = |packet_rx: &mut ConnReceiver| {
+        received(packet_rx)
+            .into_iter()
+            .find_map(|packet| match packet {
+                Packet::ACK_BUY_ITEM(result) => Some(result),
+                _ => None,
+            })
+    };
+
+    // Up to the new cap is fine, but not a single one past it
+    received(&mut packet_rx);
+    gs.handle_buy_item(who, CountedItem::new(ball, 2))
+        .await
+        .unwrap();
+    assert!(matches!(bought(&mut packet_rx), Some(BuyItemResult::OK)));
+    gs.handle_buy_item(who, ball.one()).await.unwrap();
+    assert!(matches!(
+        bought(&mut packet_rx),
+        Some(BuyItemResult::InvalidCount)
+    ));
+    assert_eq!(gs.conns[who].user.item_amount(ball), 2);
+
+    // Categories that aren't overridden keep their usual limit
+    let gauge = ItemCategory::CarryItemPowerGauge;
+    assert_eq!(gs.config.shop.maximum(gauge), gauge.maximum());
+}
+
+#[tokio::test]
+async fn delivery_box_count_includes_everything_waiting() {
+    let (gs, db, who, mut packet_rx) = logged_in_server().await;
+    let uid = gs.conns[who].uid;
+
+    let ball = CountedItem::new(Item::new(ItemCategory::Ball, 1), 1);
+    for _ in 0..3 {
+        let message = "for you".to_string();
+        assert!(db
+            .deposit_delivery(uid, uid, ball, message, DELIVERY_BOX_LIMIT)
+            .await
+            .unwrap());
+    }
+
+    received(&mut packet_rx);
+    gs.handle_count_deliveries(0, who).await.unwrap();
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::PKT_193 {
+            unk: 0,
+            num_items: 3
+        })
+    ));
+}
+
+#[tokio::test]
+async fn online_recipients_refuse_deliveries_by_their_live_setting() {
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (sender, mut sender_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (recipient, _recipient_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let ball = Item::new(ItemCategory::Ball, 1);
+    gs.conns[sender].user.add_item(CountedItem::new(ball, 2));
+    let delivery = Delivery {
+        unk1: 0,
+        dest_uid: gs.conns[recipient].uid,
+        item: ball,
+        unk2: 0,
+        delivery_index: 0,
+        unk3: [0; 3],
+        msg: "for you".parse().unwrap(),
+    };
+
+    // Saved as accepting deliveries, but refusing them now
+    gs.conns[recipient].user.refuses_deliveries = true;
+    received(&mut sender_rx);
+    gs.handle_send_delivery(sender, delivery.clone())
+        .await
+        .unwrap();
+    assert!(matches!(
+        last_packet(&mut sender_rx),
+        Some(Packet::ACK_SEND_DELIVER(
+            0,
+            SendDeliverResult::DeliveryDisabled
+        ))
+    ));
+
+    // Saved as refusing deliveries, but accepting them now
+    gs.save_user(recipient).await;
+    gs.conns[recipient].user.refuses_deliveries = false;
+    gs.handle_send_delivery(sender, delivery).await.unwrap();
+    assert!(matches!(
+        last_packet(&mut sender_rx),
+        Some(Packet::ACK_SEND_DELIVER(0, SendDeliverResult::OK))
+    ));
+    assert_eq!(gs.conns[sender].user.item_amount(ball), 1);
+}
+
+#[test]
+fn character_positions_are_kept_on_the_course() {
+    let far = course::MAX_EXTENT * 10.0;
+    assert_eq!(
+        course::clamp_position(far, 1.0, -far),
+        Some((course::MAX_EXTENT, 1.0, -course::MAX_EXTENT))
+    );
+    assert_eq!(
+        course::clamp_position(5.0, -2.0, 3.0),
+        Some((5.0, -2.0, 3.0))
+    );
+    assert_eq!(course::clamp_position(f32::NAN, 0.0, 0.0), None);
+    assert_eq!(course::clamp_position(0.0, f32::INFINITY, 0.0), None);
+}
+
+#[test]
+fn competition_rank_limits_keep_out_higher_ranks() {
+    run_with_big_stack(enter_rank_limited_room);
+}
+
+async fn enter_rank_limited_room() {
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (novice, mut novice_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (veteran, mut veteran_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    gs.conns[novice].user.class = Rank::E2;
+    gs.conns[veteran].user.class = Rank::A1;
+    for who in [host, novice, veteran] {
+        send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+        send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Competition)).await;
+        send(&mut gs, who, Packet::REQ_ENTER_LOBBY(0)).await;
+    }
+
+    // Nobody above D1 may play here
+    let room = Packet19 {
+        mode: Mode::Competition,
+        lobby: 0,
+        room_stat: RoomStat {
+            member_max: 4,
+            num_holes: 3,
+            course: ROUND_COURSE,
+            season: ROUND_SEASON,
+            limit_b_0: 1,
+            limit_b_1: Rank::D1 as u8,
+            ..Default::default()
+        },
+        room_name: "Beginners Only".parse().unwrap(),
+        room_password: Default::default(),
+    };
+    send(&mut gs, host, Packet::REQ_MAKE_ROOM(room)).await;
+    let room_num = gs.conns[host].cur_room;
+    let enter = || Packet::REQ_ENTER_ROOM {
+        room: room_num,
+        unk_room_flag: false,
+        room_password: Default::default(),
+    };
+
+    received(&mut veteran_rx);
+    send(&mut gs, veteran, enter()).await;
+    match last_packet(&mut veteran_rx) {
+        Some(Packet::ACK_ENTER_ROOM(data)) => assert!(data.room_stat.room < 0),
+        other => panic!("expected a refusal, got {other:?}"),
+    }
+    assert_eq!(gs.conns[veteran].cur_room, -1);
+
+    received(&mut novice_rx);
+    send(&mut gs, novice, enter()).await;
+    match last_packet(&mut novice_rx) {
+        Some(Packet::ACK_ENTER_ROOM(data)) => assert_eq!(data.room_stat.room, room_num),
+        other => panic!("expected to get in, got {other:?}"),
+    }
+    let room = gs.lobbies.room(Mode::Competition, 0, room_num).unwrap();
+    assert_eq!(room.num_players(), 2);
+}
+
+#[test]
+fn only_players_can_use_items_in_a_round() {
+    run_with_big_stack(use_items_from_the_gallery);
+}
+
+async fn use_items_from_the_gallery() {
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (watcher, mut watcher_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    let room_num = start_vs_round(&mut gs, &[host, rival], 3).await;
+    let room = gs.lobbies.room_mut(Mode::VS, 0, room_num).unwrap();
+    room.allow_spectators = true;
+
+    send(&mut gs, watcher, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    send(&mut gs, watcher, Packet::REQ_CHG_MODE(Mode::VS)).await;
+    send(&mut gs, watcher, Packet::REQ_ENTER_LOBBY(0)).await;
+    let enter = Packet::REQ_ENTER_ROOM {
+        room: room_num,
+        unk_room_flag: false,
+        room_password: Default::default(),
+    };
+    send(&mut gs, watcher, enter).await;
+    assert_eq!(gs.conns[watcher].cur_room, room_num);
+
+    let item = Item::new(ItemCategory::CarryItemPowerGauge, 1);
+    for who in [rival, watcher] {
+        gs.conns[who].user.add_item(CountedItem::new(item, 2));
+    }
+
+    // Watching the round doesn't let them use anything up, or show it to the players
+    received(&mut rival_rx);
+    received(&mut watcher_rx);
+    send(&mut gs, watcher, Packet::PKT_155(item, 0)).await;
+    assert!(matches!(
+        last_packet(&mut watcher_rx),
+        Some(Packet::ACK_USE_ITEM(_, -1))
+    ));
+    assert_eq!(gs.conns[watcher].user.item_amount(item), 2);
+    assert!(received(&mut rival_rx).is_empty());
+
+    // The players can, though
+    send(&mut gs, rival, Packet::PKT_155(item, 0)).await;
+    assert!(matches!(
+        last_packet(&mut rival_rx),
+        Some(Packet::ACK_USE_ITEM(_, 1))
+    ));
+    assert!(matches!(
+        last_packet(&mut watcher_rx),
+        Some(Packet::REP_USE_ITEM(_, _, 1))
+    ));
+}
+
+#[test]
+fn rooms_cant_be_set_to_a_missing_course() {
+    run_with_big_stack(start_on_missing_course);
+}
+
+async fn start_on_missing_course() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::VS)).await;
+    send(&mut gs, who, Packet::REQ_ENTER_LOBBY(0)).await;
+    let room_data = |course, season| Packet19 {
+        mode: Mode::VS,
+        lobby: 0,
+        room_stat: RoomStat {
+            member_max: 1,
+            num_holes: 3,
+            course,
+            season,
+            ..Default::default()
+        },
+        room_name: "Nowhere".parse().unwrap(),
+        room_password: Default::default(),
+    };
+
+    // A room on a course that doesn't exist never gets made, so there's nothing to start
+    received(&mut packet_rx);
+    let missing = Packet::REQ_MAKE_ROOM(room_data(course::NUM_COURSES, ROUND_SEASON));
+    assert!(gs.handle_player_data(who, 0, missing).await.is_err());
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_MAKE_ROOM(-1))
+    ));
+    assert!(gs
+        .handle_player_data(who, 0, Packet::REQ_GAMESTART)
+        .await
+        .is_err());
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_GAMESTART(Status::Err))
+    ));
+
+    // Nor can a real room be moved onto one
+    send(
+        &mut gs,
+        who,
+        Packet::REQ_MAKE_ROOM(room_data(ROUND_COURSE, ROUND_SEASON)),
+    )
+    .await;
+    for (course, season) in [(-1, ROUND_SEASON), (ROUND_COURSE, course::NUM_SEASONS)] {
+        send(&mut gs, who, Packet::PKT_28(room_data(course, season))).await;
+        assert!(matches!(
+            last_packet(&mut packet_rx),
+            Some(Packet::PKT_29(Status::Err))
+        ));
+    }
+
+    send(&mut gs, who, Packet::REQ_GAMESTART).await;
+    let packets = received(&mut packet_rx);
+    assert!(packets.iter().any(|p| matches!(
+        p,
+        Packet::ORD_GAMESTART {
+            course: ROUND_COURSE,
+            season: ROUND_SEASON,
+            ..
+        }
+    )));
+    assert!(matches!(
+        packets.last(),
+        Some(Packet::ACK_GAMESTART(Status::OK))
+    ));
+}
+
+#[test]
+fn oversized_rooms_are_cut_down_to_the_configured_limit() {
+    run_with_big_stack(make_oversized_rooms);
+}
+
+/// Rooms asked for with far too many seats, or with settings that don't exist
+async fn make_oversized_rooms() {
+    let mut config = Config::default();
+    config.rooms.max_vs_members = 3;
+    let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db, Arc::new(config));
+    let (who, mut packet_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::VS)).await;
+    send(&mut gs, who, Packet::REQ_ENTER_LOBBY(0)).await;
+    let room_data = |member_max, num_holes| Packet19 {
+        mode: Mode::VS,
+        lobby: 0,
+        room_stat: RoomStat {
+            member_max,
+            num_holes,
+            course: ROUND_COURSE,
+            season: ROUND_SEASON,
+            ..Default::default()
+        },
+        room_name: "Stadium".parse().unwrap(),
+        room_password: Default::default(),
+    };
+    received(&mut packet_rx);
+
+    // Hole counts the game doesn't offer, and empty rooms, are refused outright
+    for (member_max, num_holes) in [(2, 5), (2, 0), (0, 3)] {
+        let packet = Packet::REQ_MAKE_ROOM(room_data(member_max, num_holes));
+        assert!(gs.handle_player_data(who, 0, packet).await.is_err());
+        assert!(matches!(
+            last_packet(&mut packet_rx),
+            Some(Packet::ACK_MAKE_ROOM(-1))
+        ));
+        assert_eq!(gs.conns[who].cur_room, -1);
+    }
+
+    // A huge room is still made, but only as big as the config allows
+    send(&mut gs, who, Packet::REQ_MAKE_ROOM(room_data(127, 3))).await;
+    let room_num = gs.conns[who].cur_room;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_MAKE_ROOM(num)) if num == room_num
+    ));
+    let room = gs.lobbies.room(Mode::VS, 0, room_num).unwrap();
+    assert_eq!(room.max_members, 3);
+}
+
+#[test]
+fn logging_out_of_a_vanished_lobby_still_cleans_up() {
+    run_with_big_stack(log_out_of_vanished_lobby);
+}
+
+/// One player's lobby disappears out from under them, and another's forgets them
+async fn log_out_of_vanished_lobby() {
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    for who in [host, rival] {
+        send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+        send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::VS)).await;
+        send(&mut gs, who, Packet::REQ_ENTER_LOBBY(0)).await;
+    }
+    let cids = [gs.conns[host].cid, gs.conns[rival].cid];
+
+    gs.conns[host].cur_lobby = 99;
+    gs.lobbies = create_initial_lobbies();
+
+    for cid in cids {
+        gs.remove_player(cid).await.unwrap();
+        assert!(gs.find_conn(cid).is_none());
+    }
+    assert!(gs.conns.is_empty());
+    assert!(gs.conn_lookup.is_empty());
+}
+
+#[test]
+fn desynced_lookups_are_skipped_instead_of_panicking() {
+    run_with_big_stack(broadcast_past_desynced_lookups);
+}
+
+/// The lookup table loses one roommate and points at the wrong slot for another,
+/// just as the room hears from the third
+async fn broadcast_past_desynced_lookups() {
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (host, mut host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (third, mut third_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    make_room(&mut gs, Mode::VS, &[host, rival, third], 3).await;
+    for packet_rx in [&mut host_rx, &mut rival_rx, &mut third_rx] {
+        received(packet_rx);
+    }
+
+    gs.conn_lookup.remove(&gs.conns[rival].cid);
+    gs.conn_lookup.insert(gs.conns[third].cid, 99);
+
+    // The host leaving goes out to nobody, and the server carries on
+    send(&mut gs, host, Packet::PKT_24).await;
+    assert_eq!(gs.conns[host].cur_room, -1);
+    assert!(received(&mut rival_rx).is_empty());
+    assert!(received(&mut third_rx).is_empty());
+    assert!(!received(&mut host_rx).is_empty());
+}
+
+#[test]
+fn game_start_only_fits_fifty_players() {
+    run_with_big_stack(start_crowded_round);
+}
+
+/// A competition room configured past what ORD_GAMESTART can hold, then crammed with 51
+async fn start_crowded_round() {
+    let mut config = Config::default();
+    config.rooms.max_compe_members = 100;
+    let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db, Arc::new(config));
+    let (who, _packet_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Competition)).await;
+    send(&mut gs, who, Packet::REQ_ENTER_LOBBY(0)).await;
+    let room = Packet19 {
+        mode: Mode::Competition,
+        lobby: 0,
+        room_stat: RoomStat {
+            member_max: 100,
+            num_holes: 3,
+            course: ROUND_COURSE,
+            season: ROUND_SEASON,
+            ..Default::default()
+        },
+        room_name: "Crowd".parse().unwrap(),
+        room_password: Default::default(),
+    };
+    send(&mut gs, who, Packet::REQ_MAKE_ROOM(room)).await;
+    let room_num = gs.conns[who].cur_room;
+
+    // The config's limit is cut down to what the packet can describe
+    let room = gs.lobbies.room_mut(Mode::Competition, 0, room_num).unwrap();
+    assert_eq!(room.max_members, MAX_GAME_MEMBERS);
+
+    // Even if more get in somehow, only the first fifty are sent
+    let first_cid = room.members[0];
+    room.members
+        .extend((1..=MAX_GAME_MEMBERS as CID).map(|n| first_cid + n));
+    assert_eq!(room.members.len(), MAX_GAME_MEMBERS + 1);
+    room.start_round();
+    let Packet::ORD_GAMESTART {
+        member,
+        member_max,
+        cid,
+        ..
+    } = generate_vs_game(Mode::Competition, room)
+    else {
+        panic!("expected ORD_GAMESTART");
+    };
+    assert_eq!((member, member_max), (50, 50));
+    assert_eq!(cid[..], room.members[..MAX_GAME_MEMBERS]);
+}
+
+#[test]
+fn rounds_wait_for_everyone_to_load() {
+    run_with_big_stack(load_into_round);
+}
+
+/// Two rooms start loading: in one, the rival is slow; in the other, the third walks out
+async fn load_into_round() {
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (host, mut host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (third, _third_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    let clock_started = |packet_rx: &mut ConnReceiver| {
+        received(packet_rx)
+            .iter()
+            .any(|packet| matches!(packet, Packet::REP_CLOCK { .. }))
+    };
+
+    for players in [&[host, rival][..], &[host, rival, third][..]] {
+        make_room(&mut gs, Mode::VS, players, 3).await;
+        for &who in players {
+            let (cid, uid) = (gs.conns[who].cid, gs.conns[who].uid);
+            let stat = Stat::READY;
+            send(&mut gs, who, Packet::SEND_USTAT { cid, uid, stat }).await;
+        }
+        send(&mut gs, host, Packet::REQ_GAMESTART).await;
+        received(&mut host_rx);
+        received(&mut rival_rx);
+
+        // The host is done, but the rival is only halfway
+        send(&mut gs, host, Packet::CLIENT_LOADSTAT(1)).await;
+        send(&mut gs, host, Packet::CLIENT_LOADSTAT2(100)).await;
+        send(&mut gs, rival, Packet::CLIENT_LOADSTAT(1)).await;
+        send(&mut gs, rival, Packet::CLIENT_LOADSTAT2(50)).await;
+        assert!(!clock_started(&mut host_rx));
+
+        send(&mut gs, rival, Packet::CLIENT_LOADSTAT2(100)).await;
+        if players.len() == 2 {
+            assert!(clock_started(&mut host_rx));
+            assert!(clock_started(&mut rival_rx));
+
+            // Clear the room out for the next go
+            send(&mut gs, rival, Packet::PKT_24).await;
+            send(&mut gs, host, Packet::PKT_24).await;
+            continue;
+        }
+
+        // The third never finishes loading, but leaving stops everyone waiting on them
+        assert!(!clock_started(&mut host_rx));
+        gs.remove_player(gs.conns[third].cid).await.unwrap();
+        assert!(clock_started(&mut host_rx));
+        assert!(clock_started(&mut rival_rx));
+    }
+}
+
+#[test]
+fn lounge_member_lists_always_end() {
+    run_with_big_stack(load_into_lounge);
+}
+
+/// The rival walks into the host's lounge, asks after a lounge that's gone, and then
+/// comes back to the lounge after a round
+async fn load_into_lounge() {
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (host, mut host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let room_num = make_room(&mut gs, Mode::VS, &[host, rival], 3).await;
+    received(&mut rival_rx);
+
+    // Everybody in the room is listed, then the list is closed off
+    let request = Packet::REQ_ULIST(Mode::VS, 0, room_num);
+    gs.handle_player_data(rival, 307, request).await.unwrap();
+    let replies = received_with_pids(&mut rival_rx);
+    assert!(replies.iter().all(|(pid, _)| *pid == Some(307)));
+    let listed = replies
+        .iter()
+        .filter(|(_, packet)| matches!(packet, Packet::SEND_ULIST(_)))
+        .count();
+    assert_eq!(listed, 2);
+    assert!(matches!(
+        replies.last(),
+        Some((_, Packet::ACK_ULIST_R(Status::OK)))
+    ));
+
+    // A lounge that isn't there still lets the client off the loading screen
+    let request = Packet::REQ_ULIST(Mode::VS, 0, room_num + 1);
+    gs.handle_player_data(rival, 308, request).await.unwrap();
+    assert!(matches!(
+        received_with_pids(&mut rival_rx)[..],
+        [(Some(308), Packet::ACK_ULIST_R(Status::Err))]
+    ));
+
+    // Coming back from a round loads the lounge again, and ends the same way
+    start_round(&mut gs, &[host, rival]).await;
+    for hole in 0..3 {
+        play_hole(&mut gs, host, hole, 3, 10).await;
+        play_hole(&mut gs, rival, hole, 4, 10).await;
+    }
+    let report = round_report(Outcome::Win, &[3; 3], 10);
+    send(&mut gs, host, Packet::SEND_SCORE(report)).await;
+    received(&mut host_rx);
+    received(&mut rival_rx);
+    let report = round_report(Outcome::Lose, &[4; 3], 10);
+    send(&mut gs, rival, Packet::SEND_SCORE(report)).await;
+    for packet_rx in [&mut host_rx, &mut rival_rx] {
+        assert!(received(packet_rx)
+            .iter()
+            .any(|packet| matches!(packet, Packet::ACK_ULIST_R(Status::OK))));
+    }
+}
+
+#[test]
+fn arriving_mid_round_means_watching() {
+    run_with_big_stack(enter_room_mid_round);
+}
+
+/// Somebody tries to join a room with a free slot while its round is being played
+async fn enter_room_mid_round() {
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (late, mut late_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    let room_num = start_vs_round(&mut gs, &[host, rival], 3).await;
+    gs.lobbies
+        .room_mut(Mode::VS, 0, room_num)
+        .unwrap()
+        .max_members = 4;
+
+    send(&mut gs, late, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    send(&mut gs, late, Packet::REQ_CHG_MODE(Mode::VS)).await;
+    send(&mut gs, late, Packet::REQ_ENTER_LOBBY(0)).await;
+    received(&mut late_rx);
+    let enter = Packet::REQ_ENTER_ROOM {
+        room: room_num,
+        unk_room_flag: false,
+        room_password: Default::default(),
+    };
+
+    // Without a gallery, there's no way in until the round is over
+    send(&mut gs, late, enter.clone()).await;
+    assert_eq!(gs.conns[late].cur_room, -1);
+    assert!(matches!(
+        last_packet(&mut late_rx),
+        Some(Packet::ACK_ENTER_ROOM(data)) if data.room_stat.room < 0
+    ));
+
+    // With one, they watch, and see the round so far
+    gs.lobbies
+        .room_mut(Mode::VS, 0, room_num)
+        .unwrap()
+        .allow_spectators = true;
+    send(&mut gs, late, enter).await;
+    let room = gs.lobbies.room(Mode::VS, 0, room_num).unwrap();
+    let late_cid = gs.conns[late].cid;
+    assert!(room.watchers.contains(&late_cid));
+    assert!(!room.players.contains(&late_cid));
+    assert!(gs.conns[late].stat.contains(Stat::GALLERY));
+    assert!(received(&mut late_rx)
+        .iter()
+        .any(|packet| matches!(packet, Packet::ORD_GAMESTART { .. })));
+}
+
+#[test]
+fn only_room_owners_call_the_lounge_back() {
+    run_with_big_stack(return_competition_lounge);
+}
+
+/// One competition room is mid-round while another sits idle, and members of the idle
+/// room ask to bring everyone back to the lounge
+async fn return_competition_lounge() {
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+        (FOURTH_LOGIN_ID, FOURTH_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, mut host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (owner, mut owner_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    let (guest, mut guest_rx) = log_in(&mut gs, FOURTH_LOGIN_ID, FOURTH_PASSWORD).await;
+
+    let playing = make_room(&mut gs, Mode::Competition, &[host, rival], 3).await;
+    start_round(&mut gs, &[host, rival]).await;
+    let idle = make_room(&mut gs, Mode::Competition, &[owner, guest], 3).await;
+    assert!(
+        gs.lobbies
+            .room(Mode::Competition, 0, playing)
+            .unwrap()
+            .in_round
+    );
+    let mut receivers = [&mut host_rx, &mut rival_rx, &mut owner_rx, &mut guest_rx];
+    for packet_rx in &mut receivers {
+        received(packet_rx);
+    }
+    let called_back = |packet_rx: &mut ConnReceiver| {
+        received(packet_rx)
+            .iter()
+            .any(|packet| matches!(packet, Packet::REP_RETURN_LOUNGE_ALL))
+    };
+
+    // A guest can't do it
+    send(&mut gs, guest, Packet::PKT_246).await;
+    assert!(receivers
+        .iter_mut()
+        .all(|packet_rx| !called_back(packet_rx)));
+
+    // The owner of the idle room can, but the round next door carries on
+    send(&mut gs, owner, Packet::PKT_246).await;
+    let [host_rx, rival_rx, owner_rx, guest_rx] = receivers;
+    assert!(called_back(owner_rx));
+    assert!(called_back(guest_rx));
+    assert!(!called_back(host_rx));
+    assert!(!called_back(rival_rx));
+    assert!(
+        gs.lobbies
+            .room(Mode::Competition, 0, playing)
+            .unwrap()
+            .in_round
+    );
+    assert!(gs.conns[host].stat.contains(Stat::ROUND));
+    assert!(
+        !gs.lobbies
+            .room(Mode::Competition, 0, idle)
+            .unwrap()
+            .in_round
+    );
+}
+
+#[test]
+fn the_chat_memorial_unlocks_on_a_first_win() {
+    run_with_big_stack(win_for_the_memorial);
+}
+
+/// The host loses a VS round, then wins two
+async fn win_for_the_memorial() {
+    const NUM_HOLES: usize = 3;
+
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let players = [host, rival];
+    make_room(&mut gs, Mode::VS, &players, NUM_HOLES as i8).await;
+
+    for host_wins in [false, true, true] {
+        start_round(&mut gs, &players).await;
+        let (winner, loser) = if host_wins {
+            (host, rival)
+        } else {
+            (rival, host)
+        };
+        for hole in 0..NUM_HOLES as i8 {
+            play_hole(&mut gs, winner, hole, 3, 10).await;
+            play_hole(&mut gs, loser, hole, 4, 10).await;
+        }
+        let report = round_report(Outcome::Win, &[3; NUM_HOLES], 10);
+        send(&mut gs, winner, Packet::SEND_SCORE(report)).await;
+        let report = round_report(Outcome::Lose, &[4; NUM_HOLES], 10);
+        send(&mut gs, loser, Packet::SEND_SCORE(report)).await;
+        db.flush().await.unwrap();
+
+        // Losing doesn't count, and winning again doesn't change anything
+        let record = db.get_u_record(gs.conns[host].uid).await.unwrap();
+        assert_eq!(record.x_74, host_wins as i32);
+    }
+}
+
+#[test]
+fn competition_placements_are_counted() {
+    run_with_big_stack(place_in_competition);
+}
+
+/// The host comes first and the rival second in a competition round
+async fn place_in_competition() {
+    const NUM_HOLES: usize = 3;
+
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let players = [host, rival];
+    make_room(&mut gs, Mode::Competition, &players, NUM_HOLES as i8).await;
+    start_round(&mut gs, &players).await;
+    for hole in 0..NUM_HOLES as i8 {
+        play_hole(&mut gs, host, hole, 3, 10).await;
+        play_hole(&mut gs, rival, hole, 4, 10).await;
+    }
+    let report = round_report(Outcome::Win, &[3; NUM_HOLES], 10);
+    send(&mut gs, host, Packet::SEND_SCORE(report)).await;
+    let report = round_report(Outcome::Lose, &[4; NUM_HOLES], 10);
+    send(&mut gs, rival, Packet::SEND_SCORE(report)).await;
+    db.flush().await.unwrap();
+
+    let host_record = db.get_u_record(gs.conns[host].uid).await.unwrap();
+    let rival_record = db.get_u_record(gs.conns[rival].uid).await.unwrap();
+    assert_eq!(
+        (host_record.num_1st, host_record.num_2nd),
+        (1, 0),
+        "the host should have come first"
+    );
+    assert_eq!((rival_record.num_1st, rival_record.num_2nd), (0, 1));
+    assert_eq!(host_record.total_tournament_participation, 1);
+    assert_eq!(rival_record.total_tournament_participation, 1);
+
+    // The awards go into the saved user too
+    let account = db
+        .authenticate_user_to_game(LOGIN_ID.to_string(), PASSWORD.to_string())
+        .await
+        .unwrap();
+    assert_eq!(account.user.awards[..3], [1, 0, 0]);
+    assert_eq!(gs.conns[rival].user.awards[..3], [0, 1, 0]);
+}
+
+#[test]
+fn colour_rewards_land_in_the_inventory() {
+    run_with_big_stack(collect_colour_rewards);
+}
+
+/// Two competition rounds where the host's colour wins, the second time with no room
+/// left for the prize
+async fn collect_colour_rewards() {
+    const NUM_HOLES: usize = 3;
+
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, mut host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let players = [host, rival];
+    make_room(&mut gs, Mode::Competition, &players, NUM_HOLES as i8).await;
+    let prize = Item::new(ItemCategory::HoldItemPoint, 1);
+
+    for round in 0..2 {
+        start_round(&mut gs, &players).await;
+        for hole in 0..NUM_HOLES as i8 {
+            play_hole(&mut gs, host, hole, 3, 10).await;
+            play_hole(&mut gs, rival, hole, 4, 10).await;
+        }
+        if round == 1 {
+            // Fill the host up with the prize before the second cycle ends
+            let maximum = gs.config.shop.maximum(prize.category());
+            let have = gs.conns[host].user.item_amount(prize);
+            gs.conns[host]
+                .user
+                .add_item(CountedItem::new(prize, maximum - have));
+        }
+        let had = gs.conns[host].user.item_amount(prize);
+        received(&mut host_rx);
+
+        let report = round_report(Outcome::Win, &[3; NUM_HOLES], 10);
+        send(&mut gs, host, Packet::SEND_SCORE(report)).await;
+        let report = round_report(Outcome::Lose, &[4; NUM_HOLES], 10);
+        send(&mut gs, rival, Packet::SEND_SCORE(report)).await;
+
+        // The packet advertises exactly what the inventory gained
+        let advertised = received(&mut host_rx)
+            .into_iter()
+            .find_map(|packet| match packet {
+                Packet::ORD_COLOR_RESULT {
+                    color_result: 0,
+                    item,
+                    ..
+                } => Some(item),
+                _ => None,
+            })
+            .expect("the host's colour should have won");
+        let gained = gs.conns[host].user.item_amount(prize) - had;
+        if round == 0 {
+            assert_eq!(advertised, prize.one());
+            assert_eq!(gained, 1);
+        } else {
+            assert_eq!(advertised, CountedItem::default());
+            assert_eq!(gained, 0);
+        }
+    }
+
+    // ...and that's what gets saved
+    db.flush().await.unwrap();
+    let account = db
+        .authenticate_user_to_game(LOGIN_ID.to_string(), PASSWORD.to_string())
+        .await
+        .unwrap();
+    assert_eq!(
+        account.user.item_amount(prize),
+        gs.conns[host].user.item_amount(prize)
+    );
+}
+
+#[test]
+fn colours_stay_in_memory_and_survive_a_walkout() {
+    run_with_big_stack(walk_out_of_colour_cycle);
+}
+
+/// Three competitors get seeded colours, then one disconnects once the others have
+/// handed in their scores
+async fn walk_out_of_colour_cycle() {
+    const NUM_HOLES: usize = 3;
+    const SEED: u64 = 708;
+
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    gs.rng = StdRng::seed_from_u64(SEED);
+    let (host, mut host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (third, _third_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    let players = [host, rival, third];
+
+    let room_num = make_room(&mut gs, Mode::Competition, &players, NUM_HOLES as i8).await;
+    let members = gs
+        .lobbies
+        .room(Mode::Competition, 0, room_num)
+        .unwrap()
+        .members
+        .clone();
+    start_round(&mut gs, &players).await;
+
+    // Everyone lands in the colour the seed picks for them, each a different one
+    let mut expected = ColorCycle::default();
+    expected.assign(&members, &mut StdRng::seed_from_u64(SEED));
+    let elements = players.map(|who| gs.conns[who].element);
+    for (&who, &element) in players.iter().zip(&elements) {
+        assert_eq!(element, expected.element(gs.conns[who].cid));
+        assert_ne!(element, Element::None);
+    }
+    assert!(elements[0] != elements[1] && elements[1] != elements[2]);
+    assert_ne!(elements[0], elements[2]);
+
+    // ...but only for as long as the cycle lasts, so none of it is saved
+    db.flush().await.unwrap();
+    let logins = [
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ];
+    for (&who, (login_id, password)) in players.iter().zip(logins) {
+        assert_eq!(gs.conns[who].user.element, Element::None);
+        let account = db
+            .authenticate_user_to_game(login_id.to_string(), password.to_string())
+            .await
+            .unwrap();
+        assert_eq!(account.user.element, Element::None);
+    }
+
+    for hole in 0..NUM_HOLES as i8 {
+        play_hole(&mut gs, host, hole, 3, 10).await;
+        play_hole(&mut gs, rival, hole, 4, 10).await;
+    }
+    let report = round_report(Outcome::Win, &[3; NUM_HOLES], 10);
+    send(&mut gs, host, Packet::SEND_SCORE(report)).await;
+    let report = round_report(Outcome::Lose, &[4; NUM_HOLES], 10);
+    send(&mut gs, rival, Packet::SEND_SCORE(report)).await;
+    received(&mut host_rx);
+    received(&mut rival_rx);
+
+    // The third player walks out instead of finishing, and the others still get results
+    gs.remove_player(gs.conns[third].cid).await.unwrap();
+    let finishers = [(host, &mut host_rx), (rival, &mut rival_rx)];
+    for ((who, packet_rx), element) in finishers.into_iter().zip(elements) {
+        assert!(received(packet_rx).iter().any(|packet| matches!(
+            packet,
+            Packet::ORD_COLOR_RESULT { last_element, .. } if *last_element == element
+        )));
+        assert_eq!(gs.conns[who].element, Element::None);
+    }
+}
+
+#[test]
+fn room_refresh_rejects_out_of_range_numbers() {
+    run_with_big_stack(refresh_out_of_range);
+}
+
+async fn refresh_out_of_range() {
+    const VS: u32 = 1;
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    let room_num = make_room(&mut gs, Mode::VS, &[who], 3).await;
+
+    // The real room comes back as it is
+    received(&mut packet_rx);
+    let refresh = |mode, lobby, room| Packet::PKT_240 { mode, lobby, room };
+    send(&mut gs, who, refresh(VS, 0, room_num as u32)).await;
+    match last_packet(&mut packet_rx) {
+        Some(Packet::PKT_19(data)) => assert_eq!(data.room_stat.room, room_num),
+        other => panic!("expected room data, got {other:?}"),
+    }
+
+    // Anything that doesn't fit gets an error, including values that would wrap around
+    let bad_refreshes = [
+        (0x1234, 0, room_num as u32),
+        (VS, 0x100, room_num as u32),
+        (VS, 0, 0x100 + room_num as u32),
+        (VS, u32::MAX, room_num as u32),
+        (VS, 0, u32::MAX),
+    ];
+    for (mode, lobby, room) in bad_refreshes {
+        send(&mut gs, who, refresh(mode, lobby, room)).await;
+        match last_packet(&mut packet_rx) {
+            Some(Packet::PKT_19(data)) => assert!(data.room_stat.room < 0),
+            other => panic!("expected an error for {mode} {lobby} {room}, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn returning_players_only_resume_once_and_not_too_late() {
+    run_with_big_stack(resume_session);
+}
+
+async fn resume_session() {
+    let (mut gs, _db, who, _packet_rx) = logged_in_server().await;
+    let uid = gs.conns[who].uid;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::VS)).await;
+    send(&mut gs, who, Packet::REQ_ENTER_LOBBY(0)).await;
+    gs.remove_player(gs.conns[who].cid).await.unwrap();
+
+    // Finishing a server move doesn't drag them into another mode
+    let (who, mut packet_rx) = connect(&mut gs, LOGIN_ID, PASSWORD).await;
+    received(&mut packet_rx);
+    send(&mut gs, who, Packet::PKT_55).await;
+    assert!(received(&mut packet_rx).is_empty());
+    assert!(gs.has_session_intent(uid, Mode::VS));
+
+    // Heading back into VS puts them back in their lobby, but only the once
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::VS)).await;
+    assert_eq!(gs.conns[who].cur_lobby, 0);
+    assert!(!gs.has_session_intent(uid, Mode::VS));
+
+    // Coming back too late is just a normal login
+    gs.remove_player(gs.conns[who].cid).await.unwrap();
+    let late = Instant::now().checked_sub(SESSION_INTENT_TTL).unwrap();
+    gs.session_intents.get_mut(&uid).unwrap().recorded = late;
+    let (who, _packet_rx) = connect(&mut gs, LOGIN_ID, PASSWORD).await;
+    let skip_menu = Packet::REQ_CHG_MODE(Mode::VS);
+    assert!(gs.handle_player_data(who, 0, skip_menu).await.is_err());
+    assert_eq!(gs.conns[who].cur_lobby, -1);
+}
+
+#[test]
+fn returning_watchers_go_back_to_watching() {
+    run_with_big_stack(resume_watching);
+}
+
+async fn resume_watching() {
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, _rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (watcher, _watcher_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    for who in [host, rival, watcher] {
+        send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+        send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::VS)).await;
+        send(&mut gs, who, Packet::REQ_ENTER_LOBBY(0)).await;
+    }
+
+    // A room for two that lets people watch, which the third arrival can only watch
+    let room = Packet19 {
+        mode: Mode::VS,
+        lobby: 0,
+        room_stat: RoomStat {
+            flag: 2,
+            member_max: 2,
+            num_holes: 3,
+            course: ROUND_COURSE,
+            season: ROUND_SEASON,
+            ..Default::default()
+        },
+        room_name: "Spectator Sport".parse().unwrap(),
+        room_password: Default::default(),
+    };
+    send(&mut gs, host, Packet::REQ_MAKE_ROOM(room)).await;
+    let room_num = gs.conns[host].cur_room;
+    for who in [rival, watcher] {
+        let enter = Packet::REQ_ENTER_ROOM {
+            room: room_num,
+            unk_room_flag: false,
+            room_password: Default::default(),
+        };
+        send(&mut gs, who, enter).await;
+    }
+    let room = gs.lobbies.room(Mode::VS, 0, room_num).unwrap();
+    assert_eq!(room.watchers, vec![gs.conns[watcher].cid]);
+
+    // Both drop out, which frees up a seat
+    gs.remove_player(gs.conns[watcher].cid).await.unwrap();
+    gs.remove_player(gs.conns[rival].cid).await.unwrap();
+
+    // Coming back puts them in the gallery again, rather than in the free seat
+    let (watcher, _watcher_rx) = connect(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    send(&mut gs, watcher, Packet::REQ_CHG_MODE(Mode::VS)).await;
+    assert_eq!(gs.conns[watcher].cur_room, room_num);
+    let room = gs.lobbies.room(Mode::VS, 0, room_num).unwrap();
+    assert_eq!(room.watchers, vec![gs.conns[watcher].cid]);
+    assert_eq!(room.num_players(), 1);
+    assert!(gs.conns[watcher].stat.contains(Stat::GALLERY));
+}
+
+#[test]
+fn round_carries_on_when_the_host_disconnects() {
+    run_with_big_stack(host_disconnects_mid_turn);
+}
+
+/// The host of a two-player room drops out in the middle of their turn
+async fn host_disconnects_mid_turn() {
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db.clone(), Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let room_num = start_vs_round(&mut gs, &[host, rival], 3).await;
+    let (host_cid, rival_cid) = (gs.conns[host].cid, gs.conns[rival].cid);
+
+    let shot = Packet::CLIENT_SHOT {
+        clock: 0,
+        server_cid: -1,
+        dir: 0.0,
+        power: 100,
+        impact: 0,
+        hit_x: 0,
+        hit_y: 0,
+        club: 0,
+    };
+    send(&mut gs, host, shot).await;
+    received(&mut rival_rx);
+    gs.remove_player(host_cid).await.unwrap();
+
+    // The rival has the room, and the course, to themselves
+    let room = gs.lobbies.room(Mode::VS, 0, room_num).unwrap();
+    assert_eq!(room.members, [rival_cid]);
+    assert_eq!(room.players, [rival_cid]);
+    assert_eq!(room.current_player, rival_cid);
+    assert!(room.in_round, "round should carry on");
+    let rival_seen = received(&mut rival_rx);
+    assert!(rival_seen.iter().any(|packet| matches!(
+        packet,
+        Packet::SEND_ULIST(ulist) if ulist.cid == host_cid && ulist.stat & Stat::EXIT.bits() as u16 != 0
+    )));
+
+    // Once the rival plays it out and hands in their score, the round is theirs
+    let rival = gs.find_conn(rival_cid).unwrap();
+    let gp_before = gs.conns[rival].user.gp;
+    for hole in 0..3 {
+        play_hole(&mut gs, rival, hole, 4, 10).await;
+    }
+    let report = round_report(Outcome::Win, &[4; 3], 10);
+    send(&mut gs, rival, Packet::SEND_SCORE(report)).await;
+    let room = gs.lobbies.room(Mode::VS, 0, room_num).unwrap();
+    assert!(!room.in_round, "round should be over");
+    assert_eq!(gs.conns[rival].user.gp, gp_before + 30);
+}
+
+#[test]
+fn exchanges_only_go_through_when_affordable() {
+    let np_for_sc = ExchangeRule {
+        name: "NP2SC".to_string(),
+        cost: Amount {
+            wallet: Wallet::NP,
+            amount: 100,
+        },
+        gives: Some(Amount {
+            wallet: Wallet::SC,
+            amount: 1,
+        }),
+        items: Vec::new(),
+    };
+    let ball = CountedItem::new(Item::new(ItemCategory::Ball, 1), 5);
+    let medals_for_balls = ExchangeRule {
+        name: "BALLS".to_string(),
+        cost: Amount {
+            wallet: Wallet::Medals,
+            amount: 3,
+        },
+        gives: None,
+        items: vec![ball],
+    };
+
+    let shop = ShopConfig::default();
+    let mut user = User {
+        np: 150,
+        sc: 0,
+        game_center_medals: 2,
+        ..Default::default()
+    };
+    assert_eq!(np_for_sc.apply(&mut user, &shop), Ok(()));
+    assert_eq!((user.np, user.sc), (50, 1));
+    assert_eq!(
+        np_for_sc.apply(&mut user, &shop),
+        Err(ExchangeError::CantAfford)
+    );
+    assert_eq!((user.np, user.sc), (50, 1));
+
+    assert_eq!(
+        medals_for_balls.apply(&mut user, &shop),
+        Err(ExchangeError::CantAfford)
+    );
+    user.game_center_medals = 3;
+    assert_eq!(medals_for_balls.apply(&mut user, &shop), Ok(()));
+    assert_eq!(user.game_center_medals, 0);
+    assert_eq!(user.item_amount(ball.item()), 5);
+}
+
+#[test]
+fn exchanges_refuse_what_the_player_cant_hold() {
+    let ball = CountedItem::new(Item::new(ItemCategory::Ball, 1), 30);
+    let medals_for_balls = ExchangeRule {
+        name: "BALLS".to_string(),
+        cost: Amount {
+            wallet: Wallet::Medals,
+            amount: 1,
+        },
+        gives: None,
+        items: vec![ball],
+    };
+    let medals_for_np = ExchangeRule {
+        name: "NP".to_string(),
+        cost: Amount {
+            wallet: Wallet::Medals,
+            amount: 1,
+        },
+        gives: Some(Amount {
+            wallet: Wallet::NP,
+            amount: 1,
+        }),
+        items: Vec::new(),
+    };
+    let shop = ShopConfig::default();
+    let mut user = User {
+        np: i32::MAX,
+        game_center_medals: 10,
+        ..Default::default()
+    };
+
+    // A second lot of balls would go over the stack maximum
+    assert_eq!(medals_for_balls.apply(&mut user, &shop), Ok(()));
+    assert_eq!(
+        medals_for_balls.apply(&mut user, &shop),
+        Err(ExchangeError::CantHold)
+    );
+    assert_eq!(user.item_amount(ball.item()), 30);
+    assert_eq!(user.game_center_medals, 9);
+
+    assert_eq!(
+        medals_for_np.apply(&mut user, &shop),
+        Err(ExchangeError::CantHold)
+    );
+    assert_eq!((user.np, user.game_center_medals), (i32::MAX, 9));
+
+    let overflowing = ExchangeRule {
+        name: "COINS".to_string(),
+        cost: Amount {
+            wallet: Wallet::Coins,
+            amount: i32::MAX,
+        },
+        gives: None,
+        items: Vec::new(),
+    };
+    assert_eq!(
+        overflowing.apply(&mut user, &shop),
+        Err(ExchangeError::BadRule)
+    );
+}
+
+#[test]
+fn codes_arent_used_up_when_the_items_dont_fit() {
+    run_with_big_stack(redeem_code_without_room);
+}
+
+async fn redeem_code_without_room() {
+    let balls = CountedItem::new(Item::new(ItemCategory::Ball, 1), 10);
+    let codes: &[(&str, &[CountedItem])] = &[("BALLS", &[balls])];
+    let db = db_task::run_in_memory_with_codes(&[(LOGIN_ID, PASSWORD)], codes).unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (who, mut packet_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let held = CountedItem::new(balls.item(), 45);
+    gs.conns[who].user.add_item(held);
+    received(&mut packet_rx);
+
+    // Ten more would go over the limit of 50
+    send(&mut gs, who, Packet::PKT_272("BALLS".parse().unwrap())).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::PKT_273(Status::Err))
+    ));
+    assert_eq!(gs.conns[who].user.item_amount(balls.item()), 45);
+
+    // Once there's room, the code is still there to be used
+    assert!(gs.conns[who].user.remove_item(held));
+    send(&mut gs, who, Packet::PKT_272("BALLS".parse().unwrap())).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::PKT_273(Status::OK))
+    ));
+    assert_eq!(gs.conns[who].user.item_amount(balls.item()), 10);
+}
+
+#[test]
+fn recycling_into_a_full_stack_takes_nothing() {
+    run_with_big_stack(recycle_without_room);
+}
+
+async fn recycle_without_room() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    let ticket = CountedItem::new(Item::new(ItemCategory::HoldItemTicket, 2), 1);
+    let result = gs.recycle_recipes[0].result;
+    let user = &mut gs.conns[who].user;
+    user.add_item(ticket);
+    for num in 1..=5 {
+        user.add_item(CountedItem::new(Item::new(ItemCategory::Ball, num), 1));
+    }
+    user.add_item(CountedItem::new(result.item(), 45));
+    let inventory = user.inventory.clone();
+    received(&mut packet_rx);
+
+    let recycle = Packet::PKT_266 {
+        index: 0,
+        is_gold_ticket: true,
+    };
+    send(&mut gs, who, recycle).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::PKT_267(-1))
+    ));
+    assert_eq!(gs.conns[who].user.inventory, inventory);
+}
+
+#[test]
+fn rooms_are_found_after_filling_a_gap() {
+    let mut lobbies = create_initial_lobbies();
+    let data = |name: &str| Packet19 {
+        mode: Mode::VS,
+        lobby: 0,
+        room_stat: RoomStat {
+            member_max: 2,
+            ..Default::default()
+        },
+        room_name: name.parse().unwrap(),
+        room_password: Default::default(),
+    };
+
+    for name in ["first", "second", "third"] {
+        lobbies.create_room(Mode::VS, 0, data(name), Vec::new());
+    }
+    assert!(lobbies.remove_room(Mode::VS, 0, 1).is_some());
+
+    // The gap gets filled in, so the new room lands before the last one
+    let room_num = lobbies.create_room(Mode::VS, 0, data("fourth"), Vec::new());
+    assert_eq!(room_num, Some(1));
+    for (room_num, name) in [(0, "first"), (1, "fourth"), (2, "third")] {
+        let room = lobbies.room(Mode::VS, 0, room_num).unwrap();
+        assert_eq!(room.name, name);
+    }
+    assert!(lobbies.room(Mode::VS, 0, 3).is_none());
+}
+
+#[test]
+fn pings_are_echoed() {
+    run_with_big_stack(echo_ping);
+}
+
+async fn echo_ping() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    received(&mut packet_rx);
+
+    gs.handle_player_data(who, 42, Packet::REQ_PING(0x1234_5678))
+        .await
+        .unwrap();
+    match packet_rx.try_recv() {
+        Ok(ConnMessage::Packet(pid, Packet::PKT_251(value))) => {
+            assert_eq!(pid, Some(42));
+            assert_eq!(value, 0x1234_5678);
+        }
+        _ => panic!("expected a PKT_251"),
+    }
+}
+
+#[test]
+fn quick_mode_goes_straight_to_matchmaking() {
+    run_with_big_stack(enter_quick_mode);
+}
+
+async fn enter_quick_mode() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    let entry = Packet50Data {
+        uid: gs.conns[who].uid,
+        score: 0,
+        x6: 0,
+        win_combo: 0,
+        server_id: 0,
+        rank: 0,
+        best_rank: 0,
+        item_on: false,
+        rule_setting: QuickMatchRuleSetting::Random,
+        hole_setting: QuickMatchHoleSetting::Random,
+        time_setting: QuickMatchTimeSetting::Random,
+        unk: 0,
+    };
+
+    // Matchmaking is only open to players who are actually in quick mode
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    received(&mut packet_rx);
+    send(&mut gs, who, Packet::REQ_ADD_RANKMEMBER(entry.clone())).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_ADD_RANKMEMBER(Status::Err))
+    ));
+
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Quick)).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_CHG_MODE(Mode::Quick))
+    ));
+    assert_eq!(gs.conns[who].cur_lobby, -1);
+
+    send(&mut gs, who, Packet::REQ_ADD_RANKMEMBER(entry)).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_ADD_RANKMEMBER(Status::OK))
+    ));
+    assert_eq!(gs.quick_pool.len(), 1);
+
+    // Heading back to the main menu takes them out of the pool again
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    assert!(gs.quick_pool.is_empty());
+}
+
+#[test]
+fn mode_changes_go_through_the_main_menu() {
+    run_with_big_stack(skip_the_main_menu);
+}
+
+async fn skip_the_main_menu() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    received(&mut packet_rx);
+    assert_eq!(gs.conns[who].mode, Mode::None);
+
+    // Jumping straight across is refused, and they're told where they still are
+    for (from, to) in [(Mode::None, Mode::VS), (Mode::VS, Mode::Quick)] {
+        assert!(gs
+            .handle_player_data(who, 0, Packet::REQ_CHG_MODE(to))
+            .await
+            .is_err());
+        assert!(matches!(
+            last_packet(&mut packet_rx),
+            Some(Packet::ACK_CHG_MODE(mode)) if mode == from
+        ));
+        assert_eq!(gs.conns[who].mode, from);
+
+        // Going by way of the main menu is fine
+        send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+        send(&mut gs, who, Packet::REQ_CHG_MODE(to)).await;
+        assert_eq!(gs.conns[who].mode, to);
+        received(&mut packet_rx);
+    }
+
+    // A mode with no lobbies still gets an answer when asked for them
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    received(&mut packet_rx);
+    assert!(gs
+        .handle_player_data(who, 0, Packet::GET_LOBBY_NUM)
+        .await
+        .is_err());
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::SEND_LOBBY_NUM(0))
+    ));
+}
+
+#[test]
+fn blocked_players_cant_invite_or_be_matched() {
+    run_with_big_stack(keep_blocked_players_apart);
+}
+
+/// The rival has blocked the host, who tries to invite them and then to get matched
+/// with them in quick mode. The third player doesn't mind either of them.
+async fn keep_blocked_players_apart() {
+    let db = db_task::run_in_memory(&[
+        (LOGIN_ID, PASSWORD),
+        (RIVAL_LOGIN_ID, RIVAL_PASSWORD),
+        (THIRD_LOGIN_ID, THIRD_PASSWORD),
+    ])
+    .unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (host, _host_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+    let (third, mut third_rx) = log_in(&mut gs, THIRD_LOGIN_ID, THIRD_PASSWORD).await;
+    let host_uid = gs.conns[host].uid;
+    gs.handle_block(0, rival, host_uid).await.unwrap();
+
+    // Invites to the rival are dropped, but the third still gets theirs
+    make_room(&mut gs, Mode::VS, &[host], 3).await;
+    received(&mut rival_rx);
+    received(&mut third_rx);
+    let invited = |packet_rx: &mut ConnReceiver| {
+        received(packet_rx).iter().any(|packet| {
+            matches!(packet, Packet::PKT_280 { source_uid, .. } if *source_uid == host_uid)
+        })
+    };
+    for target in [rival, third] {
+        let cid = gs.conns[target].cid;
+        send(&mut gs, host, Packet::PKT_279(cid)).await;
+    }
+    assert!(!invited(&mut rival_rx));
+    assert!(invited(&mut third_rx));
+
+    // The matchmaker won't pair the rival with the host, however long they've waited
+    let entry = |uid| Packet50Data {
+        uid,
+        score: 0,
+        x6: 0,
+        win_combo: 0,
+        server_id: 0,
+        rank: 0,
+        best_rank: 0,
+        item_on: false,
+        rule_setting: QuickMatchRuleSetting::Stroke,
+        hole_setting: QuickMatchHoleSetting::Hole3,
+        time_setting: QuickMatchTimeSetting::Time60,
+        unk: 0,
+    };
+    send(&mut gs, host, Packet::PKT_24).await;
+    for who in [host, rival] {
+        send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+        send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Quick)).await;
+        let data = entry(gs.conns[who].uid);
+        send(&mut gs, who, Packet::REQ_ADD_RANKMEMBER(data)).await;
+    }
+    assert_eq!(gs.quick_pool.len(), 2);
+    assert_eq!(gs.conns[host].cur_room, -1);
+
+    send(&mut gs, third, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    send(&mut gs, third, Packet::REQ_CHG_MODE(Mode::Quick)).await;
+    let data = entry(gs.conns[third].uid);
+    send(&mut gs, third, Packet::REQ_ADD_RANKMEMBER(data)).await;
+    assert!(gs.conns[host].cur_room >= 0);
+    assert_eq!(gs.conns[third].cur_room, gs.conns[host].cur_room);
+    assert_eq!(gs.conns[rival].cur_room, -1);
+    let rival_cid = gs.conns[rival].cid;
+    assert!(gs.quick_pool.iter().all(|e| e.cid == rival_cid));
+}
+
+#[test]
+fn quick_matches_use_a_hidden_lobby() {
+    run_with_big_stack(quick_match_lobby);
+}
+
+async fn quick_match_lobby() {
+    let db =
+        db_task::run_in_memory(&[(LOGIN_ID, PASSWORD), (RIVAL_LOGIN_ID, RIVAL_PASSWORD)]).unwrap();
+    let mut gs = GameServer::new(db, Arc::new(Config::default()));
+    let (who, mut packet_rx) = log_in(&mut gs, LOGIN_ID, PASSWORD).await;
+    let (rival, mut rival_rx) = log_in(&mut gs, RIVAL_LOGIN_ID, RIVAL_PASSWORD).await;
+
+    for index in [who, rival] {
+        send(&mut gs, index, Packet::REQ_CHG_MODE(Mode::Main)).await;
+        send(&mut gs, index, Packet::REQ_CHG_MODE(Mode::Quick)).await;
+    }
+
+    // The quick lobby can't be listed or walked into
+    received(&mut packet_rx);
+    assert!(gs
+        .handle_player_data(who, 0, Packet::GET_LOBBY_NUM)
+        .await
+        .is_err());
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::SEND_LOBBY_NUM(0))
+    ));
+    assert!(gs
+        .handle_player_data(who, 0, Packet::REQ_ENTER_LOBBY(0))
+        .await
+        .is_err());
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::ACK_ENTER_LOBBY(-1))
+    ));
+    assert_eq!(gs.conns[who].cur_lobby, -1);
+
+    // Being matched puts both players in it
+    let entry = |uid| Packet50Data {
+        uid,
+        score: 0,
+        x6: 0,
+        win_combo: 0,
+        server_id: 0,
+        rank: 0,
+        best_rank: 0,
+        item_on: false,
+        rule_setting: QuickMatchRuleSetting::Stroke,
+        hole_setting: QuickMatchHoleSetting::Hole3,
+        time_setting: QuickMatchTimeSetting::Time60,
+        unk: 0,
+    };
+    for index in [who, rival] {
+        let data = entry(gs.conns[index].uid);
+        send(&mut gs, index, Packet::REQ_ADD_RANKMEMBER(data)).await;
+    }
+    let cids = [gs.conns[who].cid, gs.conns[rival].cid];
+    assert_eq!(gs.lobbies.lobby_members(Mode::Quick, 0), Some(&cids[..]));
+
+    received(&mut rival_rx);
+    let data = entry(gs.conns[who].uid);
+    send(&mut gs, who, Packet::PKT_56(data)).await;
+    assert!(matches!(
+        last_packet(&mut rival_rx),
+        Some(Packet::ORD_GAMESTART { time: 60, .. })
+    ));
+
+    // Nobody else in the quick lobby hears about comings and goings
+    send(&mut gs, who, Packet::PKT_24).await;
+    received(&mut rival_rx);
+    send(&mut gs, who, Packet::REQ_CHG_MODE(Mode::Main)).await;
+    assert_eq!(gs.lobbies.lobby_members(Mode::Quick, 0), Some(&cids[1..]));
+    assert!(received(&mut rival_rx).is_empty());
+
+    // Rooms elsewhere can't have a negative time limit either
+    let stat = RoomStat {
+        member_max: 2,
+        num_holes: 3,
+        course: ROUND_COURSE,
+        season: ROUND_SEASON,
+        time_limit: -1,
+        ..Default::default()
+    };
+    assert!(!gs.config.rooms.validate(&stat));
+    assert!(gs.config.rooms.validate(&RoomStat {
+        time_limit: 0,
+        ..stat
+    }));
+}
+
+#[tokio::test]
+async fn quick_ratings_move_with_results_and_survive_a_reload() {
+    let (mut gs, db, who, mut packet_rx) = logged_in_server().await;
+    let uid = gs.conns[who].uid;
+
+    // Both players' item settings decide which rating the match counts towards
+    let entry = |item_on| Packet50Data {
+        uid,
+        score: 0,
+        x6: 0,
+        win_combo: 0,
+        server_id: 0,
+        rank: 0,
+        best_rank: 0,
+        item_on,
+        rule_setting: QuickMatchRuleSetting::Stroke,
+        hole_setting: QuickMatchHoleSetting::Hole3,
+        time_setting: QuickMatchTimeSetting::Time60,
+        unk: 0,
+    };
+    let mut rng = StdRng::seed_from_u64(759);
+    for (a, b) in [(true, false), (false, true)] {
+        assert!(QuickSettings::resolve(&entry(a), &entry(b), &mut rng).is_none());
+    }
+    for item_on in [false, true] {
+        let settings = QuickSettings::resolve(&entry(item_on), &entry(item_on), &mut rng);
+        assert_eq!(settings.unwrap().item_on, item_on);
+    }
+
+    // Four wins take them up a rank
+    for _ in 0..4 {
+        gs.settle_quick_rating(who, true, Outcome::Win)
+            .await
+            .unwrap();
+    }
+    let won = gs.conns[who].user.rating_item_on;
+    assert_eq!((won.score, won.win_combo, won.rank()), (120, 4, 1));
+    assert_eq!(won.best_rank, 1);
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::SEND_RANKDATA {
+            score: 120,
+            rank: 1,
+            rank_up: true,
+            ..
+        })
+    ));
+
+    // A loss drops them back down, but their best rank stays
+    gs.settle_quick_rating(who, true, Outcome::Lose)
+        .await
+        .unwrap();
+    let lost = gs.conns[who].user.rating_item_on;
+    assert_eq!((lost.score, lost.win_combo, lost.rank()), (100, 0, 1));
+    gs.settle_quick_rating(who, true, Outcome::Aborted)
+        .await
+        .unwrap();
+    let lost = gs.conns[who].user.rating_item_on;
+    assert_eq!((lost.score, lost.rank(), lost.best_rank), (80, 0, 1));
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::SEND_RANKDATA {
+            rank_down: true,
+            ..
+        })
+    ));
+
+    // The item-off rating is left alone, and everything comes back after a reload
+    gs.save_user(who).await;
+    let account = db
+        .authenticate_user_to_game(LOGIN_ID.to_string(), PASSWORD.to_string())
+        .await
+        .unwrap();
+    assert_eq!(account.user.rating_item_on, lost);
+    assert_eq!(account.user.rating_item_off, QuickRating::default());
+}
+
+#[test]
+fn whispers_to_a_garbled_name_are_dropped() {
+    run_with_big_stack(whisper_garbled_name);
+}
+
+async fn whisper_garbled_name() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    received(&mut packet_rx);
+
+    // A lone surrogate can't be turned into a name
+    let mut bytes = vec![27, 0, 0, 0];
+    bytes.extend((-1i32).to_le_bytes());
+    bytes.extend([1, 0]);
+    let mut name = [0u8; 38];
+    name[..2].copy_from_slice(&0xD800u16.to_le_bytes());
+    bytes.extend(name);
+    bytes.extend(2i16.to_le_bytes());
+    bytes.extend("hi".encode_utf16().flat_map(u16::to_le_bytes));
+    let packet = EntirePacket::from_bytes((&bytes, 0)).unwrap().1.packet;
+    assert!(matches!(packet, Packet::SEND_MESSAGE { .. }));
+
+    assert!(gs.handle_player_data(who, 0, packet).await.is_err());
+    assert!(received(&mut packet_rx).is_empty());
+}
+
+#[test]
+fn searches_for_a_garbled_name_find_nobody() {
+    run_with_big_stack(search_garbled_name);
+}
+
+async fn search_garbled_name() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    received(&mut packet_rx);
+
+    let mut bytes = vec![123, 0, 0, 0];
+    let mut name = [0u8; 38];
+    name[..2].copy_from_slice(&0xDC00u16.to_le_bytes());
+    bytes.extend(name);
+    bytes.extend([0, 0]);
+    bytes.extend(0u32.to_le_bytes());
+    let packet = EntirePacket::from_bytes((&bytes, 0)).unwrap().1.packet;
+    assert!(matches!(packet, Packet::PKT_123 { .. }));
+
+    assert!(gs.handle_player_data(who, 0, packet).await.is_err());
+    assert!(received(&mut packet_rx).is_empty());
+}
+
+/// Make a login packet the way a client would, with whatever bytes we like in the username
+fn raw_login(id: u16, username: &[u8]) -> Packet {
+    let mut bytes = id.to_le_bytes().to_vec();
+    bytes.extend([0, 0]);
+    let mut field = [0u8; 17];
+    field[..username.len()].copy_from_slice(username);
+    bytes.extend(field);
+    let mut field = [0u8; 17];
+    field[..PASSWORD.len()].copy_from_slice(PASSWORD.as_bytes());
+    bytes.extend(field);
+    bytes.extend(956u16.to_le_bytes());
+    EntirePacket::from_bytes((&bytes, 0)).unwrap().1.packet
+}
+
+/// Username bytes that aren't valid UTF-8
+const GARBLED: &[u8] = &[b't', 0xFF, 0xFE, b's'];
+
+#[test]
+fn credentials_must_be_utf8() {
+    match raw_login(1, LOGIN_ID.as_bytes()) {
+        Packet::SEND_IDPASS(login) => {
+            let expected = (LOGIN_ID.to_string(), PASSWORD.to_string());
+            assert_eq!(login.credentials(), Some(expected));
+        }
+        other => panic!("expected SEND_IDPASS, got {other:?}"),
+    }
+
+    // Garbled bytes still make a packet, so that the login can be answered
+    match raw_login(1, GARBLED) {
+        Packet::SEND_IDPASS(login) => assert_eq!(login.credentials(), None),
+        other => panic!("expected SEND_IDPASS, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn login_server_answers_garbled_credentials() {
+    let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+    let config = Config::default();
+    let (server_end, client_end) = tokio::io::duplex(4096);
+
+    let server = async {
+        let mut conn = Connection::new(server_end);
+        login_server::serve_connection(&db, &config, &mut conn).await
+    };
+    let client = async {
+        let mut client = Connection::new(client_end);
+        client.write_packet(raw_login(1, GARBLED)).await.unwrap();
+        client.read_packet().await.unwrap().unwrap().packet
+    };
+
+    let (served, reply) = tokio::join!(server, client);
+    served.unwrap();
+    assert!(matches!(
+        reply,
+        Packet::ACK_IDPASS(AckIDPassResult::IDError)
+    ));
+}
+
+#[tokio::test]
+async fn game_server_answers_garbled_credentials() {
+    let db = db_task::run_in_memory(&[(LOGIN_ID, PASSWORD)]).unwrap();
+    let tx = GameServer::start(db, Arc::new(Config::default()));
+    let addr = "127.0.0.1:2051".parse().unwrap();
+    let (server_end, client_end) = tokio::io::duplex(4096);
+
+    let server = async {
+        let mut conn = Connection::new(server_end);
+        do_handshake(tx, addr, &mut conn).await
+    };
+    let client = async {
+        let mut client = Connection::new(client_end);
+        client.write_packet(raw_login(6, GARBLED)).await.unwrap();
+        client.read_packet().await.unwrap().unwrap().packet
+    };
+
+    let (handshake, reply) = tokio::join!(server, client);
+    assert!(handshake.unwrap().is_none());
+    match reply {
+        Packet::ACK_IDPASS_G(udata) => {
+            assert_eq!(udata.cid, AckIDPassResult::IDError as i8 as CID)
+        }
+        other => panic!("expected ACK_IDPASS_G, got {other:?}"),
+    }
+}
+
+#[test]
+fn garbled_macros_are_refused() {
+    run_with_big_stack(save_garbled_macro);
+}
+
+async fn save_garbled_macro() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    let mut bytes = 164u16.to_le_bytes().to_vec();
+    bytes.extend([0, 0, 2]);
+    let mut field = [0u8; 65];
+    field[..GARBLED.len()].copy_from_slice(GARBLED);
+    bytes.extend(field);
+    let (_, entire) = EntirePacket::from_bytes((&bytes, 0)).unwrap();
+
+    send(&mut gs, who, entire.packet).await;
+    assert!(matches!(
+        last_packet(&mut packet_rx),
+        Some(Packet::PKT_165 {
+            which: 2,
+            status: Status::Err
+        })
+    ));
+    assert!(gs.conns[who].user.macros.iter().all(String::is_empty));
+}
+
+#[test]
+fn room_passwords_line_up_after_the_flag() {
+    let mut bytes = vec![20, 0, 0, 0, 5];
+    bytes.extend(1u32.to_le_bytes());
+    let mut password = [0u16; 17];
+    for (unit, c) in password.iter_mut().zip("hunter2".encode_utf16()) {
+        *unit = c;
+    }
+    bytes.extend(password.iter().flat_map(|unit| unit.to_le_bytes()));
+
+    let (_, entire) = EntirePacket::from_bytes((&bytes, 0)).unwrap();
+    match &entire.packet {
+        Packet::REQ_ENTER_ROOM {
+            room,
+            unk_room_flag,
+            room_password,
+        } => {
+            assert_eq!(*room, 5);
+            assert!(*unk_room_flag);
+            assert_eq!(room_password.to_string(), "hunter2");
+        }
+        other => panic!("expected REQ_ENTER_ROOM, got {other:?}"),
+    }
+    assert_eq!(entire.to_bytes().unwrap(), bytes);
+}
+
+#[test]
+fn chruid_lists_count_the_cid_too() {
+    for chr_uids in [vec![7], vec![7, 8, 9]] {
+        let entire = EntirePacket {
+            header: PacketHeader { id: 99, pid: 0 },
+            packet: make_chruid_packet(1234, chr_uids.clone()),
+        };
+        let bytes = entire.to_bytes().unwrap();
+
+        let mut expected = vec![99, 0, 0, 0];
+        expected.extend((chr_uids.len() as i32 + 1).to_le_bytes());
+        expected.extend(1234i32.to_le_bytes());
+        for chr_uid in &chr_uids {
+            expected.extend(chr_uid.to_le_bytes());
+        }
+        assert_eq!(bytes, expected);
+
+        // ...and it should read back the same way
+        match EntirePacket::from_bytes((&bytes, 0)).unwrap().1.packet {
+            Packet::SEND_CHRUID {
+                cid,
+                chr_uids: read,
+                ..
+            } => {
+                assert_eq!(cid, 1234);
+                assert_eq!(read, chr_uids);
+            }
+            other => panic!("expected SEND_CHRUID, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn svitem_data_is_the_size_the_client_expects() {
+    let entire = EntirePacket {
+        header: PacketHeader { id: 309, pid: 0 },
+        packet: Packet::SEND_SVITEMDATA(build_svitem_data()),
+    };
+    let bytes = entire.to_bytes().unwrap();
+
+    // The header, then 32 entries of 40 bytes each
+    assert_eq!(bytes.len(), 4 + SVITEM_SLOTS * 40);
+
+    match EntirePacket::from_bytes((&bytes, 0)).unwrap().1.packet {
+        Packet::SEND_SVITEMDATA(items) => {
+            for (read, built) in items.iter().zip(build_svitem_data()) {
+                assert_eq!(read.item_code, built.item_code);
+                assert_eq!(read.brand_index, built.brand_index);
+                assert_eq!(read.mood, built.mood);
+                assert_eq!(read.flags, built.flags);
+            }
+        }
+        other => panic!("expected SEND_SVITEMDATA, got {other:?}"),
+    }
+}
+
+#[test]
+fn club_data_count_matches_the_list() {
+    run_with_big_stack(request_club_data);
+}
+
+async fn request_club_data() {
+    let (mut gs, _db, who, mut packet_rx) = logged_in_server().await;
+    received(&mut packet_rx);
+
+    send(&mut gs, who, Packet::REQ_CLUBDATA).await;
+    let packets = received(&mut packet_rx);
+    match packets.as_slice() {
+        [Packet::SEND_CLUBDATA { count, clubdata }, Packet::REP_END_CLUBDATA { count: end_count }] =>
+        {
+            assert_eq!(*count as usize, clubdata.len());
+            assert_eq!(clubdata.len(), build_club_data().len());
+            assert_eq!(end_count, count);
+        }
+        other => panic!("unexpected reply to REQ_CLUBDATA: {other:?}"),
+    }
+}
+
+#[test]
+fn udata_ranks_share_one_u32() {
+    let mut bits = BitVec::new();
+    UData::default().write(&mut bits, ()).unwrap();
+    let mut bytes = bits.into_vec();
+
+    // The ranks come just before x_f4 and debug, which make up the last 5 bytes
+    let ranks_at = bytes.len() - 9;
+    bytes[ranks_at..ranks_at + 4].copy_from_slice(&[0x03, 0x31, 0x0A, 0x00]);
+
+    let (_, data) = UData::read(bytes.view_bits::<Msb0>(), ()).unwrap();
+    assert_eq!(data.rank_item_on, 3);
+    assert_eq!(data.rank_item_off, 8);
+    assert_eq!(data.best_rank_item_on, 12);
+    assert_eq!(data.best_rank_item_off, 20);
+
+    let mut bits = BitVec::new();
+    data.write(&mut bits, ()).unwrap();
+    assert_eq!(bits.into_vec(), bytes);
+}
+
+#[test]
+fn competition_room_limits_round_trip() {
+    let settings = [3, 2, 10, 1, 0, 0, 1, 2, 1, 9, 0];
+    // limit_0 is 3 and limit_2 is 5
+    let limits = [0x03, 0x05, 0x00, 0x00];
+    // ranks 4 up to 12 only, split into 2 teams
+    let limits_b = [0x19, 0x92, 0x00, 0x00];
+    let bytes = [&settings[..], &limits, &limits_b].concat();
+
+    let (rest, stat) = RoomStat::read(bytes.view_bits::<Msb0>(), ()).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!((stat.room, stat.member_max, stat.num_holes), (3, 10, 9));
+    assert_eq!((stat.limit_0, stat.limit_1, stat.limit_2), (3, 0, 5));
+    assert_eq!((stat.limit_b_0, stat.limit_b_1), (1, 12));
+    assert_eq!(stat.limit_b_2, 2);
+    assert_eq!((stat.limit_b_3, stat.limit_b_4), (1, 4));
+
+    let mut bits = BitVec::new();
+    stat.write(&mut bits, ()).unwrap();
+    assert_eq!(bits.into_vec(), bytes);
+}
+
+#[test]
+fn random_quick_settings_defer_to_the_other_player() {
+    use QuickMatchHoleSetting as Holes;
+    use QuickMatchRuleSetting as Rule;
+    use QuickMatchTimeSetting as Time;
+
+    let entry = |rule_setting, hole_setting, time_setting| Packet50Data {
+        uid: 0,
+        score: 0,
+        x6: 0,
+        win_combo: 0,
+        server_id: 0,
+        rank: 0,
+        best_rank: 0,
+        item_on: false,
+        rule_setting,
+        hole_setting,
+        time_setting,
+        unk: 0,
+    };
+    let mut rng = StdRng::seed_from_u64(711);
+
+    // Random takes on whatever the other player picked, whichever side it's on
+    let fixed = entry(Rule::Match, Holes::Hole9, Time::Time90);
+    let random = entry(Rule::Random, Holes::Random, Time::Random);
+    for (a, b) in [(&fixed, &random), (&random, &fixed)] {
+        let settings = QuickSettings::resolve(a, b, &mut rng).unwrap();
+        assert_eq!(
+            (settings.rule, settings.holes, settings.time),
+            (Rule::Match, Holes::Hole9, Time::Time90)
+        );
+    }
+
+    // Settings can be mixed, but two different fixed choices can't meet
+    let mixed = entry(Rule::Random, Holes::Hole9, Time::Random);
+    assert!(QuickSettings::resolve(&fixed, &mixed, &mut rng).is_some());
+    let other = entry(Rule::Stroke, Holes::Random, Time::Random);
+    assert!(QuickSettings::resolve(&fixed, &other, &mut rng).is_none());
+
+    // With nobody choosing, we pick something concrete
+    for _ in 0..20 {
+        let settings = QuickSettings::resolve(&random, &random, &mut rng).unwrap();
+        assert_ne!(settings.rule, Rule::Random);
+        assert_ne!(settings.holes, Holes::Random);
+        assert_ne!(settings.time, Time::Random);
+    }
+}
+
+#[test]
+fn quick_match_settings_share_one_u32() {
+    // Put together by hand from the layout, as there's no client capture of this yet
+    let bytes = [
+        0x34, 0x12, 0x00, 0x00, // uid
+        0xDC, 0x05, 0x00, 0x03, // score, x6, win_combo
+        0xE1, 0xA4, 0x8A, 0x00, // settings
+        0x01, 0x00, 0x00, 0x00, // unk
+    ];
+
+    let (rest, data) = Packet50Data::read(bytes.view_bits::<Msb0>(), ()).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!((data.uid, data.score, data.win_combo), (0x1234, 1500, 3));
+    assert_eq!((data.server_id, data.rank, data.best_rank), (1, 7, 9));
+    assert!(data.item_on);
+    assert_eq!(data.rule_setting, QuickMatchRuleSetting::Random);
+    assert_eq!(data.hole_setting, QuickMatchHoleSetting::Hole9);
+    assert_eq!(data.time_setting, QuickMatchTimeSetting::Random);
+    assert_eq!(data.unk, 1);
+
+    let mut bits = BitVec::new();
+    data.write(&mut bits, ()).unwrap();
+    assert_eq!(bits.into_vec(), bytes);
+}
+
+#[tokio::test]
+async fn ulist_team_is_the_bottom_bit_of_its_word() {
+    let (mut gs, _db, who, _packet_rx) = logged_in_server().await;
+    let conn = &mut gs.conns[who];
+    conn.team = 1;
+    conn.stat = Stat::READY;
+    let (cid, uid) = (conn.cid, conn.uid);
+
+    let mut expected = [cid.to_le_bytes(), uid.to_le_bytes()].concat();
+    expected.extend((Stat::READY.bits() as u16).to_le_bytes());
+    // team, then mode, lobby and room
+    expected.extend([0x01, 0x00, 0x00, 0x00]);
+    expected.extend([0xFF, 0xFF, 0xFF]);
+
+    let ulist = gs.conns[who].make_ulist().to_bytes().unwrap();
+    assert_eq!(ulist[..expected.len()], expected);
+    let ulist_l = gs.conns[who].make_ulist_l().to_bytes().unwrap();
+    assert_eq!(ulist_l[..expected.len()], expected);
+
+    // ...and it reads back the same way
+    let (_, read) = UList::from_bytes((&ulist, 0)).unwrap();
+    assert_eq!(read.team, 1);
+    assert_eq!(read.mode, Mode::None);
+}
+
+/// Compare serialising a ball sync separately for each member of a full room against
+/// sharing one frame between them. Timings only mean much in release builds:
+/// `cargo test --release broadcast_serialisation -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn broadcast_serialisation_benchmark() {
+    const ROOMMATES: i16 = MAX_GAME_MEMBERS as i16 - 1;
+    const ROUNDS: u32 = 10_000;
+    let packet = Packet::SEND_BALLPOS {
+        cid: 1,
+        hole: 0,
+        stat: 0,
+        x: 12.5,
+        y: 0.25,
+        z: -40.0,
+    };
+    let id = packet.deku_id().unwrap();
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for pid in 0..ROOMMATES {
+            let entire = EntirePacket {
+                header: PacketHeader { id, pid },
+                packet: packet.clone(),
+            };
+            std::hint::black_box(entire.to_bytes().unwrap());
+        }
+    }
+    let separately = start.elapsed() / ROUNDS;
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let frame = Frame::new(packet.clone()).unwrap();
+        for pid in 0..ROOMMATES {
+            std::hint::black_box(frame.with_pid(pid).unwrap());
+        }
+    }
+    let shared = start.elapsed() / ROUNDS;
+
+    println!("ball sync to {ROOMMATES} roommates: {separately:?} serialising each copy, {shared:?} sharing a frame");
+}
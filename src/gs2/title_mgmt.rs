@@ -0,0 +1,82 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::packets::{Packet, Status};
+
+use super::GameServer;
+
+/// How many titles fit in the owned title bitmask
+const NUM_TITLES: i16 = 128;
+
+impl GameServer {
+    pub(super) async fn handle_get_titles(&self, pid: i16, who: usize) -> Result<()> {
+        let conn = &self.conns[who];
+        conn.write_with_pid(Packet::SEND_TITLES(conn.uid, conn.user.titles), pid)
+            .await
+    }
+
+    /// Mark a title as obtained
+    pub(super) async fn handle_obtain_title(
+        &mut self,
+        pid: i16,
+        who: usize,
+        title: i16,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let status = if (0..NUM_TITLES).contains(&title) {
+            info!("🎖️ {uid} obtained title {title}");
+            self.conns[who].user.titles |= 1 << title;
+            self.save_user(who).await;
+            Status::OK
+        } else {
+            warn!("{uid} tried to obtain invalid title {title}");
+            Status::Err
+        };
+
+        self.conns[who]
+            .write_with_pid(Packet::ACK_GET_TITLE(status), pid)
+            .await
+    }
+
+    /// Show off a different title, which has to have been obtained first
+    pub(super) async fn handle_change_title(
+        &mut self,
+        pid: i16,
+        who: usize,
+        title: i16,
+    ) -> Result<()> {
+        let uid = self.conns[who].uid;
+        let owned =
+            (0..NUM_TITLES).contains(&title) && self.conns[who].user.titles & (1 << title) != 0;
+        if !owned {
+            warn!("{uid} tried to equip title {title}, which they don't have");
+            return self.conns[who]
+                .write_with_pid(Packet::ACK_CHG_TITLE(Status::Err), pid)
+                .await;
+        }
+
+        self.conns[who].user.title = title as u8;
+        self.save_user(who).await;
+        self.conns[who]
+            .write_with_pid(Packet::ACK_CHG_TITLE(Status::OK), pid)
+            .await?;
+
+        // Everyone else in the lobby sees it on their player list
+        let conn = &self.conns[who];
+        let members = match self.lobbies.lobby_members(conn.mode, conn.cur_lobby) {
+            Some(members) => members.to_vec(),
+            None => return Ok(()),
+        };
+        let packet = Packet::SEND_CHG_TITLE {
+            uid,
+            title: title.into(),
+        };
+        for cid in members {
+            if let Some(index) = self.find_conn(cid) {
+                self.conns[index].write(packet.clone()).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
-use crate::data::Item;
+use crate::config::unix_now;
+use crate::data::{Item, User};
 use anyhow::Result;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 
 use crate::packets::{Packet, SetPlayerName, Stat, Status, CID, UID};
 
@@ -79,7 +80,7 @@ impl GameServer {
 
         for conn in &self.conns {
             if conn.uid == uid {
-                let packet = Packet::PKT_181(conn.make_udata());
+                let packet = Packet::PKT_181(conn.make_udata(&self.config.now_datetime()));
                 self.conns[who].write_with_pid(packet, pid).await?;
                 return Ok(());
             }
@@ -89,37 +90,138 @@ impl GameServer {
         Ok(())
     }
 
-    /// Get the amount of money you have
+    fn make_money_packet(&self, who: usize) -> Packet {
+        Packet::REP_MONEY {
+            gp: self.conns[who].user.gp,
+            sc: self.conns[who].user.sc,
+        }
+    }
+
+    /// Get the amount of money you have.
+    /// This is a reply to PKT_93, so it has to carry the request's pid.
     pub(super) async fn handle_get_money(&self, pid: i16, who: usize) -> Result<()> {
+        let packet = self.make_money_packet(who);
+        self.conns[who].write_with_pid(packet, pid).await
+    }
+
+    /// Give a player the configured welcome bonus, unless they've already had it.
+    /// Returns true if the user was changed and needs saving.
+    pub(super) fn grant_welcome(&self, uid: UID, user: &mut User) -> bool {
+        if user.has_received_welcome {
+            return false;
+        }
+        user.has_received_welcome = true;
+
+        let welcome = &self.config.game.welcome;
+        if welcome.is_empty() {
+            return true;
+        }
+
+        info!("🎁 granting welcome bonus to {uid}");
+        user.gp += welcome.gp;
+        user.sc += welcome.sc;
+        for &counted_item in &welcome.items {
+            let item = counted_item.item();
+            let maximum = self.config.shop.maximum(item.category());
+            if user.item_amount(item) + counted_item.count() <= maximum {
+                user.add_item(counted_item);
+            } else {
+                warn!("{uid} can't hold any more of {item:?}, skipping it");
+            }
+        }
+
+        true
+    }
+
+    /// Show the welcome telop to someone who has just received the bonus
+    pub(super) async fn send_welcome_telop(&self, who: usize) -> Result<()> {
+        let text: Vec<u16> = self.config.game.welcome.message.encode_utf16().collect();
         self.conns[who]
-            .write_with_pid(
-                Packet::REP_MONEY {
-                    gp: self.conns[who].user.gp,
-                    sc: self.conns[who].user.sc,
-                },
-                pid,
-            )
+            .write(Packet::PKT_304 {
+                unk: [0; 26],
+                len: text.len() as i16,
+                text,
+            })
             .await
     }
 
+    /// Get your NP balance
+    pub(super) async fn handle_get_np(&self, pid: i16, who: usize) -> Result<()> {
+        let conn = &self.conns[who];
+        let packet = Packet::SEND_NP {
+            uid: conn.uid,
+            sp: conn.user.np,
+        };
+        conn.write_with_pid(packet, pid).await
+    }
+
+    /// The client asks to be credited with the GP from a round.
+    /// Rounds are already paid out from the score report, so nothing is added here; the
+    /// reply just carries the balance as it stands.
+    /// TODO: the meaning of both ACK_ADD_GP fields is a guess
+    pub(super) async fn handle_add_gp(&self, who: usize, result_gp: i32) -> Result<()> {
+        let conn = &self.conns[who];
+        debug!("{} claims {result_gp} GP from their round", conn.uid);
+        conn.write(Packet::ACK_ADD_GP {
+            inum0: 0,
+            inum1: conn.user.gp,
+        })
+        .await
+    }
+
+    /// Answer the client's keepalive ping with the server's clock.
+    /// The ping carries nothing but its pid, so that doubles as the sequence number.
+    pub(super) async fn handle_ping(&self, pid: i16, who: usize) -> Result<()> {
+        self.conns[who]
+            .write_with_pid(Packet::PKT_230(unix_now(), pid), pid)
+            .await
+    }
+
+    /// Answer DisconCheckTask's ping, echoing whatever it sent
+    pub(super) async fn handle_discon_check_ping(
+        &self,
+        pid: i16,
+        who: usize,
+        value: i32,
+    ) -> Result<()> {
+        self.conns[who]
+            .write_with_pid(Packet::PKT_251(value), pid)
+            .await
+    }
+
+    /// Push every balance to the client after one of them changes.
+    /// Nobody asked for these, so like any other push they get a pid from
+    /// the connection's own counter rather than a made-up request pid.
+    /// Medals are only reported as part of the UDATA, so the client picks
+    /// those up the next time it asks for it.
+    pub(super) async fn refresh_currencies(&self, who: usize) -> Result<()> {
+        let conn = &self.conns[who];
+        conn.write(self.make_money_packet(who)).await?;
+        conn.write(Packet::SEND_NP {
+            uid: conn.uid,
+            sp: conn.user.np,
+        })
+        .await
+    }
+
     /// Get your inventory contents
-    pub(super) async fn handle_get_inventory(&self, who: usize) -> Result<()> {
+    pub(super) async fn handle_get_inventory(&self, pid: i16, who: usize) -> Result<()> {
         let packet = Packet::PKT_132 {
             count: self.conns[who].user.inventory.len() as i32,
             items: self.conns[who].user.inventory.clone(),
         };
-        self.conns[who].write(packet).await
+        self.conns[who].write_with_pid(packet, pid).await
     }
 
     /// Get your golfbag contents
-    pub(super) async fn handle_get_golfbag(&self, who: usize) -> Result<()> {
+    pub(super) async fn handle_get_golfbag(&self, pid: i16, who: usize) -> Result<()> {
         let packet = Packet::PKT_134 {
             x4: 0,
             cid: self.conns[who].cid,
             items: self.conns[who].user.golfbag,
             unk: [0; 4060],
         };
-        self.conns[who].write(packet).await
+        self.conns[who].write_with_pid(packet, pid).await
     }
 
     /// Set your holdbox contents
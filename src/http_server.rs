@@ -0,0 +1,216 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use log::{error, info, warn};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use crate::{config::Config, db_task::DBTask, gs2::GameTask};
+
+/// Largest request head we're willing to read; we never need a body
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// How long a client or the database gets before we give up on them
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json<T: Serialize>(value: &T) -> Result<Self> {
+        Ok(Response {
+            status: "200 OK",
+            body: serde_json::to_string(value)?,
+        })
+    }
+
+    fn error(status: &'static str) -> Self {
+        Response {
+            status,
+            body: format!("{{\"error\":\"{status}\"}}"),
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    /// Everything after the `?` in the request target
+    query: String,
+    /// Contents of the Authorization header, if there was one
+    authorization: Option<String>,
+}
+
+impl Request {
+    /// Look up a query string parameter
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Check the request carries the admin bearer token
+    fn is_admin(&self, config: &Config) -> bool {
+        match (&config.http.admin_token, &self.authorization) {
+            (Some(token), Some(auth)) => auth.strip_prefix("Bearer ") == Some(token.as_str()),
+            _ => false,
+        }
+    }
+}
+
+/// Read the request head and pull out the parts we care about
+async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() >= MAX_REQUEST_SIZE {
+            bail!("request too large");
+        }
+        let amount = stream.read(&mut chunk).await?;
+        if amount == 0 {
+            bail!("connection closed mid-request");
+        }
+        buf.extend_from_slice(&chunk[..amount]);
+    }
+
+    let head = String::from_utf8_lossy(&buf);
+    let mut lines = head.lines();
+    let mut parts = lines.next().unwrap_or_default().split(' ');
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => bail!("malformed request line"),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let authorization = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+        .map(|(_, value)| value.trim().to_string());
+
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query: query.to_string(),
+        authorization,
+    })
+}
+
+async fn get_leaderboard(db: &DBTask, config: &Config) -> Response {
+    let size = config.http.leaderboard_size;
+    // don't let a slow query hold the connection open forever
+    match timeout(TIMEOUT, db.get_leaderboard(size)).await {
+        Ok(Ok(entries)) => match Response::json(&entries) {
+            Ok(response) => response,
+            Err(e) => {
+                error!("failed to encode leaderboard: {e:?}");
+                Response::error("500 Internal Server Error")
+            }
+        },
+        Ok(Err(e)) => {
+            error!("failed to fetch leaderboard: {e:?}");
+            Response::error("500 Internal Server Error")
+        }
+        Err(_) => Response::error("503 Service Unavailable"),
+    }
+}
+
+/// Write every online player to the DB, e.g. before taking a backup
+async fn save_all(gs2: &GameTask) -> Response {
+    match gs2.save_all().await {
+        Ok(saved) => Response {
+            status: "200 OK",
+            body: format!("{{\"saved\":{saved}}}"),
+        },
+        Err(e) => {
+            error!("failed to save all players: {e:?}");
+            Response::error("500 Internal Server Error")
+        }
+    }
+}
+
+/// Wipe an account's progress, e.g. when a broken inventory is crashing their client.
+/// Takes `uid`, and `keep_profile=1` to hold on to the player's name and characters.
+async fn reset_user(gs2: &GameTask, request: &Request) -> Response {
+    let uid = match request.param("uid").and_then(|uid| uid.parse().ok()) {
+        Some(uid) => uid,
+        None => return Response::error("400 Bad Request"),
+    };
+    let keep_profile = request.param("keep_profile") == Some("1");
+
+    match gs2.reset_user(uid, keep_profile).await {
+        Ok(true) => Response {
+            status: "200 OK",
+            body: format!("{{\"reset\":{uid}}}"),
+        },
+        Ok(false) => Response::error("404 Not Found"),
+        Err(e) => {
+            error!("failed to reset user {uid}: {e:?}");
+            Response::error("500 Internal Server Error")
+        }
+    }
+}
+
+async fn route(db: &DBTask, gs2: &GameTask, config: &Config, request: &Request) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/leaderboard") => get_leaderboard(db, config).await,
+        (_, path) if path.starts_with("/admin/") && !request.is_admin(config) => {
+            Response::error("404 Not Found")
+        }
+        ("POST", "/admin/save-all") => save_all(gs2).await,
+        ("POST", "/admin/reset-user") => reset_user(gs2, request).await,
+        (_, "/leaderboard" | "/admin/save-all" | "/admin/reset-user") => {
+            Response::error("405 Method Not Allowed")
+        }
+        _ => Response::error("404 Not Found"),
+    }
+}
+
+async fn handle_connection(
+    db: DBTask,
+    gs2: GameTask,
+    config: Arc<Config>,
+    mut stream: TcpStream,
+) -> Result<()> {
+    let request = timeout(TIMEOUT, read_request(&mut stream)).await??;
+    let response = route(&db, &gs2, &config, &request).await;
+
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Serve read-only stats over plain HTTP, plus a few token-protected admin actions.
+/// Every request gets its own task, so nothing here can hold up the game server.
+pub async fn run(db: DBTask, gs2: GameTask, config: Arc<Config>) -> Result<()> {
+    let listener = TcpListener::bind(&config.http.addr).await?;
+    info!("HTTP server listening on {}", config.http.addr);
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let db = db.clone();
+        let gs2 = gs2.clone();
+        let config = config.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(db, gs2, config, stream).await {
+                warn!("HTTP request from {addr} failed: {e:?}");
+            }
+        });
+    }
+}
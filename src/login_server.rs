@@ -2,16 +2,19 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use log::{error, info, warn};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
 use tokio_rustls::rustls::ServerConfig;
 use tokio_rustls::TlsAcceptor;
 
+use crate::config::Config;
 use crate::db_task::DBTask;
 use crate::packets::{AckIDPassResult, GmsvData, Packet};
 use crate::stream::Connection;
 
 async fn authenticate_user(
     db: &DBTask,
+    settings: &Config,
     username: String,
     password: String,
     version: u16,
@@ -22,7 +25,7 @@ async fn authenticate_user(
     if password.is_empty() {
         return AckIDPassResult::PassError;
     }
-    if version != 956 {
+    if !settings.login.versions.contains(&version) {
         return AckIDPassResult::VersionError;
     }
 
@@ -42,38 +45,75 @@ async fn authenticate_user(
     AckIDPassResult::OK
 }
 
-async fn handle_connection(db: DBTask, tcp_stream: TcpStream, acceptor: TlsAcceptor) -> Result<()> {
+async fn handle_connection(
+    db: DBTask,
+    settings: Arc<Config>,
+    tcp_stream: TcpStream,
+    acceptor: TlsAcceptor,
+) -> Result<()> {
     info!("Login connection from {}", tcp_stream.peer_addr()?);
 
     let tls_stream = acceptor.accept(tcp_stream).await?;
     let mut connection = Connection::new(tls_stream);
-    let mut authenticated = false;
+    serve_connection(&db, &settings, &mut connection).await?;
+
+    info!("Login connection ending");
+    connection.shutdown().await?;
+    info!("Login connection ended");
+
+    Ok(())
+}
+
+/// Answer a client's packets until it hangs up
+pub(crate) async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    db: &DBTask,
+    settings: &Config,
+    connection: &mut Connection<S>,
+) -> Result<()> {
+    let mut authenticated = None;
 
     while let Some(packet) = connection.read_packet().await? {
         match packet.packet {
-            Packet::SEND_IDPASS(p) if !authenticated => {
-                let username = p.username.to_string();
-                let password = p.password.to_string();
+            Packet::SEND_IDPASS(p) if authenticated.is_none() => {
+                let result = match p.credentials() {
+                    Some((username, password)) => {
+                        authenticate_user(db, settings, username, password, p.version).await
+                    }
+                    None => {
+                        warn!("rejecting login with malformed credentials: {p:?}");
+                        AckIDPassResult::IDError
+                    }
+                };
                 let version = p.version;
-                let result = authenticate_user(&db, username, password, version).await;
                 if result == AckIDPassResult::OK {
-                    authenticated = true;
+                    // remember the version, since it decides which servers they get
+                    authenticated = Some(version);
                 }
                 connection.write_packet(Packet::ACK_IDPASS(result)).await?;
             }
 
-            Packet::REQ_GMSVLIST if authenticated => {
-                let gmsv = GmsvData {
-                    number: 1,
-                    ip_address: "splash.wuffs.org".parse()?,
-                    port: 2051,
-                    enc_key: "i am not used".parse()?,
-                    name: "CoolServer2".parse()?,
-                    comment: "hewwo???".parse()?,
-                    max: 20,
-                    now: 1,
+            Packet::REQ_GMSVLIST => {
+                let version = match authenticated {
+                    Some(version) => version,
+                    None => {
+                        warn!("server list requested before logging in");
+                        continue;
+                    }
                 };
-                connection.write_packet(Packet::SEND_GMSVDATA(gmsv)).await?;
+
+                for server in settings.login.servers_for(version) {
+                    let gmsv = GmsvData {
+                        number: server.number,
+                        ip_address: server.address.parse()?,
+                        port: server.port,
+                        enc_key: "i am not used".parse()?,
+                        name: server.name.parse()?,
+                        comment: server.comment.parse()?,
+                        max: server.max,
+                        now: 1,
+                    };
+                    connection.write_packet(Packet::SEND_GMSVDATA(gmsv)).await?;
+                }
                 connection.write_packet(Packet::ACK_GMSVLIST).await?;
             }
             _ => {
@@ -82,14 +122,15 @@ async fn handle_connection(db: DBTask, tcp_stream: TcpStream, acceptor: TlsAccep
         }
     }
 
-    info!("Login connection ending");
-    connection.shutdown().await?;
-    info!("Login connection ended");
-
     Ok(())
 }
 
-pub async fn run<A: ToSocketAddrs>(db: DBTask, config: Arc<ServerConfig>, addr: A) -> Result<()> {
+pub async fn run<A: ToSocketAddrs>(
+    db: DBTask,
+    config: Arc<ServerConfig>,
+    settings: Arc<Config>,
+    addr: A,
+) -> Result<()> {
     let acceptor = TlsAcceptor::from(config);
     let listener = TcpListener::bind(addr).await?;
 
@@ -97,9 +138,10 @@ pub async fn run<A: ToSocketAddrs>(db: DBTask, config: Arc<ServerConfig>, addr:
         let (stream, _) = listener.accept().await?;
         let acceptor = acceptor.clone();
         let db = db.clone();
+        let settings = settings.clone();
 
         tokio::spawn(async move {
-            let res = handle_connection(db, stream, acceptor).await;
+            let res = handle_connection(db, settings, stream, acceptor).await;
             match res {
                 Ok(_) => {}
                 Err(err) => {
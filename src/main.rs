@@ -1,12 +1,14 @@
 use std::{fs::File, io::BufReader, sync::Arc};
 
 use anyhow::Result;
-use log::info;
+use log::{error, info};
 use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
 
+mod config;
 mod data;
 mod db_task;
 mod gs2;
+mod http_server;
 mod login_server;
 mod packets;
 mod stream;
@@ -38,13 +40,27 @@ async fn main() -> Result<()> {
     env_logger::init();
 
     let config = Arc::new(load_config()?);
+    let settings = Arc::new(config::load("config.json")?);
     let db = db_task::run()?;
     let login_future = tokio::spawn(login_server::run(
         db.clone(),
         config.clone(),
+        settings.clone(),
         "0.0.0.0:2050",
     ));
-    let game_future = tokio::spawn(gs2::run(db, config, "0.0.0.0:2051"));
+    let gs2 = gs2::start(db.clone(), settings.clone());
+
+    // the HTTP server is optional, so it shouldn't take the game down with it
+    if settings.http.enabled {
+        let http_future = http_server::run(db, gs2.clone(), settings.clone());
+        tokio::spawn(async move {
+            if let Err(e) = http_future.await {
+                error!("HTTP server stopped: {e:?}");
+            }
+        });
+    }
+
+    let game_future = tokio::spawn(gs2::run(gs2, config, settings, "0.0.0.0:2051"));
 
     info!("starting server");
     let (login, game) = tokio::join!(login_future, game_future);
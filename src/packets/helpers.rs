@@ -9,9 +9,18 @@ use deku::prelude::*;
 pub struct AString<const L: usize>([u8; L]);
 
 impl<const L: usize> AString<L> {
+    fn bytes(&self) -> &[u8] {
+        self.0.as_slice().split(|b| *b == 0).next().unwrap()
+    }
+
+    /// Get the string, replacing anything that isn't valid UTF-8
     pub fn to_string(&self) -> String {
-        let s = self.0.as_slice().split(|b| *b == 0).next().unwrap();
-        String::from_utf8(s.to_owned()).unwrap()
+        String::from_utf8_lossy(self.bytes()).into_owned()
+    }
+
+    /// Get the string, or None if it isn't valid UTF-8
+    pub fn try_to_string(&self) -> Option<String> {
+        String::from_utf8(self.bytes().to_owned()).ok()
     }
 }
 
@@ -23,18 +32,10 @@ impl<'a, const L: usize> DekuRead<'a> for AString<L> {
     where
         Self: Sized,
     {
-        let (rest, array): (&'a BitSlice<u8, Msb0>, [u8; L]) = DekuRead::read(input, ctx)?;
-        let s = array.as_slice().split(|b| *b == 0).next().unwrap();
-        match std::str::from_utf8(s) {
-            Ok(_) => {
-                // this is valid UTF-8 so we'll return it
-                Ok((rest, AString(array)))
-            }
-            Err(err) => {
-                // not valid UTF-8, raise the alarm
-                Err(DekuError::Parse(format!("Invalid String: {err}")))
-            }
-        }
+        // Whatever the client sent is kept as-is, so that handlers can decide how to
+        // answer text that isn't valid UTF-8 (see try_to_string)
+        let (rest, array) = DekuRead::read(input, ctx)?;
+        Ok((rest, AString(array)))
     }
 }
 
@@ -46,10 +47,8 @@ impl<const L: usize> DekuWrite for AString<L> {
 
 impl<const L: usize> Debug for AString<L> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = self.0.as_slice().split(|b| *b == 0).next().unwrap();
-        let s = std::str::from_utf8(s).unwrap();
-        let s = s.trim_end_matches('\0');
-        s.fmt(f)
+        let s = String::from_utf8_lossy(self.bytes());
+        s.trim_end_matches('\0').fmt(f)
     }
 }
 
@@ -77,9 +76,17 @@ impl<const L: usize> Default for WString<L> {
 }
 
 impl<const L: usize> WString<L> {
+    fn units(&self) -> &[u16] {
+        self.0.as_slice().split(|b| *b == 0).next().unwrap()
+    }
+
     pub fn to_string(&self) -> String {
-        let s = self.0.as_slice().split(|b| *b == 0).next().unwrap();
-        String::from_utf16_lossy(s)
+        String::from_utf16_lossy(self.units())
+    }
+
+    /// Get the string, or None if it isn't valid UTF-16
+    pub fn try_to_string(&self) -> Option<String> {
+        String::from_utf16(self.units()).ok()
     }
 }
 
@@ -1,4 +1,5 @@
 use bitflags::bitflags;
+use deku::bitvec::{BitSlice, BitVec, Msb0};
 use deku::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -74,6 +75,34 @@ pub struct DateTime {
     pub second: i8,
 }
 
+impl DateTime {
+    /// Convert a Unix timestamp into a UTC date and time
+    pub fn from_unix(timestamp: i64) -> Self {
+        let days = timestamp.div_euclid(86400);
+        let secs = timestamp.rem_euclid(86400);
+
+        // Days to civil date, from Howard Hinnant's date algorithms
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year: year as i16,
+            month: month as i8,
+            day: day as i8,
+            hour: (secs / 3600) as i8,
+            minute: (secs / 60 % 60) as i8,
+            second: (secs % 60) as i8,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, DekuRead, DekuWrite)]
 #[deku(type = "i8")]
 pub enum Mode {
@@ -86,6 +115,60 @@ pub enum Mode {
     Single = 5,
 }
 
+impl Mode {
+    /// Narrow a mode that the client stored as a 32-bit value
+    pub fn from_u32(val: u32) -> Option<Self> {
+        match val as i32 {
+            -1 => Some(Self::None),
+            0 => Some(Self::Main),
+            1 => Some(Self::VS),
+            2 => Some(Self::Competition),
+            3 => Some(Self::Quick),
+            4 => Some(Self::Mode4),
+            5 => Some(Self::Single),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    /// Extra bits that CLIENT_SHOT sets on the club, depending on the shot type
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub struct ShotFlags: u8 {
+        const S10 = 0x10;
+        const S20 = 0x20;
+        const S40 = 0x40;
+    }
+}
+
+/// What the club value in CLIENT_SHOT actually means
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ShotClub {
+    /// The player ran out of time
+    Timeout,
+    /// The player swung and missed the ball entirely
+    Whiff,
+    Club {
+        club: i8,
+        flags: ShotFlags,
+    },
+}
+
+impl ShotClub {
+    pub fn decode(raw: i8) -> Option<Self> {
+        match raw {
+            -1 => Some(Self::Timeout),
+            -2 => Some(Self::Whiff),
+            0.. => {
+                let flags = ShotFlags::from_bits(raw as u8 & 0xF0)?;
+                let club = raw & 0x0F;
+                (club <= 14).then_some(Self::Club { club, flags })
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, DekuRead, DekuWrite)]
 #[deku(type = "i8")]
 pub enum Status {
@@ -193,7 +276,9 @@ pub enum Packet {
     #[deku(id = "20")]
     REQ_ENTER_ROOM {
         room: RoomNum,
-        #[deku(bits = 1, pad_bits_after = "31")]
+        // This is bit 0 of a little-endian u32, so it's at the bottom of the first byte.
+        // The whole field is 4 bytes either way, so the password lines up regardless.
+        #[deku(bits = 1, pad_bits_before = "7", pad_bits_after = "24")]
         unk_room_flag: bool,
         room_password: WString<17>,
     },
@@ -203,6 +288,8 @@ pub enum Packet {
     ACK_ENTER_ROOM(Packet19),
 
     // Client - Request UList for room (PID important)
+    // Sent on reaching a VS/Compe lounge (the inside of a room), after ACK_ENTER_ROOM
+    // or ACK_MAKE_ROOM. Answered with a SEND_ULIST per member and then ACK_ULIST_R.
     #[deku(id = "22")]
     REQ_ULIST(Mode, LobbyNum, RoomNum),
 
@@ -646,8 +733,8 @@ pub enum Packet {
     // Server
     #[deku(id = "99")]
     SEND_CHRUID {
+        // counts the cid as well as the chr_uids, as they seem to be one array
         count: i32,
-        // are these part of the same array...?
         cid: CID,
         #[deku(count = "count - 1")]
         chr_uids: Vec<ChrUID>,
@@ -1524,7 +1611,7 @@ pub enum Packet {
     PKT_264 {
         count: i16,
         #[deku(count = "count")]
-        items: Vec<[u8; 0x1C]>,
+        items: Vec<RecycleData>,
     },
 
     // Server - RecycleTaskZ vf08
@@ -1634,7 +1721,7 @@ pub enum Packet {
     // 284 - unused
     // 285 - unused
 
-    // Client - Player out from gallery? (cRoundMain_SendRetire)
+    // Client - Retire from the round, or leave the gallery (cRoundMain_SendRetire)
     #[deku(id = "286")]
     PKT_286,
 
@@ -1658,7 +1745,9 @@ pub enum Packet {
     // 305 - unused
     // 306 - unused
 
-    // Server - VS lounge stuff
+    // Server - ends a list of room members
+    // The lounge stays on its loading screen until this arrives, so it has to be sent
+    // even if the room has gone. The lobby-level list (REQ_ULIST_L) doesn't use it.
     #[deku(id = "307")]
     ACK_ULIST_R(Status),
 
@@ -1725,6 +1814,16 @@ pub struct IDPass {
     pub version: u16,
 }
 
+impl IDPass {
+    /// Get the username and password, or None if either of them is garbled
+    pub fn credentials(&self) -> Option<(String, String)> {
+        Some((
+            self.username.try_to_string()?,
+            self.password.try_to_string()?,
+        ))
+    }
+}
+
 // 2
 #[derive(Debug, Clone, Copy, Eq, PartialEq, DekuRead, DekuWrite)]
 #[deku(type = "i8")]
@@ -1756,7 +1855,7 @@ pub struct GmsvData {
     pub now: i16,
 }
 
-#[derive(Debug, Clone, DekuRead, DekuWrite)]
+#[derive(Debug, Clone)]
 pub struct UData {
     pub cid: CID,
     pub uid: UID,
@@ -1774,20 +1873,100 @@ pub struct UData {
     pub name: WString<19>,
     pub element: Element,
     pub class: Rank,
-    // TODO: I'm 99% sure these bits are not mapped properly by Deku
-    // these should all be part of a single u32
-    #[deku(bits = 5)]
+    // these four are 5-bit fields packed into a single u32, lowest bits first
     pub rank_item_on: u8,
-    #[deku(bits = 5)]
     pub rank_item_off: u8,
-    #[deku(bits = 5)]
     pub best_rank_item_on: u8,
-    #[deku(bits = 5, pad_bits_after = "12")]
     pub best_rank_item_off: u8,
     pub x_f4: u32, //&4 : refuses home delivery
     pub debug: bool,
 }
 
+impl DekuRead<'_> for UData {
+    fn read(input: &BitSlice<u8, Msb0>, ctx: ()) -> Result<(&BitSlice<u8, Msb0>, Self), DekuError>
+    where
+        Self: Sized,
+    {
+        let (input, cid) = CID::read(input, ctx)?;
+        let (input, uid) = UID::read(input, ctx)?;
+        let (input, chr_uid) = ChrUID::read(input, ctx)?;
+        let (input, golfbag) = <[Item; 8]>::read(input, ctx)?;
+        let (input, holdbox) = <[Item; 8]>::read(input, ctx)?;
+        let (input, medals) = <[[i16; 4]; 4]>::read(input, ctx)?;
+        let (input, awards) = <[i32; 20]>::read(input, ctx)?;
+        let (input, rank_score_item_on) = i16::read(input, ctx)?;
+        let (input, rank_score_item_off) = i16::read(input, ctx)?;
+        let (input, mp) = i32::read(input, ctx)?;
+        let (input, year) = i16::read(input, ctx)?;
+        let (input, month) = i8::read(input, ctx)?;
+        let (input, day) = i8::read(input, ctx)?;
+        let (input, name) = WString::<19>::read(input, ctx)?;
+        let (input, element) = Element::read(input, ctx)?;
+        let (input, class) = Rank::read(input, ctx)?;
+        let (input, ranks) = u32::read(input, ctx)?;
+        let (input, x_f4) = u32::read(input, ctx)?;
+        let (input, debug) = bool::read(input, ctx)?;
+
+        let data = UData {
+            cid,
+            uid,
+            chr_uid,
+            golfbag,
+            holdbox,
+            medals,
+            awards,
+            rank_score_item_on,
+            rank_score_item_off,
+            mp,
+            year,
+            month,
+            day,
+            name,
+            element,
+            class,
+            rank_item_on: (ranks & 0x1F) as u8,
+            rank_item_off: ((ranks >> 5) & 0x1F) as u8,
+            best_rank_item_on: ((ranks >> 10) & 0x1F) as u8,
+            best_rank_item_off: ((ranks >> 15) & 0x1F) as u8,
+            x_f4,
+            debug,
+        };
+        Ok((input, data))
+    }
+}
+
+impl DekuWrite for UData {
+    fn write(&self, output: &mut BitVec<u8, Msb0>, ctx: ()) -> Result<(), DekuError> {
+        self.cid.write(output, ctx)?;
+        self.uid.write(output, ctx)?;
+        self.chr_uid.write(output, ctx)?;
+        self.golfbag.write(output, ctx)?;
+        self.holdbox.write(output, ctx)?;
+        self.medals.write(output, ctx)?;
+        self.awards.write(output, ctx)?;
+        self.rank_score_item_on.write(output, ctx)?;
+        self.rank_score_item_off.write(output, ctx)?;
+        self.mp.write(output, ctx)?;
+        self.year.write(output, ctx)?;
+        self.month.write(output, ctx)?;
+        self.day.write(output, ctx)?;
+        self.name.write(output, ctx)?;
+        self.element.write(output, ctx)?;
+        self.class.write(output, ctx)?;
+
+        // the top 12 bits are left clear
+        let ranks: u32 = (self.rank_item_on as u32 & 0x1F)
+            | ((self.rank_item_off as u32 & 0x1F) << 5)
+            | ((self.best_rank_item_on as u32 & 0x1F) << 10)
+            | ((self.best_rank_item_off as u32 & 0x1F) << 15);
+        ranks.write(output, ctx)?;
+
+        self.x_f4.write(output, ctx)?;
+        self.debug.write(output, ctx)?;
+        Ok(())
+    }
+}
+
 impl Default for UData {
     fn default() -> Self {
         UData {
@@ -1828,7 +2007,7 @@ pub struct LobbyData {
     pub mode: Mode,
 }
 
-#[derive(Debug, Clone, DekuRead, DekuWrite)]
+#[derive(Debug, Clone, Default)]
 pub struct RoomStat {
     pub room: RoomNum,
     pub flag: i8,
@@ -1842,35 +2021,113 @@ pub struct RoomStat {
     pub num_holes: i8,
     pub course_setting: i8,
     // need to review these for competition rooms
-    // TODO: these bits are probably not mapped properly by deku
-    #[deku(bits = 4)]
+    // eight 4-bit fields packed into one u32, lowest bits first
     pub limit_0: u8,
-    #[deku(bits = 4)]
     pub limit_1: u8,
-    #[deku(bits = 4)]
     pub limit_2: u8,
-    #[deku(bits = 4)]
     pub limit_3: u8,
-    #[deku(bits = 4)]
     pub limit_4: u8,
-    #[deku(bits = 4)]
     pub limit_5: u8,
-    #[deku(bits = 4)]
     pub limit_6: u8,
-    #[deku(bits = 4)]
     pub limit_7: u8,
-    #[deku(bits = 1)]
+    // then another u32 of 1, 7, 4, 1 and 7 bits, with the top 12 bits unused
     pub limit_b_0: u8,
-    #[deku(bits = 7)]
     pub limit_b_1: u8,
-    #[deku(bits = 4)]
     pub limit_b_2: u8,
-    #[deku(bits = 1)]
     pub limit_b_3: u8,
-    #[deku(bits = 7, pad_bits_after = "12")]
     pub limit_b_4: u8,
 }
 
+impl DekuRead<'_> for RoomStat {
+    fn read(input: &BitSlice<u8, Msb0>, ctx: ()) -> Result<(&BitSlice<u8, Msb0>, Self), DekuError>
+    where
+        Self: Sized,
+    {
+        let (input, room) = RoomNum::read(input, ctx)?;
+        let (input, flag) = i8::read(input, ctx)?;
+        let (input, member_max) = i8::read(input, ctx)?;
+        let (input, member) = i8::read(input, ctx)?;
+        let (input, watcher) = i8::read(input, ctx)?;
+        let (input, rules) = i8::read(input, ctx)?;
+        let (input, time_limit) = i8::read(input, ctx)?;
+        let (input, course) = i8::read(input, ctx)?;
+        let (input, season) = i8::read(input, ctx)?;
+        let (input, num_holes) = i8::read(input, ctx)?;
+        let (input, course_setting) = i8::read(input, ctx)?;
+        let (input, limits) = u32::read(input, ctx)?;
+        let (input, limits_b) = u32::read(input, ctx)?;
+
+        let nibble = |n: u32| ((limits >> (n * 4)) & 0xF) as u8;
+        let stat = RoomStat {
+            room,
+            flag,
+            member_max,
+            member,
+            watcher,
+            rules,
+            time_limit,
+            course,
+            season,
+            num_holes,
+            course_setting,
+            limit_0: nibble(0),
+            limit_1: nibble(1),
+            limit_2: nibble(2),
+            limit_3: nibble(3),
+            limit_4: nibble(4),
+            limit_5: nibble(5),
+            limit_6: nibble(6),
+            limit_7: nibble(7),
+            limit_b_0: (limits_b & 1) as u8,
+            limit_b_1: ((limits_b >> 1) & 0x7F) as u8,
+            limit_b_2: ((limits_b >> 8) & 0xF) as u8,
+            limit_b_3: ((limits_b >> 12) & 1) as u8,
+            limit_b_4: ((limits_b >> 13) & 0x7F) as u8,
+        };
+        Ok((input, stat))
+    }
+}
+
+impl DekuWrite for RoomStat {
+    fn write(&self, output: &mut BitVec<u8, Msb0>, ctx: ()) -> Result<(), DekuError> {
+        self.room.write(output, ctx)?;
+        self.flag.write(output, ctx)?;
+        self.member_max.write(output, ctx)?;
+        self.member.write(output, ctx)?;
+        self.watcher.write(output, ctx)?;
+        self.rules.write(output, ctx)?;
+        self.time_limit.write(output, ctx)?;
+        self.course.write(output, ctx)?;
+        self.season.write(output, ctx)?;
+        self.num_holes.write(output, ctx)?;
+        self.course_setting.write(output, ctx)?;
+
+        let limits: u32 = [
+            self.limit_0,
+            self.limit_1,
+            self.limit_2,
+            self.limit_3,
+            self.limit_4,
+            self.limit_5,
+            self.limit_6,
+            self.limit_7,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (n, &limit)| {
+            acc | ((limit as u32 & 0xF) << (n * 4))
+        });
+        limits.write(output, ctx)?;
+
+        let limits_b: u32 = (self.limit_b_0 as u32 & 1)
+            | ((self.limit_b_1 as u32 & 0x7F) << 1)
+            | ((self.limit_b_2 as u32 & 0xF) << 8)
+            | ((self.limit_b_3 as u32 & 1) << 12)
+            | ((self.limit_b_4 as u32 & 0x7F) << 13);
+        limits_b.write(output, ctx)
+    }
+}
+
 // 19
 #[derive(Debug, Clone, DekuRead, DekuWrite)]
 pub struct Packet19 {
@@ -1888,29 +2145,7 @@ impl Packet19 {
             lobby,
             room_stat: RoomStat {
                 room: code,
-                flag: 0,
-                member_max: 0,
-                member: 0,
-                watcher: 0,
-                rules: 0,
-                time_limit: 0,
-                course: 0,
-                season: 0,
-                num_holes: 0,
-                course_setting: 0,
-                limit_0: 0,
-                limit_1: 0,
-                limit_2: 0,
-                limit_3: 0,
-                limit_4: 0,
-                limit_5: 0,
-                limit_6: 0,
-                limit_7: 0,
-                limit_b_0: 0,
-                limit_b_1: 0,
-                limit_b_2: 0,
-                limit_b_3: 0,
-                limit_b_4: 0,
+                ..Default::default()
             },
             room_name: WString::default(),
             room_password: WString::default(),
@@ -1923,7 +2158,7 @@ pub struct UList {
     pub cid: CID,
     pub uid: UID,
     pub stat: u16,
-    // TODO: double-check that this field is mapped as expected
+    // The team is bit 0 of a little-endian u32, so it's at the bottom of the first byte
     #[deku(bits = 1, pad_bits_before = "7", pad_bits_after = "24")]
     pub team: u8,
     pub mode: Mode,
@@ -1942,7 +2177,7 @@ pub struct UListL {
     pub cid: CID,
     pub uid: UID,
     pub stat: u16,
-    // TODO: double-check that this field is mapped as expected
+    // Laid out the same way as in UList
     #[deku(bits = 1, pad_bits_before = "7", pad_bits_after = "24")]
     pub team: u8, // assumed, this might be fake
     pub mode: Mode,
@@ -2039,8 +2274,7 @@ impl Outcome {
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(type = "u8", bits = "3")]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum QuickMatchHoleSetting {
     Hole3 = 0,
     Hole6 = 1,
@@ -2049,8 +2283,20 @@ pub enum QuickMatchHoleSetting {
     Random = 4,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(type = "u8", bits = "3")]
+impl QuickMatchHoleSetting {
+    pub fn from_u32(val: u32) -> Option<Self> {
+        match val {
+            0 => Some(Self::Hole3),
+            1 => Some(Self::Hole6),
+            2 => Some(Self::Hole9),
+            3 => Some(Self::Hole18),
+            4 => Some(Self::Random),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum QuickMatchTimeSetting {
     Time30 = 0,
     Time60 = 1,
@@ -2059,44 +2305,114 @@ pub enum QuickMatchTimeSetting {
     Random = 4,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(type = "u8", bits = "2")]
+impl QuickMatchTimeSetting {
+    pub fn from_u32(val: u32) -> Option<Self> {
+        match val {
+            0 => Some(Self::Time30),
+            1 => Some(Self::Time60),
+            2 => Some(Self::Time90),
+            3 => Some(Self::Time120),
+            4 => Some(Self::Random),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum QuickMatchRuleSetting {
     Stroke = 0,
     Match = 1,
     Random = 2,
 }
 
-#[derive(Debug, Clone, DekuRead, DekuWrite)]
+impl QuickMatchRuleSetting {
+    pub fn from_u32(val: u32) -> Option<Self> {
+        match val {
+            0 => Some(Self::Stroke),
+            1 => Some(Self::Match),
+            2 => Some(Self::Random),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Packet50Data {
     pub uid: UID,
     pub score: i16,
     pub x6: u8,
     pub win_combo: i8,
 
-    // TODO: these bits are almost certainly not mapped properly by Deku
-    // all fields from here up to (and including) time_setting are part of one u32
-    #[deku(bits = 5)]
+    // all fields from here up to (and including) time_setting are part of one u32,
+    // lowest bits first: 5, 5, 5, 1, 2, 3 and 3 bits, then 8 unused
     pub server_id: i8,
-    #[deku(bits = 5)]
     pub rank: i8,
-    #[deku(bits = 5)]
     pub best_rank: i8,
-    #[deku(bits = 1)]
     pub item_on: bool,
-    // 2 bits
     pub rule_setting: QuickMatchRuleSetting,
-    // 3 bits
     pub hole_setting: QuickMatchHoleSetting,
-    // 3 bits
-    #[deku(pad_bits_after = "8")]
     pub time_setting: QuickMatchTimeSetting,
 
-    // unsure
-    #[deku(bits = 2, pad_bits_after = "30")]
+    // unsure; the bottom 2 bits of another u32
     pub unk: i8,
 }
 
+impl DekuRead<'_> for Packet50Data {
+    fn read(input: &BitSlice<u8, Msb0>, ctx: ()) -> Result<(&BitSlice<u8, Msb0>, Self), DekuError>
+    where
+        Self: Sized,
+    {
+        let (input, uid) = UID::read(input, ctx)?;
+        let (input, score) = i16::read(input, ctx)?;
+        let (input, x6) = u8::read(input, ctx)?;
+        let (input, win_combo) = i8::read(input, ctx)?;
+        let (input, val) = u32::read(input, ctx)?;
+        let (input, val2) = u32::read(input, ctx)?;
+
+        let invalid =
+            |what: &str| DekuError::Parse(format!("invalid quick match {what}: {val:#x}"));
+        let data = Packet50Data {
+            uid,
+            score,
+            x6,
+            win_combo,
+            server_id: (val & 0x1F) as i8,
+            rank: ((val >> 5) & 0x1F) as i8,
+            best_rank: ((val >> 10) & 0x1F) as i8,
+            item_on: (val >> 15) & 1 != 0,
+            rule_setting: QuickMatchRuleSetting::from_u32((val >> 16) & 3)
+                .ok_or_else(|| invalid("rule"))?,
+            hole_setting: QuickMatchHoleSetting::from_u32((val >> 18) & 7)
+                .ok_or_else(|| invalid("holes"))?,
+            time_setting: QuickMatchTimeSetting::from_u32((val >> 21) & 7)
+                .ok_or_else(|| invalid("time"))?,
+            unk: (val2 & 3) as i8,
+        };
+        Ok((input, data))
+    }
+}
+
+impl DekuWrite for Packet50Data {
+    fn write(&self, output: &mut BitVec<u8, Msb0>, ctx: ()) -> Result<(), DekuError> {
+        self.uid.write(output, ctx)?;
+        self.score.write(output, ctx)?;
+        self.x6.write(output, ctx)?;
+        self.win_combo.write(output, ctx)?;
+
+        let val: u32 = (self.server_id as u32 & 0x1F)
+            | ((self.rank as u32 & 0x1F) << 5)
+            | ((self.best_rank as u32 & 0x1F) << 10)
+            | ((self.item_on as u32) << 15)
+            | ((self.rule_setting as u32) << 16)
+            | ((self.hole_setting as u32) << 18)
+            | ((self.time_setting as u32) << 21);
+        val.write(output, ctx)?;
+
+        let val2: u32 = self.unk as u32 & 3;
+        val2.write(output, ctx)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, DekuRead, DekuWrite)]
 #[deku(type = "i8")]
 pub enum BuyItemResult {
@@ -2137,6 +2453,18 @@ pub enum MailSendResult {
     Err = -4,
 }
 
+/// One recipe in the recycling shop, as listed in PKT_264
+#[derive(Debug, Clone, DekuRead, DekuWrite)]
+pub struct RecycleData {
+    /// Sent back in PKT_266 to pick this recipe
+    pub index: i16,
+    // TODO: the layout past the index is a best guess; the client takes away one of
+    // each of five materials when a recycle succeeds
+    pub unk: i16,
+    pub result: Item,
+    pub materials: [Item; 5],
+}
+
 #[derive(Debug, Clone, DekuRead, DekuWrite)]
 pub struct Delivery {
     pub unk1: i32,
@@ -2206,7 +2534,9 @@ pub enum ChgUDataType {
     FriendRequestRejected = 4,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, DekuRead, DekuWrite, Serialize, Deserialize)]
+#[derive(
+    Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, DekuRead, DekuWrite, Serialize, Deserialize,
+)]
 #[deku(type = "i8")]
 pub enum Element {
     None = -1,
@@ -1,23 +1,63 @@
-use anyhow::Result;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
 use bytes::{Buf, BytesMut};
 use deku::{DekuContainerRead, DekuContainerWrite, DekuEnumExt};
 use log::{debug, error};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
 };
 use tokio_rustls::server::TlsStream;
 
 use crate::packets::{EntirePacket, Packet, PacketHeader};
 
-pub struct Connection {
-    stream: TlsStream<TcpStream>,
+/// A packet that has been serialised once, so that it can go out to several clients
+/// without redoing the work. Only the pid in the header changes between copies.
+#[derive(Clone)]
+pub struct Frame {
+    pub packet: Arc<Packet>,
+    data: Arc<[u8]>,
+}
+
+impl Frame {
+    pub fn new(packet: Packet) -> Result<Frame> {
+        let id = packet.deku_id()?;
+        let entire = EntirePacket {
+            header: PacketHeader { id, pid: 0 },
+            packet,
+        };
+        let data = entire.to_bytes()?;
+
+        Ok(Frame {
+            packet: Arc::new(entire.packet),
+            data: data.into(),
+        })
+    }
+
+    /// Make the bytes for one copy of the packet
+    pub fn with_pid(&self, pid: i16) -> Result<Vec<u8>> {
+        let header = PacketHeader {
+            id: self.packet.deku_id()?,
+            pid,
+        }
+        .to_bytes()?;
+        let mut data = self.data.to_vec();
+        data[..header.len()].copy_from_slice(&header);
+        Ok(data)
+    }
+}
+
+/// A stream of packets to or from a client. This is normally a TLS stream, but
+/// anything else that reads and writes bytes will do.
+pub struct Connection<S = TlsStream<TcpStream>> {
+    stream: S,
     buffer: BytesMut,
     next_pid: i16,
 }
 
-impl Connection {
-    pub fn new(stream: TlsStream<TcpStream>) -> Connection {
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(stream: S) -> Connection<S> {
         Connection {
             stream,
             buffer: BytesMut::with_capacity(4 * 1024),
@@ -52,10 +92,21 @@ impl Connection {
             packet,
         };
         let data = packet.to_bytes()?;
+        self.write_data(&data).await
+    }
+
+    pub async fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        let pid = self.next_pid;
+        self.next_pid += 1;
 
+        let data = frame.with_pid(pid)?;
+        self.write_data(&data).await
+    }
+
+    async fn write_data(&mut self, data: &[u8]) -> Result<()> {
         debug!("writing {data:?}");
         self.stream.write_u16_le(data.len().try_into()?).await?;
-        self.stream.write_all(&data).await?;
+        self.stream.write_all(data).await?;
         Ok(())
     }
 
@@ -73,15 +124,24 @@ impl Connection {
 
         // we should have enough data
         let payload = &self.buffer[2..2 + packet_size];
+        let hex = || -> String { payload.iter().map(|b| format!("{b:02x}")).collect() };
+
+        // The header usually decodes fine even when the body doesn't, and says
+        // which packet layout is to blame
+        let header = match PacketHeader::from_bytes((payload, 0)) {
+            Ok((_, header)) => header,
+            Err(e) => {
+                error!("failed to parse packet header: [{}]", hex());
+                return Err(e.into());
+            }
+        };
+
         let (_remain, packet) = match EntirePacket::from_bytes((payload, 0)) {
             Ok(p) => p,
             Err(e) => {
-                let mut buf = String::new();
-                for b in payload {
-                    buf.push_str(&format!("{b:02x}"));
-                }
-                error!("failed to parse packet: [{buf}]");
-                return Err(e.into());
+                let PacketHeader { id, pid } = header;
+                error!("failed to parse packet {id} (pid {pid}): [{}]", hex());
+                return Err(anyhow!(e).context(format!("failed to parse packet {id}")));
             }
         };
 